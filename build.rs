@@ -0,0 +1,34 @@
+//! Embeds the git commit and the rustc version of the build as `GIT_COMMIT` and `RUSTC_VERSION`,
+//! see `spreading_operator::GIT_COMMIT`. A `GIT_COMMIT` set in the environment wins, e.g. for
+//! builds without the repository.
+
+use std::path::Path;
+use std::process::Command;
+
+fn main() {
+    let output = |program: &str, args: &[&str]| {
+        Command::new(program)
+            .args(args)
+            .output()
+            .ok()
+            .filter(|o| o.status.success())
+            .and_then(|o| String::from_utf8(o.stdout).ok())
+            .map(|s| s.trim().to_string())
+    };
+    let commit = std::env::var("GIT_COMMIT")
+        .ok()
+        .filter(|c| !c.is_empty())
+        .or_else(|| output("git", &["rev-parse", "--short", "HEAD"]))
+        .unwrap_or_else(|| "unknown".to_string());
+    let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    let rustc_version = output(&rustc, &["--version"]).unwrap_or_else(|| "unknown".to_string());
+    println!("cargo:rustc-env=GIT_COMMIT={}", commit);
+    println!("cargo:rustc-env=RUSTC_VERSION={}", rustc_version);
+
+    println!("cargo:rerun-if-env-changed=GIT_COMMIT");
+    for path in [".git/HEAD", ".git/refs"] {
+        if Path::new(path).exists() {
+            println!("cargo:rerun-if-changed={}", path);
+        }
+    }
+}
@@ -0,0 +1,285 @@
+//! Self-check of the operator: whether its service account has the permissions the controllers
+//! need, asked with `SelfSubjectAccessReview`, and whether the spread annotations of the sources
+//! parse. The same permissions are printed as RBAC objects by `--dump-rbac`.
+//!
+//! Nothing in the cluster is modified.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use k8s_openapi::api::authorization::v1::{ResourceAttributes, SelfSubjectAccessReview, SelfSubjectAccessReviewSpec};
+use k8s_openapi::api::core::v1::Secret;
+use k8s_openapi::api::rbac::v1::{ClusterRole, ClusterRoleBinding, PolicyRule, Role, RoleBinding, RoleRef, Subject};
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::ObjectMeta;
+use kube::api::PostParams;
+use kube::{Api, Client, Resource};
+use serde::Serialize;
+use serde_json::{json, Value};
+use tracing::warn;
+
+use crate::cli::Outcome;
+use crate::config::SpreadConfig;
+use crate::opts::Opts;
+use crate::{scoped_api, source_list_params, targets, watch_scopes, Error};
+
+/// API group, resource and verbs of a permission.
+type Permission = (&'static str, &'static str, &'static [&'static str]);
+
+/// API groups, resources and verbs the operator needs in all namespaces.
+const REQUIRED: [Permission; 6] = [
+    ("", "secrets", &["get", "list", "watch", "create", "patch", "delete"]),
+    ("", "namespaces", &["get", "list", "watch"]),
+    // the ConfigMap controller always runs
+    ("", "configmaps", &["get", "list", "watch", "create", "patch", "delete"]),
+    ("", "events", &["create"]),
+    // target-for-group
+    ("rbac.authorization.k8s.io", "rolebindings", &["list"]),
+    ("authorization.k8s.io", "selfsubjectaccessreviews", &["create"]),
+];
+
+/// What the operator needs in addition with `--status-crd`.
+const STATUS_CRD_REQUIRED: [Permission; 2] = [
+    ("secretspreading.fitzek.eu", "spreadstatuses", &["get", "patch"]),
+    ("secretspreading.fitzek.eu", "spreadstatuses/status", &["patch"]),
+];
+
+/// What the operator needs in addition with `--secret-spread-crd`.
+const SECRET_SPREAD_CRD_REQUIRED: [Permission; 2] = [
+    ("secretspreading.fitzek.eu", "secretspreads", &["get", "list", "watch", "patch"]),
+    ("secretspreading.fitzek.eu", "secretspreads/status", &["patch"]),
+];
+
+/// What the operator needs in addition with `--create-namespaces`.
+const CREATE_NAMESPACES_REQUIRED: [Permission; 1] = [("", "namespaces", &["create"])];
+
+/// What the operator needs in addition with `--pull-secret-source-namespace`.
+const PULL_SECRETS_REQUIRED: [Permission; 1] = [("apps", "deployments", &["get", "list", "watch"])];
+
+/// What the operator needs in the namespace of `--leader-election-namespace`, if set.
+const LEADER_ELECTION_REQUIRED: [Permission; 1] = [("coordination.k8s.io", "leases", &["get", "create", "update"])];
+
+/// Returns the permissions the operator needs in all namespaces with the options `opts`.
+fn required(opts: &Opts) -> Vec<Permission> {
+    let mut required = REQUIRED.to_vec();
+    if opts.status_crd {
+        required.extend(STATUS_CRD_REQUIRED);
+    }
+    if opts.secret_spread_crd {
+        required.extend(SECRET_SPREAD_CRD_REQUIRED);
+    }
+    if opts.create_namespaces {
+        required.extend(CREATE_NAMESPACES_REQUIRED);
+    }
+    if opts.pull_secret_source_namespace.as_ref().is_some_and(|ns| !ns.is_empty()) {
+        required.extend(PULL_SECRETS_REQUIRED);
+    }
+    required
+}
+
+/// Returns the permissions required with the options `opts` the operator lacks, as
+/// `<verb> <resource>`.
+pub async fn missing_permissions(client: Client, opts: &Opts) -> Result<Vec<String>, Error> {
+    let api: Api<SelfSubjectAccessReview> = Api::all(client);
+    let mut missing = Vec::new();
+    for (group, resource, verbs) in required(opts) {
+        // subresources are asked for separately
+        let (resource, subresource) = match resource.split_once('/') {
+            Some((resource, subresource)) => (resource, Some(subresource.to_string())),
+            None => (resource, None),
+        };
+        for verb in verbs.iter() {
+            let review = SelfSubjectAccessReview {
+                spec: SelfSubjectAccessReviewSpec {
+                    resource_attributes: Some(ResourceAttributes {
+                        group: Some(group.to_string()),
+                        resource: Some(resource.to_string()),
+                        subresource: subresource.clone(),
+                        verb: Some(verb.to_string()),
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                },
+                ..Default::default()
+            };
+            let allowed = api
+                .create(&PostParams::default(), &review)
+                .await?
+                .status
+                .is_some_and(|status| status.allowed);
+            if !allowed {
+                let resource = match &subresource {
+                    Some(subresource) => format!("{}/{}", resource, subresource),
+                    None => resource.to_string(),
+                };
+                missing.push(format!("{} {}", verb, resource));
+            }
+        }
+    }
+    Ok(missing)
+}
+
+/// Checks the permissions of the operator and the spread annotations of the sources watched with
+/// the options `opts`. Reports the missing permissions and the invalid sources, passes if there
+/// are none.
+pub async fn run(client: Client, opts: &Opts) -> Result<Outcome, Error> {
+    let mut text = String::new();
+    let missing = missing_permissions(client.clone(), opts).await?;
+    if missing.is_empty() {
+        text.push_str("Permissions: ok\n");
+    }
+    for permission in &missing {
+        text.push_str(&format!("Permissions: missing {} in all namespaces\n", permission));
+    }
+
+    let mut invalid = Vec::new();
+    let mut sources = 0;
+    for scope in watch_scopes(opts) {
+        let secret_api: Api<Secret> = scoped_api(client.clone(), scope.as_deref());
+        for sec in secret_api.list(&source_list_params(opts.source_label_selector.as_deref())).await? {
+            if !targets::has_targets(&sec.metadata) {
+                continue;
+            }
+            sources += 1;
+            if let Err(e) = SpreadConfig::from_secret(&sec) {
+                let source = format!("{}/{}", sec.namespace().unwrap_or_default(), sec.name());
+                text.push_str(&format!("Source {}: {}\n", source, e));
+                invalid.push(json!({ "source": source, "error": e.to_string() }));
+            }
+        }
+    }
+    text.push_str(&format!("Sources: {} checked\n", sources));
+
+    Ok(Outcome {
+        passed: missing.is_empty() && invalid.is_empty(),
+        text,
+        json: json!({
+            "missingPermissions": missing,
+            "sources": { "checked": sources, "invalid": invalid },
+        }),
+    })
+}
+
+/// Warns about every required permission the operator lacks, so a controller that can't do its
+/// job says so right at startup.
+pub async fn warn_missing(client: Client, opts: &Opts) {
+    match missing_permissions(client, opts).await {
+        Ok(missing) => {
+            for permission in missing {
+                warn!(permission = %permission, "Missing permission in all namespaces");
+            }
+        }
+        Err(e) => warn!(error = %e, "Can't check the permissions of the operator"),
+    }
+}
+
+/// Returns the RBAC objects granting the service account `<namespace>/<name>` what the operator
+/// needs with the options `opts`, as YAML documents and as JSON list: a ClusterRole with its
+/// ClusterRoleBinding and, with leader election, a Role with its RoleBinding for the Lease. The
+/// objects are named like the instance, see `--instance-name`.
+///
+/// The `hasResource` criterion of the `target` policy lists resources only known from the
+/// sources, they are not included.
+pub fn rbac(service_account: &str, opts: &Opts) -> Result<Outcome, Error> {
+    let (namespace, name) = service_account
+        .split_once('/')
+        .filter(|(namespace, name)| !namespace.is_empty() && !name.is_empty())
+        .ok_or_else(|| Error::UserInputError(format!("Expected <namespace>/<name> of the service account, got {}", service_account)))?;
+    let instance_name = &opts.instance_name;
+    let subjects = Some(vec![Subject {
+        kind: "ServiceAccount".to_string(),
+        name: name.to_string(),
+        namespace: Some(namespace.to_string()),
+        ..Default::default()
+    }]);
+    let metadata = |namespace: Option<&str>| ObjectMeta {
+        name: Some(instance_name.clone()),
+        namespace: namespace.map(str::to_string),
+        ..Default::default()
+    };
+    let role_ref = |kind: &str| RoleRef {
+        api_group: "rbac.authorization.k8s.io".to_string(),
+        kind: kind.to_string(),
+        name: instance_name.clone(),
+    };
+
+    let mut yaml = String::new();
+    let mut objects = Vec::new();
+    let mut push = |(document, object): (String, Value)| {
+        yaml.push_str(&document);
+        objects.push(object);
+    };
+    push(rendered(&ClusterRole {
+        metadata: metadata(None),
+        rules: Some(policy_rules(&required(opts))),
+        ..Default::default()
+    }));
+    push(rendered(&ClusterRoleBinding {
+        metadata: metadata(None),
+        role_ref: role_ref("ClusterRole"),
+        subjects: subjects.clone(),
+    }));
+    if let Some(lease_namespace) = opts.leader_election_namespace.as_deref().filter(|ns| !ns.is_empty()) {
+        push(rendered(&Role {
+            metadata: metadata(Some(lease_namespace)),
+            rules: Some(policy_rules(&LEADER_ELECTION_REQUIRED)),
+        }));
+        push(rendered(&RoleBinding {
+            metadata: metadata(Some(lease_namespace)),
+            role_ref: role_ref("Role"),
+            subjects,
+        }));
+    }
+    Ok(Outcome::passed(yaml, Value::Array(objects)))
+}
+
+/// Renders the RBAC object `object` as YAML document and as JSON.
+fn rendered<T: Serialize>(object: &T) -> (String, Value) {
+    let document = serde_yaml::to_string(object).expect("RBAC objects are always serializable");
+    (document, serde_json::to_value(object).expect("RBAC objects are always serializable"))
+}
+
+/// Returns one rule per API group and resource of `permissions`, with the verbs of all
+/// permissions on it.
+fn policy_rules(permissions: &[Permission]) -> Vec<PolicyRule> {
+    let mut verbs: BTreeMap<(&str, &str), BTreeSet<&str>> = BTreeMap::new();
+    for (group, resource, permitted) in permissions {
+        verbs.entry((group, resource)).or_default().extend(permitted.iter());
+    }
+    verbs
+        .into_iter()
+        .map(|((group, resource), verbs)| PolicyRule {
+            api_groups: Some(vec![group.to_string()]),
+            resources: Some(vec![resource.to_string()]),
+            verbs: verbs.into_iter().map(str::to_string).collect(),
+            ..Default::default()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+    use serde::Deserialize;
+
+    use crate::cli::{self, ERROR, SUCCESS};
+
+    #[test]
+    fn rbac_is_printed_as_json_list() {
+        let opts = Opts::parse_from(["spreading-operator", "--leader-election-namespace", "operators"]);
+        let rendered = cli::render("dump-rbac", "json", rbac("operators/spreader", &opts));
+        assert_eq!(rendered.code, SUCCESS);
+        let report: Value = serde_json::from_str(&rendered.stdout).unwrap();
+        let kinds: Vec<&str> = report["result"].as_array().unwrap().iter().map(|o| o["kind"].as_str().unwrap()).collect();
+        assert_eq!(kinds, vec!["ClusterRole", "ClusterRoleBinding", "Role", "RoleBinding"]);
+        assert_eq!(report["result"][1]["subjects"], json!([{ "kind": "ServiceAccount", "name": "spreader", "namespace": "operators" }]));
+
+        // the YAML holds the same objects
+        let documents: Vec<Value> = serde_yaml::Deserializer::from_str(&rbac("operators/spreader", &opts).unwrap().text).map(|d| Value::deserialize(d).unwrap()).collect();
+        assert_eq!(Value::Array(documents), report["result"]);
+
+        let rendered = cli::render("dump-rbac", "json", rbac("spreader", &opts));
+        assert_eq!(rendered.code, ERROR);
+        let report: Value = serde_json::from_str(&rendered.stdout).unwrap();
+        assert_eq!(report["error"], "Invalid spread configuration: Expected <namespace>/<name> of the service account, got spreader");
+    }
+}
@@ -0,0 +1,202 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use futures::stream::StreamExt;
+use k8s_openapi::api::core::v1::{ConfigMap, Secret};
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::ObjectMeta;
+use kube::api::ListParams;
+use kube::{Api, Client, Resource};
+use kube_runtime::controller::{Context, ReconcilerAction};
+use kube_runtime::Controller;
+use tokio::time::Duration;
+use tracing::{error, warn};
+use vaultrs::client::{VaultClient, VaultClientSettingsBuilder};
+
+use crate::config::SpreadConfig;
+use crate::opts::Opts;
+use crate::sinks::EventSink;
+use crate::{concurrency, delete_copies, finalizer, on_error, scoped_api, settings, shutdown, sync_secret, targets, ContextData, Error, VAULT_PATH_ANNOTATION};
+
+/// A store holding the content of secrets that do not live in Kubernetes.
+#[async_trait]
+pub trait SourceBackend: Send + Sync {
+    /// Fetches the key/value pairs stored at `path`.
+    async fn fetch(&self, path: &str) -> Result<BTreeMap<String, String>, Error>;
+}
+
+/// Reads KV version 2 secrets from Vault.
+///
+/// The connection is configured via the usual `VAULT_ADDR`, `VAULT_TOKEN`, `VAULT_CACERT` and
+/// `VAULT_SKIP_VERIFY` environment variables.
+pub struct VaultBackend {
+    client: VaultClient,
+}
+
+impl VaultBackend {
+    /// Constructs a new VaultBackend from the environment.
+    pub fn from_env() -> Result<Self, Error> {
+        let settings = VaultClientSettingsBuilder::default()
+            .build()
+            .map_err(|e| Error::BackendError(e.to_string()))?;
+        let client = VaultClient::new(settings).map_err(|e| Error::BackendError(e.to_string()))?;
+        Ok(VaultBackend { client })
+    }
+}
+
+#[async_trait]
+impl SourceBackend for VaultBackend {
+    async fn fetch(&self, path: &str) -> Result<BTreeMap<String, String>, Error> {
+        let (mount, secret_path) = match path.split_once('/') {
+            Some((m, p)) if !m.is_empty() && !p.is_empty() => (m, p),
+            _ => {
+                return Err(Error::UserInputError(format!(
+                    "Expected Vault path in the form <mount>/<path>, got {}",
+                    path
+                )))
+            }
+        };
+        vaultrs::kv2::read(&self.client, mount, secret_path)
+            .await
+            .map_err(|e| Error::BackendError(e.to_string()))
+    }
+}
+
+/// Runs the controller spreading Vault backed sources declared by annotated ConfigMaps in the
+/// watch `scopes`, with the options `opts` and the `settings` shared with the Secret controller.
+pub async fn run(client: Client, scopes: &[Option<String>], opts: &Opts, settings: settings::Shared) {
+    let backend = match VaultBackend::from_env() {
+        Ok(b) => b,
+        Err(e) => {
+            warn!(error = %e, "Vault backend disabled");
+            return;
+        }
+    };
+
+    let context: Context<ContextData> = Context::new(
+        ContextData::new(client.clone(), opts)
+            .with_settings(settings)
+            .with_backend(Arc::new(backend), Duration::from_secs(opts.vault_refresh_interval))
+            .with_slots(concurrency::Slots::for_controller(concurrency::VAULT, opts)),
+    );
+
+    futures::future::join_all(scopes.iter().map(|scope| {
+        let configmap_api: Api<ConfigMap> = scoped_api(client.clone(), scope.as_deref());
+        Controller::new(configmap_api, ListParams::default())
+            .run(reconcile, on_error, context.clone())
+            .for_each(|reconciliation_result| async move {
+                if let Err(reconciliation_err) = reconciliation_result {
+                    error!(error = ?reconciliation_err, "Reconciliation error")
+                }
+            })
+    }))
+    .await;
+}
+
+/// Reconciles a ConfigMap declaring a backend source. The secret fetched from the backend is
+/// spread like a Secret source would be, using the ConfigMap's name and uid as the source.
+async fn reconcile(cm: ConfigMap, context: Context<ContextData>) -> Result<ReconcilerAction, Error> {
+    // no reconcile starts once the operator shuts down, see shutdown::drain
+    let _running = match shutdown::begin() {
+        Some(guard) => guard,
+        None => return Ok(ReconcilerAction { requeue_after: None }),
+    };
+    let path = match targets::annotation(&cm.metadata, VAULT_PATH_ANNOTATION) {
+        Some(p) => p,
+        None => return Ok(ReconcilerAction { requeue_after: None }),
+    };
+    // paused in the settings ConfigMap, see the Secret reconcile
+    if context.get_ref().paused() {
+        return Ok(ReconcilerAction {
+            requeue_after: Some(context.get_ref().jitter.apply(context.get_ref().requeue().idle)),
+        });
+    }
+
+    let source_namespace: String = match cm.namespace() {
+        None => {
+            return Err(Error::UserInputError(
+                "Expected ConfigMap resource to be namespaced.".to_owned(),
+            ));
+        }
+        Some(namespace) => namespace,
+    };
+
+    let source_uid: String = match &cm.meta().uid {
+        None => {
+            return Err(Error::UserInputError(
+                "Expected ConfigMap resource to have an uid".to_owned(),
+            ));
+        }
+        Some(v) => v.clone(),
+    };
+
+    let name = cm.name();
+    // the Vault controller has reconcile slots of its own, see concurrency
+    let _slot = context.get_ref().reconcile_slots.acquire().await;
+    let client: Client = context.get_ref().client.clone();
+
+    if cm.metadata.deletion_timestamp.is_some() {
+        // notifications refer to the source by its metadata only
+        let source = Secret {
+            metadata: cm.metadata.clone(),
+            ..Default::default()
+        };
+        for (ns, copy_name) in delete_copies::<Secret>(client.clone(), &source_uid, &context.get_ref().delete_params()).await? {
+            context.get_ref().sinks.on_deleted(&source, &ns, &copy_name).await;
+        }
+        finalizer::rm(client, &name, &source_namespace, &cm, &context.get_ref().patch_params()).await?;
+        return Ok(ReconcilerAction { requeue_after: None });
+    }
+
+    let metadata_only = Secret {
+        metadata: cm.metadata.clone(),
+        ..Default::default()
+    };
+    let config = match SpreadConfig::from_secret(&metadata_only)? {
+        Some(config) => config,
+        None => {
+            return Err(Error::UserInputError(format!(
+                "Vault source {}.{} has no target namespace annotation",
+                source_namespace, name
+            )))
+        }
+    };
+
+    let backend = match &context.get_ref().backend {
+        Some(b) => b.clone(),
+        None => return Err(Error::BackendError("No source backend configured".to_owned())),
+    };
+    let data = backend.fetch(&path).await?;
+
+    if context.get_ref().use_finalizer && !finalizer::add(client, &name, &source_namespace, &cm, &context.get_ref().patch_params()).await? {
+        warn!(source_namespace = %source_namespace, name = %name, "Finalizer not confirmed on the Vault source, not spreading yet");
+        return Ok(ReconcilerAction {
+            requeue_after: Some(context.get_ref().requeue().error),
+        });
+    }
+
+    let sec = Secret {
+        type_: Some("Opaque".to_string()),
+        string_data: Some(data),
+        metadata: ObjectMeta {
+            name: Some(name.clone()),
+            namespace: Some(source_namespace.clone()),
+            uid: Some(source_uid.clone()),
+            labels: cm.metadata.labels.clone(),
+            annotations: cm.metadata.annotations.clone(),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+
+    let outcome = sync_secret(sec, &config, context.clone(), source_uid, source_namespace, name).await?;
+    if outcome.failure.is_some() {
+        return Ok(ReconcilerAction {
+            requeue_after: Some(context.get_ref().requeue().error),
+        });
+    }
+
+    Ok(ReconcilerAction {
+        requeue_after: Some(context.get_ref().jitter.apply(context.get_ref().backend_refresh)),
+    })
+}
@@ -0,0 +1,125 @@
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use futures::stream::StreamExt;
+use futures::Future;
+use k8s_openapi::api::core::v1::Secret;
+use kube::api::ListParams;
+use kube::{Api, Client, Resource};
+use kube_runtime::reflector::store::Writer;
+use kube_runtime::reflector::{reflector, ObjectRef, Store};
+use kube_runtime::watcher;
+use tracing::warn;
+
+use crate::keys;
+
+/// Read only view of all copies in the cluster, kept up to date by a watch on the owner label.
+///
+/// The view may lag behind the cluster. A copy missing in the cache is looked up live, and
+/// copies are written with server-side apply, which only sets the fields of the operator, so a
+/// write based on an outdated cached copy doesn't undo the changes of others made since.
+#[derive(Clone)]
+pub struct CopyCache {
+    store: Store<Secret>,
+    /// Whether the initial list of the watch completed, the store is cold before.
+    warm: Arc<AtomicBool>,
+}
+
+impl CopyCache {
+    /// Returns the cached copy `name` in `namespace`.
+    pub fn get(&self, namespace: &str, name: &str) -> Option<Secret> {
+        self.store.get(&ObjectRef::new(name).within(namespace))
+    }
+
+    /// Returns the names of the cached copies of the source with uid `source_uid` by namespace,
+    /// or `None` while the cache is cold. A copy created just before may be missing, the orphan
+    /// scan is the backstop for it.
+    pub fn copies_of(&self, source_uid: &str) -> Option<BTreeMap<String, Vec<String>>> {
+        if !self.warm.load(Ordering::SeqCst) {
+            return None;
+        }
+        let mut by_namespace: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        for copy in self.store.state() {
+            let owner = copy.meta().labels.as_ref().and_then(|l| l.get(keys::owner_label()));
+            if let (Some(ns), true) = (copy.namespace(), owner.map(String::as_str) == Some(source_uid)) {
+                by_namespace.entry(ns).or_default().push(copy.name());
+            }
+        }
+        Some(by_namespace)
+    }
+}
+
+/// Constructs a new CopyCache and the future feeding it, which has to be polled for the cache
+/// to fill.
+pub fn copies(client: Client) -> (CopyCache, impl Future<Output = ()>) {
+    let writer: Writer<Secret> = Writer::default();
+    let warm = Arc::new(AtomicBool::new(false));
+    let cache = CopyCache {
+        store: writer.as_reader(),
+        warm: warm.clone(),
+    };
+
+    let secret_api: Api<Secret> = Api::all(client);
+    let lp = ListParams::default().labels(keys::owner_label());
+    let runner = reflector(writer, watcher(secret_api, lp)).for_each(move |event| {
+        match event {
+            Ok(watcher::Event::Restarted(_)) => warm.store(true, Ordering::SeqCst),
+            Ok(_) => {}
+            Err(e) => warn!(error = ?e, "Copy cache watch error"),
+        }
+        futures::future::ready(())
+    });
+    (cache, runner)
+}
+
+#[cfg(test)]
+impl CopyCache {
+    /// Constructs a warm CopyCache holding `copies`, as listed by the watch.
+    pub fn of(copies: Vec<Secret>) -> Self {
+        let mut writer: Writer<Secret> = Writer::default();
+        writer.apply_watcher_event(&watcher::Event::Restarted(copies));
+        CopyCache {
+            store: writer.as_reader(),
+            warm: Arc::new(AtomicBool::new(true)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kube::api::ObjectMeta;
+
+    fn copy(namespace: &str, name: &str, owner: &str) -> Secret {
+        Secret {
+            metadata: ObjectMeta {
+                name: Some(name.to_string()),
+                namespace: Some(namespace.to_string()),
+                labels: Some(std::iter::once((keys::owner_label().to_string(), owner.to_string())).collect()),
+                ..ObjectMeta::default()
+            },
+            ..Secret::default()
+        }
+    }
+
+    #[test]
+    fn copies_are_found_by_name_and_by_source() {
+        let cache = CopyCache::of(vec![copy("a", "db", "uid"), copy("a", "db-2", "uid"), copy("b", "db", "uid"), copy("c", "db", "other")]);
+        assert_eq!(cache.get("a", "db").map(|c| c.name()).as_deref(), Some("db"));
+        assert!(cache.get("d", "db").is_none());
+        let mut by_namespace = BTreeMap::new();
+        by_namespace.insert("a".to_string(), vec!["db".to_string(), "db-2".to_string()]);
+        by_namespace.insert("b".to_string(), vec!["db".to_string()]);
+        let mut copies = cache.copies_of("uid").unwrap();
+        copies.values_mut().for_each(|names| names.sort());
+        assert_eq!(copies, by_namespace);
+    }
+
+    #[test]
+    fn cold_cache_lists_nothing() {
+        let cache = CopyCache::of(vec![copy("a", "db", "uid")]);
+        cache.warm.store(false, Ordering::SeqCst);
+        assert!(cache.copies_of("uid").is_none());
+    }
+}
@@ -0,0 +1,255 @@
+//! Compares the copies in the cluster against source manifests kept in git.
+//!
+//! The directory given to `--check-against` is read non-recursively. Every `*.yaml` or `*.yml`
+//! file in it may hold several documents separated by `---`; documents of `kind: Secret`
+//! carrying spread annotations are the desired sources, everything else is ignored. The sources
+//! have to exist in the cluster under the same namespace and name, their uid identifies the
+//! copies through the owner label.
+//!
+//! Nothing in the cluster is modified.
+
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::Path;
+
+use k8s_openapi::api::core::v1::Secret;
+use kube::api::ListParams;
+use kube::{Api, Client, Resource};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::cli::Outcome;
+use crate::config::SpreadConfig;
+use crate::opts::Opts;
+use crate::{keys, targets, Error};
+
+/// Difference between the sources declared in the manifests and the cluster.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Difference {
+    /// `<namespace>/<name>` of the source.
+    pub source: String,
+    /// `sourceMissing`, `missingCopy` or `unexpectedCopy`.
+    pub kind: &'static str,
+    /// Namespace of the copy, none for a missing source.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub namespace: Option<String>,
+    /// Name of the copy, empty for copies named by `generateName`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+}
+
+impl Difference {
+    /// The line printed with `--output text`.
+    fn line(&self) -> String {
+        let (source_namespace, name) = self.source.split_once('/').unwrap_or_default();
+        let copy = self.name.as_deref().unwrap_or_default();
+        let ns = self.namespace.as_deref().unwrap_or_default();
+        match self.kind {
+            "sourceMissing" => format!("! {}.{}: source does not exist in the cluster\n", source_namespace, name),
+            "missingCopy" => format!("+ {}.{}: missing copy {} in {}\n", source_namespace, name, copy, ns),
+            _ => format!("- {}.{}: unexpected copy {} in {}\n", source_namespace, name, copy, ns),
+        }
+    }
+}
+
+/// Reads the source secrets declared in the manifests of `dir`.
+fn read_sources(dir: &Path) -> Result<Vec<Secret>, Error> {
+    let entries = fs::read_dir(dir)
+        .map_err(|e| Error::UserInputError(format!("Can't read {}: {}", dir.display(), e)))?;
+
+    let mut paths: Vec<_> = entries
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| matches!(p.extension().and_then(|e| e.to_str()), Some("yaml") | Some("yml")))
+        .collect();
+    paths.sort();
+
+    let mut sources = Vec::new();
+    for path in paths {
+        let content = fs::read_to_string(&path)
+            .map_err(|e| Error::UserInputError(format!("Can't read {}: {}", path.display(), e)))?;
+        for document in serde_yaml::Deserializer::from_str(&content) {
+            let value = serde_yaml::Value::deserialize(document)
+                .map_err(|e| Error::UserInputError(format!("Invalid YAML in {}: {}", path.display(), e)))?;
+            if value.get("kind").and_then(|k| k.as_str()) != Some("Secret") {
+                continue;
+            }
+            let secret: Secret = serde_yaml::from_value(value)
+                .map_err(|e| Error::UserInputError(format!("Invalid Secret in {}: {}", path.display(), e)))?;
+            if targets::has_targets(&secret.metadata) {
+                sources.push(secret);
+            }
+        }
+    }
+    Ok(sources)
+}
+
+/// Checks that the copies in the cluster match the sources declared in `dir`, targeted with the
+/// options `opts`. Reports every missing and unexpected copy, passes if the cluster is in sync.
+pub async fn run(client: Client, dir: &Path, opts: &Opts) -> Result<Outcome, Error> {
+    let mut differences = Vec::new();
+    let targeting = targets::Targeting::from_opts(opts);
+
+    for source in read_sources(dir)? {
+        let name = source.name();
+        let source_namespace = source.namespace().unwrap_or_else(|| "default".to_string());
+
+        let live_api: Api<Secret> = Api::namespaced(client.clone(), &source_namespace);
+        let live = match live_api.get(&name).await {
+            Ok(s) => s,
+            Err(kube::Error::Api(kube::error::ErrorResponse { code: 404, .. })) => {
+                differences.push(Difference {
+                    source: format!("{}/{}", source_namespace, name),
+                    kind: "sourceMissing",
+                    namespace: None,
+                    name: None,
+                });
+                continue;
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let source_uid = live.metadata.uid.clone().unwrap_or_default();
+
+        // With generateName the copy names are picked by the API server, only the namespaces
+        // can be compared.
+        let config = match SpreadConfig::from_secret(&source)? {
+            Some(config) => config,
+            None => continue,
+        };
+        let use_generate_name = config.use_generate_name;
+        let mut desired: BTreeSet<(String, String)> = BTreeSet::new();
+        for (ns, target_name) in config.resolve_copies(client.clone(), client.clone(), &source.metadata, &name, &targeting).await? {
+            if ns == source_namespace {
+                continue;
+            }
+            let copy_name = if use_generate_name { String::new() } else { target_name };
+            desired.insert((ns, copy_name));
+        }
+
+        let secret_api: Api<Secret> = Api::all(client.clone());
+        let lp = ListParams::default().labels(format!("{}={}", keys::owner_label(), source_uid).as_str());
+        let actual: BTreeSet<(String, String)> = secret_api
+            .list(&lp)
+            .await?
+            .iter()
+            .map(|s| {
+                let copy_name = if use_generate_name { String::new() } else { s.name() };
+                (s.namespace().unwrap_or_default(), copy_name)
+            })
+            .collect();
+
+        let differing = desired.difference(&actual).map(|copy| ("missingCopy", copy)).chain(actual.difference(&desired).map(|copy| ("unexpectedCopy", copy)));
+        for (kind, (ns, copy_name)) in differing {
+            differences.push(Difference {
+                source: format!("{}/{}", source_namespace, name),
+                kind,
+                namespace: Some(ns.clone()),
+                name: Some(copy_name.clone()),
+            });
+        }
+    }
+
+    Ok(Outcome {
+        passed: differences.is_empty(),
+        text: differences.iter().map(Difference::line).collect(),
+        json: json!({ "inSync": differences.is_empty(), "differences": differences }),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+    use k8s_openapi::api::core::v1::Namespace;
+    use k8s_openapi::apimachinery::pkg::apis::meta::v1::ObjectMeta;
+    use kube_runtime::controller::Context;
+    use serde_json::Value;
+
+    use crate::cli::{self, ERROR, FAILED, SUCCESS};
+    use crate::fake_api::FakeApi;
+    use crate::ContextData;
+
+    const MANIFEST: &str = "apiVersion: v1
+kind: ConfigMap
+metadata:
+  name: unrelated
+---
+apiVersion: v1
+kind: Secret
+metadata:
+  name: db
+  namespace: source
+  annotations:
+    eu.fitzek.spread.target-namespace: a,b
+";
+
+    /// Writes `manifest` to a directory of its own named `name` and returns the directory.
+    fn manifests(name: &str, manifest: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("spread-check-{}-{}", std::process::id(), name));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("sources.yaml"), manifest).unwrap();
+        dir
+    }
+
+    /// Runs the check of `dir` with `--output json`, returns the exit code and the JSON printed.
+    async fn check_json(client: Client, dir: &Path) -> (i32, Value) {
+        let opts = Opts::parse_from(["spreading-operator"]);
+        let rendered = cli::render("check-against", "json", run(client, dir, &opts).await);
+        (rendered.code, serde_json::from_str(&rendered.stdout).unwrap())
+    }
+
+    #[tokio::test]
+    async fn drift_is_reported_as_json_with_its_exit_code() {
+        let (client, fake) = FakeApi::start();
+        for ns in &["source", "a", "b", "c"] {
+            fake.insert(&Namespace {
+                metadata: ObjectMeta {
+                    name: Some(ns.to_string()),
+                    ..ObjectMeta::default()
+                },
+                ..Namespace::default()
+            });
+        }
+        let dir = manifests("drift", MANIFEST);
+
+        // the source doesn't exist yet
+        let (code, report) = check_json(client.clone(), &dir).await;
+        assert_eq!(code, FAILED);
+        assert_eq!(report["result"], json!({ "inSync": false, "differences": [{ "source": "source/db", "kind": "sourceMissing" }] }));
+
+        // spread as declared
+        let sec: Secret = serde_yaml::from_str(MANIFEST.split("---\n").nth(1).unwrap()).unwrap();
+        let sec: Secret = serde_json::from_value(fake.insert(&sec)).unwrap();
+        let context = Context::new(ContextData::new(client.clone(), &Opts::parse_from(["spreading-operator"])));
+        crate::reconcile(sec, context).await.unwrap();
+        let (code, report) = check_json(client.clone(), &dir).await;
+        assert_eq!(code, SUCCESS);
+        assert_eq!(report, json!({ "mode": "check-against", "exitCode": 0, "result": { "inSync": true, "differences": [] } }));
+
+        // the manifest moved on to a and c
+        let moved = manifests("moved", &MANIFEST.replace("a,b", "a,c"));
+        let (code, report) = check_json(client.clone(), &moved).await;
+        assert_eq!(code, FAILED);
+        assert_eq!(
+            report["result"]["differences"],
+            json!([
+                { "source": "source/db", "kind": "missingCopy", "namespace": "c", "name": "db" },
+                { "source": "source/db", "kind": "unexpectedCopy", "namespace": "b", "name": "db" },
+            ])
+        );
+        let opts = Opts::parse_from(["spreading-operator"]);
+        assert_eq!(
+            cli::render("check-against", "text", run(client.clone(), &moved, &opts).await).stdout,
+            "+ source.db: missing copy db in c\n- source.db: unexpected copy db in b\n"
+        );
+
+        // the check itself fails on a directory that can't be read
+        let (code, report) = check_json(client, &dir.join("missing")).await;
+        assert_eq!(code, ERROR);
+        assert!(report["error"].as_str().unwrap().starts_with("Invalid spread configuration: Can't read"), "{}", report);
+        assert!(report.get("result").is_none());
+
+        fs::remove_dir_all(dir).unwrap();
+        fs::remove_dir_all(moved).unwrap();
+    }
+}
@@ -0,0 +1,165 @@
+//! Output and exit codes of the command line modes: `--check-against`, `--validate-config`,
+//! `--export`, `--topology`, `--dump-rbac` and `--print-crd`.
+//!
+//! Every mode exits with
+//! - [`SUCCESS`], 0, if it did its job and found nothing wrong,
+//! - [`FAILED`], 1, if it did its job and found something wrong: `--check-against` found
+//!   diverging copies, `--validate-config` missing permissions or invalid sources,
+//! - [`ERROR`], 2, if it couldn't do its job, e.g. the cluster was unreachable or an argument was
+//!   malformed.
+//!
+//! `--once` uses the same codes, its results are in the logs.
+//!
+//! With `--output text`, the default, a mode prints its result in its own format, e.g. YAML for
+//! `--export`, and errors go to stderr. With `--output json` it prints exactly one JSON object on
+//! stdout, errors included, so CI can parse the output no matter the exit code:
+//!
+//! ```json
+//! {"mode": "check-against", "exitCode": 1, "result": {"inSync": false, "differences": [...]}}
+//! {"mode": "export", "exitCode": 2, "error": "..."}
+//! ```
+
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::opts::Opts;
+use crate::Error;
+
+/// Exit code of a mode that found nothing wrong.
+pub const SUCCESS: i32 = 0;
+/// Exit code of a mode that found something wrong, e.g. diverging copies.
+pub const FAILED: i32 = 1;
+/// Exit code of a mode that failed itself.
+pub const ERROR: i32 = 2;
+
+/// Result of a command line mode, in both output formats.
+#[derive(Debug)]
+pub struct Outcome {
+    /// Whether nothing wrong was found.
+    pub passed: bool,
+    pub text: String,
+    pub json: Value,
+}
+
+impl Outcome {
+    /// Constructs an Outcome of a mode that found nothing wrong.
+    pub fn passed(text: String, json: Value) -> Self {
+        Outcome { passed: true, text, json }
+    }
+}
+
+/// Output of a mode as printed by [`finish`].
+#[derive(Debug, PartialEq)]
+pub struct Rendered {
+    pub stdout: String,
+    pub stderr: String,
+    pub code: i32,
+}
+
+/// The JSON object printed with `--output json`.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Report<'a> {
+    mode: &'a str,
+    exit_code: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    result: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// Returns the command line mode selected by `opts`, e.g. `export`, none if the operator is to
+/// run its controllers.
+pub fn mode(opts: &Opts) -> Option<&'static str> {
+    if opts.print_crd {
+        Some("print-crd")
+    } else if opts.dump_rbac.is_some() {
+        Some("dump-rbac")
+    } else if opts.check_against.is_some() {
+        Some("check-against")
+    } else if opts.validate_config {
+        Some("validate-config")
+    } else if opts.export.is_some() {
+        Some("export")
+    } else if opts.topology.is_some() {
+        Some("topology")
+    } else {
+        None
+    }
+}
+
+/// Renders the `result` of the command line mode `mode`, e.g. `export`, in the format `output`,
+/// `text` or `json`.
+pub fn render(mode: &str, output: &str, result: Result<Outcome, Error>) -> Rendered {
+    let (code, result, error) = match result {
+        Ok(outcome) => (if outcome.passed { SUCCESS } else { FAILED }, Some(outcome), None),
+        Err(e) => (ERROR, None, Some(e.to_string())),
+    };
+    if output == "json" {
+        let report = Report {
+            mode,
+            exit_code: code,
+            result: result.map(|outcome| outcome.json),
+            error,
+        };
+        let stdout = serde_json::to_string(&report).expect("a report is always serializable");
+        return Rendered {
+            stdout: format!("{}\n", stdout),
+            stderr: String::new(),
+            code,
+        };
+    }
+    Rendered {
+        stdout: result.map(|outcome| outcome.text).unwrap_or_default(),
+        stderr: error.map(|e| format!("{} failed: {}\n", mode, e)).unwrap_or_default(),
+        code,
+    }
+}
+
+/// Prints the `result` of the command line mode `mode` as [`render`] does and exits with its
+/// code.
+pub fn finish(mode: &str, output: &str, result: Result<Outcome, Error>) -> ! {
+    let rendered = render(mode, output, result);
+    print!("{}", rendered.stdout);
+    eprint!("{}", rendered.stderr);
+    std::process::exit(rendered.code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn failed() -> Outcome {
+        Outcome {
+            passed: false,
+            text: "- source.db: unexpected copy db in b\n".to_string(),
+            json: json!({"inSync": false}),
+        }
+    }
+
+    #[test]
+    fn text_is_printed_as_is_and_errors_go_to_stderr() {
+        let passed = render("export", "text", Ok(Outcome::passed("kind: Secret\n".to_string(), json!([]))));
+        assert_eq!(passed, Rendered { stdout: "kind: Secret\n".to_string(), stderr: String::new(), code: SUCCESS });
+        assert_eq!(render("check-against", "text", Ok(failed())).code, FAILED);
+        let error = render("export", "text", Err(Error::UserInputError("Expected source in the form <namespace>/<name>, got db".to_string())));
+        assert_eq!(error.stdout, "");
+        assert_eq!(error.stderr, "export failed: Invalid spread configuration: Expected source in the form <namespace>/<name>, got db\n");
+        assert_eq!(error.code, ERROR);
+    }
+
+    #[test]
+    fn json_is_one_object_with_the_exit_code() {
+        let failed = render("check-against", "json", Ok(failed()));
+        assert_eq!(failed.code, FAILED);
+        assert_eq!(failed.stderr, "");
+        let report: Value = serde_json::from_str(&failed.stdout).unwrap();
+        assert_eq!(report, json!({"mode": "check-against", "exitCode": 1, "result": {"inSync": false}}));
+
+        let error = render("topology", "json", Err(Error::UserInputError("no".to_string())));
+        assert_eq!(error.code, ERROR);
+        let report: Value = serde_json::from_str(&error.stdout).unwrap();
+        assert_eq!(report, json!({"mode": "topology", "exitCode": 2, "error": "Invalid spread configuration: no"}));
+    }
+}
@@ -0,0 +1,872 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use k8s_openapi::api::core::v1::Secret;
+use k8s_openapi::ByteString;
+use regex::Regex;
+use serde_json::json;
+
+use crate::{keys, targets, Error};
+
+/// JSON object mapping a target namespace, or `*` for all others, to the annotations a copy in
+/// that namespace carries.
+pub const TARGET_ANNOTATIONS_ANNOTATION: &str = "eu.fitzek.spread.target-annotations";
+
+/// Comma separated list of the annotations of the source copied to the copies, none by default.
+pub const COPY_ANNOTATIONS_ANNOTATION: &str = "eu.fitzek.spread.copy-annotations";
+
+/// Comma separated list of `key=value` labels added to every copy besides the source labels.
+pub const ADD_LABELS_ANNOTATION: &str = "eu.fitzek.spread.add-labels";
+
+/// Annotation on copies holding the resourceVersion of the source they were last written from.
+pub const SOURCE_RESOURCE_VERSION_ANNOTATION: &str = "eu.fitzek.spread.source-resource-version";
+
+/// Annotation on copies holding the hash of the content they were written with, see
+/// [`content_hash`].
+pub const CONTENT_HASH_ANNOTATION: &str = "eu.fitzek.spread.content-hash";
+
+/// Annotation of a source whose changed value, e.g. a timestamp, writes all copies once more even
+/// if their content hash matches, to repair copies edited by hand. It doesn't change what is
+/// copied, it only forces the write.
+pub const RESYNC_ANNOTATION: &str = "eu.fitzek.spread.resync";
+
+/// Annotation on copies holding the value of the [`RESYNC_ANNOTATION`] they were last written for.
+pub const RESYNCED_ANNOTATION: &str = "eu.fitzek.spread.resynced";
+
+/// Annotation on copies naming their source like an owner reference, as JSON object with the
+/// `namespace`, `name` and `uid` of the source. Owner references can't point across namespaces,
+/// so the owner label and this annotation take their place.
+pub const OWNER_REFERENCE_ANNOTATION: &str = "eu.fitzek.spread.owner-reference";
+
+/// Comma separated list of the data keys copied, all others are left out.
+pub const INCLUDE_KEYS_ANNOTATION: &str = "eu.fitzek.spread.include-keys";
+/// Comma separated list of the data keys left out, all others are copied.
+pub const EXCLUDE_KEYS_ANNOTATION: &str = "eu.fitzek.spread.exclude-keys";
+/// Comma separated list of `source=target` pairs renaming data keys in the copies, e.g.
+/// `tls.crt=ca.crt`. Keys not listed keep their name.
+pub const KEY_MAP_ANNOTATION: &str = "eu.fitzek.spread.key-map";
+
+/// Label marking a secret as a copy made by the operator.
+pub const COPY_LABEL: &str = "eu.fitzek.spread.copy";
+/// Label naming the source of a copy as `<source-namespace>.<name>`, for people looking at the
+/// copy. Label values are limited to 63 characters, a longer one is cut. The cleanup goes by
+/// the owner label only.
+pub const SOURCE_LABEL: &str = "eu.fitzek.spread.source";
+/// Recommended Kubernetes label naming the tool managing an object.
+pub const MANAGED_BY_LABEL: &str = "app.kubernetes.io/managed-by";
+
+/// Annotations to set on copies, keyed by target namespace.
+pub type TargetAnnotations = BTreeMap<String, BTreeMap<String, String>>;
+
+/// Returns the `data` of a secret the way the API server stores it: every `string_data` entry
+/// is folded into `data`, overwriting a `data` entry with the same key.
+pub fn normalized_data(sec: &Secret) -> BTreeMap<String, ByteString> {
+    let mut data = sec.data.clone().unwrap_or_default();
+    if let Some(string_data) = &sec.string_data {
+        for (k, v) in string_data {
+            data.insert(k.clone(), ByteString(v.clone().into_bytes()));
+        }
+    }
+    data
+}
+
+/// Returns the normalized data of the copy `target` the way it is compared with `source`: keys of
+/// `ignored_keys`, see `--ignored-copy-keys`, the source doesn't have are left out, others added
+/// them.
+pub fn managed_data(source: &Secret, target: &Secret, ignored_keys: &[String]) -> BTreeMap<String, ByteString> {
+    let source_data = normalized_data(source);
+    let mut data = normalized_data(target);
+    data.retain(|k, _| source_data.contains_key(k) || !ignored_keys.iter().any(|i| i.trim() == k));
+    data
+}
+
+/// Names of the data keys an update of a copy adds, removes and changes. The values are left
+/// out, they are secret.
+#[derive(Debug, Default, PartialEq)]
+pub struct DataDiff {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub changed: Vec<String>,
+}
+
+/// Returns the keys that differ between the `current` data of a copy and the `desired` data, for
+/// the logs of an update. The update itself is a server-side apply of the whole data.
+pub fn diff_data(current: &BTreeMap<String, ByteString>, desired: &BTreeMap<String, ByteString>) -> DataDiff {
+    DataDiff {
+        added: desired.keys().filter(|k| !current.contains_key(*k)).cloned().collect(),
+        removed: current.keys().filter(|k| !desired.contains_key(*k)).cloned().collect(),
+        changed: desired.iter().filter(|(k, v)| current.get(*k).is_some_and(|c| c != *v)).map(|(k, _)| k.clone()).collect(),
+    }
+}
+
+/// Data keys of the source copied to the targets.
+pub enum KeyFilter {
+    All,
+    Include(BTreeSet<String>),
+    Exclude(BTreeSet<String>),
+}
+
+impl KeyFilter {
+    /// Parses the `include-keys` or `exclude-keys` annotation of the source. Setting both is
+    /// rejected, it's ambiguous which one wins.
+    pub fn from_source(source: &Secret) -> Result<Self, Error> {
+        let keys = |value: String| value.split(',').map(str::trim).filter(|k| !k.is_empty()).map(str::to_string).collect();
+        match (
+            targets::annotation(&source.metadata, INCLUDE_KEYS_ANNOTATION),
+            targets::annotation(&source.metadata, EXCLUDE_KEYS_ANNOTATION),
+        ) {
+            (Some(_), Some(_)) => Err(Error::UserInputError(format!(
+                "Only one of the {} and {} annotations may be set",
+                INCLUDE_KEYS_ANNOTATION, EXCLUDE_KEYS_ANNOTATION
+            ))),
+            (Some(include), None) => Ok(KeyFilter::Include(keys(include))),
+            (None, Some(exclude)) => Ok(KeyFilter::Exclude(keys(exclude))),
+            (None, None) => Ok(KeyFilter::All),
+        }
+    }
+
+    /// Returns `source` with only the copied keys in `data` and `string_data`. The copies are
+    /// written and compared from the result, so a left out key is removed from existing copies.
+    pub fn apply(&self, source: Secret) -> Secret {
+        let copied = |key: &String| match self {
+            KeyFilter::All => true,
+            KeyFilter::Include(keys) => keys.contains(key),
+            KeyFilter::Exclude(keys) => !keys.contains(key),
+        };
+        Secret {
+            data: source.data.map(|data| data.into_iter().filter(|(k, _)| copied(k)).collect()),
+            string_data: source.string_data.map(|data| data.into_iter().filter(|(k, _)| copied(k)).collect()),
+            ..source
+        }
+    }
+}
+
+/// Renames of data keys of the source in the copies, by source key.
+#[derive(Debug, Default)]
+pub struct KeyMap(BTreeMap<String, String>);
+
+impl KeyMap {
+    /// Parses the `key-map` annotation of the source. Two keys may not be renamed to the same
+    /// one, the copy could only keep one of them.
+    pub fn from_source(source: &Secret) -> Result<Self, Error> {
+        let value = match targets::annotation(&source.metadata, KEY_MAP_ANNOTATION) {
+            Some(v) => v,
+            None => return Ok(KeyMap::default()),
+        };
+        let mut renames: BTreeMap<String, String> = BTreeMap::new();
+        for entry in value.split(',').map(str::trim).filter(|entry| !entry.is_empty()) {
+            let (from, to) = match entry.split_once('=') {
+                Some((from, to)) if !from.trim().is_empty() && !to.trim().is_empty() => (from.trim(), to.trim()),
+                _ => {
+                    return Err(Error::UserInputError(format!(
+                        "Invalid entry {} in {}, expected source=target",
+                        entry, KEY_MAP_ANNOTATION
+                    )))
+                }
+            };
+            if let Some((other, _)) = renames.iter().find(|(_, target)| *target == to) {
+                return Err(Error::UserInputError(format!(
+                    "Invalid {} annotation: {} and {} are both renamed to {}",
+                    KEY_MAP_ANNOTATION, other, from, to
+                )));
+            }
+            if renames.insert(from.to_string(), to.to_string()).is_some() {
+                return Err(Error::UserInputError(format!("Invalid {} annotation: {} is renamed twice", KEY_MAP_ANNOTATION, from)));
+            }
+        }
+        Ok(KeyMap(renames))
+    }
+
+    /// Returns `source` with the keys of `data` and `string_data` renamed. Like [`KeyFilter`]
+    /// the copies are written and compared from the result. A renamed key replaces a key of the
+    /// source that already has the new name and keeps it.
+    pub fn apply(&self, source: Secret) -> Secret {
+        if self.0.is_empty() {
+            return source;
+        }
+        Secret {
+            data: source.data.map(|data| self.rename(data)),
+            string_data: source.string_data.map(|data| self.rename(data)),
+            ..source
+        }
+    }
+
+    fn rename<V>(&self, data: BTreeMap<String, V>) -> BTreeMap<String, V> {
+        let (renamed, mut kept): (BTreeMap<String, V>, BTreeMap<String, V>) = data.into_iter().partition(|(k, _)| self.0.contains_key(k));
+        kept.extend(renamed.into_iter().map(|(k, v)| (self.0[&k].clone(), v)));
+        kept
+    }
+}
+
+/// Returns the type of a secret the way the API server defaults it: a secret without type is
+/// `Opaque`.
+pub fn normalized_type(sec: &Secret) -> String {
+    match sec.type_.as_deref() {
+        None | Some("") => "Opaque".to_string(),
+        Some(t) => t.to_string(),
+    }
+}
+
+/// Labels a copy of `source` is expected to carry: the source labels, the labels of
+/// `add-labels`, the owner label and the source label. `add-labels` is validated with the config
+/// of the source.
+pub fn desired_labels(source: &Secret, source_uid: &str) -> BTreeMap<String, String> {
+    let mut labels = source.metadata.labels.clone().unwrap_or_default();
+    labels.extend(added_labels(source).unwrap_or_default());
+    labels.insert(keys::owner_label().to_string(), source_uid.to_string());
+    labels.insert(
+        keys::key(SOURCE_LABEL),
+        source_label_value(source.metadata.namespace.as_deref().unwrap_or_default(), source.metadata.name.as_deref().unwrap_or_default()),
+    );
+    labels
+}
+
+/// Value of the [`SOURCE_LABEL`] of the copies of the source `namespace`/`name`.
+pub fn source_label_value(namespace: &str, name: &str) -> String {
+    let value = format!("{}.{}", namespace, name);
+    // namespaces and names are valid label characters already, a cut value has to end with an
+    // alphanumeric character again
+    value.chars().take(63).collect::<String>().trim_end_matches(|c: char| !c.is_ascii_alphanumeric()).to_string()
+}
+
+/// Parses the `add-labels` annotation of the source.
+pub fn added_labels(source: &Secret) -> Result<BTreeMap<String, String>, Error> {
+    let value = match targets::annotation(&source.metadata, ADD_LABELS_ANNOTATION) {
+        Some(v) => v,
+        None => return Ok(BTreeMap::new()),
+    };
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| match entry.split_once('=') {
+            Some((key, value)) if !key.trim().is_empty() => Ok((key.trim().to_string(), value.trim().to_string())),
+            _ => Err(Error::UserInputError(format!(
+                "Invalid entry {} in {}, expected key=value",
+                entry, ADD_LABELS_ANNOTATION
+            ))),
+        })
+        .collect()
+}
+
+/// Labels every copy carries to make it discoverable as managed by `managed_by`. They are not
+/// part of the comparison in [`secrets_equivalent`], a copy missing them is not out of date.
+pub fn recommended_labels(managed_by: &str) -> BTreeMap<String, String> {
+    let mut labels = BTreeMap::new();
+    labels.insert(MANAGED_BY_LABEL.to_string(), managed_by.to_string());
+    labels.insert(keys::key(COPY_LABEL), "true".to_string());
+    labels
+}
+
+/// Parses and validates the per namespace annotations of the source.
+pub fn target_annotations(source: &Secret) -> Result<TargetAnnotations, Error> {
+    let value = match targets::annotation(&source.metadata, TARGET_ANNOTATIONS_ANNOTATION) {
+        Some(v) => v,
+        None => return Ok(TargetAnnotations::new()),
+    };
+    let mapping: TargetAnnotations = serde_json::from_str(&value).map_err(|e| {
+        Error::UserInputError(format!("Invalid {} annotation: {}", TARGET_ANNOTATIONS_ANNOTATION, e))
+    })?;
+
+    let key_format = Regex::new(
+        r"^([a-z0-9]([-a-z0-9]*[a-z0-9])?(\.[a-z0-9]([-a-z0-9]*[a-z0-9])?)*/)?[A-Za-z0-9]([-A-Za-z0-9_.]*[A-Za-z0-9])?$",
+    )
+    .unwrap();
+    for key in mapping.values().flat_map(|a| a.keys()) {
+        let (prefix, name) = key.rsplit_once('/').unwrap_or(("", key));
+        if !key_format.is_match(key) || prefix.len() > 253 || name.len() > 63 {
+            return Err(Error::UserInputError(format!(
+                "Invalid {} annotation: {} is not a valid annotation key",
+                TARGET_ANNOTATIONS_ANNOTATION, key
+            )));
+        }
+    }
+    Ok(mapping)
+}
+
+/// Annotation `kubectl apply` keeps the applied manifest in, spread annotations included.
+const LAST_APPLIED_ANNOTATION: &str = "kubectl.kubernetes.io/last-applied-configuration";
+
+/// Annotations a copy of `source` in `namespace` is expected to carry: the annotations of the
+/// source listed in its `copy-annotations` annotation, minus the `eu.fitzek.spread.*` ones
+/// controlling the operator, plus the per namespace annotations of `mapping`. Namespaces without
+/// an entry of their own use the `*` entry.
+///
+/// Without the control annotations a copy never selects target namespaces of its own.
+pub fn desired_annotations(source: &Secret, mapping: &TargetAnnotations, namespace: &str) -> BTreeMap<String, String> {
+    let copied = copied_annotations(source);
+    let mut annotations: BTreeMap<String, String> = source
+        .metadata
+        .annotations
+        .iter()
+        .flatten()
+        .filter(|(k, _)| copied.contains(*k) && !is_control_annotation(k))
+        .map(|(k, v)| (k.clone(), v.clone()))
+        .collect();
+    if let Some(entry) = mapping.get(namespace).or_else(|| mapping.get("*")) {
+        annotations.extend(entry.clone());
+    }
+    annotations
+}
+
+/// Keys of the annotations of `source` listed in its `copy-annotations` annotation.
+fn copied_annotations(source: &Secret) -> BTreeSet<String> {
+    targets::annotation(&source.metadata, COPY_ANNOTATIONS_ANNOTATION)
+        .map(|value| value.split(',').map(str::trim).filter(|k| !k.is_empty()).map(str::to_string).collect())
+        .unwrap_or_default()
+}
+
+/// Keys of the annotations `current` of a copy to be removed: annotations listed in
+/// `copy-annotations` that the source no longer carries and that are not `desired` otherwise.
+/// Other annotations of the copy are left alone, they may be added by others.
+fn stale_annotations(source: &Secret, current: &BTreeMap<String, String>, desired: &BTreeMap<String, String>) -> Vec<String> {
+    copied_annotations(source)
+        .into_iter()
+        .filter(|k| current.contains_key(k) && !desired.contains_key(k))
+        .collect()
+}
+
+/// Returns whether the annotation `key` of a source is not propagated to the copies.
+fn is_control_annotation(key: &str) -> bool {
+    key.to_ascii_lowercase().starts_with(&keys::prefix().to_ascii_lowercase()) || key == LAST_APPLIED_ANNOTATION
+}
+
+/// Returns `annotations` plus the owner reference annotation pointing at `source`.
+pub fn with_owner_reference(annotations: &BTreeMap<String, String>, source: &Secret) -> BTreeMap<String, String> {
+    let mut annotations = annotations.clone();
+    let reference = json!({
+        "namespace": source.metadata.namespace,
+        "name": source.metadata.name,
+        "uid": source.metadata.uid,
+    });
+    annotations.insert(keys::key(OWNER_REFERENCE_ANNOTATION), reference.to_string());
+    annotations
+}
+
+/// Returns whether `copy` carries the owner reference annotation pointing at the source with
+/// `source_uid`, i.e. was written by the operator for that source, whatever its labels say.
+pub fn references_source(copy: &Secret, source_uid: &str) -> bool {
+    targets::annotation(&copy.metadata, OWNER_REFERENCE_ANNOTATION)
+        .and_then(|reference| serde_json::from_str::<serde_json::Value>(&reference).ok())
+        .is_some_and(|reference| reference.get("uid").and_then(|uid| uid.as_str()) == Some(source_uid))
+}
+
+/// Returns `annotations` plus the resourceVersion of `source`, to be written on a copy.
+///
+/// The stamp is not part of the comparison in [`secrets_equivalent`]. The resourceVersion also
+/// changes when the operator patches the source itself, which must not rewrite every copy.
+pub fn with_source_version(annotations: &BTreeMap<String, String>, source: &Secret) -> BTreeMap<String, String> {
+    let mut annotations = annotations.clone();
+    if let Some(version) = &source.metadata.resource_version {
+        annotations.insert(keys::key(SOURCE_RESOURCE_VERSION_ANNOTATION), version.clone());
+    }
+    annotations
+}
+
+/// Offset basis and prime of 64 bit FNV-1a.
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// Hash of everything [`secrets_equivalent`] compares a copy of `source` by: the normalized data
+/// and type, the immutability, the desired labels and the desired `annotations`. It is FNV-1a
+/// over a JSON rendering with sorted keys, so it is the same across restarts and versions of the
+/// operator.
+pub fn content_hash(source: &Secret, source_uid: &str, annotations: &BTreeMap<String, String>) -> String {
+    let content = json!({
+        "type": normalized_type(source),
+        "immutable": source.immutable == Some(true),
+        "data": normalized_data(source),
+        "labels": desired_labels(source, source_uid),
+        "annotations": annotations,
+    });
+    let hash = content
+        .to_string()
+        .bytes()
+        .fold(FNV_OFFSET, |hash, byte| (hash ^ u64::from(byte)).wrapping_mul(FNV_PRIME));
+    format!("{:016x}", hash)
+}
+
+/// Returns `annotations` plus the content `hash`, to be written on a copy.
+pub fn with_content_hash(annotations: &BTreeMap<String, String>, hash: String) -> BTreeMap<String, String> {
+    let mut annotations = annotations.clone();
+    annotations.insert(keys::key(CONTENT_HASH_ANNOTATION), hash);
+    annotations
+}
+
+/// Returns `annotations` plus the resync value of `source`, if any, to be written on a copy.
+pub fn with_resynced(annotations: &BTreeMap<String, String>, source: &Secret) -> BTreeMap<String, String> {
+    let mut annotations = annotations.clone();
+    if let Some(resync) = targets::annotation(&source.metadata, RESYNC_ANNOTATION) {
+        annotations.insert(keys::key(RESYNCED_ANNOTATION), resync);
+    }
+    annotations
+}
+
+/// Returns whether the resync value of `source` changed since the copy `target` was written.
+pub fn resync_pending(source: &Secret, target: &Secret) -> bool {
+    let resync = targets::annotation(&source.metadata, RESYNC_ANNOTATION);
+    resync.is_some() && resync != targets::annotation(&target.metadata, RESYNCED_ANNOTATION)
+}
+
+/// Returns whether the copy `target` was last written with the content hashing to `hash`.
+pub fn content_hash_matches(target: &Secret, hash: &str) -> bool {
+    targets::annotation(&target.metadata, CONTENT_HASH_ANNOTATION).as_deref() == Some(hash)
+}
+
+/// Decides whether the copy `target` is up to date with `source`.
+///
+/// Only the fields the operator propagates are compared: the normalized data and type, the
+/// desired labels and the desired `annotations`, a copy still carrying a stale copied
+/// annotation is out of date. Everything the API server adds (`creationTimestamp`,
+/// `resourceVersion`, `managedFields`, ...) as well as labels, annotations and, see
+/// [`managed_data`], the `ignored_keys` added by others on the copy are ignored, so a mutating
+/// webhook doesn't get the copy patched on every reconcile.
+pub fn secrets_equivalent(source: &Secret, target: &Secret, source_uid: &str, annotations: &BTreeMap<String, String>, ignored_keys: &[String]) -> bool {
+    if normalized_data(source) != managed_data(source, target, ignored_keys) {
+        return false;
+    }
+
+    if normalized_type(source) != normalized_type(target) {
+        return false;
+    }
+
+    let target_annotations = target.metadata.annotations.clone().unwrap_or_default();
+    if !annotations.iter().all(|(k, v)| target_annotations.get(k) == Some(v)) {
+        return false;
+    }
+    if !stale_annotations(source, &target_annotations, annotations).is_empty() {
+        return false;
+    }
+
+    let target_labels = target.metadata.labels.clone().unwrap_or_default();
+    desired_labels(source, source_uid)
+        .iter()
+        .all(|(k, v)| target_labels.get(k) == Some(v))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kube::api::ObjectMeta;
+
+    const UID: &str = "source-uid";
+
+    fn source() -> Secret {
+        let mut data = BTreeMap::new();
+        data.insert("password".to_string(), ByteString(b"secret".to_vec()));
+        let mut annotations = BTreeMap::new();
+        annotations.insert(keys::key(COPY_ANNOTATIONS_ANNOTATION), "team".to_string());
+        annotations.insert("team".to_string(), "platform".to_string());
+        Secret {
+            metadata: ObjectMeta {
+                name: Some("db".to_string()),
+                namespace: Some("source".to_string()),
+                uid: Some(UID.to_string()),
+                annotations: Some(annotations),
+                ..ObjectMeta::default()
+            },
+            data: Some(data),
+            ..Secret::default()
+        }
+    }
+
+    /// Returns an up to date copy of `source` in `namespace` and the annotations it is compared
+    /// with.
+    fn copy(source: &Secret, namespace: &str) -> (Secret, BTreeMap<String, String>) {
+        let annotations = desired_annotations(source, &TargetAnnotations::new(), namespace);
+        let copy = Secret {
+            metadata: ObjectMeta {
+                name: source.metadata.name.clone(),
+                namespace: Some(namespace.to_string()),
+                resource_version: Some("42".to_string()),
+                labels: Some(desired_labels(source, UID)),
+                annotations: Some(annotations.clone()),
+                ..ObjectMeta::default()
+            },
+            data: source.data.clone(),
+            type_: Some("Opaque".to_string()),
+            ..Secret::default()
+        };
+        (copy, annotations)
+    }
+
+    #[test]
+    fn up_to_date_copy_is_equivalent() {
+        let source = source();
+        let (target, annotations) = copy(&source, "target");
+        assert!(secrets_equivalent(&source, &target, UID, &annotations, &[]));
+    }
+
+    #[test]
+    fn type_drift_is_detected() {
+        let source = source();
+        let (mut target, annotations) = copy(&source, "target");
+        target.type_ = Some("kubernetes.io/tls".to_string());
+        assert!(!secrets_equivalent(&source, &target, UID, &annotations, &[]));
+    }
+
+    #[test]
+    fn data_drift_is_detected() {
+        let source = source();
+        let (mut target, annotations) = copy(&source, "target");
+        target.data.get_or_insert_with(BTreeMap::new).insert("password".to_string(), ByteString(b"changed".to_vec()));
+        assert!(!secrets_equivalent(&source, &target, UID, &annotations, &[]));
+
+        let (mut target, _) = copy(&source, "target");
+        target.data.get_or_insert_with(BTreeMap::new).insert("extra".to_string(), ByteString(b"added".to_vec()));
+        assert!(!secrets_equivalent(&source, &target, UID, &annotations, &[]));
+    }
+
+    #[test]
+    fn string_data_of_the_source_is_compared_as_data() {
+        let mut source = source();
+        let (target, annotations) = copy(&source, "target");
+        source.data = None;
+        source.string_data = Some(vec![("password".to_string(), "secret".to_string())].into_iter().collect());
+        assert!(secrets_equivalent(&source, &target, UID, &annotations, &[]));
+    }
+
+    #[test]
+    fn annotation_drift_is_detected() {
+        let source = source();
+        let (mut target, annotations) = copy(&source, "target");
+        target.metadata.annotations.get_or_insert_with(BTreeMap::new).insert("team".to_string(), "other".to_string());
+        assert!(!secrets_equivalent(&source, &target, UID, &annotations, &[]));
+
+        // a copied annotation the source no longer carries is stale
+        let (target, _) = copy(&source, "target");
+        let mut source = source;
+        source.metadata.annotations.get_or_insert_with(BTreeMap::new).remove("team");
+        let annotations = desired_annotations(&source, &TargetAnnotations::new(), "target");
+        assert!(!secrets_equivalent(&source, &target, UID, &annotations, &[]));
+    }
+
+    #[test]
+    fn label_drift_is_detected() {
+        let source = source();
+        let (mut target, annotations) = copy(&source, "target");
+        target.metadata.labels.get_or_insert_with(BTreeMap::new).insert(keys::owner_label().to_string(), "other-uid".to_string());
+        assert!(!secrets_equivalent(&source, &target, UID, &annotations, &[]));
+    }
+
+    #[test]
+    fn additions_of_others_are_ignored() {
+        let source = source();
+        let (mut target, annotations) = copy(&source, "target");
+        target.metadata.annotations.get_or_insert_with(BTreeMap::new).insert("injected-by".to_string(), "webhook".to_string());
+        target.metadata.labels.get_or_insert_with(BTreeMap::new).insert("injected".to_string(), "true".to_string());
+        target.metadata.creation_timestamp = Some(k8s_openapi::apimachinery::pkg::apis::meta::v1::Time(chrono::Utc::now()));
+        assert!(secrets_equivalent(&source, &target, UID, &annotations, &[]));
+    }
+
+    #[test]
+    fn ignored_keys_added_to_the_copy_are_ignored() {
+        let source = source();
+        let ignored = vec!["ca.crt".to_string()];
+        let (mut target, annotations) = copy(&source, "target");
+        target.data.get_or_insert_with(BTreeMap::new).insert("ca.crt".to_string(), ByteString(b"injected".to_vec()));
+        assert!(secrets_equivalent(&source, &target, UID, &annotations, &ignored));
+        assert!(!secrets_equivalent(&source, &target, UID, &annotations, &[]));
+    }
+
+    #[test]
+    fn managed_data_keeps_the_keys_of_the_operator() {
+        let source = source();
+        let ignored = vec![" ca.crt ".to_string(), "password".to_string()];
+        let target = Secret {
+            data: Some(data(&[("password", "secret"), ("ca.crt", "injected"), ("token", "foreign")])),
+            ..Secret::default()
+        };
+        // an ignored key the source has is managed by the operator, a foreign one not listed is
+        // compared and removed
+        assert_eq!(managed_data(&source, &target, &ignored), data(&[("password", "secret"), ("token", "foreign")]));
+        assert_eq!(managed_data(&source, &target, &[]), data(&[("password", "secret"), ("ca.crt", "injected"), ("token", "foreign")]));
+    }
+
+    fn annotated(annotations: &[(&str, &str)]) -> Secret {
+        let mut source = source();
+        source.metadata.annotations = Some(annotations.iter().map(|(k, v)| (keys::key(k), v.to_string())).collect());
+        source
+    }
+
+    #[test]
+    fn key_map_renames_data_keys() {
+        let mut source = annotated(&[(KEY_MAP_ANNOTATION, " tls.crt = ca.crt, password=pass,")]);
+        source.data = Some(data(&[("tls.crt", "cert"), ("ca.crt", "replaced"), ("password", "secret"), ("user", "admin")]));
+        source.string_data = Some(vec![("password".to_string(), "string".to_string())].into_iter().collect());
+        let copied = KeyMap::from_source(&source).unwrap().apply(source);
+        assert_eq!(copied.data, Some(data(&[("ca.crt", "cert"), ("pass", "secret"), ("user", "admin")])));
+        assert_eq!(copied.string_data.unwrap().keys().collect::<Vec<_>>(), vec!["pass"]);
+
+        assert!(KeyMap::from_source(&self::source()).unwrap().0.is_empty());
+        for invalid in &["tls.crt", "=ca.crt", "tls.crt=", "a=c,b=c", "a=b,a=c"] {
+            assert!(KeyMap::from_source(&annotated(&[(KEY_MAP_ANNOTATION, invalid)])).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn source_label_value_is_a_valid_label_value() {
+        assert_eq!(source_label_value("source", "db"), "source.db");
+        let long = source_label_value("team-a", &"x".repeat(70));
+        assert_eq!(long, format!("team-a.{}", "x".repeat(56)));
+        assert_eq!(long.len(), 63);
+        // a value cut after a dash or dot would end in an invalid character
+        let cut = source_label_value("team-a", &format!("{}-.ab", "x".repeat(54)));
+        assert_eq!(cut, format!("team-a.{}", "x".repeat(54)));
+    }
+
+    #[test]
+    fn added_labels_are_parsed() {
+        let source = annotated(&[(ADD_LABELS_ANNOTATION, "managed-by=platform, tier = backend,,empty=")]);
+        let labels = added_labels(&source).unwrap();
+        assert_eq!(labels.into_iter().collect::<Vec<_>>(), vec![
+            ("empty".to_string(), String::new()),
+            ("managed-by".to_string(), "platform".to_string()),
+            ("tier".to_string(), "backend".to_string()),
+        ]);
+        assert!(added_labels(&self::source()).unwrap().is_empty());
+        for invalid in &["managed-by", "=platform", "a=b,c"] {
+            assert!(added_labels(&annotated(&[(ADD_LABELS_ANNOTATION, invalid)])).is_err(), "{}", invalid);
+        }
+        assert_eq!(desired_labels(&source, UID)["managed-by"], "platform");
+    }
+
+    #[test]
+    fn recommended_labels_are_not_compared() {
+        let labels = recommended_labels("platform-operator");
+        assert_eq!(labels[MANAGED_BY_LABEL], "platform-operator");
+        assert_eq!(labels[&keys::key(COPY_LABEL)], "true");
+
+        // a copy without them, e.g. written by an older version, is up to date
+        let source = source();
+        let (target, annotations) = copy(&source, "target");
+        assert!(!target.metadata.labels.as_ref().unwrap().contains_key(MANAGED_BY_LABEL));
+        assert!(secrets_equivalent(&source, &target, UID, &annotations, &[]));
+    }
+
+    #[test]
+    fn source_version_is_stamped_but_not_compared() {
+        let mut source = source();
+        let (_, annotations) = copy(&source, "target");
+        assert_eq!(with_source_version(&annotations, &source), annotations);
+        source.metadata.resource_version = Some("7".to_string());
+        let stamped = with_source_version(&annotations, &source);
+        assert_eq!(stamped[&keys::key(SOURCE_RESOURCE_VERSION_ANNOTATION)], "7");
+
+        // a newer version of the source with the same content leaves the copy up to date
+        let (mut target, _) = copy(&source, "target");
+        target.metadata.annotations = Some(stamped);
+        source.metadata.resource_version = Some("8".to_string());
+        assert!(secrets_equivalent(&source, &target, UID, &annotations, &[]));
+    }
+
+    #[test]
+    fn target_annotations_apply_per_namespace_with_a_default() {
+        let source = annotated(&[
+            (COPY_ANNOTATIONS_ANNOTATION, "team"),
+            (TARGET_ANNOTATIONS_ANNOTATION, r#"{"a": {"cost-center": "1234"}, "*": {"cost-center": "shared", "example.com/tier": "default"}}"#),
+        ]);
+        let mut source = source;
+        source.metadata.annotations.as_mut().unwrap().insert("team".to_string(), "platform".to_string());
+        let mapping = target_annotations(&source).unwrap();
+
+        let a = desired_annotations(&source, &mapping, "a");
+        assert_eq!(a.into_iter().collect::<Vec<_>>(), vec![
+            ("cost-center".to_string(), "1234".to_string()),
+            ("team".to_string(), "platform".to_string()),
+        ]);
+        let b = desired_annotations(&source, &mapping, "b");
+        assert_eq!(b["cost-center"], "shared");
+        assert_eq!(b["example.com/tier"], "default");
+        assert_eq!(b["team"], "platform");
+
+        assert!(target_annotations(&self::source()).unwrap().is_empty());
+        for invalid in &["not json", r#"{"a": "x"}"#, r#"{"a": {"Invalid Key": "x"}}"#, r#"{"a": {"-a": "x"}}"#] {
+            assert!(target_annotations(&annotated(&[(TARGET_ANNOTATIONS_ANNOTATION, invalid)])).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn owner_reference_names_the_source() {
+        let source = source();
+        let annotations = with_owner_reference(&BTreeMap::new(), &source);
+        let reference: serde_json::Value = serde_json::from_str(&annotations[&keys::key(OWNER_REFERENCE_ANNOTATION)]).unwrap();
+        assert_eq!(reference, json!({ "namespace": "source", "name": "db", "uid": UID }));
+
+        let mut copy = Secret::default();
+        assert!(!references_source(&copy, UID));
+        copy.metadata.annotations = Some(annotations);
+        assert!(references_source(&copy, UID));
+        assert!(!references_source(&copy, "other-uid"));
+        copy.metadata.annotations = Some(vec![(keys::key(OWNER_REFERENCE_ANNOTATION), UID.to_string())].into_iter().collect());
+        assert!(!references_source(&copy, UID));
+    }
+
+    #[test]
+    fn key_filter_includes_or_excludes_keys() {
+        let with_data = |annotations: &[(&str, &str)]| {
+            let mut source = annotated(annotations);
+            source.data = Some(data(&[("ca.crt", "cert"), ("tls.key", "key"), ("tls.crt", "crt")]));
+            source.string_data = Some(vec![("tls.key".to_string(), "key".to_string())].into_iter().collect());
+            source
+        };
+        let copied = |source: Secret| KeyFilter::from_source(&source).unwrap().apply(source);
+
+        let included = copied(with_data(&[(INCLUDE_KEYS_ANNOTATION, "ca.crt, tls.crt,")]));
+        assert_eq!(included.data, Some(data(&[("ca.crt", "cert"), ("tls.crt", "crt")])));
+        assert_eq!(included.string_data, Some(BTreeMap::new()));
+        let excluded = copied(with_data(&[(EXCLUDE_KEYS_ANNOTATION, "tls.key")]));
+        assert_eq!(excluded.data, Some(data(&[("ca.crt", "cert"), ("tls.crt", "crt")])));
+        assert_eq!(excluded.string_data, Some(BTreeMap::new()));
+        assert_eq!(copied(with_data(&[])).data.unwrap().len(), 3);
+
+        assert!(KeyFilter::from_source(&with_data(&[(INCLUDE_KEYS_ANNOTATION, "ca.crt"), (EXCLUDE_KEYS_ANNOTATION, "tls.key")])).is_err());
+    }
+
+    #[test]
+    fn only_allow_listed_annotations_are_copied() {
+        let allow_list = format!(" team, ,{}, example.com/owner", crate::PAUSED_ANNOTATION);
+        let mut source = annotated(&[(COPY_ANNOTATIONS_ANNOTATION, &allow_list), (crate::PAUSED_ANNOTATION, "true")]);
+        let annotations = source.metadata.annotations.as_mut().unwrap();
+        annotations.insert("team".to_string(), "platform".to_string());
+        annotations.insert("example.com/owner".to_string(), "alice".to_string());
+        annotations.insert("example.com/other".to_string(), "ignored".to_string());
+        annotations.insert(LAST_APPLIED_ANNOTATION.to_string(), "{}".to_string());
+        assert_eq!(copied_annotations(&source).into_iter().collect::<Vec<_>>(), vec!["eu.fitzek.spread.paused", "example.com/owner", "team"]);
+
+        // the paused annotation is a control annotation, it is never copied
+        let desired = desired_annotations(&source, &TargetAnnotations::new(), "target");
+        assert_eq!(desired.into_iter().collect::<Vec<_>>(), vec![
+            ("example.com/owner".to_string(), "alice".to_string()),
+            ("team".to_string(), "platform".to_string()),
+        ]);
+        assert!(desired_annotations(&annotated(&[]), &TargetAnnotations::new(), "target").is_empty());
+    }
+
+    #[test]
+    fn desired_labels_merge_source_added_and_operator_labels() {
+        let mut source = annotated(&[(ADD_LABELS_ANNOTATION, "tier=backend,team=shared")]);
+        let labels = vec![
+            ("team".to_string(), "platform".to_string()),
+            ("app".to_string(), "db".to_string()),
+            (keys::owner_label().to_string(), "forged".to_string()),
+            (keys::key(SOURCE_LABEL), "forged".to_string()),
+        ];
+        source.metadata.labels = Some(labels.into_iter().collect());
+        let desired = desired_labels(&source, UID);
+        assert_eq!(desired.into_iter().collect::<Vec<_>>(), vec![
+            ("app".to_string(), "db".to_string()),
+            (keys::owner_label().to_string(), UID.to_string()),
+            (keys::key(SOURCE_LABEL), "source.db".to_string()),
+            ("team".to_string(), "shared".to_string()),
+            ("tier".to_string(), "backend".to_string()),
+        ]);
+
+        // labels only the copy has are kept and not compared
+        let (mut target, annotations) = copy(&source, "target");
+        target.metadata.labels.as_mut().unwrap().insert("injected".to_string(), "true".to_string());
+        assert!(secrets_equivalent(&source, &target, UID, &annotations, &[]));
+    }
+
+    #[test]
+    fn ignored_keys_of_the_source_are_compared() {
+        let mut source = source();
+        source.data.get_or_insert_with(BTreeMap::new).insert("ca.crt".to_string(), ByteString(b"source".to_vec()));
+        let ignored = vec!["ca.crt".to_string()];
+        let (mut target, annotations) = copy(&source, "target");
+        target.data.get_or_insert_with(BTreeMap::new).insert("ca.crt".to_string(), ByteString(b"injected".to_vec()));
+        assert!(!secrets_equivalent(&source, &target, UID, &annotations, &ignored));
+    }
+
+    fn data(entries: &[(&str, &str)]) -> BTreeMap<String, ByteString> {
+        entries.iter().map(|(k, v)| (k.to_string(), ByteString(v.as_bytes().to_vec()))).collect()
+    }
+
+    #[test]
+    fn diff_data_names_added_removed_and_changed_keys() {
+        let current = data(&[("user", "admin"), ("password", "old"), ("stale", "x")]);
+        let desired = data(&[("user", "admin"), ("password", "new"), ("token", "t")]);
+        assert_eq!(
+            diff_data(&current, &desired),
+            DataDiff {
+                added: vec!["token".to_string()],
+                removed: vec!["stale".to_string()],
+                changed: vec!["password".to_string()],
+            }
+        );
+        assert_eq!(diff_data(&current, &current), DataDiff::default());
+        assert_eq!(diff_data(&BTreeMap::new(), &desired).added.len(), 3);
+        assert_eq!(diff_data(&current, &BTreeMap::new()).removed, vec!["password", "stale", "user"]);
+    }
+
+    #[test]
+    fn string_data_is_folded_into_data() {
+        let mut source = source();
+        source.string_data = Some(vec![("password".to_string(), "plain".to_string()), ("user".to_string(), "admin".to_string())].into_iter().collect());
+        // a key in both is stored with the value of stringData
+        assert_eq!(normalized_data(&source), data(&[("password", "plain"), ("user", "admin")]));
+        source.data = None;
+        assert_eq!(normalized_data(&source), data(&[("password", "plain"), ("user", "admin")]));
+        assert!(normalized_data(&Secret::default()).is_empty());
+    }
+
+    #[test]
+    fn missing_and_empty_types_are_opaque() {
+        let source = source();
+        let (target, annotations) = copy(&source, "target");
+        for type_ in &[None, Some(""), Some("Opaque")] {
+            let source = Secret {
+                type_: type_.map(str::to_string),
+                ..source.clone()
+            };
+            assert_eq!(normalized_type(&source), "Opaque");
+            assert!(secrets_equivalent(&source, &target, UID, &annotations, &[]));
+        }
+        let tls = Secret {
+            type_: Some("kubernetes.io/tls".to_string()),
+            ..source
+        };
+        assert_eq!(normalized_type(&tls), "kubernetes.io/tls");
+    }
+
+    #[test]
+    fn content_hash_covers_everything_compared() {
+        let source = source();
+        let (_, annotations) = copy(&source, "target");
+        let hash = content_hash(&source, UID, &annotations);
+        assert_eq!(hash, content_hash(&source.clone(), UID, &annotations.clone()));
+        assert_eq!(hash.len(), 16);
+
+        let mut changed_data = source.clone();
+        changed_data.data.get_or_insert_with(BTreeMap::new).insert("password".to_string(), ByteString(b"rotated".to_vec()));
+        let changed_type = Secret {
+            type_: Some("kubernetes.io/tls".to_string()),
+            ..source.clone()
+        };
+        let changed_immutability = Secret {
+            immutable: Some(true),
+            ..source.clone()
+        };
+        let mut changed_labels = source.clone();
+        changed_labels.metadata.labels = Some(vec![("team".to_string(), "platform".to_string())].into_iter().collect());
+        for changed in &[changed_data, changed_type, changed_immutability, changed_labels] {
+            assert_ne!(content_hash(changed, UID, &annotations), hash);
+        }
+        assert_ne!(content_hash(&source, "other-uid", &annotations), hash);
+        let mut changed_annotations = annotations.clone();
+        changed_annotations.insert("team".to_string(), "other".to_string());
+        assert_ne!(content_hash(&source, UID, &changed_annotations), hash);
+
+        // an untyped source hashes like an Opaque one, data like the same string data
+        let opaque = Secret {
+            type_: Some("Opaque".to_string()),
+            ..source.clone()
+        };
+        assert_eq!(content_hash(&opaque, UID, &annotations), hash);
+        let string_data = Secret {
+            data: None,
+            string_data: Some(vec![("password".to_string(), "secret".to_string())].into_iter().collect()),
+            ..source
+        };
+        assert_eq!(content_hash(&string_data, UID, &annotations), hash);
+    }
+}
@@ -0,0 +1,158 @@
+//! Concurrency limits of the controllers.
+//!
+//! kube-runtime starts the reconcile of every due object right away. Every controller has
+//! reconcile slots of its own, so a controller flooded with reconciles can't keep the others from
+//! making progress: when a new namespace makes every source spread by `*` due in the Secret
+//! controller, the ConfigMap controller keeps reconciling its sources meanwhile. Within a
+//! controller the slots are handed out first come, first served.
+//!
+//! The limit of a controller is its entry in `--controller-concurrency`, e.g.
+//! `secret=16,configmap=4`, else `--max-concurrent-reconciles`, 0 for no limit. The
+//! `spread_controller_*` metrics show the reconciles running and waiting per controller and, for
+//! the Secret controller, which watch triggers them.
+
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+use crate::metrics;
+use crate::opts::Opts;
+
+/// The controller of Secret sources, triggered by its watches of Secrets, ConfigMaps and
+/// namespaces.
+pub const SECRET: &str = "secret";
+/// The controller of ConfigMap sources.
+pub const CONFIGMAP: &str = "configmap";
+/// The controller of the pull secrets of Deployments.
+pub const PULL_SECRET: &str = "pull-secret";
+/// The controller of Vault backed sources.
+pub const VAULT: &str = "vault";
+/// The controllers with a limit of their own.
+pub const CONTROLLERS: [&str; 4] = [SECRET, CONFIGMAP, PULL_SECRET, VAULT];
+
+/// Reconcile slots of one controller.
+pub struct Slots {
+    controller: &'static str,
+    /// None without limit.
+    semaphore: Option<Semaphore>,
+}
+
+impl Slots {
+    /// Constructs Slots of `controller` permitting `limit` reconciles at once, 0 for no limit.
+    pub fn new(controller: &'static str, limit: u16) -> Self {
+        Slots {
+            controller,
+            semaphore: Some(limit).filter(|n| *n > 0).map(|n| Semaphore::new(n as usize)),
+        }
+    }
+
+    /// Constructs Slots of `controller` limited as configured by `opts`.
+    pub fn for_controller(controller: &'static str, opts: &Opts) -> Self {
+        let limit = opts.controller_concurrency.iter().rev().find(|(c, _)| c == controller).map(|(_, n)| *n);
+        Slots::new(controller, limit.unwrap_or(opts.max_concurrent_reconciles))
+    }
+
+    /// Waits for a free slot, the reconcile holds it until the returned [`Slot`] is dropped.
+    pub async fn acquire(&self) -> Slot<'_> {
+        metrics::controller_add("spread_controller_reconciles_waiting", self.controller, 1);
+        let permit = match &self.semaphore {
+            Some(semaphore) => Some(semaphore.acquire().await.expect("the reconcile slots are never closed")),
+            None => None,
+        };
+        metrics::controller_add("spread_controller_reconciles_waiting", self.controller, -1);
+        metrics::controller_add("spread_controller_reconciles_running", self.controller, 1);
+        metrics::controller_add("spread_controller_reconciles_total", self.controller, 1);
+        Slot {
+            controller: self.controller,
+            _permit: permit,
+        }
+    }
+}
+
+/// A slot held by a running reconcile, freed when dropped.
+pub struct Slot<'a> {
+    controller: &'static str,
+    _permit: Option<SemaphorePermit<'a>>,
+}
+
+impl Drop for Slot<'_> {
+    fn drop(&mut self) {
+        metrics::controller_add("spread_controller_reconciles_running", self.controller, -1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use clap::Parser;
+    use tokio::time::{sleep, Duration, Instant};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn flooded_controller_does_not_hold_up_the_others() {
+        let secret = Arc::new(Slots::new(SECRET, 2));
+        let configmap = Slots::new(CONFIGMAP, 2);
+        let secrets_done = Arc::new(AtomicUsize::new(0));
+
+        // 100 Secret reconciles of 10ms each keep the Secret controller busy for half a second
+        let flood: Vec<_> = (0..100)
+            .map(|_| {
+                let (secret, secrets_done) = (secret.clone(), secrets_done.clone());
+                tokio::spawn(async move {
+                    let _slot = secret.acquire().await;
+                    sleep(Duration::from_millis(10)).await;
+                    secrets_done.fetch_add(1, Ordering::SeqCst);
+                })
+            })
+            .collect();
+        sleep(Duration::from_millis(5)).await;
+
+        // meanwhile the ConfigMap controller gets its reconciles done right away
+        let started = Instant::now();
+        for _ in 0..4 {
+            let _slot = configmap.acquire().await;
+            sleep(Duration::from_millis(10)).await;
+        }
+        assert!(started.elapsed() < Duration::from_millis(250), "{:?}", started.elapsed());
+        assert!(secrets_done.load(Ordering::SeqCst) < 100);
+
+        // and the Secret controller makes progress as well, two at a time
+        futures::future::join_all(flood).await;
+        assert_eq!(secrets_done.load(Ordering::SeqCst), 100);
+    }
+
+    #[tokio::test]
+    async fn slots_are_reported_per_controller() {
+        let slots = Arc::new(Slots::new("test", 1));
+        let running = slots.acquire().await;
+        let waiting = tokio::spawn({
+            let slots = slots.clone();
+            async move {
+                let _slot = slots.acquire().await;
+            }
+        });
+        sleep(Duration::from_millis(20)).await;
+        let rendered = metrics::render();
+        assert!(rendered.contains("spread_controller_reconciles_running{controller=\"test\"} 1\n"), "{}", rendered);
+        assert!(rendered.contains("spread_controller_reconciles_waiting{controller=\"test\"} 1\n"), "{}", rendered);
+
+        drop(running);
+        waiting.await.unwrap();
+        let rendered = metrics::render();
+        assert!(rendered.contains("spread_controller_reconciles_running{controller=\"test\"} 0\n"), "{}", rendered);
+        assert!(rendered.contains("spread_controller_reconciles_waiting{controller=\"test\"} 0\n"), "{}", rendered);
+        assert!(rendered.contains("spread_controller_reconciles_total{controller=\"test\"} 2\n"), "{}", rendered);
+    }
+
+    #[test]
+    fn limits_are_configured_per_controller() {
+        let opts = Opts::parse_from(["spreading-operator", "--max-concurrent-reconciles", "8", "--controller-concurrency", "secret=16,configmap=0"]);
+        let permits = |controller| Slots::for_controller(controller, &opts).semaphore.map(|s| s.available_permits());
+        assert_eq!(permits(SECRET), Some(16));
+        assert_eq!(permits(CONFIGMAP), None);
+        assert_eq!(permits(PULL_SECRET), Some(8));
+        assert!(Opts::try_parse_from(["spreading-operator", "--controller-concurrency", "namespace=4"]).is_err());
+        assert!(Opts::try_parse_from(["spreading-operator", "--controller-concurrency", "secret"]).is_err());
+    }
+}
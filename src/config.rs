@@ -0,0 +1,384 @@
+//! Spread configuration of a source, parsed from its `eu.fitzek.spread.*` annotations.
+
+use std::collections::BTreeMap;
+
+use k8s_openapi::api::core::v1::Secret;
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::ObjectMeta;
+use kube::Client;
+
+use crate::compare::{self, KeyFilter, KeyMap, TargetAnnotations};
+use crate::naming::{self, CopyNamer};
+use crate::{generated, is_copy, keys, status, targets, Error};
+use crate::{
+    ADOPT_UNMANAGED_ANNOTATION, ALLOW_SA_TOKEN_ANNOTATION, CONDITION_ANNOTATION, CONDITION_CLEANUP_ANNOTATION,
+    CREATE_NAMESPACE_ANNOTATION, DELETE_POLICY_ANNOTATION, PAUSED_ANNOTATION, VAULT_PATH_ANNOTATION,
+};
+
+/// A rule of the `targets` annotation with the namer of its copies.
+pub type NamedRule = (targets::TargetRule, Box<dyn CopyNamer>);
+
+/// How a source is spread. The target namespaces themselves are resolved on every reconcile, as
+/// they depend on the namespaces in the cluster.
+pub struct SpreadConfig {
+    /// Names the copy in each target namespace.
+    pub namer: Box<dyn CopyNamer>,
+    /// Rules of the `targets` annotation, each with the namer of its copies.
+    pub target_rules: Vec<NamedRule>,
+    /// Annotations of the copies by target namespace.
+    pub target_annotations: TargetAnnotations,
+    /// Data keys of the source copied to the targets.
+    pub key_filter: KeyFilter,
+    /// Renames of data keys in the copies.
+    pub key_map: KeyMap,
+    /// Whether copies are created with `generateName`.
+    pub use_generate_name: bool,
+    /// Whether copies in namespaces no longer targeted are deleted.
+    pub prune_untargeted: bool,
+    /// Whether a secret of the copy name not written by the operator is taken over.
+    pub adopt_unmanaged: bool,
+    /// Whether missing target namespaces are created, `--create-namespaces` if the source
+    /// doesn't say.
+    pub create_namespace: Option<bool>,
+    /// Whether a service account token may be spread.
+    pub allow_sa_token: bool,
+    /// Annotation key and value the source has to carry to be spread.
+    pub condition: Option<(String, String)>,
+    /// Whether the copies are deleted while the condition is not met.
+    pub condition_cleanup: bool,
+    /// Whether the copies are kept as unmanaged secrets once the source is deleted.
+    pub orphan_on_delete: bool,
+}
+
+impl SpreadConfig {
+    /// Parses and validates the annotations of `sec`. Returns `None` if the source carries no
+    /// targeting annotation, i.e. is not to be spread.
+    pub fn from_secret(sec: &Secret) -> Result<Option<Self>, Error> {
+        if !targets::has_targets(&sec.metadata) {
+            return Ok(None);
+        }
+        targets::validate(&sec.metadata)?;
+        Self::for_copies(sec).map(Some)
+    }
+
+    /// Parses the annotations of `sec` shaping its copies, whether or not it selects target
+    /// namespaces itself. Pull secrets are targeted by the Deployments referencing them.
+    pub fn for_copies(sec: &Secret) -> Result<Self, Error> {
+        let meta = &sec.metadata;
+        let flag = |key: &str| targets::annotation(meta, key).as_deref() == Some("true");
+        // the labels are parsed again where the copies are compared, only validated here
+        compare::added_labels(sec)?;
+        Ok(SpreadConfig {
+            namer: naming::namer_for(meta)?,
+            target_rules: target_rules(meta)?,
+            target_annotations: compare::target_annotations(sec)?,
+            key_filter: KeyFilter::from_source(sec)?,
+            key_map: KeyMap::from_source(sec)?,
+            use_generate_name: generated::enabled(meta),
+            prune_untargeted: targets::prune_untargeted(meta),
+            adopt_unmanaged: flag(ADOPT_UNMANAGED_ANNOTATION),
+            create_namespace: targets::annotation(meta, CREATE_NAMESPACE_ANNOTATION).map(|v| v == "true"),
+            allow_sa_token: flag(ALLOW_SA_TOKEN_ANNOTATION),
+            condition: condition(meta)?,
+            condition_cleanup: flag(CONDITION_CLEANUP_ANNOTATION),
+            orphan_on_delete: orphan_on_delete(meta)?,
+        })
+    }
+
+    /// Returns `sec` as its copies are written and compared: with the keys that are spread only,
+    /// renamed by `key-map`. The key filter applies to the keys of the source.
+    pub fn copied(&self, sec: Secret) -> Secret {
+        self.key_map.apply(self.key_filter.apply(sec))
+    }
+
+    /// Computes the target namespaces of the source `meta` named `source_name`, with the name of
+    /// the copy in each: the namespaces of the targeting annotations, see
+    /// [`targets::resolve_target_namespaces`], named by `namer`, plus the namespaces of every rule
+    /// named by the rule. A namespace selected more than once has to get the same copy name each
+    /// time, a source can't have two copies in one namespace.
+    pub async fn resolve_copies(&self, client: Client, source_client: Client, meta: &ObjectMeta, source_name: &str, targeting: &targets::Targeting) -> Result<BTreeMap<String, String>, Error> {
+        let mut copies: BTreeMap<String, String> = BTreeMap::new();
+        let mut add = |ns: String, copy_name: String| match copies.get(&ns) {
+            Some(other) if *other != copy_name => Err(Error::UserInputError(format!(
+                "Conflicting copies {} and {} in namespace {}, a source has one copy per namespace",
+                other, copy_name, ns
+            ))),
+            _ => {
+                copies.insert(ns, copy_name);
+                Ok(())
+            }
+        };
+        for ns in targets::resolve_target_namespaces(client.clone(), source_client, meta, targeting).await? {
+            let copy_name = self.namer.name_for(source_name, &ns)?;
+            add(ns, copy_name)?;
+        }
+        let rules: &[NamedRule] = if targets::rules_honored(meta, targeting.conflict_mode) { &self.target_rules } else { &[] };
+        for (rule, namer) in rules {
+            for ns in targets::resolve_rule(client.clone(), meta, rule, targeting).await? {
+                let copy_name = namer.name_for(source_name, &ns)?;
+                add(ns, copy_name)?;
+            }
+        }
+        Ok(copies)
+    }
+
+    /// Checks the `key=value` condition on `meta`: spreading only happens while the source
+    /// carries the annotation `key` with the value `value`. Sources without condition always
+    /// spread.
+    pub fn condition_met(&self, meta: &ObjectMeta) -> bool {
+        match &self.condition {
+            Some((key, value)) => targets::annotation(meta, key).as_deref() == Some(value.as_str()),
+            None => true,
+        }
+    }
+}
+
+/// Parses the rules of the `targets` annotation with their namers. A rule can't combine `name`
+/// with `namePrefix` or `nameSuffix`.
+fn target_rules(meta: &ObjectMeta) -> Result<Vec<NamedRule>, Error> {
+    let mut rules = Vec::new();
+    for rule in targets::target_rules(meta)? {
+        if rule.name.is_some() && (rule.name_prefix.is_some() || rule.name_suffix.is_some()) {
+            return Err(Error::UserInputError(format!(
+                "Invalid {} annotation: name can not be combined with namePrefix or nameSuffix",
+                targets::TARGETS_ANNOTATION
+            )));
+        }
+        let namer = naming::namer(rule.name.clone(), rule.name_prefix.clone(), rule.name_suffix.clone());
+        rules.push((rule, namer));
+    }
+    Ok(rules)
+}
+
+/// Parses the `delete-policy` of the source: whether its copies are orphaned instead of deleted
+/// along with it.
+pub fn orphan_on_delete(meta: &ObjectMeta) -> Result<bool, Error> {
+    match targets::annotation(meta, DELETE_POLICY_ANNOTATION).as_deref() {
+        None | Some("delete") => Ok(false),
+        Some("orphan") => Ok(true),
+        Some(policy) => Err(Error::UserInputError(format!(
+            "Invalid {} annotation: expected delete or orphan, got {}",
+            DELETE_POLICY_ANNOTATION, policy
+        ))),
+    }
+}
+
+/// Parses the `key=value` condition of the source, if any.
+fn condition(meta: &ObjectMeta) -> Result<Option<(String, String)>, Error> {
+    let condition = match targets::annotation(meta, CONDITION_ANNOTATION) {
+        Some(c) => c,
+        None => return Ok(None),
+    };
+    match condition.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok(Some((key.to_string(), value.to_string()))),
+        _ => Err(Error::UserInputError(format!(
+            "Invalid {} annotation: expected key=value, got {}",
+            CONDITION_ANNOTATION, condition
+        ))),
+    }
+}
+
+/// Returns `sec` with the default target namespaces of `targeting` as its `target-namespace`
+/// annotation if it carries the default trigger label but no targeting annotation of its own.
+/// Copies carry the labels of their source and are left as they are.
+pub fn with_default_targets(sec: Secret, targeting: &targets::Targeting) -> Secret {
+    let trigger = match &targeting.default_trigger_label {
+        Some(trigger) if !targeting.default_target_namespaces.is_empty() => trigger,
+        _ => return sec,
+    };
+    if targets::has_targets(&sec.metadata) || is_copy(&sec) {
+        return sec;
+    }
+    let labels = sec.metadata.labels.clone().unwrap_or_default();
+    let triggered = match trigger.split_once('=') {
+        Some((key, value)) => labels.get(key.trim()).map(String::as_str) == Some(value.trim()),
+        None => labels.contains_key(trigger.trim()),
+    };
+    if !triggered {
+        return sec;
+    }
+    let mut sec = sec;
+    sec.metadata
+        .annotations
+        .get_or_insert_with(BTreeMap::new)
+        .insert(keys::key(targets::TARGET_NAMESPACE_ANNOTATION), targeting.default_target_namespaces.join(","));
+    sec
+}
+
+/// Annotations the operator reads or writes on Secrets, declared with the default prefix.
+pub const KNOWN_ANNOTATIONS: [&str; 38] = [
+    targets::TARGET_NAMESPACE_ANNOTATION,
+    targets::EXCLUDE_NAMESPACES_ANNOTATION,
+    targets::TARGET_NAMESPACE_SELECTOR_ANNOTATION,
+    targets::TARGET_FOR_GROUP_ANNOTATION,
+    targets::TARGET_SUBTREE_ANNOTATION,
+    targets::MAX_NAMESPACES_ANNOTATION,
+    targets::TARGET_NAMESPACES_FROM_ANNOTATION,
+    targets::TARGET_URL_ANNOTATION,
+    targets::TARGET_POLICY_ANNOTATION,
+    targets::PRUNE_UNTARGETED_ANNOTATION,
+    targets::MODE_ANNOTATION,
+    targets::TARGETS_ANNOTATION,
+    naming::TARGET_NAME_ANNOTATION,
+    naming::TARGET_NAME_PREFIX_ANNOTATION,
+    naming::TARGET_NAME_SUFFIX_ANNOTATION,
+    generated::USE_GENERATE_NAME_ANNOTATION,
+    generated::GENERATED_NAMES_ANNOTATION,
+    compare::TARGET_ANNOTATIONS_ANNOTATION,
+    compare::COPY_ANNOTATIONS_ANNOTATION,
+    compare::ADD_LABELS_ANNOTATION,
+    compare::SOURCE_RESOURCE_VERSION_ANNOTATION,
+    compare::CONTENT_HASH_ANNOTATION,
+    compare::RESYNC_ANNOTATION,
+    compare::RESYNCED_ANNOTATION,
+    compare::OWNER_REFERENCE_ANNOTATION,
+    compare::INCLUDE_KEYS_ANNOTATION,
+    compare::EXCLUDE_KEYS_ANNOTATION,
+    compare::KEY_MAP_ANNOTATION,
+    status::LAST_SYNCED_ANNOTATION,
+    status::TARGET_COUNT_ANNOTATION,
+    ALLOW_SA_TOKEN_ANNOTATION,
+    CONDITION_ANNOTATION,
+    CONDITION_CLEANUP_ANNOTATION,
+    ADOPT_UNMANAGED_ANNOTATION,
+    CREATE_NAMESPACE_ANNOTATION,
+    VAULT_PATH_ANNOTATION,
+    PAUSED_ANNOTATION,
+    DELETE_POLICY_ANNOTATION,
+];
+
+/// Returns the annotations of the object with the prefix of the operator that the operator
+/// doesn't know, each with the known annotation closest to it. A misspelled annotation is
+/// ignored, which looks like a source that isn't spread, so they are reported, but not rejected:
+/// an annotation of a newer version of the operator is no error.
+pub fn unknown_annotations(meta: &ObjectMeta) -> Vec<(String, String)> {
+    let prefix = keys::prefix().to_ascii_lowercase();
+    let known: Vec<String> = KNOWN_ANNOTATIONS.iter().map(|k| keys::key(k)).collect();
+    meta.annotations
+        .iter()
+        .flatten()
+        .map(|(key, _)| key)
+        .filter(|key| key.to_ascii_lowercase().starts_with(&prefix) && !known.iter().any(|k| k.eq_ignore_ascii_case(key)))
+        .map(|key| {
+            let lowercase = key.to_ascii_lowercase();
+            let closest = known
+                .iter()
+                .min_by_key(|k| edit_distance(&lowercase, &k.to_ascii_lowercase()))
+                .cloned()
+                .unwrap_or_default();
+            (key.clone(), closest)
+        })
+        .collect()
+}
+
+/// Levenshtein distance of `a` and `b`: the number of characters inserted, removed or replaced
+/// to turn one into the other.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let replaced = previous[j] + usize::from(ca != *cb);
+            current.push(replaced.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    use crate::opts::Opts;
+
+    fn secret(labels: &[(&str, &str)], annotations: &[(&str, &str)]) -> Secret {
+        Secret {
+            metadata: ObjectMeta {
+                name: Some("db".to_string()),
+                namespace: Some("source".to_string()),
+                labels: Some(labels.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()),
+                annotations: Some(annotations.iter().map(|(k, v)| (keys::key(k), v.to_string())).collect()),
+                ..ObjectMeta::default()
+            },
+            ..Secret::default()
+        }
+    }
+
+    fn targeting(args: &[&str]) -> targets::Targeting {
+        targets::Targeting::from_opts(&Opts::parse_from(["spreading-operator"].iter().chain(args)))
+    }
+
+    fn target_namespace(sec: &Secret) -> Option<String> {
+        targets::annotation(&sec.metadata, targets::TARGET_NAMESPACE_ANNOTATION)
+    }
+
+    #[test]
+    fn trigger_label_alone_spreads_to_the_default_targets() {
+        let targeting = targeting(&["--default-trigger-label", "spread=true", "--default-target-namespaces", "a,b"]);
+        let sec = with_default_targets(secret(&[("spread", "true")], &[]), &targeting);
+        assert_eq!(target_namespace(&sec).as_deref(), Some("a,b"));
+        assert!(SpreadConfig::from_secret(&sec).unwrap().is_some());
+
+        // a trigger label with another value doesn't, a trigger without value matches any
+        assert_eq!(target_namespace(&with_default_targets(secret(&[("spread", "false")], &[]), &targeting)), None);
+        let any_value = self::targeting(&["--default-trigger-label", "spread", "--default-target-namespaces", "a"]);
+        assert_eq!(target_namespace(&with_default_targets(secret(&[("spread", "false")], &[]), &any_value)).as_deref(), Some("a"));
+    }
+
+    #[test]
+    fn targeting_annotation_overrides_the_default_targets() {
+        let targeting = targeting(&["--default-trigger-label", "spread=true", "--default-target-namespaces", "a,b"]);
+        let explicit = secret(&[("spread", "true")], &[(targets::TARGET_NAMESPACE_ANNOTATION, "c")]);
+        assert_eq!(target_namespace(&with_default_targets(explicit, &targeting)).as_deref(), Some("c"));
+        let selector = secret(&[("spread", "true")], &[(targets::TARGET_NAMESPACE_SELECTOR_ANNOTATION, "env=prod")]);
+        assert_eq!(target_namespace(&with_default_targets(selector, &targeting)), None);
+    }
+
+    #[test]
+    fn unknown_annotations_come_with_the_closest_known_one() {
+        let mut sec = secret(&[], &[
+            (targets::TARGET_NAMESPACE_ANNOTATION, "a"),
+            ("eu.fitzek.spread.target-namespaces", "a"),
+            ("eu.fitzek.spread.targetnamespace", "a"),
+            ("eu.fitzek.spread.Paused", "true"),
+        ]);
+        sec.metadata.annotations.as_mut().unwrap().insert("example.com/target-namespaces".to_string(), "a".to_string());
+        let closest = keys::key(targets::TARGET_NAMESPACE_ANNOTATION);
+        // known keys are matched case insensitively, other prefixes are not the operator's
+        assert_eq!(unknown_annotations(&sec.metadata), vec![
+            ("eu.fitzek.spread.target-namespaces".to_string(), closest.clone()),
+            ("eu.fitzek.spread.targetnamespace".to_string(), closest),
+        ]);
+        assert!(unknown_annotations(&secret(&[], &[]).metadata).is_empty());
+        assert_eq!(edit_distance("target-namespaces", "target-namespace"), 1);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("", "abc"), 3);
+    }
+
+    #[test]
+    fn secrets_without_trigger_label_or_defaults_are_left_alone() {
+        let targeting = targeting(&["--default-trigger-label", "spread=true", "--default-target-namespaces", "a,b"]);
+        let plain = secret(&[("team", "platform")], &[]);
+        assert_eq!(with_default_targets(plain.clone(), &targeting), plain);
+        // a copy carries the labels of its source, it is never spread itself
+        let copy = secret(&[("spread", "true"), (keys::owner_label(), "source-uid")], &[]);
+        assert_eq!(with_default_targets(copy.clone(), &targeting), copy);
+        // without the options nothing is spread by label
+        let labeled = secret(&[("spread", "true")], &[]);
+        assert_eq!(with_default_targets(labeled.clone(), &self::targeting(&[])), labeled);
+    }
+
+    #[test]
+    fn condition_is_parsed_and_checked() {
+        let config = |condition: &str| SpreadConfig::from_secret(&secret(&[], &[(targets::TARGET_NAMESPACE_ANNOTATION, "a"), (CONDITION_ANNOTATION, condition)]));
+        let met = config("myapp.io/ready=true").unwrap().unwrap();
+        assert_eq!(met.condition, Some(("myapp.io/ready".to_string(), "true".to_string())));
+        assert!(met.condition_met(&secret(&[], &[("myapp.io/ready", "true")]).metadata));
+        assert!(!met.condition_met(&secret(&[], &[("myapp.io/ready", "True")]).metadata));
+        assert!(!met.condition_met(&secret(&[], &[]).metadata));
+        assert!(config("myapp.io/ready").is_err());
+        assert!(config("=true").is_err());
+    }
+}
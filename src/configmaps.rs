@@ -0,0 +1,266 @@
+use std::collections::BTreeMap;
+
+use futures::stream::StreamExt;
+use k8s_openapi::api::core::v1::ConfigMap;
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::ObjectMeta;
+use kube::api::{DeleteParams, ListParams, Patch, PostParams};
+use kube::{Api, Client, Resource};
+use kube_runtime::controller::{Context, ReconcilerAction};
+use kube_runtime::Controller;
+use serde_json::{json, Value};
+use tracing::{error, info, warn};
+
+use crate::opts::Opts;
+use crate::{compare, concurrency, delete_copies, delete_stale_copies, finalizer, keys, on_error, scoped_api, settings, shutdown, targets, ContextData, Error, VAULT_PATH_ANNOTATION};
+
+/// Runs the controller spreading ConfigMaps. ConfigMaps are selected by the same annotations as
+/// Secrets and their copies carry the same owner label, the source is guarded by the same
+/// finalizer.
+///
+/// ConfigMaps declaring a Vault backed source are left to the backend controller. Only ConfigMaps
+/// in the watch `scopes` are spread, see `--watch-namespaces`, with the options `opts` and the
+/// `settings` shared with the Secret controller.
+pub async fn run(client: Client, scopes: &[Option<String>], opts: &Opts, settings: settings::Shared) {
+    let context: Context<ContextData> = Context::new(
+        ContextData::new(client.clone(), opts)
+            .with_settings(settings)
+            .with_slots(concurrency::Slots::for_controller(concurrency::CONFIGMAP, opts)),
+    );
+
+    futures::future::join_all(scopes.iter().map(|scope| {
+        let configmap_api: Api<ConfigMap> = scoped_api(client.clone(), scope.as_deref());
+        Controller::new(configmap_api, ListParams::default())
+            .run(reconcile, on_error, context.clone())
+            .for_each(|reconciliation_result| async move {
+                if let Err(reconciliation_err) = reconciliation_result {
+                    error!(error = ?reconciliation_err, "Reconciliation error")
+                }
+            })
+    }))
+    .await;
+}
+
+/// Reconciles a ConfigMap by spreading it to its target namespaces, or cleaning up its copies
+/// when it is deleted.
+async fn reconcile(cm: ConfigMap, context: Context<ContextData>) -> Result<ReconcilerAction, Error> {
+    // no reconcile starts once the operator shuts down, see shutdown::drain
+    let _running = match shutdown::begin() {
+        Some(guard) => guard,
+        None => return Ok(ReconcilerAction { requeue_after: None }),
+    };
+    // paused in the settings ConfigMap, see the Secret reconcile
+    if context.get_ref().paused() {
+        return Ok(ReconcilerAction {
+            requeue_after: Some(context.get_ref().jitter.apply(context.get_ref().requeue().idle)),
+        });
+    }
+    if targets::annotation(&cm.metadata, VAULT_PATH_ANNOTATION).is_some()
+        || (!targets::has_targets(&cm.metadata) && !finalizer::has_finalizer(&cm))
+    {
+        return Ok(ReconcilerAction {
+            // Check again later if an annotation was added
+            requeue_after: Some(context.get_ref().jitter.apply(context.get_ref().requeue().idle)),
+        });
+    }
+
+    let source_namespace: String = match cm.namespace() {
+        None => {
+            return Err(Error::UserInputError(
+                "Expected ConfigMap resource to be namespaced.".to_owned(),
+            ));
+        }
+        Some(namespace) => namespace,
+    };
+
+    let source_uid: String = match &cm.meta().uid {
+        None => {
+            return Err(Error::UserInputError(
+                "Expected ConfigMap resource to have an uid".to_owned(),
+            ));
+        }
+        Some(v) => v.clone(),
+    };
+
+    let name = cm.name();
+    // the ConfigMap controller has reconcile slots of its own, see concurrency
+    let _slot = context.get_ref().reconcile_slots.acquire().await;
+    let client: Client = context.get_ref().client.clone();
+
+    // a ConfigMap no longer spread still carries the finalizer, it is cleaned up like on deletion
+    if cm.metadata.deletion_timestamp.is_some() || !targets::has_targets(&cm.metadata) {
+        for (ns, copy_name) in delete_copies::<ConfigMap>(client.clone(), &source_uid, &context.get_ref().delete_params()).await? {
+            info!(target_namespace = %ns, secret_name = %copy_name, "Deleted copy");
+        }
+        finalizer::rm(client, &name, &source_namespace, &cm, &context.get_ref().patch_params()).await?;
+        return Ok(ReconcilerAction { requeue_after: None });
+    }
+
+    // A copy is never a source as well, see the Secret reconcile
+    if is_copy(&cm) {
+        warn!(
+            source_namespace = %source_namespace, name = %name,
+            "ConfigMap carries the owner label {} of a copy and a target annotation, refusing to spread it",
+            keys::owner_label()
+        );
+        return Ok(ReconcilerAction {
+            requeue_after: Some(context.get_ref().jitter.apply(context.get_ref().requeue().idle)),
+        });
+    }
+
+    context.get_ref().pacer.acquire().await;
+    if context.get_ref().use_finalizer && !finalizer::add(client.clone(), &name, &source_namespace, &cm, &context.get_ref().patch_params()).await? {
+        warn!(source_namespace = %source_namespace, name = %name, "Finalizer not confirmed on the ConfigMap, not spreading yet");
+        return Ok(ReconcilerAction {
+            requeue_after: Some(context.get_ref().requeue().error),
+        });
+    }
+
+    info!(source_namespace = %source_namespace, name = %name, source_uid = %source_uid, "Spreading ConfigMap");
+
+    // copies of ConfigMaps are always named like their source, the name transforms of the
+    // `targets` rules only apply to Secrets
+    let mut namespaces = targets::resolve_target_namespaces(client.clone(), client.clone(), &cm.metadata, &context.get_ref().targeting()).await?;
+    let rules = if targets::rules_honored(&cm.metadata, context.get_ref().targeting().conflict_mode) { targets::target_rules(&cm.metadata)? } else { Vec::new() };
+    for rule in rules {
+        namespaces.extend(targets::resolve_rule(client.clone(), &cm.metadata, &rule, &context.get_ref().targeting()).await?);
+    }
+    let mut desired_names: BTreeMap<String, String> = BTreeMap::new();
+    for ns in namespaces {
+        if ns == source_namespace {
+            continue;
+        }
+        sync_copy(&cm, &context, &source_uid, &ns).await?;
+        desired_names.insert(ns, name.clone());
+    }
+
+    let prune_untargeted = targets::prune_untargeted(&cm.metadata);
+    for (ns, copy_name) in delete_stale_copies::<ConfigMap>(client, &source_uid, &desired_names, prune_untargeted, &context.get_ref().delete_params()).await? {
+        info!(target_namespace = %ns, name = %copy_name, "Deleted copy");
+    }
+
+    Ok(ReconcilerAction {
+        requeue_after: Some(context.get_ref().jitter.apply(context.get_ref().requeue().synced)),
+    })
+}
+
+/// Returns whether `cm` carries the owner label, i.e. is a copy written by the operator.
+fn is_copy(cm: &ConfigMap) -> bool {
+    cm.metadata.labels.as_ref().is_some_and(|l| l.contains_key(keys::owner_label()))
+}
+
+/// Labels a copy of `source` carries: the source labels plus the owner label, the source label
+/// and the recommended labels.
+fn desired_labels(source: &ConfigMap, source_uid: &str, managed_by: &str) -> BTreeMap<String, String> {
+    let mut labels = source.metadata.labels.clone().unwrap_or_default();
+    labels.insert(keys::owner_label().to_string(), source_uid.to_string());
+    labels.insert(keys::key(compare::SOURCE_LABEL), compare::source_label_value(&source.namespace().unwrap_or_default(), &source.name()));
+    labels.extend(compare::recommended_labels(managed_by));
+    labels
+}
+
+/// Returns the map of a merge patch turning `target` into `source`, keys missing in `source` are
+/// set to null.
+fn map_patch<V: serde::Serialize>(source: &Option<BTreeMap<String, V>>, target: &Option<BTreeMap<String, V>>) -> BTreeMap<String, Value> {
+    let mut patch: BTreeMap<String, Value> = target
+        .iter()
+        .flat_map(|t| t.keys())
+        .map(|k| (k.clone(), Value::Null))
+        .collect();
+    for (k, v) in source.iter().flatten() {
+        patch.insert(k.clone(), json!(v));
+    }
+    patch
+}
+
+/// Returns whether the copy `existing` can't be patched to match `cm` and is to be recreated.
+fn needs_replacement(cm: &ConfigMap, existing: &ConfigMap) -> bool {
+    let immutable = |c: &ConfigMap| c.immutable == Some(true);
+    immutable(existing) != immutable(cm)
+        || (immutable(existing)
+            && (cm.data.clone().unwrap_or_default() != existing.data.clone().unwrap_or_default()
+                || cm.binary_data.clone().unwrap_or_default() != existing.binary_data.clone().unwrap_or_default()))
+}
+
+/// Makes sure the copy of `cm` in the namespace `ns` exists and matches the source. A ConfigMap
+/// with the same name not written by the operator is left alone.
+async fn sync_copy(cm: &ConfigMap, context: &Context<ContextData>, source_uid: &str, ns: &str) -> Result<(), Error> {
+    let client: Client = context.get_ref().client.clone();
+    let api: Api<ConfigMap> = Api::namespaced(client, ns);
+    let name = cm.name();
+    let labels = desired_labels(cm, source_uid, &context.get_ref().managed_by);
+
+    let existing = match api.get(&name).await {
+        Ok(v) => Some(v),
+        Err(kube::Error::Api(kube::error::ErrorResponse { code: 404, .. })) => None,
+        Err(e) => return Err(e.into()),
+    };
+
+    // Data and immutability of an immutable ConfigMap can't be patched, the copy is recreated
+    let existing = match existing {
+        Some(existing) if is_copy(&existing) && needs_replacement(cm, &existing) => {
+            if context.get_ref().dry_run {
+                info!(target_namespace = %ns, name = %name, "[dry-run] Would recreate immutable copy");
+                return Ok(());
+            }
+            match api.delete(&name, &DeleteParams::default()).await {
+                Ok(_) | Err(kube::Error::Api(kube::error::ErrorResponse { code: 404, .. })) => {}
+                Err(e) => return Err(e.into()),
+            }
+            info!(target_namespace = %ns, name = %name, "Deleted immutable copy to recreate it");
+            None
+        }
+        existing => existing,
+    };
+
+    match existing {
+        None => {
+            let copy = ConfigMap {
+                immutable: cm.immutable,
+                metadata: ObjectMeta {
+                    name: Some(name.clone()),
+                    namespace: Some(ns.to_string()),
+                    labels: Some(labels),
+                    ..Default::default()
+                },
+                data: cm.data.clone(),
+                binary_data: cm.binary_data.clone(),
+            };
+            if context.get_ref().dry_run {
+                info!(target_namespace = %ns, name = %name, "[dry-run] Would create copy");
+                return Ok(());
+            }
+            let pp: PostParams = context.get_ref().post_params();
+            api.create(&pp, &copy).await?;
+            info!(target_namespace = %ns, name = %name, "Created copy");
+        }
+        Some(existing) if is_copy(&existing) => {
+            let existing_labels = existing.metadata.labels.clone().unwrap_or_default();
+            let labels_match = labels.iter().all(|(k, v)| existing_labels.get(k) == Some(v));
+            if labels_match
+                && cm.data.clone().unwrap_or_default() == existing.data.clone().unwrap_or_default()
+                && cm.binary_data.clone().unwrap_or_default() == existing.binary_data.clone().unwrap_or_default()
+            {
+                return Ok(());
+            }
+            if context.get_ref().dry_run {
+                info!(target_namespace = %ns, name = %name, "[dry-run] Would update copy");
+                return Ok(());
+            }
+            let patch: Value = json!({
+                "metadata": {
+                    "resourceVersion": existing.metadata.resource_version,
+                    "labels": labels
+                },
+                "data": map_patch(&cm.data, &existing.data),
+                "binaryData": map_patch(&cm.binary_data, &existing.binary_data)
+            });
+            api.patch(&name, &context.get_ref().patch_params(), &Patch::Merge(&patch)).await?;
+            info!(target_namespace = %ns, name = %name, "Updated copy");
+        }
+        Some(_) => {
+            info!(target_namespace = %ns, name = %name, "Skipped, there is an unmanaged ConfigMap with the same name");
+        }
+    }
+
+    Ok(())
+}
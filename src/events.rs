@@ -0,0 +1,87 @@
+use k8s_openapi::api::core::v1::{Event, ObjectReference, Secret};
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::{ObjectMeta, Time};
+use kube::api::PostParams;
+use kube::{Api, Client, Resource};
+use tracing::{debug, warn};
+
+use crate::Error;
+
+/// Records events on source secrets, created once and shared by all reconciles. Failing to
+/// record an event is only reported, it never fails a reconcile.
+#[derive(Clone)]
+pub struct Recorder {
+    client: Client,
+    component: String,
+    dry_run: bool,
+}
+
+impl Recorder {
+    /// Constructs a new Recorder reporting events as `component`. With `dry_run` the events are
+    /// only logged.
+    pub fn new(client: Client, component: &str, dry_run: bool) -> Self {
+        Recorder {
+            client,
+            component: component.to_string(),
+            dry_run,
+        }
+    }
+
+    /// Records a Normal event on the source secret `sec`.
+    pub async fn normal(&self, sec: &Secret, reason: &str, message: &str) {
+        self.publish(sec, "Normal", reason, message).await
+    }
+
+    /// Records a Warning event on the source secret `sec`.
+    pub async fn warn(&self, sec: &Secret, reason: &str, message: &str) {
+        self.publish(sec, "Warning", reason, message).await
+    }
+
+    /// Records an event of type `type_`, `Normal` or `Warning`, on the source secret `sec`.
+    pub async fn publish(&self, sec: &Secret, type_: &str, reason: &str, message: &str) {
+        if self.dry_run {
+            debug!(reason, message, "[dry-run] Would record event");
+            return;
+        }
+        if let Err(e) = record(self.client.clone(), sec, type_, reason, message, &self.component).await {
+            warn!(reason, error = %e, "Failed to record event");
+        }
+    }
+}
+
+/// Creates an event of type `type_`, `Normal` or `Warning`, on the source secret `sec`, reported
+/// by `component`.
+pub async fn record(client: Client, sec: &Secret, type_: &str, reason: &str, message: &str, component: &str) -> Result<(), Error> {
+    let namespace = sec.namespace().unwrap_or_default();
+    let now = Time(chrono::Utc::now());
+    let event = Event {
+        metadata: ObjectMeta {
+            generate_name: Some(format!("{}.", sec.name())),
+            namespace: Some(namespace.clone()),
+            ..Default::default()
+        },
+        involved_object: ObjectReference {
+            api_version: Some("v1".to_string()),
+            kind: Some("Secret".to_string()),
+            name: Some(sec.name()),
+            namespace: Some(namespace.clone()),
+            uid: sec.metadata.uid.clone(),
+            resource_version: sec.metadata.resource_version.clone(),
+            ..Default::default()
+        },
+        reason: Some(reason.to_string()),
+        message: Some(message.to_string()),
+        type_: Some(type_.to_string()),
+        first_timestamp: Some(now.clone()),
+        last_timestamp: Some(now),
+        count: Some(1),
+        reporting_component: Some(component.to_string()),
+        source: Some(k8s_openapi::api::core::v1::EventSource {
+            component: Some(component.to_string()),
+            host: None,
+        }),
+        ..Default::default()
+    };
+    let api: Api<Event> = Api::namespaced(client, &namespace);
+    api.create(&PostParams::default(), &event).await?;
+    Ok(())
+}
@@ -0,0 +1,146 @@
+//! Exports the copies of a source as manifests, e.g. to keep them when the operator is removed.
+//!
+//! Nothing in the cluster is modified.
+
+use k8s_openapi::api::core::v1::Secret;
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::ObjectMeta;
+use kube::api::ListParams;
+use kube::{Api, Client};
+use serde_json::Value;
+
+use crate::cli::Outcome;
+use crate::{compare, keys, Error};
+
+/// Renders all copies of the source `<namespace>/<name>` given as `source` as multi document
+/// YAML and as JSON list. Fields set by the API server are dropped. With `strip_managed` the labels and
+/// annotations of the operator are dropped as well, so the copies can be applied as standalone
+/// secrets.
+pub async fn run(client: Client, source: &str, strip_managed: bool) -> Result<Outcome, Error> {
+    let (namespace, name) = source.split_once('/').ok_or_else(|| {
+        Error::UserInputError(format!("Expected source in the form <namespace>/<name>, got {}", source))
+    })?;
+
+    let source_api: Api<Secret> = Api::namespaced(client.clone(), namespace);
+    let source_uid = source_api.get(name).await?.metadata.uid.unwrap_or_default();
+
+    let secret_api: Api<Secret> = Api::all(client);
+    let lp = ListParams::default().labels(format!("{}={}", keys::owner_label(), source_uid).as_str());
+    let mut copies = secret_api.list(&lp).await?.items;
+    copies.sort_by(|a, b| (&a.metadata.namespace, &a.metadata.name).cmp(&(&b.metadata.namespace, &b.metadata.name)));
+
+    let mut documents = Vec::new();
+    let mut objects = Vec::new();
+    for copy in copies {
+        let mut labels = copy.metadata.labels.unwrap_or_default();
+        let mut annotations = copy.metadata.annotations.unwrap_or_default();
+        if strip_managed {
+            labels.remove(keys::owner_label());
+            labels.remove(&keys::key(compare::COPY_LABEL));
+            labels.remove(&keys::key(compare::SOURCE_LABEL));
+            labels.remove(compare::MANAGED_BY_LABEL);
+            annotations.remove(&keys::key(compare::SOURCE_RESOURCE_VERSION_ANNOTATION));
+            annotations.remove(&keys::key(compare::CONTENT_HASH_ANNOTATION));
+            annotations.remove(&keys::key(compare::RESYNCED_ANNOTATION));
+            annotations.remove(&keys::key(compare::OWNER_REFERENCE_ANNOTATION));
+        }
+        let exported = Secret {
+            immutable: copy.immutable,
+            metadata: ObjectMeta {
+                name: copy.metadata.name,
+                namespace: copy.metadata.namespace,
+                labels: if labels.is_empty() { None } else { Some(labels) },
+                annotations: if annotations.is_empty() { None } else { Some(annotations) },
+                ..Default::default()
+            },
+            type_: copy.type_,
+            data: copy.data,
+            string_data: None,
+        };
+        let document = serde_yaml::to_string(&exported)
+            .map_err(|e| Error::UserInputError(format!("Can't render copy as YAML: {}", e)))?;
+        documents.push(document);
+        objects.push(serde_json::to_value(&exported).map_err(|e| Error::UserInputError(format!("Can't render copy as JSON: {}", e)))?);
+    }
+    Ok(Outcome::passed(documents.concat(), Value::Array(objects)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+    use k8s_openapi::api::core::v1::Namespace;
+    use k8s_openapi::ByteString;
+    use kube_runtime::controller::Context;
+    use serde::Deserialize;
+
+    use crate::fake_api::FakeApi;
+    use crate::opts::Opts;
+    use crate::{targets, ContextData};
+
+    /// Spreads `source/db` to `a` and `b` and returns the documents of its export.
+    async fn export(strip_managed: bool) -> Vec<Secret> {
+        let (client, fake) = FakeApi::start();
+        for ns in &["source", "a", "b"] {
+            fake.insert(&Namespace {
+                metadata: ObjectMeta {
+                    name: Some(ns.to_string()),
+                    ..ObjectMeta::default()
+                },
+                ..Namespace::default()
+            });
+        }
+        let sec = Secret {
+            metadata: ObjectMeta {
+                name: Some("db".to_string()),
+                namespace: Some("source".to_string()),
+                labels: Some(std::iter::once(("team".to_string(), "db".to_string())).collect()),
+                annotations: Some(std::iter::once((keys::key(targets::TARGET_NAMESPACE_ANNOTATION), "b,a".to_string())).collect()),
+                ..ObjectMeta::default()
+            },
+            data: Some(std::iter::once(("password".to_string(), ByteString(b"secret".to_vec()))).collect()),
+            ..Secret::default()
+        };
+        let sec: Secret = serde_json::from_value(fake.insert(&sec)).unwrap();
+        let context = Context::new(ContextData::new(client.clone(), &Opts::parse_from(["spreading-operator"])));
+        crate::reconcile(sec, context).await.unwrap();
+
+        let outcome = run(client, "source/db", strip_managed).await.unwrap();
+        let documents: Vec<Secret> = serde_yaml::Deserializer::from_str(&outcome.text).map(|document| Secret::deserialize(document).unwrap()).collect();
+        // `--output json` lists the same copies
+        assert_eq!(serde_json::from_value::<Vec<Secret>>(outcome.json).unwrap(), documents);
+        documents
+    }
+
+    #[tokio::test]
+    async fn export_holds_a_document_per_copy() {
+        let documents = export(false).await;
+        let names: Vec<(Option<String>, Option<String>)> = documents.iter().map(|d| (d.metadata.namespace.clone(), d.metadata.name.clone())).collect();
+        assert_eq!(names, vec![(Some("a".to_string()), Some("db".to_string())), (Some("b".to_string()), Some("db".to_string()))]);
+        for document in documents {
+            assert_eq!(document.data.unwrap()["password"], ByteString(b"secret".to_vec()));
+            assert!(document.metadata.uid.is_none() && document.metadata.resource_version.is_none() && document.metadata.creation_timestamp.is_none());
+            let labels = document.metadata.labels.unwrap();
+            assert_eq!(labels["team"], "db");
+            assert!(labels.contains_key(keys::owner_label()));
+            assert!(document.metadata.annotations.unwrap().contains_key(&keys::key(compare::CONTENT_HASH_ANNOTATION)));
+        }
+    }
+
+    #[tokio::test]
+    async fn export_strips_the_labels_and_annotations_of_the_operator() {
+        let documents = export(true).await;
+        assert_eq!(documents.len(), 2);
+        for document in documents {
+            assert_eq!(document.data.unwrap()["password"], ByteString(b"secret".to_vec()));
+            assert_eq!(document.metadata.labels, Some(std::iter::once(("team".to_string(), "db".to_string())).collect()));
+            assert_eq!(document.metadata.annotations, None);
+        }
+    }
+
+    #[tokio::test]
+    async fn export_needs_a_namespaced_source() {
+        let (client, _fake) = FakeApi::start();
+        assert!(matches!(run(client.clone(), "db", false).await, Err(Error::UserInputError(_))));
+        assert!(run(client, "source/missing", false).await.is_err());
+    }
+}
@@ -0,0 +1,391 @@
+//! In-memory Kubernetes API server for the tests, answering the requests of a [`Client`] sent
+//! through a `tower_test::mock` service.
+//!
+//! Objects are kept as JSON by API path. Reads, lists with label selectors and pages, creates,
+//! server-side applies, merge patches and deletes are supported, which is what the reconciles
+//! use. Deleting an object with finalizers only marks it deleted, it is gone once the last
+//! finalizer is removed. Watches are not supported, the tests call the reconcile functions
+//! directly. Requests can be made to fail with [`FakeApi::fail`].
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+use hyper::{Body, Method, Request, Response, StatusCode};
+use kube::Client;
+use serde::Serialize;
+use serde_json::{json, Value};
+
+/// Handle on the objects of the fake API server, shared with the task answering the requests.
+#[derive(Clone, Default)]
+pub struct FakeApi {
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Default)]
+struct State {
+    /// Objects by the path they are read from, e.g. `/api/v1/namespaces/a/secrets/b`.
+    objects: BTreeMap<String, Value>,
+    /// Counter of the resourceVersions, uids and generated names handed out.
+    version: u64,
+    /// Method and path of every request that changed an object.
+    writes: Vec<(Method, String)>,
+    /// Path and query of every read.
+    reads: Vec<String>,
+    /// Status codes requests are answered with instead, by method and path.
+    failures: BTreeMap<(String, String), StatusCode>,
+}
+
+/// Path of a request, split into its parts.
+struct Route {
+    /// `/api/v1` or `/apis/<group>/<version>`.
+    api: String,
+    plural: String,
+    namespace: Option<String>,
+    name: Option<String>,
+}
+
+impl Route {
+    fn parse(path: &str) -> Option<Self> {
+        let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+        let (api, rest) = match segments.as_slice() {
+            ["api", version, rest @ ..] => (format!("/api/{}", version), rest),
+            ["apis", group, version, rest @ ..] => (format!("/apis/{}/{}", group, version), rest),
+            _ => return None,
+        };
+        let (plural, namespace, name) = match rest {
+            ["namespaces"] => ("namespaces", None, None),
+            ["namespaces", name] => ("namespaces", None, Some(*name)),
+            ["namespaces", namespace, plural] => (*plural, Some(*namespace), None),
+            // a subresource like `status` is served from the object itself
+            ["namespaces", namespace, plural, name, ..] => (*plural, Some(*namespace), Some(*name)),
+            [plural] => (*plural, None, None),
+            [plural, name, ..] => (*plural, None, Some(*name)),
+            _ => return None,
+        };
+        Some(Route {
+            api,
+            plural: plural.to_string(),
+            namespace: namespace.map(str::to_string),
+            name: name.map(str::to_string),
+        })
+    }
+
+    /// Path of the object named `name` in the collection of the route.
+    fn object_path(&self, name: &str) -> String {
+        match &self.namespace {
+            Some(namespace) => format!("{}/namespaces/{}/{}/{}", self.api, namespace, self.plural, name),
+            None => format!("{}/{}/{}", self.api, self.plural, name),
+        }
+    }
+
+    /// Prefix of the paths of the objects listed by the route, across all namespaces if it names
+    /// none.
+    fn matches(&self, path: &str) -> bool {
+        match &self.namespace {
+            Some(namespace) => path.starts_with(&format!("{}/namespaces/{}/{}/", self.api, namespace, self.plural)),
+            None if self.plural == "namespaces" => path.starts_with(&format!("{}/namespaces/", self.api)) && path.matches('/').count() == 4,
+            None => {
+                path.starts_with(&format!("{}/{}/", self.api, self.plural))
+                    || (path.starts_with(&format!("{}/namespaces/", self.api)) && path.split('/').rev().nth(1) == Some(self.plural.as_str()))
+            }
+        }
+    }
+}
+
+impl FakeApi {
+    /// Starts answering the requests of the returned client. Has to be called on a Tokio runtime.
+    pub fn start() -> (Client, FakeApi) {
+        let (service, mut handle) = tower_test::mock::pair::<Request<Body>, Response<Body>>();
+        let fake = FakeApi::default();
+        let server = fake.clone();
+        tokio::spawn(async move {
+            while let Some((request, send)) = handle.next_request().await {
+                send.send_response(server.serve(request).await);
+            }
+        });
+        (Client::new(kube::Service::new(service)), fake)
+    }
+
+    /// Stores `obj` as if it was created, e.g. a Secret or a Namespace.
+    pub fn insert<K: k8s_openapi::Resource + Serialize>(&self, obj: &K) -> Value {
+        let value = serde_json::to_value(obj).unwrap();
+        let name = value["metadata"]["name"].as_str().unwrap();
+        let path = match value["metadata"]["namespace"].as_str() {
+            Some(namespace) => object_path::<K>(namespace, name),
+            None => format!("/{}/{}s/{}", api_path::<K>(), K::KIND.to_ascii_lowercase(), name),
+        };
+        let mut state = self.state.lock().unwrap();
+        let stored = state.create(path, value);
+        state.writes.clear();
+        state.reads.clear();
+        stored
+    }
+
+    /// Returns the objects of type `K`, in all namespaces.
+    pub fn list<K: k8s_openapi::Resource + serde::de::DeserializeOwned>(&self) -> Vec<K> {
+        let plural = format!("/{}s/", K::KIND.to_ascii_lowercase());
+        self.state
+            .lock()
+            .unwrap()
+            .objects
+            .iter()
+            .filter(|(path, _)| path.contains(&plural))
+            .filter(|(_, obj)| obj["kind"] == K::KIND)
+            .map(|(_, obj)| serde_json::from_value(obj.clone()).unwrap())
+            .collect()
+    }
+
+    /// Returns the object of type `K` named `name` in `namespace`, if any.
+    pub fn get<K: k8s_openapi::Resource + serde::de::DeserializeOwned>(&self, namespace: &str, name: &str) -> Option<K> {
+        self.state.lock().unwrap().objects.get(&object_path::<K>(namespace, name)).map(|obj| serde_json::from_value(obj.clone()).unwrap())
+    }
+
+    /// Returns the method and path of the requests that changed objects since the last call, or
+    /// since the objects were inserted.
+    pub fn take_writes(&self) -> Vec<(Method, String)> {
+        std::mem::take(&mut self.state.lock().unwrap().writes)
+    }
+
+    /// Returns the path and query of the reads since the last call, or since the objects were
+    /// inserted.
+    pub fn take_reads(&self) -> Vec<String> {
+        std::mem::take(&mut self.state.lock().unwrap().reads)
+    }
+
+    /// Answers the requests with `method` to `path`, e.g. `/api/v1/namespaces` for the list of
+    /// namespaces, with the status `code` from now on, `None` serves them again.
+    pub fn fail(&self, method: Method, path: &str, code: Option<u16>) {
+        let key = (method.to_string(), path.to_string());
+        let mut state = self.state.lock().unwrap();
+        match code {
+            Some(code) => state.failures.insert(key, StatusCode::from_u16(code).unwrap()),
+            None => state.failures.remove(&key),
+        };
+    }
+
+    async fn serve(&self, request: Request<Body>) -> Response<Body> {
+        let (parts, body) = request.into_parts();
+        let body = hyper::body::to_bytes(body).await.unwrap_or_default();
+        let query: BTreeMap<String, String> = form_urlencoded::parse(parts.uri.query().unwrap_or_default().as_bytes()).into_owned().collect();
+        let content_type = parts.headers.get(hyper::header::CONTENT_TYPE).and_then(|v| v.to_str().ok()).unwrap_or_default().to_string();
+        let route = match Route::parse(parts.uri.path()) {
+            Some(route) => route,
+            None => return status(StatusCode::NOT_FOUND, "NotFound", "unknown path"),
+        };
+        let mut state = self.state.lock().unwrap();
+        if parts.method != Method::GET {
+            state.writes.push((parts.method.clone(), parts.uri.path().to_string()));
+        } else {
+            state.reads.push(parts.uri.to_string());
+        }
+        if let Some(code) = state.failures.get(&(parts.method.to_string(), parts.uri.path().to_string())) {
+            return status(*code, code.canonical_reason().unwrap_or_default(), "failure injected by the test");
+        }
+        let body: Value = if body.is_empty() { Value::Null } else { serde_json::from_slice(&body).unwrap_or(Value::Null) };
+
+        match (parts.method, &route.name) {
+            (Method::GET, None) => {
+                let selector = query.get("labelSelector").map(String::as_str).unwrap_or_default();
+                // the continue token is the number of objects listed before
+                let offset: usize = query.get("continue").and_then(|token| token.parse().ok()).unwrap_or_default();
+                let limit: usize = query.get("limit").and_then(|limit| limit.parse().ok()).unwrap_or(usize::MAX);
+                let mut items: Vec<Value> = state
+                    .objects
+                    .iter()
+                    .filter(|(path, obj)| route.matches(path) && labels_match(obj, selector))
+                    .skip(offset)
+                    .map(|(_, obj)| obj.clone())
+                    .take(limit.saturating_add(1))
+                    .collect();
+                let continue_ = if items.len() > limit {
+                    items.pop();
+                    (offset + limit).to_string()
+                } else {
+                    String::new()
+                };
+                let kind = items.first().and_then(|item| item["kind"].as_str()).unwrap_or("Object").to_string();
+                ok(StatusCode::OK, &json!({
+                    "apiVersion": route.api.trim_start_matches("/api/").trim_start_matches("/apis/"),
+                    "kind": format!("{}List", kind),
+                    "metadata": { "resourceVersion": state.version.to_string(), "continue": continue_ },
+                    "items": items,
+                }))
+            }
+            (Method::GET, Some(name)) => match state.objects.get(&route.object_path(name)) {
+                Some(obj) => ok(StatusCode::OK, obj),
+                None => not_found(name),
+            },
+            (Method::POST, None) => {
+                let mut obj = body;
+                let name = match obj["metadata"]["name"].as_str() {
+                    Some(name) => name.to_string(),
+                    None => {
+                        let generated = format!("{}{}", obj["metadata"]["generateName"].as_str().unwrap_or_default(), state.next_version());
+                        obj["metadata"]["name"] = json!(generated);
+                        generated
+                    }
+                };
+                let path = route.object_path(&name);
+                if state.objects.contains_key(&path) {
+                    return status(StatusCode::CONFLICT, "AlreadyExists", &format!("{} already exists", name));
+                }
+                if let Some(namespace) = &route.namespace {
+                    obj["metadata"]["namespace"] = json!(namespace);
+                }
+                ok(StatusCode::CREATED, &state.create(path, obj))
+            }
+            (Method::PATCH, Some(name)) => {
+                let path = route.object_path(name);
+                let existing = state.objects.get(&path).cloned();
+                if content_type.starts_with("application/apply-patch") {
+                    // approximates server-side apply: the metadata is merged, keeping labels and
+                    // annotations of others, the rest of the object is replaced
+                    let obj = match existing {
+                        Some(mut obj) => {
+                            for (key, value) in body.as_object().cloned().unwrap_or_default() {
+                                if key == "metadata" {
+                                    merge(&mut obj["metadata"], &value);
+                                } else {
+                                    obj[key] = value;
+                                }
+                            }
+                            state.update(&path, obj)
+                        }
+                        None => state.create(path, body),
+                    };
+                    return ok(StatusCode::OK, &obj);
+                }
+                let mut obj = match existing {
+                    Some(obj) => obj,
+                    None => return not_found(name),
+                };
+                if content_type != "application/merge-patch+json" {
+                    return status(StatusCode::UNSUPPORTED_MEDIA_TYPE, "UnsupportedMediaType", &content_type);
+                }
+                if let Some(version) = body["metadata"]["resourceVersion"].as_str() {
+                    if obj["metadata"]["resourceVersion"].as_str() != Some(version) {
+                        return status(StatusCode::CONFLICT, "Conflict", "the object has been modified");
+                    }
+                }
+                merge(&mut obj, &body);
+                ok(StatusCode::OK, &state.update(&path, obj))
+            }
+            (Method::DELETE, Some(name)) => {
+                let path = route.object_path(name);
+                let mut obj = match state.objects.get(&path).cloned() {
+                    Some(obj) => obj,
+                    None => return not_found(name),
+                };
+                if obj["metadata"]["finalizers"].as_array().is_some_and(|f| !f.is_empty()) {
+                    obj["metadata"]["deletionTimestamp"] = json!(chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true));
+                    return ok(StatusCode::OK, &state.update(&path, obj));
+                }
+                state.objects.remove(&path);
+                ok(StatusCode::OK, &obj)
+            }
+            _ => status(StatusCode::METHOD_NOT_ALLOWED, "MethodNotAllowed", "not supported by the fake API server"),
+        }
+    }
+}
+
+impl State {
+    fn next_version(&mut self) -> u64 {
+        self.version += 1;
+        self.version
+    }
+
+    /// Stores the new object `obj` at `path` with a uid and a resourceVersion.
+    fn create(&mut self, path: String, mut obj: Value) -> Value {
+        let version = self.next_version();
+        let metadata = &mut obj["metadata"];
+        if metadata["uid"].is_null() {
+            metadata["uid"] = json!(format!("uid-{}", version));
+        }
+        metadata["resourceVersion"] = json!(version.to_string());
+        metadata["creationTimestamp"] = json!(chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true));
+        self.objects.insert(path, obj.clone());
+        obj
+    }
+
+    /// Replaces the object at `path` by `obj` with a new resourceVersion. An object marked
+    /// deleted is removed once it has no finalizers left.
+    fn update(&mut self, path: &str, mut obj: Value) -> Value {
+        obj["metadata"]["resourceVersion"] = json!(self.next_version().to_string());
+        let finalized = obj["metadata"]["finalizers"].as_array().is_none_or(|f| f.is_empty());
+        if !obj["metadata"]["deletionTimestamp"].is_null() && finalized {
+            self.objects.remove(path);
+            return obj;
+        }
+        self.objects.insert(path.to_string(), obj.clone());
+        obj
+    }
+}
+
+/// Path of the API group of `K`, without leading slash.
+fn api_path<K: k8s_openapi::Resource>() -> String {
+    match K::GROUP {
+        "" => format!("api/{}", K::VERSION),
+        group => format!("apis/{}/{}", group, K::VERSION),
+    }
+}
+
+/// Path of the object of type `K` named `name` in `namespace`.
+fn object_path<K: k8s_openapi::Resource>(namespace: &str, name: &str) -> String {
+    format!("/{}/namespaces/{}/{}s/{}", api_path::<K>(), namespace, K::KIND.to_ascii_lowercase(), name)
+}
+
+/// Applies the JSON merge patch `patch` to `target`, see RFC 7386.
+fn merge(target: &mut Value, patch: &Value) {
+    match patch {
+        Value::Object(entries) => {
+            if !target.is_object() {
+                *target = json!({});
+            }
+            for (key, value) in entries {
+                if value.is_null() {
+                    target.as_object_mut().unwrap().remove(key);
+                } else {
+                    merge(&mut target[key.as_str()], value);
+                }
+            }
+        }
+        patch => *target = patch.clone(),
+    }
+}
+
+/// Returns whether the labels of `obj` match the label `selector`, of terms `key`, `!key`,
+/// `key=value` and `key!=value`.
+fn labels_match(obj: &Value, selector: &str) -> bool {
+    let labels = &obj["metadata"]["labels"];
+    selector.split(',').map(str::trim).filter(|term| !term.is_empty()).all(|term| {
+        if let Some((key, value)) = term.split_once("!=") {
+            labels[key.trim()].as_str() != Some(value.trim())
+        } else if let Some((key, value)) = term.split_once('=') {
+            labels[key.trim_end_matches('=').trim()].as_str() == Some(value.trim_start_matches('=').trim())
+        } else if let Some(key) = term.strip_prefix('!') {
+            labels[key].is_null()
+        } else {
+            !labels[term].is_null()
+        }
+    })
+}
+
+fn ok(code: StatusCode, body: &Value) -> Response<Body> {
+    Response::builder().status(code).body(Body::from(body.to_string())).unwrap()
+}
+
+fn not_found(name: &str) -> Response<Body> {
+    status(StatusCode::NOT_FOUND, "NotFound", &format!("{} not found", name))
+}
+
+fn status(code: StatusCode, reason: &str, message: &str) -> Response<Body> {
+    let body = json!({
+        "apiVersion": "v1",
+        "kind": "Status",
+        "status": "Failure",
+        "reason": reason,
+        "message": message,
+        "code": code.as_u16(),
+    });
+    Response::builder().status(code).body(Body::from(body.to_string())).unwrap()
+}
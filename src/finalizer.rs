@@ -1,56 +1,207 @@
-use k8s_openapi::api::core::v1::Secret;
+use std::fmt::Debug;
+
 use kube::api::{Patch, PatchParams};
-use kube::{Api, Client, Error};
+use kube::{Api, Client, Error, Resource};
+use serde::de::DeserializeOwned;
 use serde_json::{json, Value};
+use tracing::{debug, info};
 
-const FINALIZER_NAME: &str = "secretspreading.fitzek.eu/finalizer";
+use crate::{keys, retry};
 
-pub async fn add(client: Client, name: &str, namespace: &str, sec: &Secret) -> Result<(), Error> {
-    if sec.metadata.finalizers.is_some() {
-        if sec.metadata.finalizers.clone().unwrap().iter().any(|s| s.eq_ignore_ascii_case(FINALIZER_NAME)) {
-            return Ok(());
+/// Adds the finalizer to `obj`. The patch carries the resourceVersion, so a concurrent change of
+/// the finalizers is not overwritten; on conflict the object is read anew and the patch retried.
+///
+/// Returns whether the finalizer is confirmed on the object, as answered by the API server or,
+/// if the answer lacks it, by reading the object anew. Copies must not be written without it,
+/// they couldn't be cleaned up once the source is deleted. An object recreated under the same
+/// name meanwhile is a different source, the finalizer is not confirmed then.
+pub async fn add<K>(client: Client, name: &str, namespace: &str, obj: &K, pp: &PatchParams) -> Result<bool, Error>
+where
+    K: Resource<DynamicType = ()> + Clone + DeserializeOwned + Debug,
+{
+    if pp.dry_run {
+        if !has_finalizer(obj) {
+            info!(namespace, name, "[dry-run] Would add finalizer");
+        }
+        return Ok(true);
+    }
+    let api: Api<K> = Api::namespaced(client, namespace);
+    let patched = retry::on_conflict(|attempt| {
+        let api = api.clone();
+        async move {
+            let current = if attempt == 0 { obj.clone() } else { api.get(name).await? };
+            if has_finalizer(&current) || !same_object(obj, &current) {
+                return Ok(current);
+            }
+            let mut fin: Vec<String> = current.meta().finalizers.clone().unwrap_or_default();
+            fin.push(keys::finalizer().to_string());
+            let patched = patch_finalizers(&api, name, &current, fin, pp).await?;
+            debug!(namespace, name, "Added finalizer");
+            Ok(patched)
         }
+    })
+    .await?;
+    if !same_object(obj, &patched) {
+        return Ok(false);
     }
+    if has_finalizer(&patched) {
+        return Ok(true);
+    }
+    let current = api.get(name).await?;
+    Ok(same_object(obj, &current) && has_finalizer(&current))
+}
 
-    let api: Api<Secret> = Api::namespaced(client, namespace);
-    let finalizers = sec.metadata.finalizers.clone();
-    let mut fin: Vec<String>;
-    if finalizers.is_none() {
-        fin = vec![FINALIZER_NAME.to_string()];
-    } else {
-        fin = finalizers.unwrap();
-        if fin.iter().find(|&f| f.eq_ignore_ascii_case(FINALIZER_NAME)).is_none() {
-            fin.push(FINALIZER_NAME.to_string());
+/// Removes the finalizer from `obj`, retrying on conflict like [`add`]. The object is identified
+/// by its uid: if it is gone and another one was created under the same name, the finalizer of
+/// the new one is left alone.
+///
+/// Removing is idempotent, so a deleted source never stays `Terminating` because of the
+/// finalizer: an object without the finalizer is not patched, and an object that is gone, 404
+/// on reading or patching it, counts as done.
+pub async fn rm<K>(client: Client, name: &str, namespace: &str, obj: &K, pp: &PatchParams) -> Result<(), Error>
+where
+    K: Resource<DynamicType = ()> + Clone + DeserializeOwned + Debug,
+{
+    if pp.dry_run {
+        if has_finalizer(obj) {
+            info!(namespace, name, "[dry-run] Would remove finalizer");
+        }
+        return Ok(());
+    }
+    let api: Api<K> = Api::namespaced(client, namespace);
+    let removed = retry::on_conflict(|attempt| {
+        let api = api.clone();
+        async move {
+            let current = if attempt == 0 { obj.clone() } else { api.get(name).await? };
+            if !same_object(obj, &current) {
+                debug!(namespace, name, "Recreated under the same name, leaving its finalizer alone");
+                return Ok(());
+            }
+            if !has_finalizer(&current) {
+                return Ok(());
+            }
+            let fin: Vec<String> = current.meta().finalizers.iter().flatten().filter(|&f| !f.eq_ignore_ascii_case(keys::finalizer())).cloned().collect();
+            patch_finalizers(&api, name, &current, fin, pp).await?;
+            debug!(namespace, name, "Removed finalizer");
+            Ok(())
+        }
+    })
+    .await;
+    match removed {
+        Err(Error::Api(kube::error::ErrorResponse { code: 404, .. })) => {
+            debug!(namespace, name, "Gone already, no finalizer to remove");
+            Ok(())
         }
+        removed => removed,
     }
+}
+
+/// Returns whether `obj` carries the finalizer of the operator.
+pub fn has_finalizer<K: Resource>(obj: &K) -> bool {
+    obj.meta()
+        .finalizers
+        .as_ref()
+        .is_some_and(|f| f.iter().any(|s| s.eq_ignore_ascii_case(keys::finalizer())))
+}
 
+/// Returns whether `a` and `b` are the same object, not just named the same.
+fn same_object<K: Resource>(a: &K, b: &K) -> bool {
+    a.meta().uid == b.meta().uid
+}
+
+/// Replaces the finalizers of `current` by `fin`. Returns the object as patched by the API server.
+async fn patch_finalizers<K>(api: &Api<K>, name: &str, current: &K, fin: Vec<String>, pp: &PatchParams) -> Result<K, Error>
+where
+    K: Resource<DynamicType = ()> + Clone + DeserializeOwned + Debug,
+{
     let finalizer: Value = json!({
         "metadata": {
+            "resourceVersion": current.meta().resource_version,
             "finalizers": fin
         }
     });
 
     let patch: Patch<&Value> = Patch::Merge(&finalizer);
-    api.patch(name, &PatchParams::default(), &patch).await?;
-    Ok(())
+    api.patch(name, pp, &patch).await
 }
 
-pub async fn rm(client: Client, name: &str, namespace: &str, sec: &Secret) -> Result<(), Error> {
-    let api: Api<Secret> = Api::namespaced(client, namespace);
-    let finalizers = sec.metadata.finalizers.clone();
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use k8s_openapi::api::core::v1::Secret;
+    use kube::api::{DeleteParams, ObjectMeta};
 
-    if finalizers.is_some() {
-        let fin:Vec<String> = finalizers.unwrap().iter().filter(|&f| !f.eq_ignore_ascii_case(FINALIZER_NAME)).cloned().collect();
+    use crate::fake_api::FakeApi;
 
-        let finalizer: Value = json!({
-            "metadata": {
-                "finalizers": fin
-            }
-        });
+    fn secret(finalizers: &[&str]) -> Secret {
+        Secret {
+            metadata: ObjectMeta {
+                name: Some("db".to_string()),
+                namespace: Some("source".to_string()),
+                finalizers: Some(finalizers.iter().map(|f| f.to_string()).collect()),
+                ..ObjectMeta::default()
+            },
+            ..Secret::default()
+        }
+    }
+
+    /// Sets the finalizers of `source/db` like another actor would, without resourceVersion.
+    async fn set_finalizers(client: &Client, finalizers: &[&str]) {
+        let patch = json!({ "metadata": { "finalizers": finalizers } });
+        Api::<Secret>::namespaced(client.clone(), "source").patch("db", &PatchParams::default(), &Patch::Merge(&patch)).await.unwrap();
+    }
 
-        let patch: Patch<&Value> = Patch::Merge(&finalizer);
-        api.patch(name, &PatchParams::default(), &patch).await?;
+    #[tokio::test]
+    async fn finalizer_removed_concurrently_is_not_removed_again() {
+        let (client, fake) = FakeApi::start();
+        let sec: Secret = serde_json::from_value(fake.insert(&secret(&[keys::finalizer(), "example.com/keep"]))).unwrap();
+        set_finalizers(&client, &["example.com/keep"]).await;
+        // the patch based on the outdated object conflicts, the current one has nothing to remove
+        rm(client, "db", "source", &sec, &PatchParams::default()).await.unwrap();
+        let current: Secret = fake.get("source", "db").unwrap();
+        assert_eq!(current.metadata.finalizers, Some(vec!["example.com/keep".to_string()]));
     }
 
-    Ok(())
+    #[tokio::test]
+    async fn source_finalized_concurrently_is_done() {
+        let (client, fake) = FakeApi::start();
+        fake.insert(&secret(&[keys::finalizer()]));
+        Api::<Secret>::namespaced(client.clone(), "source").delete("db", &DeleteParams::default()).await.unwrap();
+        let deleted: Secret = fake.get("source", "db").unwrap();
+        assert!(deleted.metadata.deletion_timestamp.is_some());
+        // somebody else removes the finalizer, the source is gone
+        set_finalizers(&client, &[]).await;
+        assert!(fake.get::<Secret>("source", "db").is_none());
+        rm(client, "db", "source", &deleted, &PatchParams::default()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn finalizer_of_a_recreated_source_is_left_alone() {
+        let (client, fake) = FakeApi::start();
+        let sec: Secret = serde_json::from_value(fake.insert(&secret(&[keys::finalizer()]))).unwrap();
+        set_finalizers(&client, &[]).await;
+        Api::<Secret>::namespaced(client.clone(), "source").delete("db", &DeleteParams::default()).await.unwrap();
+        fake.insert(&secret(&[keys::finalizer()]));
+        rm(client.clone(), "db", "source", &sec, &PatchParams::default()).await.unwrap();
+        assert!(has_finalizer(&fake.get::<Secret>("source", "db").unwrap()));
+        // and not confirmed on behalf of the former one
+        let mut former = sec;
+        former.metadata.finalizers = None;
+        assert!(!add(client, "db", "source", &former, &PatchParams::default()).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn not_found_during_removal_is_done() {
+        let (client, fake) = FakeApi::start();
+        let sec: Secret = serde_json::from_value(fake.insert(&secret(&[keys::finalizer()]))).unwrap();
+
+        // the patch finds the source gone
+        fake.fail(hyper::Method::PATCH, "/api/v1/namespaces/source/secrets/db", Some(404));
+        rm(client.clone(), "db", "source", &sec, &PatchParams::default()).await.unwrap();
+
+        // the patch conflicts and reading the source anew finds it gone
+        fake.fail(hyper::Method::PATCH, "/api/v1/namespaces/source/secrets/db", Some(409));
+        fake.fail(hyper::Method::GET, "/api/v1/namespaces/source/secrets/db", Some(404));
+        rm(client, "db", "source", &sec, &PatchParams::default()).await.unwrap();
+    }
 }
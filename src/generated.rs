@@ -0,0 +1,69 @@
+use std::collections::BTreeMap;
+
+use k8s_openapi::api::core::v1::Secret;
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::ObjectMeta;
+use kube::api::{DeleteParams, Patch, PatchParams};
+use kube::{Api, Client};
+use serde_json::{json, Value};
+use tracing::info;
+
+use crate::{keys, targets, Error};
+
+/// Opt-in to create copies with `generateName` instead of the source name.
+pub const USE_GENERATE_NAME_ANNOTATION: &str = "eu.fitzek.spread.use-generate-name";
+/// JSON object mapping target namespaces to the name the API server generated for the copy.
+/// Maintained by the operator on the source.
+pub const GENERATED_NAMES_ANNOTATION: &str = "eu.fitzek.spread.generated-names";
+
+/// Returns true if copies of the source are created with `generateName`.
+pub fn enabled(meta: &ObjectMeta) -> bool {
+    targets::annotation(meta, USE_GENERATE_NAME_ANNOTATION).as_deref() == Some("true")
+}
+
+/// Reads the generated copy names recorded on the source.
+pub fn recorded_names(meta: &ObjectMeta) -> Result<BTreeMap<String, String>, Error> {
+    match targets::annotation(meta, GENERATED_NAMES_ANNOTATION) {
+        Some(v) => serde_json::from_str(&v).map_err(|e| {
+            Error::UserInputError(format!("Invalid {} annotation: {}", GENERATED_NAMES_ANNOTATION, e))
+        }),
+        None => Ok(BTreeMap::new()),
+    }
+}
+
+/// Stores the generated copy names on the source secret. Only metadata is patched, which is
+/// allowed on immutable sources as well.
+pub async fn record_names(client: Client, name: &str, namespace: &str, names: &BTreeMap<String, String>, pp: &PatchParams) -> Result<(), Error> {
+    let api: Api<Secret> = Api::namespaced(client, namespace);
+    let value = serde_json::to_string(names).expect("a string map is always serializable");
+    let patch: Value = json!({
+        "metadata": {
+            "annotations": {
+                keys::key(GENERATED_NAMES_ANNOTATION): value
+            }
+        }
+    });
+    api.patch(name, pp, &Patch::Merge(&patch)).await?;
+    Ok(())
+}
+
+/// Deletes the copies recorded on the source. Copies already gone are ignored.
+///
+/// The names were generated by the API server when the operator created the copies, so they
+/// can not belong to anybody else. Returns the namespaces and names of the deleted copies, none
+/// with a dry run `dp`.
+pub async fn delete_recorded(client: Client, names: &BTreeMap<String, String>, dp: &DeleteParams) -> Result<Vec<(String, String)>, Error> {
+    let mut deleted = Vec::new();
+    for (ns, name) in names {
+        if dp.dry_run {
+            info!(target_namespace = %ns, name = %name, "[dry-run] Would delete copy");
+            continue;
+        }
+        let api: Api<Secret> = Api::namespaced(client.clone(), ns);
+        match api.delete(name, dp).await {
+            Ok(_) => deleted.push((ns.clone(), name.clone())),
+            Err(kube::Error::Api(kube::error::ErrorResponse { code: 404, .. })) => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(deleted)
+}
@@ -0,0 +1,122 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+
+use serde::Serialize;
+
+/// Outcome of one reconcile of a source.
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Entry {
+    /// Time the reconcile finished, RFC 3339.
+    pub timestamp: String,
+    pub created: u32,
+    pub updated: u32,
+    pub unchanged: u32,
+    pub skipped: u32,
+    /// Error the reconcile failed with.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl Entry {
+    /// Constructs a new empty Entry stamped with the current time.
+    pub fn now() -> Self {
+        Entry {
+            timestamp: chrono::Utc::now().to_rfc3339(),
+            ..Default::default()
+        }
+    }
+}
+
+/// Recent reconcile outcomes per source, kept in memory. Only the last `capacity` entries of
+/// each source are kept, older ones are dropped.
+pub struct History {
+    capacity: usize,
+    entries: Mutex<HashMap<String, VecDeque<Entry>>>,
+}
+
+impl History {
+    /// Constructs a new History keeping `capacity` entries per source.
+    pub fn new(capacity: usize) -> Self {
+        History {
+            capacity,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Appends `entry` to the history of the source `<namespace>/<name>` given as `source`.
+    pub fn record(&self, source: &str, entry: Entry) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        let ring = entries.entry(source.to_string()).or_default();
+        if ring.len() == self.capacity {
+            ring.pop_front();
+        }
+        ring.push_back(entry);
+    }
+
+    /// Returns up to `limit` of the most recent entries of `source`, newest first.
+    pub fn recent(&self, source: &str, limit: usize) -> Vec<Entry> {
+        let entries = self.entries.lock().unwrap();
+        match entries.get(source) {
+            Some(ring) => ring.iter().rev().take(limit).cloned().collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Drops the history of `source`.
+    pub fn forget(&self, source: &str) {
+        self.entries.lock().unwrap().remove(source);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(created: u32) -> Entry {
+        Entry {
+            created,
+            ..Entry::now()
+        }
+    }
+
+    fn created(entries: &[Entry]) -> Vec<u32> {
+        entries.iter().map(|e| e.created).collect()
+    }
+
+    #[test]
+    fn ring_keeps_the_most_recent_entries() {
+        let history = History::new(3);
+        for i in 1..=5 {
+            history.record("source/db", entry(i));
+        }
+        assert_eq!(created(&history.recent("source/db", 10)), vec![5, 4, 3]);
+        assert_eq!(created(&history.recent("source/db", 2)), vec![5, 4]);
+        assert!(history.recent("source/db", 0).is_empty());
+        assert!(history.recent("source/other", 10).is_empty());
+    }
+
+    #[test]
+    fn sources_have_their_own_ring() {
+        let history = History::new(2);
+        history.record("source/db", entry(1));
+        history.record("source/other", entry(2));
+        history.record("source/other", entry(3));
+        history.record("source/other", entry(4));
+        assert_eq!(created(&history.recent("source/db", 10)), vec![1]);
+        assert_eq!(created(&history.recent("source/other", 10)), vec![4, 3]);
+        history.forget("source/other");
+        assert!(history.recent("source/other", 10).is_empty());
+        assert_eq!(created(&history.recent("source/db", 10)), vec![1]);
+    }
+
+    #[test]
+    fn capacity_zero_keeps_nothing() {
+        let history = History::new(0);
+        history.record("source/db", entry(1));
+        assert!(history.recent("source/db", 10).is_empty());
+    }
+}
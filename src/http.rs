@@ -0,0 +1,159 @@
+//! Small HTTP server for introspecting the operator.
+//!
+//! `GET /history?source=<namespace>/<name>&limit=<n>` returns the most recent reconcile outcomes
+//! of a source as a JSON array, newest first. `limit` defaults to 50.
+//!
+//! `GET /metrics` returns the metrics in the Prometheus text format.
+//!
+//! `GET /healthz` answers 200 as long as the runtime serves requests. `GET /readyz` answers 503
+//! until the operator is ready, see [`set_ready`], and 200 afterwards. With
+//! `--readiness-staleness` it answers 503 again while the Secret controller had no heartbeat for
+//! longer than that, see [`metrics::heartbeat`], which usually means its watch died silently.
+
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use tracing::{error, warn};
+
+use crate::history::History;
+use crate::metrics;
+
+/// Default number of history entries returned.
+const DEFAULT_LIMIT: usize = 50;
+
+/// Whether the operator is ready, reported on `/readyz`.
+static READY: AtomicBool = AtomicBool::new(false);
+
+/// Marks the operator as ready.
+pub fn set_ready() {
+    READY.store(true, Ordering::Relaxed);
+}
+
+/// Runs the server on `addr`, see `--http-addr`. An empty `addr` disables it. `/readyz` answers
+/// 503 after `readiness_staleness` seconds without heartbeat, see `--readiness-staleness`.
+pub async fn run(addr: &str, history: Arc<History>, readiness_staleness: u64) {
+    if addr.is_empty() {
+        return;
+    }
+    let addr: SocketAddr = match addr.parse() {
+        Ok(a) => a,
+        Err(e) => {
+            warn!(addr = %addr, error = %e, "HTTP server disabled, invalid address");
+            return;
+        }
+    };
+
+    let make_service = make_service_fn(move |_| {
+        let history = history.clone();
+        async move { Ok::<_, Infallible>(service_fn(move |req| handle(req, history.clone(), readiness_staleness))) }
+    });
+
+    if let Err(e) = Server::bind(&addr).serve(make_service).await {
+        error!(error = %e, "HTTP server failed");
+    }
+}
+
+async fn handle(req: Request<Body>, history: Arc<History>, readiness_staleness: u64) -> Result<Response<Body>, Infallible> {
+    if req.method() == Method::GET {
+        match req.uri().path() {
+            "/metrics" => return Ok(respond(StatusCode::OK, metrics::render())),
+            "/healthz" => return Ok(respond(StatusCode::OK, "ok\n".to_string())),
+            "/readyz" if !READY.load(Ordering::Relaxed) => return Ok(respond(StatusCode::SERVICE_UNAVAILABLE, "not ready\n".to_string())),
+            "/readyz" => return Ok(readiness(readiness_staleness)),
+            _ => {}
+        }
+    }
+    if req.method() != Method::GET || req.uri().path() != "/history" {
+        return Ok(respond(StatusCode::NOT_FOUND, "not found\n".to_string()));
+    }
+
+    let mut source = None;
+    let mut limit = DEFAULT_LIMIT;
+    for (key, value) in form_urlencoded::parse(req.uri().query().unwrap_or("").as_bytes()) {
+        match key.as_ref() {
+            "source" => source = Some(value.into_owned()),
+            "limit" => match value.parse() {
+                Ok(l) => limit = l,
+                Err(_) => return Ok(respond(StatusCode::BAD_REQUEST, format!("invalid limit {}\n", value))),
+            },
+            _ => {}
+        }
+    }
+    let source = match source {
+        Some(s) => s,
+        None => return Ok(respond(StatusCode::BAD_REQUEST, "missing source=<namespace>/<name>\n".to_string())),
+    };
+
+    let body = serde_json::to_string(&history.recent(&source, limit)).expect("history entries are always serializable");
+    Ok(respond(StatusCode::OK, body))
+}
+
+/// Answers `/readyz` of a ready operator, unless its Secret controller is stale for more than
+/// `staleness` seconds.
+fn readiness(staleness: u64) -> Response<Body> {
+    match metrics::since_heartbeat() {
+        Some(since) if staleness > 0 && since.as_secs() > staleness => {
+            respond(StatusCode::SERVICE_UNAVAILABLE, format!("stale, no heartbeat for {}s\n", since.as_secs()))
+        }
+        _ => respond(StatusCode::OK, "ok\n".to_string()),
+    }
+}
+
+fn respond(status: StatusCode, body: String) -> Response<Body> {
+    let mut response = Response::new(Body::from(body));
+    *response.status_mut() = status;
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::history::Entry;
+
+    async fn get(uri: &str, history: &Arc<History>) -> (StatusCode, String) {
+        let req = Request::get(uri).body(Body::empty()).unwrap();
+        let response = handle(req, history.clone(), 0).await.unwrap();
+        let status = response.status();
+        (status, String::from_utf8(hyper::body::to_bytes(response.into_body()).await.unwrap().to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn history_is_queried_by_source() {
+        let history = Arc::new(History::new(50));
+        for created in 1..=3 {
+            history.record("source/db", Entry {
+                timestamp: format!("2026-10-15T10:00:0{}Z", created),
+                created,
+                ..Entry::default()
+            });
+        }
+        history.record("source/db", Entry {
+            timestamp: "2026-10-15T10:00:04Z".to_string(),
+            error: Some("namespace a: forbidden".to_string()),
+            ..Entry::default()
+        });
+
+        let (status, body) = get("/history?source=source%2Fdb&limit=2", &history).await;
+        assert_eq!(status, StatusCode::OK);
+        let entries: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(entries, serde_json::json!([
+            {"timestamp": "2026-10-15T10:00:04Z", "created": 0, "updated": 0, "unchanged": 0, "skipped": 0, "error": "namespace a: forbidden"},
+            {"timestamp": "2026-10-15T10:00:03Z", "created": 3, "updated": 0, "unchanged": 0, "skipped": 0},
+        ]));
+        let (_, body) = get("/history?source=source/db", &history).await;
+        assert_eq!(serde_json::from_str::<Vec<serde_json::Value>>(&body).unwrap().len(), 4);
+        assert_eq!(get("/history?source=source/unknown", &history).await, (StatusCode::OK, "[]".to_string()));
+    }
+
+    #[tokio::test]
+    async fn invalid_history_queries_are_rejected() {
+        let history = Arc::new(History::new(50));
+        assert_eq!(get("/history", &history).await.0, StatusCode::BAD_REQUEST);
+        assert_eq!(get("/history?source=source/db&limit=many", &history).await.0, StatusCode::BAD_REQUEST);
+        assert_eq!(get("/other", &history).await.0, StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,234 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::Mutex;
+
+use k8s_openapi::api::core::v1::{Namespace, Secret};
+use kube_runtime::reflector::ObjectRef;
+use tokio::time::{sleep, Duration, Instant};
+
+use crate::spread::SecretSpread;
+use crate::targets;
+
+/// Namespace and name of an object.
+type Key = (String, String);
+
+/// Remembers which sources read their target namespaces from which ConfigMap, so a change of
+/// the ConfigMap can trigger a reconcile of exactly those sources.
+///
+/// The index is filled by the reconciles, a source is known once it was reconciled.
+#[derive(Default)]
+pub struct ConfigMapIndex {
+    by_configmap: Mutex<HashMap<Key, HashSet<Key>>>,
+}
+
+impl ConfigMapIndex {
+    /// Records that the source `source` references the ConfigMap `configmap`, or none. A
+    /// previously recorded reference of the source is replaced.
+    pub fn update(&self, source: Key, configmap: Option<Key>) {
+        let mut by_configmap = self.by_configmap.lock().unwrap();
+        for sources in by_configmap.values_mut() {
+            sources.remove(&source);
+        }
+        by_configmap.retain(|_, sources| !sources.is_empty());
+        if let Some(configmap) = configmap {
+            by_configmap.entry(configmap).or_default().insert(source);
+        }
+    }
+
+    /// Returns the sources referencing the ConfigMap `namespace`/`name`.
+    pub fn sources_for(&self, namespace: &str, name: &str) -> Vec<ObjectRef<Secret>> {
+        let by_configmap = self.by_configmap.lock().unwrap();
+        match by_configmap.get(&(namespace.to_string(), name.to_string())) {
+            Some(sources) => sources
+                .iter()
+                .map(|(ns, name)| ObjectRef::new(name).within(ns))
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+}
+
+/// Remembers which `SecretSpread`s name which Secret, so a change of the Secret triggers a
+/// reconcile of exactly those.
+///
+/// The index is filled by the reconciles, a `SecretSpread` is known once it was reconciled.
+#[derive(Default)]
+pub struct SpreadIndex {
+    by_secret: Mutex<HashMap<Key, HashSet<Key>>>,
+}
+
+impl SpreadIndex {
+    /// Records that the `SecretSpread` `spread` names the Secret `secret_name` in its namespace,
+    /// or none. A previously recorded Secret of the `SecretSpread` is replaced.
+    pub fn update(&self, spread: Key, secret_name: Option<String>) {
+        let mut by_secret = self.by_secret.lock().unwrap();
+        for spreads in by_secret.values_mut() {
+            spreads.remove(&spread);
+        }
+        by_secret.retain(|_, spreads| !spreads.is_empty());
+        if let Some(secret_name) = secret_name {
+            by_secret.entry((spread.0.clone(), secret_name)).or_default().insert(spread);
+        }
+    }
+
+    /// Returns the `SecretSpread`s naming the Secret `namespace`/`name`.
+    pub fn spreads_for(&self, namespace: &str, name: &str) -> Vec<ObjectRef<SecretSpread>> {
+        let by_secret = self.by_secret.lock().unwrap();
+        match by_secret.get(&(namespace.to_string(), name.to_string())) {
+            Some(spreads) => spreads
+                .iter()
+                .map(|(ns, name)| ObjectRef::new(name).within(ns))
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+}
+
+/// Age up to which a namespace counts as new when its first event arrives. Namespaces already
+/// there when the operator starts are older, their sources are reconciled on start anyway.
+const NEW_NAMESPACE_AGE: i64 = 60;
+
+/// Time without further namespace creation after which a burst of new namespaces is over.
+const NAMESPACE_DEBOUNCE: Duration = Duration::from_secs(2);
+
+/// Remembers which sources expand to the namespaces of the cluster, e.g. by `*`, so a namespace
+/// created later triggers a reconcile of exactly those sources instead of waiting for their
+/// requeue. Likewise a namespace relabeled into or out of the label selectors of a source
+/// triggers a reconcile of the source, which adds or prunes the copy there.
+///
+/// The index is filled by the reconciles, a source is known once it was reconciled.
+#[derive(Default)]
+pub struct NamespaceIndex {
+    sources: Mutex<HashSet<Key>>,
+    /// Namespace label selectors of the sources selecting by labels, see
+    /// [`targets::namespace_selectors`].
+    selectors: Mutex<HashMap<Key, Vec<String>>>,
+    /// Labels of the namespaces by name, as of their last event.
+    labels: Mutex<HashMap<String, BTreeMap<String, String>>>,
+    /// Uids of the new namespaces already triggered on, with the time they were seen.
+    seen: Mutex<HashMap<String, Instant>>,
+    /// Time the last new namespace was seen.
+    last_created: Mutex<Option<Instant>>,
+}
+
+impl NamespaceIndex {
+    /// Records whether the source `source` expands to the namespaces of the cluster, and the
+    /// label selectors it selects namespaces by.
+    pub fn update(&self, source: Key, expands: bool, selectors: Vec<String>) {
+        let mut sources = self.sources.lock().unwrap();
+        let mut by_source = self.selectors.lock().unwrap();
+        if expands {
+            sources.insert(source.clone());
+        } else {
+            sources.remove(&source);
+        }
+        if selectors.is_empty() {
+            by_source.remove(&source);
+        } else {
+            by_source.insert(source, selectors);
+        }
+    }
+
+    /// Returns the sources to reconcile for an event of the namespace `ns`: the expanding ones
+    /// on the first event of a new namespace, and the ones a selector of which matches the
+    /// namespace differently after a label change. None otherwise.
+    pub fn sources_for(&self, ns: &Namespace) -> Vec<ObjectRef<Secret>> {
+        let mut sources = self.relabeled_sources(ns);
+        if self.first_seen_new(ns) {
+            sources.extend(self.sources.lock().unwrap().iter().cloned());
+        }
+        sources.into_iter().map(|(ns, name)| ObjectRef::new(&name).within(&ns)).collect()
+    }
+
+    /// Returns whether this is the first event of the new namespace `ns`.
+    fn first_seen_new(&self, ns: &Namespace) -> bool {
+        let uid = match &ns.metadata.uid {
+            Some(uid) => uid.clone(),
+            None => return false,
+        };
+        let new = ns
+            .metadata
+            .creation_timestamp
+            .as_ref()
+            .is_some_and(|created| (chrono::Utc::now() - created.0).num_seconds() < NEW_NAMESPACE_AGE);
+        let mut seen = self.seen.lock().unwrap();
+        seen.retain(|_, at| at.elapsed().as_secs() < NEW_NAMESPACE_AGE as u64);
+        if !new || seen.insert(uid, Instant::now()).is_some() {
+            return false;
+        }
+        *self.last_created.lock().unwrap() = Some(Instant::now());
+        true
+    }
+
+    /// Returns the sources whose selectors match the namespace `ns` differently than before its
+    /// labels changed. The first event of a namespace has nothing to compare to, the namespaces
+    /// there on start are spread by the initial reconciles and new ones by the expanding sources.
+    /// A selector that can't be evaluated counts as changed.
+    fn relabeled_sources(&self, ns: &Namespace) -> HashSet<Key> {
+        let name = match &ns.metadata.name {
+            Some(name) => name.clone(),
+            None => return HashSet::new(),
+        };
+        let current = ns.metadata.labels.clone().unwrap_or_default();
+        let previous = match self.labels.lock().unwrap().insert(name, current.clone()) {
+            Some(previous) if previous != current => previous,
+            _ => return HashSet::new(),
+        };
+        let changed = |selector: &String| match (targets::selector_matches(selector, &previous), targets::selector_matches(selector, &current)) {
+            (Some(before), Some(after)) => before != after,
+            _ => true,
+        };
+        self.selectors
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, selectors)| selectors.iter().any(changed))
+            .map(|(source, _)| source.clone())
+            .collect()
+    }
+
+    /// Waits until no new namespace was seen for a moment, so a burst of namespace creations
+    /// is spread in one reconcile per source instead of one per namespace. The triggers of the
+    /// burst are merged by the controller while the reconcile waits.
+    pub async fn settle(&self) {
+        loop {
+            let last_created = *self.last_created.lock().unwrap();
+            match last_created.map(|at| at.elapsed()) {
+                Some(elapsed) if elapsed < NAMESPACE_DEBOUNCE => sleep(NAMESPACE_DEBOUNCE - elapsed).await,
+                _ => return,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(namespace: &str, name: &str) -> Key {
+        (namespace.to_string(), name.to_string())
+    }
+
+    fn sorted(mut refs: Vec<ObjectRef<Secret>>) -> Vec<String> {
+        let mut names: Vec<String> = refs.drain(..).map(|r| format!("{}/{}", r.namespace.unwrap_or_default(), r.name)).collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn configmap_maps_to_the_sources_referencing_it() {
+        let index = ConfigMapIndex::default();
+        index.update(key("source", "db"), Some(key("source", "targets")));
+        index.update(key("source", "api"), Some(key("source", "targets")));
+        index.update(key("other", "db"), Some(key("shared", "targets")));
+        assert_eq!(sorted(index.sources_for("source", "targets")), vec!["source/api", "source/db"]);
+        assert_eq!(sorted(index.sources_for("shared", "targets")), vec!["other/db"]);
+        assert!(index.sources_for("other", "targets").is_empty());
+
+        // the reference of a source is replaced
+        index.update(key("source", "db"), Some(key("shared", "targets")));
+        assert_eq!(sorted(index.sources_for("source", "targets")), vec!["source/api"]);
+        assert_eq!(sorted(index.sources_for("shared", "targets")), vec!["other/db", "source/db"]);
+        index.update(key("source", "api"), None);
+        assert!(index.sources_for("source", "targets").is_empty());
+    }
+}
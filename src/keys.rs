@@ -0,0 +1,68 @@
+//! Keys of the labels and annotations the operator reads and writes, and of its finalizer. Two
+//! operator instances with different keys leave each other's sources and copies alone, e.g. one
+//! per trust domain.
+//!
+//! `--annotation-prefix` replaces the prefix of all annotations and labels of the operator,
+//! `--owner-label` names the label linking a copy to its source and `--finalizer-name` the
+//! finalizer guarding the cleanup of a source. They are set once by [`configure`].
+
+use std::sync::OnceLock;
+
+use crate::opts::Opts;
+
+/// Prefix the annotation and label keys are declared with throughout the operator.
+pub const DEFAULT_PREFIX: &str = "eu.fitzek.spread.";
+/// Default name of the finalizer.
+const DEFAULT_FINALIZER: &str = "secretspreading.fitzek.eu/finalizer";
+
+struct Keys {
+    prefix: String,
+    owner_label: String,
+    finalizer: String,
+}
+
+static KEYS: OnceLock<Keys> = OnceLock::new();
+
+/// Sets the keys of the process from `opts`. Keys already in use are kept, so it has to be
+/// called before the first key is read.
+pub fn configure(opts: &Opts) {
+    let prefix = Some(opts.annotation_prefix.clone()).filter(|p| !p.is_empty()).unwrap_or_else(|| DEFAULT_PREFIX.to_string());
+    let _ = KEYS.set(Keys {
+        owner_label: opts.owner_label.clone().filter(|l| !l.is_empty()).unwrap_or_else(|| format!("{}owner", prefix)),
+        finalizer: Some(opts.finalizer_name.clone()).filter(|f| !f.is_empty()).unwrap_or_else(|| DEFAULT_FINALIZER.to_string()),
+        prefix,
+    });
+}
+
+/// The configured keys, the defaults unless [`configure`]d.
+fn keys() -> &'static Keys {
+    KEYS.get_or_init(|| Keys {
+        prefix: DEFAULT_PREFIX.to_string(),
+        owner_label: format!("{}owner", DEFAULT_PREFIX),
+        finalizer: DEFAULT_FINALIZER.to_string(),
+    })
+}
+
+/// Translates `key`, declared with the default prefix, to the configured prefix. Keys with
+/// another prefix are returned as they are.
+pub fn key(key: &str) -> String {
+    match key.strip_prefix(DEFAULT_PREFIX) {
+        Some(name) => format!("{}{}", keys().prefix, name),
+        None => key.to_string(),
+    }
+}
+
+/// The configured prefix of the annotations and labels.
+pub fn prefix() -> &'static str {
+    &keys().prefix
+}
+
+/// The label carrying the uid of the source on each copy.
+pub fn owner_label() -> &'static str {
+    &keys().owner_label
+}
+
+/// The finalizer guarding the cleanup of a source.
+pub fn finalizer() -> &'static str {
+    &keys().finalizer
+}
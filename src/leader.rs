@@ -0,0 +1,160 @@
+//! Leader election over a `coordination.k8s.io/v1` Lease, so only one of several replicas
+//! reconciles.
+//!
+//! Election is enabled by `--leader-election-namespace`, the namespace of the Lease. `--lease-name`
+//! names it, `--lease-duration` is the time in seconds a leader holds the Lease without renewing
+//! it and `--lease-renew-interval` the time in seconds between renewals. The identity of a replica
+//! is `--pod-name`, or `HOSTNAME`.
+
+use chrono::Utc;
+use k8s_openapi::api::coordination::v1::{Lease, LeaseSpec};
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::{MicroTime, ObjectMeta};
+use kube::api::PostParams;
+use kube::{Api, Client};
+use tokio::time::{sleep, Duration};
+use tracing::{info, warn};
+
+use crate::opts::Opts;
+use crate::Error;
+
+/// Election of the replica allowed to reconcile.
+pub struct Election {
+    api: Api<Lease>,
+    name: String,
+    identity: String,
+    lease_duration: Duration,
+    renew_interval: Duration,
+}
+
+impl Election {
+    /// Constructs the Election configured by `opts`, or `None` if leader election is disabled.
+    pub fn new(client: Client, opts: &Opts) -> Option<Self> {
+        let namespace = opts.leader_election_namespace.as_deref().filter(|ns| !ns.is_empty())?;
+        let identity = opts
+            .pod_name
+            .clone()
+            .filter(|name| !name.is_empty())
+            .or_else(|| std::env::var("HOSTNAME").ok())
+            .unwrap_or_else(|| format!("spreading-operator-{}", rand::random::<u32>()));
+        Some(Election {
+            api: Api::namespaced(client, namespace),
+            name: opts.lease_name.clone(),
+            identity,
+            lease_duration: Duration::from_secs(opts.lease_duration),
+            renew_interval: Duration::from_secs(opts.lease_renew_interval),
+        })
+    }
+
+    /// Waits until this replica holds the Lease.
+    pub async fn acquire(&self) {
+        info!(identity = %self.identity, lease = %self.name, "Waiting for leadership");
+        loop {
+            match self.try_acquire().await {
+                Ok(true) => {
+                    info!(identity = %self.identity, "Acquired leadership");
+                    return;
+                }
+                Ok(false) => {}
+                Err(e) => warn!(error = %e, "Can't acquire the lease"),
+            }
+            sleep(self.renew_interval).await;
+        }
+    }
+
+    /// Renews the Lease until leadership is lost, then returns. Leadership is lost when another
+    /// replica took over the Lease or renewing failed for longer than the lease duration.
+    pub async fn hold(&self) {
+        let mut last_renewal = tokio::time::Instant::now();
+        loop {
+            sleep(self.renew_interval).await;
+            match self.try_acquire().await {
+                Ok(true) => last_renewal = tokio::time::Instant::now(),
+                Ok(false) => return,
+                Err(e) => {
+                    warn!(error = %e, "Can't renew the lease");
+                    if last_renewal.elapsed() >= self.lease_duration {
+                        return;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Gives up the Lease, so another replica can take over right away.
+    pub async fn release(&self) {
+        let mut lease = match self.api.get(&self.name).await {
+            Ok(lease) => lease,
+            Err(_) => return,
+        };
+        let spec = lease.spec.get_or_insert_with(LeaseSpec::default);
+        if spec.holder_identity.as_deref() != Some(self.identity.as_str()) {
+            return;
+        }
+        spec.holder_identity = None;
+        if let Err(e) = self.api.replace(&self.name, &PostParams::default(), &lease).await {
+            warn!(error = %e, "Can't release the lease");
+        }
+    }
+
+    /// Takes or renews the Lease if it is free, expired or held by this replica. Returns whether
+    /// this replica holds the Lease afterwards.
+    async fn try_acquire(&self) -> Result<bool, Error> {
+        let now = Utc::now();
+        let lease = match self.api.get(&self.name).await {
+            Ok(lease) => lease,
+            Err(kube::Error::Api(kube::error::ErrorResponse { code: 404, .. })) => {
+                let lease = Lease {
+                    metadata: ObjectMeta {
+                        name: Some(self.name.clone()),
+                        ..Default::default()
+                    },
+                    spec: Some(self.spec(now, Some(now), 0)),
+                };
+                return match self.api.create(&PostParams::default(), &lease).await {
+                    Ok(_) => Ok(true),
+                    // another replica created it first
+                    Err(kube::Error::Api(kube::error::ErrorResponse { code: 409, .. })) => Ok(false),
+                    Err(e) => Err(e.into()),
+                };
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        let current = lease.spec.clone().unwrap_or_default();
+        let held_by_us = current.holder_identity.as_deref() == Some(self.identity.as_str());
+        let expired = match (&current.renew_time, current.lease_duration_seconds) {
+            (Some(MicroTime(renewed)), Some(duration)) => *renewed + chrono::Duration::seconds(duration.into()) < now,
+            _ => true,
+        };
+        if !held_by_us && current.holder_identity.is_some() && !expired {
+            return Ok(false);
+        }
+
+        let transitions = current.lease_transitions.unwrap_or(0);
+        let spec = if held_by_us {
+            self.spec(now, current.acquire_time.map(|t| t.0), transitions)
+        } else {
+            self.spec(now, Some(now), transitions + 1)
+        };
+        // the resourceVersion of the read Lease makes a concurrent takeover fail with 409
+        let updated = Lease {
+            metadata: lease.metadata,
+            spec: Some(spec),
+        };
+        match self.api.replace(&self.name, &PostParams::default(), &updated).await {
+            Ok(_) => Ok(true),
+            Err(kube::Error::Api(kube::error::ErrorResponse { code: 409, .. })) => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn spec(&self, now: chrono::DateTime<Utc>, acquired: Option<chrono::DateTime<Utc>>, transitions: i32) -> LeaseSpec {
+        LeaseSpec {
+            holder_identity: Some(self.identity.clone()),
+            lease_duration_seconds: Some(self.lease_duration.as_secs() as i32),
+            acquire_time: acquired.map(MicroTime),
+            renew_time: Some(MicroTime(now)),
+            lease_transitions: Some(transitions),
+        }
+    }
+}
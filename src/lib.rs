@@ -0,0 +1,3384 @@
+//! Spreads Secrets and ConfigMaps annotated with target namespaces into those namespaces.
+//!
+//! The operator binary is a thin wrapper around [`run_controller`]. Embedding the operator in
+//! another binary works the same way; [`reconcile`], [`sync_secret`] and [`secret_cleanup`] act
+//! on single sources with a [`ContextData`] for finer control.
+
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
+use std::fmt::Debug;
+
+use futures::stream::{BoxStream, StreamExt};
+use kube::Resource;
+use kube::{api::{ListParams, PostParams, DeleteParams, PatchParams, Patch}, client::Client, Api};
+use kube_runtime::controller::{applier, trigger_self, trigger_with, Context, ReconcilerAction};
+use kube_runtime::reflector::{self, ObjectRef};
+use kube_runtime::utils::{try_flatten_applied, try_flatten_touched, CancelableJoinHandle};
+use kube_runtime::watcher::{self, watcher};
+use tokio::time::Duration;
+use sinks::EventSink;
+use plan::CopyStep;
+use opts::Opts;
+
+use k8s_openapi::{Metadata, api::core::v1::{ConfigMap, Namespace, Secret}};
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::{ObjectMeta};
+
+use serde::de::DeserializeOwned;
+use tracing::{debug, error, info, info_span, warn, Instrument};
+
+pub mod access;
+#[cfg(feature = "vault")]
+mod backend;
+mod cache;
+pub mod check;
+pub mod cli;
+mod compare;
+mod concurrency;
+pub mod config;
+mod configmaps;
+mod events;
+#[cfg(test)]
+mod fake_api;
+pub mod export;
+pub mod finalizer;
+mod generated;
+mod history;
+mod http;
+mod index;
+pub mod keys;
+mod leader;
+mod metrics;
+mod naming;
+pub mod once;
+pub mod opts;
+mod orphans;
+mod pacing;
+pub mod plan;
+mod policy;
+mod pull_secrets;
+mod quarantine;
+mod remote;
+pub mod report;
+mod requeue;
+mod retry;
+pub mod settings;
+mod shutdown;
+mod sinks;
+pub mod spread;
+mod status;
+mod targets;
+pub mod topology;
+mod webhook;
+
+/// Version of the operator.
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+/// Git commit the operator was built from, `unknown` if the build had no repository.
+pub const GIT_COMMIT: &str = env!("GIT_COMMIT");
+/// Version of the compiler that built the operator.
+pub const RUSTC_VERSION: &str = env!("RUSTC_VERSION");
+
+const ALLOW_SA_TOKEN_ANNOTATION: &str = "eu.fitzek.spread.allow-sa-token";
+const SA_TOKEN_TYPE: &str = "kubernetes.io/service-account-token";
+const CONDITION_ANNOTATION: &str = "eu.fitzek.spread.condition";
+const CONDITION_CLEANUP_ANNOTATION: &str = "eu.fitzek.spread.condition-cleanup";
+/// Opt-in to take over secrets in target namespaces named like the copy but not written by the
+/// operator. Without it such a secret is left alone and the copy skipped.
+const ADOPT_UNMANAGED_ANNOTATION: &str = "eu.fitzek.spread.adopt-unmanaged";
+/// Opt-in to create target namespaces that don't exist, instead of skipping them. Enabled for
+/// all sources by `CREATE_NAMESPACES=true`.
+const CREATE_NAMESPACE_ANNOTATION: &str = "eu.fitzek.spread.create-namespace";
+/// Annotation on a ConfigMap declaring it as a Vault backed source. The value is
+/// `<mount>/<path>` of a KV version 2 secret, e.g. `secret/team-a/registry`.
+const VAULT_PATH_ANNOTATION: &str = "eu.fitzek.spread.vault-path";
+/// What happens to the copies when the source is deleted or no longer spread: `delete` (default)
+/// deletes them, `orphan` keeps them as unmanaged secrets, without the labels and annotations
+/// linking them to the source.
+const DELETE_POLICY_ANNOTATION: &str = "eu.fitzek.spread.delete-policy";
+/// Set to `true` on a source, the operator leaves it and its copies alone until the annotation
+/// is removed: nothing is created, updated or deleted, not even once the source is deleted.
+const PAUSED_ANNOTATION: &str = "eu.fitzek.spread.paused";
+
+/// Runs the operator with the options `opts` until the process is asked to stop: the Secret,
+/// ConfigMap, pull secret and, with the `vault` feature, Vault controllers, the orphan scan and
+/// the HTTP server, behind leader election if configured. The command line modes like `--once`
+/// are up to the caller.
+///
+/// The keys of the labels and annotations are process-wide, they are configured from `opts`
+/// unless [`keys::configure`]d before.
+pub async fn run_controller(kubernetes_client: Client, opts: Opts) {
+    let opts = &opts;
+    keys::configure(opts);
+
+    // Sources are only watched in the namespaces of --watch-namespaces, if set.
+    let scopes = watch_scopes(opts);
+
+    // A controller without the permissions it needs would fail quietly on every reconcile.
+    access::warn_missing(kubernetes_client.clone(), opts).await;
+
+    // With TARGET_KUBECONFIG the Secret sources are spread into the namespaces of another cluster.
+    let target_client = match &opts.target_kubeconfig {
+        None => kubernetes_client.clone(),
+        Some(path) => match target_client(path).await {
+            Ok(client) => client,
+            Err(e) => {
+                error!(error = %e, path = %path.display(), "Can't create the client of the target cluster");
+                return;
+            }
+        },
+    };
+
+    // Copies are looked up in a cache fed by a watch instead of one GET per target namespace.
+    let (copy_cache, copy_cache_runner) = cache::copies(target_client.clone());
+    let context: Context<ContextData> = Context::new(
+        ContextData::new(kubernetes_client.clone(), opts)
+            .with_target_client(target_client)
+            .with_copy_cache(copy_cache),
+    );
+
+    // With SETTINGS_CONFIGMAP some settings follow the ConfigMap while the operator runs, all
+    // controllers share them.
+    let settings_watch = async {
+        if let Some(reference) = &opts.settings_configmap {
+            settings::watch(kubernetes_client.clone(), reference, context.get_ref().settings(), settings::Settings::from_opts(opts)).await;
+        }
+    };
+
+    // Serves the reconcile history of the Secret controller.
+    let http_server = http::run(&opts.http_addr, context.get_ref().history.clone(), opts.readiness_staleness);
+
+    // Rejects Secrets with malformed spread annotations, only with --webhook.
+    let admission_webhook = async {
+        if let (true, Some(cert), Some(key)) = (opts.webhook, &opts.webhook_cert, &opts.webhook_key) {
+            if let Err(e) = webhook::run(&opts.webhook_addr, cert, key).await {
+                error!(error = %e, "Admission webhook failed");
+            }
+        }
+    };
+
+    // Ready once Secrets can be listed, which is what the controller's initial watch does first.
+    let readiness = wait_until_listable(
+        scopes.iter().map(|scope| scoped_api(kubernetes_client.clone(), scope.as_deref())).collect(),
+        context.get_ref().source_list_params(),
+    );
+
+    // With RESYNC_INTERVAL set (in seconds) every known source is reconciled after each interval,
+    // even if an event was missed, e.g. while the API server restarted.
+    let resync_interval = Some(opts.resync_interval).filter(|v| *v > 0).map(Duration::from_secs);
+    // Changing the settings or the policy may change the copies of any source, all of them are
+    // reconciled instead of waiting out their requeue.
+    let config_configmaps: Vec<(String, String)> = opts.settings_configmap.iter().chain(&opts.spread_policy_configmap).cloned().collect();
+    // One Secret controller per watched scope, each watching its sources and ConfigMaps and the
+    // namespaces of the target cluster, see run_secret_controller.
+    let secret_controller = futures::future::join_all(scopes.iter().map(|scope| {
+        run_secret_controller(
+            scoped_api(kubernetes_client.clone(), scope.as_deref()),
+            scoped_api(kubernetes_client.clone(), scope.as_deref()),
+            context.clone(),
+            resync_interval,
+            &config_configmaps,
+        )
+    }));
+
+    // Pull secrets requested by Deployments are spread by their own controller, it only runs if
+    // --pull-secret-source-namespace is set.
+    let pull_secret_controller = pull_secrets::run(kubernetes_client.clone(), opts);
+
+    // ConfigMaps are spread by their own controller alongside the Secret controller.
+    let configmap_controller = configmaps::run(kubernetes_client.clone(), &scopes, opts, context.get_ref().settings());
+
+    // Deletes copies left behind when a source vanished before its cleanup was complete.
+    let orphan_scan = orphans::run(kubernetes_client.clone(), &scopes, opts);
+
+    // Secrets declared by SecretSpread resources are spread by a further controller sharing the
+    // context of the Secret controller, only with --secret-spread-crd.
+    let secret_spread_controller = async {
+        if opts.secret_spread_crd {
+            spread::run(kubernetes_client.clone(), context.clone()).await;
+        }
+    };
+
+    // Sources held in Vault are declared by annotated ConfigMaps and handled by a further
+    // controller running alongside the Secret controller.
+    #[cfg(feature = "vault")]
+    let controllers = async {
+        futures::join!(secret_controller, configmap_controller, copy_cache_runner, pull_secret_controller, orphan_scan, secret_spread_controller, settings_watch, backend::run(kubernetes_client.clone(), &scopes, opts, context.get_ref().settings()));
+    };
+    #[cfg(not(feature = "vault"))]
+    let controllers = async {
+        futures::join!(secret_controller, configmap_controller, copy_cache_runner, pull_secret_controller, orphan_scan, secret_spread_controller, settings_watch);
+    };
+
+    // With leader election only the replica holding the lease runs the controllers, the others
+    // wait for it. A leader losing the lease exits, so the new leader takes over cleanly.
+    let election = leader::Election::new(kubernetes_client.clone(), opts);
+    let leading = async {
+        match &election {
+            None => controllers.await,
+            Some(election) => {
+                election.acquire().await;
+                tokio::select! {
+                    _ = controllers => {}
+                    _ = election.hold() => {
+                        error!("Lost leadership, exiting");
+                        std::process::exit(1);
+                    }
+                }
+            }
+        }
+    };
+
+    // The HTTP server and the webhook run on standbys as well, they are alive and ready to take
+    // over.
+    let operator = async {
+        futures::join!(leading, http_server, admission_webhook, readiness);
+    };
+
+    // When the process is asked to stop, no further reconcile starts and the running ones are
+    // waited for. The controllers keep being polled meanwhile, so they can finish, then they and
+    // the HTTP server are dropped together.
+    let stopping = async {
+        shutdown_signal().await;
+        info!("Shutting down");
+        shutdown::drain(Duration::from_secs(opts.shutdown_timeout)).await;
+    };
+    tokio::select! {
+        _ = operator => {}
+        _ = stopping => {}
+    }
+    if let Some(election) = &election {
+        election.release().await;
+    }
+}
+
+/// Creates the client of the cluster of the sources: the in-cluster or kubeconfig one, with the
+/// `--api-server-url` and `--kube-ca-file` overrides of `opts` applied if set.
+pub async fn client(opts: &Opts) -> Result<Client, Error> {
+    if opts.api_server_url.is_none() && opts.kube_ca_file.is_none() {
+        return Ok(Client::try_default().await?);
+    }
+    let ca_pem = match &opts.kube_ca_file {
+        Some(path) => Some(
+            std::fs::read(path).map_err(|e| Error::UserInputError(format!("Can't read the CA file {}: {}", path.display(), e)))?,
+        ),
+        None => None,
+    };
+    let config = client_config(kube::Config::infer().await?, opts.api_server_url.as_deref(), ca_pem.as_deref())?;
+    Ok(Client::try_from(config)?)
+}
+
+/// Returns `config` with the cluster url replaced by `api_server_url` and the certificates of
+/// the PEM `ca_pem` trusted in addition to its own, if given.
+pub fn client_config(mut config: kube::Config, api_server_url: Option<&str>, ca_pem: Option<&[u8]>) -> Result<kube::Config, Error> {
+    if let Some(url) = api_server_url {
+        config.cluster_url = url
+            .parse()
+            .map_err(|e| Error::UserInputError(format!("Invalid API server url {}: {}", url, e)))?;
+    }
+    if let Some(ca_pem) = ca_pem {
+        let certs: Vec<Vec<u8>> = pem::parse_many(ca_pem)
+            .into_iter()
+            .filter(|pem| pem.tag == "CERTIFICATE")
+            .map(|pem| pem.contents)
+            .collect();
+        if certs.is_empty() {
+            return Err(Error::UserInputError("The CA file contains no PEM certificate".to_string()));
+        }
+        config.root_cert.get_or_insert_with(Vec::new).extend(certs);
+    }
+    Ok(config)
+}
+
+/// Creates the client of the target cluster from the kubeconfig at `path`, using its current
+/// context.
+async fn target_client(path: &std::path::Path) -> Result<Client, Error> {
+    let kubeconfig = kube::config::Kubeconfig::read_from(path)?;
+    let config = kube::Config::from_custom_kubeconfig(kubeconfig, &kube::config::KubeConfigOptions::default()).await?;
+    Ok(Client::try_from(config)?)
+}
+
+/// Waits until the Secrets of `lp` can be listed in all watched namespaces, then marks the
+/// operator ready.
+async fn wait_until_listable(secret_apis: Vec<Api<Secret>>, lp: ListParams) {
+    for secret_api in secret_apis {
+        loop {
+            match secret_api.list(&lp.clone().limit(1)).await {
+                Ok(_) => break,
+                Err(e) => {
+                    warn!(error = %e, "Can't list secrets yet");
+                    tokio::time::sleep(Duration::from_secs(5)).await;
+                }
+            }
+        }
+    }
+    http::set_ready();
+}
+
+/// Namespaces the sources are watched in, from `--watch-namespaces` of `opts`. `None` stands for
+/// all namespaces, the only scope if the option is unset.
+///
+/// Only the sources are restricted: their targets, `*` included, are still resolved among all
+/// namespaces of the cluster, so spreading from a watched namespace into the others works as
+/// before and needs the same access to them.
+pub fn watch_scopes(opts: &Opts) -> Vec<Option<String>> {
+    let namespaces: Vec<Option<String>> = opts
+        .watch_namespaces
+        .iter()
+        .map(|ns| ns.trim())
+        .filter(|ns| !ns.is_empty())
+        .map(|ns| Some(ns.to_string()))
+        .collect();
+    if namespaces.is_empty() {
+        vec![None]
+    } else {
+        namespaces
+    }
+}
+
+/// API of the objects of kind `K` in the watch scope `scope`, see [`watch_scopes`].
+fn scoped_api<K>(client: Client, scope: Option<&str>) -> Api<K>
+where
+    K: Resource<DynamicType = ()>,
+{
+    match scope {
+        Some(ns) => Api::namespaced(client, ns),
+        None => Api::all(client),
+    }
+}
+
+/// Parameters listing the Secret sources: all Secrets, or with the `--source-label-selector`
+/// `selector` only the ones matching it. Secrets outside the selection are never reconciled, even
+/// with targeting annotations.
+fn source_list_params(selector: Option<&str>) -> ListParams {
+    match selector {
+        Some(selector) if !selector.is_empty() => ListParams::default().labels(selector),
+        _ => ListParams::default(),
+    }
+}
+
+/// Runs the Secret controller for the sources listed by `secret_api`. Its reconciles are
+/// requested by
+/// - `secret`, the watch of the sources themselves, which feeds the store the reconciles read
+///   the sources from,
+/// - `configmap`, the watch of the ConfigMaps of `configmap_api`: a change of a ConfigMap holding
+///   target namespaces re-spreads the sources reading from it, see [`index::ConfigMapIndex`]. A
+///   ConfigMap outside the watched namespaces is only picked up by the periodic reconcile,
+/// - `namespace`, the watch of the namespaces of the target cluster: a new or relabeled namespace
+///   re-spreads the sources expanding to the namespaces of the cluster, e.g. by `*`, a pattern or
+///   a selector, see [`index::NamespaceIndex`],
+/// - `resync`, with `resync_interval` all sources known to the controller after each interval.
+///   The controller keeps running meanwhile, so no reconcile is interrupted by a resync,
+/// - `config`, a change of one of the `config_configmaps` enqueues all sources spread, see
+///   [`settings::changes`].
+///
+/// The requests are merged and deduplicated by [`secret_applier`].
+async fn run_secret_controller(secret_api: Api<Secret>, configmap_api: Api<ConfigMap>, context: Context<ContextData>, resync_interval: Option<Duration>, config_configmaps: &[(String, String)]) {
+    // every completed reconcile and every event of the watches is a heartbeat, see
+    // --readiness-staleness
+    metrics::heartbeat();
+    let configmap_index = context.get_ref().configmap_index.clone();
+    let namespace_index = context.get_ref().namespace_index.clone();
+    let namespace_api: Api<Namespace> = Api::all(context.get_ref().target_client.clone());
+
+    let writer: reflector::store::Writer<Secret> = Default::default();
+    let store = writer.as_reader();
+    let sources = trigger_self(try_flatten_applied(reflector::reflector(writer, watcher(secret_api, context.get_ref().source_list_params()))), ());
+    let configmaps = trigger_with(try_flatten_touched(watcher(configmap_api, ListParams::default())), move |cm: ConfigMap| {
+        metrics::heartbeat();
+        configmap_index.sources_for(&cm.namespace().unwrap_or_default(), &cm.name())
+    });
+    let namespaces = trigger_with(try_flatten_touched(watcher(namespace_api, ListParams::default())), move |ns: Namespace| {
+        metrics::heartbeat();
+        namespace_index.sources_for(&ns)
+    });
+    let resync: BoxStream<'static, Result<ObjectRef<Secret>, watcher::Error>> = match resync_interval {
+        None => futures::stream::empty().boxed(),
+        Some(interval) => {
+            let store = store.clone();
+            futures::stream::unfold((), move |()| async move {
+                tokio::time::sleep(interval).await;
+                Some(((), ()))
+            })
+            .flat_map(move |()| {
+                info!("Full resync of all sources");
+                futures::stream::iter(store.state().iter().map(|sec| Ok(ObjectRef::from_obj(sec))).collect::<Vec<_>>())
+            })
+            .boxed()
+        }
+    };
+    let config_changes = config_triggers(settings::changes(context.get_ref().client.clone(), config_configmaps, settings::DEBOUNCE), store.clone());
+    let triggers = vec![
+        counted("secret", sources),
+        counted("configmap", configmaps),
+        counted("namespace", namespaces),
+        counted("resync", resync),
+        counted("config", config_changes),
+    ];
+
+    secret_applier(context, store, triggers)
+        .for_each(|reconciliation_result| async move {
+            metrics::heartbeat();
+            match reconciliation_result {
+                Ok((object, action)) => {
+                    debug!(object = %object, requeue_after = ?action.requeue_after, "Reconciliation successful");
+                }
+                Err(reconciliation_err) => {
+                    error!(error = ?reconciliation_err, "Reconciliation error")
+                }
+            }
+        })
+        .await
+}
+
+/// Reconciles the sources of `store` requested by `triggers`, each counted by [`counted`].
+///
+/// The controller is put together from the pieces of [`kube_runtime::Controller`], which can't be
+/// triggered by a stream of its own. The triggers are merged round robin, a source requested by
+/// several of them before its reconcile starts is reconciled once, and one requested while it is
+/// reconciled once more afterwards, never twice at the same time. The reconciles requested by each
+/// trigger are counted in `spread_controller_triggers_total`, so a dominating one, e.g. the
+/// namespace watch of a cluster creating namespaces all the time, shows.
+fn secret_applier(
+    context: Context<ContextData>,
+    store: reflector::Store<Secret>,
+    triggers: Vec<BoxStream<'static, Result<ObjectRef<Secret>, watcher::Error>>>,
+) -> impl futures::Stream<Item = Result<(ObjectRef<Secret>, ReconcilerAction), kube_runtime::controller::Error<Error, watcher::Error>>> {
+    applier(
+        |sec, context| CancelableJoinHandle::spawn(reconcile(sec, context), &tokio::runtime::Handle::current()),
+        on_error,
+        context,
+        store,
+        futures::stream::select_all(triggers),
+    )
+}
+
+/// Counts the reconciles of sources `requests` asks for as triggered by `trigger`, e.g. the
+/// namespace watch, see [`metrics::triggered`].
+fn counted<S>(trigger: &'static str, requests: S) -> BoxStream<'static, Result<ObjectRef<Secret>, watcher::Error>>
+where
+    S: futures::Stream<Item = Result<ObjectRef<Secret>, watcher::Error>> + Send + 'static,
+{
+    requests
+        .inspect(move |request| {
+            if request.is_ok() {
+                metrics::triggered(concurrency::SECRET, trigger);
+            }
+        })
+        .boxed()
+}
+
+/// Enqueues the sources of `store` that are spread, by a targeting annotation or the finalizer of
+/// an earlier spread, on every item of `changes`.
+fn config_triggers(changes: BoxStream<'static, ()>, store: reflector::Store<Secret>) -> BoxStream<'static, Result<ObjectRef<Secret>, watcher::Error>> {
+    changes
+        .flat_map(move |()| {
+            let spread: Vec<_> = store
+                .state()
+                .iter()
+                .filter(|sec| targets::has_targets(&sec.metadata) || finalizer::has_finalizer(*sec))
+                .map(|sec| Ok(ObjectRef::from_obj(sec)))
+                .collect();
+            info!(sources = spread.len(), "Configuration changed, reconciling all spread sources");
+            futures::stream::iter(spread)
+        })
+        .boxed()
+}
+
+/// Waits for SIGTERM, as sent by Kubernetes when stopping the pod, or Ctrl-C.
+async fn shutdown_signal() {
+    let mut sigterm = match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+        Ok(s) => s,
+        Err(e) => {
+            warn!(error = %e, "Can't listen for SIGTERM");
+            let _ = tokio::signal::ctrl_c().await;
+            return;
+        }
+    };
+    tokio::select! {
+        _ = sigterm.recv() => {}
+        _ = tokio::signal::ctrl_c() => {}
+    }
+}
+
+/// Context injected with each `reconcile` and `on_error` method invocation.
+pub struct ContextData {
+    /// Kubernetes client to make Kubernetes API requests with. Required for K8S resource management.
+    client: Client,
+    /// Client of the cluster the copies are written to, the cluster of the sources unless
+    /// `TARGET_KUBECONFIG` is set.
+    target_client: Client,
+    /// Name identifying this operator instance, used as field manager of all writes.
+    instance_name: String,
+    /// Value of the `app.kubernetes.io/managed-by` label set on copies.
+    managed_by: String,
+    /// Whether copies are stamped with the resourceVersion of their source.
+    stamp_source_version: bool,
+    /// Slows reconciles down while the API server is throttling.
+    pacer: pacing::Pacer,
+    /// Defers reconciles of a source following each other too closely.
+    source_limiter: pacing::SourceLimiter,
+    /// Bounds the reconciles of the controller running at once, see
+    /// `--max-concurrent-reconciles` and `--controller-concurrency`.
+    reconcile_slots: concurrency::Slots,
+    /// Randomizes the requeue durations of successful reconciles.
+    jitter: requeue::Jitter,
+    /// Settings changing while the operator runs: the requeue intervals, the excluded and allowed
+    /// namespaces and the pause of all sources, see [`settings`].
+    settings: settings::Shared,
+    /// Retry durations of sources failing in a row, based on the error interval of the options.
+    backoff: requeue::Backoff,
+    /// Whether sources get the finalizer guarding the cleanup of their copies.
+    use_finalizer: bool,
+    /// Whether writes are only logged, see `--dry-run`.
+    dry_run: bool,
+    /// Whether applying a copy not owned by the operator takes over fields managed by others, see
+    /// `--force-apply`.
+    force_apply: bool,
+    /// Whether the sync status goes to `SpreadStatus` resources instead of annotations on the
+    /// source, see `--status-crd`.
+    status_crd: bool,
+    /// Target namespaces skipped for a while after failing repeatedly.
+    quarantine: quarantine::Quarantine,
+    /// Recent reconcile outcomes per source, served on `/history`.
+    history: std::sync::Arc<history::History>,
+    /// Cached copies consulted before reading a copy from the API server.
+    copy_cache: Option<cache::CopyCache>,
+    /// Sources by the ConfigMap they read their target namespaces from.
+    configmap_index: std::sync::Arc<index::ConfigMapIndex>,
+    /// Sources expanding to the namespaces of the cluster, reconciled when a namespace is created.
+    namespace_index: std::sync::Arc<index::NamespaceIndex>,
+    /// Namespace holding the central pull secrets spread on demand of Deployments.
+    pull_secret_namespace: Option<String>,
+    /// How the target namespaces of the sources are resolved.
+    targeting: targets::Targeting,
+    /// Secret types only reconciled with a targeting annotation, see `--exclude-types`.
+    exclude_types: Vec<String>,
+    /// Label selector the Secret sources have to match, see `--source-label-selector`.
+    source_label_selector: Option<String>,
+    /// Data keys added to copies by others that are ignored when comparing, see
+    /// `--ignored-copy-keys`.
+    ignored_copy_keys: Vec<String>,
+    /// Whether missing target namespaces are created for sources that don't say.
+    create_namespaces: bool,
+    /// ConfigMap `<namespace>/<name>` holding the spread policy, see `--spread-policy-configmap`.
+    policy_configmap: Option<(String, String)>,
+    /// Most target namespaces a source may have, unlimited if 0.
+    max_targets_per_source: usize,
+    /// Number of target namespaces a source is synced to concurrently.
+    sync_concurrency: usize,
+    /// Records events on the source secrets.
+    recorder: events::Recorder,
+    /// Receives notifications about created, updated, deleted and skipped copies.
+    sinks: sinks::Sinks,
+    /// Backend to fetch the content of sources not stored as Kubernetes Secrets.
+    #[cfg(feature = "vault")]
+    backend: Option<std::sync::Arc<dyn backend::SourceBackend>>,
+    /// Interval after which the sources held in the backend are fetched again.
+    #[cfg(feature = "vault")]
+    backend_refresh: Duration,
+}
+
+impl ContextData {
+    /// Constructs a new instance of ContextData.
+    ///
+    /// # Arguments:
+    /// - `client`: A Kubernetes client to make Kubernetes REST API requests with. Resources
+    ///   will be created and deleted with this client.
+    /// - `opts`: The options of the operator, the settings of the reconciles are taken from them.
+    pub fn new(client: Client, opts: &Opts) -> Self {
+        let recorder = events::Recorder::new(client.clone(), &opts.instance_name, opts.dry_run);
+        let settings = settings::Settings::from_opts(opts);
+        ContextData {
+            sinks: sinks::Sinks::new(recorder.clone(), &opts.event_sinks, opts.event_webhook_url.as_deref(), opts.event_webhook_secret.as_deref().filter(|s| !s.is_empty())),
+            recorder,
+            target_client: client.clone(),
+            client,
+            instance_name: opts.instance_name.clone(),
+            managed_by: opts.managed_by.clone(),
+            stamp_source_version: opts.stamp_source_version,
+            pacer: pacing::Pacer::new(opts.reconcile_rate),
+            source_limiter: pacing::SourceLimiter::new(Duration::from_secs(opts.min_reconcile_interval)),
+            reconcile_slots: concurrency::Slots::for_controller(concurrency::SECRET, opts),
+            jitter: requeue::Jitter::new(opts.requeue_jitter, opts.requeue_jitter_seed),
+            backoff: requeue::Backoff::new(settings.requeue.error),
+            settings: std::sync::Arc::new(std::sync::RwLock::new(settings)),
+            use_finalizer: !opts.disable_finalizer,
+            dry_run: opts.dry_run,
+            force_apply: opts.force_apply,
+            status_crd: opts.status_crd,
+            quarantine: quarantine::Quarantine::new(opts.quarantine_threshold, Duration::from_secs(opts.quarantine_retry)),
+            history: std::sync::Arc::new(history::History::new(opts.history_size)),
+            copy_cache: None,
+            configmap_index: Default::default(),
+            namespace_index: Default::default(),
+            pull_secret_namespace: opts.pull_secret_source_namespace.clone().filter(|ns| !ns.is_empty()),
+            targeting: targets::Targeting::from_opts(opts),
+            exclude_types: opts.exclude_types.clone(),
+            source_label_selector: opts.source_label_selector.clone(),
+            ignored_copy_keys: opts.ignored_copy_keys.clone(),
+            create_namespaces: opts.create_namespaces,
+            policy_configmap: opts.spread_policy_configmap.clone(),
+            max_targets_per_source: opts.max_targets_per_source,
+            sync_concurrency: opts.sync_concurrency.into(),
+            #[cfg(feature = "vault")]
+            backend: None,
+            #[cfg(feature = "vault")]
+            backend_refresh: Duration::from_secs(opts.vault_refresh_interval),
+        }
+    }
+
+    /// Parameters for creating objects, attributed to this operator instance. With `--dry-run`
+    /// they ask for a dry run, so a write slipping past the dry-run checks isn't persisted.
+    pub fn post_params(&self) -> PostParams {
+        PostParams {
+            dry_run: self.dry_run,
+            field_manager: Some(self.instance_name.clone()),
+        }
+    }
+
+    /// Parameters for server-side applying copies, attributed to this operator instance. Fields
+    /// managed by others are taken over on objects the operator `owned`, i.e. copies carrying its
+    /// owner label or being adopted, on other objects only with `--force-apply`.
+    pub fn apply_params(&self, owned: bool) -> PatchParams {
+        PatchParams {
+            force: owned || self.force_apply,
+            dry_run: self.dry_run,
+            ..PatchParams::apply(&self.instance_name)
+        }
+    }
+
+    /// Parameters for patching objects, attributed to this operator instance.
+    pub fn patch_params(&self) -> PatchParams {
+        PatchParams {
+            field_manager: Some(self.instance_name.clone()),
+            dry_run: self.dry_run,
+            ..Default::default()
+        }
+    }
+
+    /// Parameters for deleting copies, a dry run with `--dry-run`.
+    pub fn delete_params(&self) -> DeleteParams {
+        DeleteParams {
+            dry_run: self.dry_run,
+            ..Default::default()
+        }
+    }
+
+    /// Parameters listing the Secret sources: all Secrets, or with `--source-label-selector`
+    /// only the ones matching the label selector.
+    pub fn source_list_params(&self) -> ListParams {
+        source_list_params(self.source_label_selector.as_deref())
+    }
+
+    /// Shares the `settings` of another context, so both follow the settings ConfigMap.
+    pub fn with_settings(mut self, settings: settings::Shared) -> Self {
+        self.settings = settings;
+        self
+    }
+
+    /// The settings of the reconciles, shared with every context given them by
+    /// [`ContextData::with_settings`].
+    pub fn settings(&self) -> settings::Shared {
+        self.settings.clone()
+    }
+
+    /// Durations after which sources are reconciled again, as currently set.
+    pub fn requeue(&self) -> requeue::Intervals {
+        self.settings.read().unwrap().requeue
+    }
+
+    /// How the target namespaces of the sources are resolved, with the currently excluded
+    /// namespaces.
+    fn targeting(&self) -> targets::Targeting {
+        targets::Targeting {
+            exclude_namespaces: self.settings.read().unwrap().exclude_namespaces.clone(),
+            ..self.targeting.clone()
+        }
+    }
+
+    /// Namespaces copies may currently be written to, all if empty.
+    fn allowed_target_namespaces(&self) -> Vec<String> {
+        self.settings.read().unwrap().allowed_target_namespaces.clone()
+    }
+
+    /// Whether the spreading of all sources is currently paused.
+    fn paused(&self) -> bool {
+        self.settings.read().unwrap().paused
+    }
+
+    /// Sets the client of the cluster the copies are written to.
+    pub fn with_target_client(mut self, target_client: Client) -> Self {
+        self.target_client = target_client;
+        self
+    }
+
+    /// Sets the reconcile slots of the controller using the context, the Secret controller's
+    /// unless set.
+    pub fn with_slots(mut self, slots: concurrency::Slots) -> Self {
+        self.reconcile_slots = slots;
+        self
+    }
+
+    /// Sets the cache of copies consulted before reading a copy from the API server.
+    pub fn with_copy_cache(mut self, copy_cache: cache::CopyCache) -> Self {
+        self.copy_cache = Some(copy_cache);
+        self
+    }
+
+    /// Sets the backend used to fetch the content of non-Secret sources, fetched again after
+    /// `refresh`.
+    #[cfg(feature = "vault")]
+    pub fn with_backend(mut self, backend: std::sync::Arc<dyn backend::SourceBackend>, refresh: Duration) -> Self {
+        self.backend = Some(backend);
+        self.backend_refresh = refresh;
+        self
+    }
+}
+
+/// All errors possible to occur during reconciliation
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// Any error originating from the `kube-rs` crate
+    #[error("Kubernetes reported error: {source}")]
+    KubeError {
+        #[from]
+        source: kube::Error,
+    },
+    /// Error in the spread annotations of a source or in the configuration of the operator,
+    /// shown in events, logs and admission webhook rejections.
+    #[error("Invalid spread configuration: {0}")]
+    UserInputError(String),
+    #[error("Missing Object key: {name}")]
+    MissingObjectKey {
+        name: &'static str
+    },
+    /// Any error reported by a source backend such as Vault.
+    #[cfg(feature = "vault")]
+    #[error("Source backend reported error: {0}")]
+    BackendError(String),
+    /// Syncing to several target namespaces failed, with the error of each namespace.
+    #[error("Syncing to target namespaces failed: {}", .0.iter().map(|(ns, e)| format!("{}: {}", ns, e)).collect::<Vec<_>>().join("; "))]
+    TargetErrors(Vec<(String, Error)>),
+    /// A failed reconcile of a source, to be retried after `requeue_after`, see [`on_error`].
+    #[error("{source}")]
+    Retry {
+        source: Box<Error>,
+        requeue_after: Duration,
+    },
+}
+
+impl Error {
+    /// Combines the errors of the target namespaces `failures`, a single one is kept as is.
+    fn from_target_failures(mut failures: Vec<(String, Error)>) -> Self {
+        if failures.len() == 1 {
+            failures.remove(0).1
+        } else {
+            Error::TargetErrors(failures)
+        }
+    }
+
+    /// Returns whether a request failed because its namespace is being deleted, the API server
+    /// forbids creating objects in a terminating namespace.
+    fn is_namespace_terminating(&self) -> bool {
+        match self {
+            Error::KubeError { source: kube::Error::Api(kube::error::ErrorResponse { code: 403, message, .. }) } => message.contains("being terminated"),
+            _ => false,
+        }
+    }
+
+    /// Returns whether the API server throttled a request, for one of the target namespaces
+    /// at least.
+    fn is_throttled(&self) -> bool {
+        match self {
+            Error::KubeError { source: kube::Error::Api(kube::error::ErrorResponse { code: 429, .. }) } => true,
+            Error::TargetErrors(failures) => failures.iter().any(|(_, e)| e.is_throttled()),
+            _ => false,
+        }
+    }
+}
+
+pub async fn reconcile(sec: Secret, context: Context<ContextData>) -> Result<ReconcilerAction, Error> {
+    // no reconcile starts once the operator shuts down, see shutdown::drain
+    let _running = match shutdown::begin() {
+        Some(guard) => guard,
+        None => return Ok(ReconcilerAction { requeue_after: None }),
+    };
+    // a Secret with the trigger label is spread as if it carried the default targets, removing
+    // the label cleans its copies up like removing the annotation
+    let sec = config::with_default_targets(sec, &context.get_ref().targeting());
+
+    // Secrets of the excluded types, e.g. service account tokens, are hardly ever meant to be
+    // spread and are left alone without requeue. Adding a targeting annotation reconciles them.
+    if excluded_type(&sec, &context.get_ref().exclude_types) && !targets::has_targets(&sec.metadata) && !finalizer::has_finalizer(&sec) {
+        return Ok(ReconcilerAction { requeue_after: None });
+    }
+
+    // A copy is never a source as well, that would be a misconfiguration or an attempt to spread
+    // copies recursively. Refuse to act on it before even reading its spread annotations, a
+    // deleted one may still be cleaned up though.
+    if is_copy(&sec) && targets::has_targets(&sec.metadata) && sec.metadata.deletion_timestamp.is_none() {
+        let message = format!(
+            "Secret carries the owner label {} of a copy and a target annotation, refusing to spread it",
+            keys::owner_label()
+        );
+        warn!(source_namespace = %sec.namespace().unwrap_or_default(), secret_name = %sec.name(), "{}", message);
+        context.get_ref().recorder.warn(&sec, "CopyIsSource", &message).await;
+        // removing the label or the annotation is a change of the secret, which reconciles it
+        return Ok(ReconcilerAction { requeue_after: None });
+    }
+
+    // A paused source keeps its copies and its finalizer, a deleted one waits for its cleanup.
+    // Removing the annotation is a change of the secret, which reconciles it.
+    if targets::annotation(&sec.metadata, PAUSED_ANNOTATION).as_deref() == Some("true") {
+        info!(source_namespace = %sec.namespace().unwrap_or_default(), secret_name = %sec.name(), "Spreading is paused");
+        return Ok(ReconcilerAction { requeue_after: None });
+    }
+    // Pausing all sources in the settings ConfigMap doesn't change the sources, they are checked
+    // again after the idle interval.
+    if context.get_ref().paused() {
+        debug!(source_namespace = %sec.namespace().unwrap_or_default(), secret_name = %sec.name(), "Spreading of all sources is paused");
+        return Ok(ReconcilerAction {
+            requeue_after: Some(context.get_ref().jitter.apply(context.get_ref().requeue().idle)),
+        });
+    }
+
+    // a misspelled annotation is silently ignored otherwise, the source looks like not spread
+    let unknown = config::unknown_annotations(&sec.metadata);
+    if !unknown.is_empty() {
+        let message = format!(
+            "Unknown annotations: {}",
+            unknown.iter().map(|(key, closest)| format!("{} (did you mean {}?)", key, closest)).collect::<Vec<_>>().join(", ")
+        );
+        warn!(source_namespace = %sec.namespace().unwrap_or_default(), secret_name = %sec.name(), "{}", message);
+        context.get_ref().recorder.warn(&sec, "UnknownAnnotation", &message).await;
+    }
+
+    let config = match config::SpreadConfig::from_secret(&sec) {
+        Ok(Some(config)) => Some(config),
+        // only a source spread before carries the finalizer, its copies are cleaned up like on
+        // deletion once it isn't spread anymore
+        Ok(None) if finalizer::has_finalizer(&sec) => {
+            info!(source_namespace = %sec.namespace().unwrap_or_default(), secret_name = %sec.name(), "Source no longer spread, cleaning up");
+            None
+        }
+        Ok(None) => {
+            return Ok(ReconcilerAction {
+                // Check again later if an annotation was added
+                requeue_after: Some(context.get_ref().jitter.apply(context.get_ref().requeue().idle)),
+            })
+        }
+        // a deleted source is cleaned up even if its annotations are invalid
+        Err(_) if sec.metadata.deletion_timestamp.is_some() => None,
+        Err(e) => return Err(e),
+    };
+
+    // A source is reconciled at most once per --min-reconcile-interval, a deleted one is cleaned
+    // up right away
+    let source_namespace = sec.namespace().unwrap_or_default();
+    if sec.metadata.deletion_timestamp.is_none() {
+        if let Some(wait) = context.get_ref().source_limiter.defer(&sec.metadata.uid.clone().unwrap_or_default()) {
+            debug!(source_namespace = %source_namespace, secret_name = %sec.name(), wait = ?wait, "Reconciled recently, deferring");
+            metrics::inc("spread_reconciles_rate_limited_total", &source_namespace);
+            return Ok(ReconcilerAction { requeue_after: Some(wait) });
+        }
+    }
+
+    // kube-runtime runs the reconciles of all due sources at once, they share one client
+    let _slot = context.get_ref().reconcile_slots.acquire().await;
+
+    // Sources are paced, the rate adapts to the throttling of the API server
+    let pacer = &context.get_ref().pacer;
+    pacer.acquire().await;
+    let started = std::time::Instant::now();
+    let source_uid = sec.metadata.uid.clone().unwrap_or_default();
+    let span = info_span!(
+        "reconcile",
+        source_namespace = %source_namespace,
+        secret_name = %sec.name(),
+        source_uid = %source_uid
+    );
+    let result = reconcile_source(sec, config, context.clone()).instrument(span).await;
+    metrics::observe_reconcile_duration(started.elapsed().as_secs_f64());
+    metrics::inc("spread_reconciles_total", &source_namespace);
+    if result.is_err() {
+        metrics::inc("spread_reconcile_errors_total", &source_namespace);
+    }
+    match &result {
+        Err(e) if e.is_throttled() => pacer.throttled(),
+        _ => pacer.succeeded(),
+    }
+    // a source failing again and again is retried less and less often
+    let backoff = &context.get_ref().backoff;
+    match result {
+        Ok(action) => {
+            backoff.succeeded(&source_uid);
+            Ok(action)
+        }
+        Err(e) => Err(Error::Retry {
+            requeue_after: backoff.failed(&source_uid),
+            source: Box::new(e),
+        }),
+    }
+}
+
+/// Returns whether `sec` is of one of the `excluded` types, see `--exclude-types`.
+fn excluded_type(sec: &Secret, excluded: &[String]) -> bool {
+    let type_ = compare::normalized_type(sec);
+    excluded.iter().any(|t| t.trim() == type_)
+}
+
+/// Returns whether `sec` carries the owner label, i.e. is a copy written by the operator.
+fn is_copy(sec: &Secret) -> bool {
+    sec.metadata.labels.as_ref().is_some_and(|l| l.contains_key(keys::owner_label()))
+}
+
+/// Spreads the source `sec` as configured by `config`, or cleans up its copies if it is deleted or
+/// comes without configuration, i.e. is no longer spread.
+async fn reconcile_source(sec: Secret, config: Option<config::SpreadConfig>, context: Context<ContextData>) -> Result<ReconcilerAction, Error> {
+
+    let source_namespace: String = match sec.namespace() {
+        None => {
+            return Err(Error::UserInputError(
+                "Expected Secret resource to be namespaced. Can't deploy to an unknown namespace."
+                    .to_owned(),
+            ));
+        }
+        // If namespace is known, proceed. In a more advanced version of the operator, perhaps
+        // the namespace could be checked for existence first.
+        Some(namespace) => namespace,
+    };
+
+    let source_uid: String = match &sec.metadata().uid {
+        None => {
+            return Err(Error::UserInputError(
+                "Expected Secret resource to have an uid"
+                    .to_owned(),
+            ));
+        },
+        Some(v) => v.clone(),
+    };
+
+    let name = sec.name();
+
+    let config = match config {
+        Some(config) if sec.metadata.deletion_timestamp.is_none() => config,
+        _ => return secret_cleanup(sec, context, source_namespace, name, source_uid).await,
+    };
+
+    // Service account tokens are bound to their namespace, a copy elsewhere is almost always a
+    // mistake. Refuse to spread them unless explicitly allowed.
+    if sec.type_.as_deref() == Some(SA_TOKEN_TYPE) && !config.allow_sa_token {
+        warn!("Refusing to spread service account token, set {}: \"true\" to allow it", ALLOW_SA_TOKEN_ANNOTATION);
+        return Ok(ReconcilerAction {
+            requeue_after: Some(context.get_ref().jitter.apply(context.get_ref().requeue().idle)),
+        });
+    }
+
+    if !config.condition_met(&sec.metadata) {
+        info!("Condition not met, not spreading");
+        if config.condition_cleanup {
+            let client: Client = context.get_ref().target_client.clone();
+            let dp = context.get_ref().delete_params();
+            let mut deleted = delete_copies::<Secret>(client.clone(), &source_uid, &dp).await?;
+            deleted.extend(generated::delete_recorded(client, &generated::recorded_names(&sec.metadata)?, &dp).await?);
+            for (ns, copy_name) in deleted {
+                context.get_ref().sinks.on_deleted(&sec, &ns, &copy_name).await;
+            }
+        }
+        return Ok(ReconcilerAction {
+            requeue_after: Some(context.get_ref().jitter.apply(context.get_ref().requeue().synced)),
+        });
+    }
+
+    // without the finalizer the copies couldn't be cleaned up once the source is deleted, unless
+    // it is disabled and the orphan scan cleans up
+    if context.get_ref().use_finalizer && !finalizer::add(context.get_ref().client.clone(), &name, &source_namespace, &sec, &context.get_ref().patch_params()).await? {
+        warn!("Finalizer not confirmed on the source, not spreading yet");
+        return Ok(ReconcilerAction {
+            requeue_after: Some(context.get_ref().requeue().error),
+        });
+    }
+    let history = context.get_ref().history.clone();
+    let source = format!("{}/{}", source_namespace, name);
+    let requeue_after = context.get_ref().jitter.apply(context.get_ref().requeue().synced);
+    match sync_secret(sec, &config, context.clone(), source_uid, source_namespace.clone(), name).await {
+        // failed namespaces are retried sooner, the status records the others already
+        Ok(outcome) if outcome.failure.is_some() => {
+            metrics::inc("spread_reconcile_errors_total", &source_namespace);
+            Ok(ReconcilerAction {
+                requeue_after: Some(context.get_ref().requeue().error),
+            })
+        }
+        // copies are synced, re-check later
+        Ok(_) => Ok(ReconcilerAction {
+            requeue_after: Some(requeue_after),
+        }),
+        Err(e) => {
+            history.record(&source, history::Entry {
+                error: Some(e.to_string()),
+                ..history::Entry::now()
+            });
+            Err(e)
+        }
+    }
+}
+
+/// Spreads the source `sec` to its target namespaces and prunes its stale copies. The outcome
+/// is logged as one event and returned.
+pub async fn sync_secret(sec: Secret, config: &config::SpreadConfig, context: Context<ContextData>, source_uid: String, source_namespace: String, name: String) -> Result<SyncOutcome, Error> {
+    // the target namespaces and the copies are in the target cluster, the source is not
+    let client: Client = context.get_ref().target_client.clone();
+    let source_client: Client = context.get_ref().client.clone();
+    // copies are written and compared from the keys that are spread only, renamed
+    let sec = config.copied(sec);
+
+    context.get_ref().configmap_index.update((source_namespace.clone(), name.clone()), targets::namespaces_from_reference(&sec.metadata));
+    context.get_ref().namespace_index.update((source_namespace.clone(), name.clone()), targets::expands(&sec.metadata), targets::namespace_selectors(&sec.metadata));
+
+    if targets::target_list_too_long(&sec.metadata) {
+        let message = format!(
+            "The {} list is longer than {} bytes and approaches the size limit of annotations, list the namespaces in a ConfigMap referenced by {} or select them with {}",
+            targets::TARGET_NAMESPACE_ANNOTATION, targets::LONG_TARGET_LIST_BYTES, targets::TARGET_NAMESPACES_FROM_ANNOTATION, targets::TARGET_POLICY_ANNOTATION
+        );
+        warn!("{}", message);
+        context.get_ref().recorder.warn(&sec, "LongTargetList", &message).await;
+    }
+    // while namespaces are being created, wait for the last one so all of them are spread at once
+    if targets::expands(&sec.metadata) {
+        context.get_ref().namespace_index.settle().await;
+    }
+    let mut targeted: BTreeMap<String, String> = config.resolve_copies(client.clone(), source_client.clone(), &sec.metadata, &name, &context.get_ref().targeting()).await?;
+    // the allow-list is the last word, neither `*` nor names listed explicitly get past it;
+    // copies already in a disallowed namespace are pruned like any untargeted copy
+    let allowed = context.get_ref().allowed_target_namespaces();
+    if !allowed.is_empty() {
+        targeted.retain(|ns, _| {
+            let is_allowed = allowed.contains(ns) || *ns == source_namespace;
+            if !is_allowed {
+                warn!(target_namespace = %ns, "Dropping target namespace not in ALLOWED_TARGET_NAMESPACES");
+            }
+            is_allowed
+        });
+    }
+    // a misconfigured `*` must not fan out into every namespace of a large cluster, such a
+    // source is refused as a whole before any copy is written
+    let max_targets = context.get_ref().max_targets_per_source;
+    let target_count = targeted.keys().filter(|ns| **ns != source_namespace).count();
+    if max_targets > 0 && target_count > max_targets {
+        let message = format!(
+            "The source targets {} namespaces, at most {} are allowed by MAX_TARGETS_PER_SOURCE",
+            target_count, max_targets
+        );
+        context.get_ref().recorder.warn(&sec, "TooManyTargets", &message).await;
+        return Err(Error::UserInputError(message));
+    }
+
+    // With generateName the API server picks the copy names, they are recorded on the source
+    // keyed by namespace so later reconciles and the cleanup find the copies again.
+    let recorded_names = generated::recorded_names(&sec.metadata)?;
+
+    // name of the copy in each target namespace, any other copy of the source there is stale
+    let mut desired_names: BTreeMap<String, String> = BTreeMap::new();
+    for (ns, target_name) in targeted.iter().filter(|(ns, _)| **ns != source_namespace) {
+        let desired_name = if config.use_generate_name { recorded_names.get(ns).cloned() } else { Some(target_name.clone()) };
+        if let Some(desired_name) = desired_name {
+            desired_names.insert(ns.clone(), desired_name);
+        }
+    }
+    let generated_names = futures::lock::Mutex::new(recorded_names);
+
+    let quarantine = &context.get_ref().quarantine;
+    let policy = policy::Policy::load(source_client.clone(), context.get_ref().policy_configmap.as_ref()).await?;
+    let mut outcome = SyncOutcome::default();
+    let mut failures: Vec<(String, Error)> = Vec::new();
+
+    info!("Spreading secret");
+
+    // The secrets under the copy names and the copies of the source are read first, then the
+    // plan decides what is done in each namespace, see plan::plan_sync. The namespaces are read
+    // like they are written, a namespace failing to be read fails on its own.
+    let copies_lp = ListParams::default().labels(&format!("{}={}", keys::owner_label(), source_uid));
+    let owned: Vec<Secret> = Api::<Secret>::all(client.clone()).list(&copies_lp).await?.items;
+    let mut fetched: HashMap<String, Result<Option<Secret>, Error>> = futures::stream::iter(targeted.keys().cloned())
+        .map(|ns| {
+            let (context, source_namespace, desired_names) = (&context, &source_namespace, &desired_names);
+            async move {
+                let existing = if ns == *source_namespace { Ok(None) } else { existing_copy(context, &ns, desired_names.get(&ns).map(String::as_str)).await };
+                (ns, existing)
+            }
+        })
+        .buffer_unordered(context.get_ref().sync_concurrency)
+        .collect()
+        .await;
+    let existing_targets: HashMap<String, Option<Secret>> = fetched
+        .iter()
+        .filter_map(|(ns, existing)| existing.as_ref().ok().map(|existing| (ns.clone(), existing.clone())))
+        .collect();
+    let plan = plan::plan_sync(&sec, &existing_targets, &owned, &desired_names, config, &context.get_ref().ignored_copy_keys);
+    if plan.skipped_source {
+        debug!(target_namespace = %source_namespace, "Skipping source namespace");
+        outcome.skipped_source = true;
+    }
+
+    let mut copies = Vec::new();
+    for (ns, target_name) in targeted.into_iter().filter(|(ns, _)| *ns != source_namespace) {
+        let planned = match fetched.remove(&ns) {
+            Some(Ok(existing)) => Ok((plan.steps[&ns], existing)),
+            Some(Err(e)) => Err(e),
+            None => continue,
+        };
+        copies.push((ns, target_name, planned));
+    }
+
+    // The namespaces are synced concurrently, up to `sync_concurrency` at a time. Each copy is
+    // written by one task only, the tasks share nothing but the generated names. A failing
+    // namespace must not keep the others from getting their copies, its error is reported once
+    // all namespaces were attempted.
+    let (sec, context, policy, generated_names, client) = (&sec, &context, &policy, &generated_names, &client);
+    let (source_uid, source_namespace, name) = (source_uid.as_str(), source_namespace.as_str(), name.as_str());
+    let mut results: Vec<(String, String, Result<Option<CopyAction>, Error>)> = futures::stream::iter(copies)
+        .map(|(ns, target_name, planned)| async move {
+            let result: Result<Option<CopyAction>, Error> = async {
+                let denied = if policy.is_empty() {
+                    None
+                } else {
+                    let namespace_api: Api<Namespace> = Api::all(client.clone());
+                    match namespace_api.get(&ns).await {
+                        Ok(namespace) => policy.check(sec, &namespace),
+                        Err(kube::Error::Api(kube::error::ErrorResponse { code: 404, .. })) => {
+                            skip_missing_namespace(context, sec, source_uid, &ns).await;
+                            return Ok(Some(CopyAction::Skipped));
+                        }
+                        Err(e) => return Err(e.into()),
+                    }
+                };
+
+                // a namespace failing over and over is skipped for a while, so it does not block the
+                // healthy ones. A namespace deleted and recreated under the same name is a new one and
+                // gets its copy right away.
+                let mut quarantined = quarantine.is_quarantined(source_uid, &ns);
+                if quarantined && quarantine.release_if_recreated(source_uid, &ns, targets::namespace_uid(client.clone(), &ns).await?.as_deref()) {
+                    info!(target_namespace = %ns, "Released recreated namespace from quarantine");
+                    quarantined = false;
+                }
+                if quarantined {
+                    context.get_ref().sinks.on_skipped(sec, &ns, "namespace is quarantined").await;
+                    return Ok(Some(CopyAction::Skipped));
+                } else if let Some(reason) = denied {
+                    metrics::inc("spread_policy_denied_total", source_namespace);
+                    context.get_ref().sinks.on_skipped(sec, &ns, &format!("denied by policy: {}", reason)).await;
+                    let message = format!("Copy to {} denied by policy: {}", ns, reason);
+                    context.get_ref().recorder.warn(sec, "PolicyDenied", &message).await;
+                    return Ok(Some(CopyAction::Skipped));
+                } else {
+                    let annotations = compare::desired_annotations(sec, &config.target_annotations, &ns);
+                    let mut result = match planned {
+                        Ok((step, existing)) => write_copy(sec, config, context, source_uid, source_namespace, name, &ns, &target_name, &annotations, existing.as_ref(), step, generated_names).await,
+                        Err(e) => Err(e),
+                    };
+                    // a copy can't be created in a namespace that doesn't exist
+                    let mut missing = matches!(result, Err(Error::KubeError { source: kube::Error::Api(kube::error::ErrorResponse { code: 404, .. }) }))
+                        && targets::namespace_uid(client.clone(), &ns).await?.is_none();
+                    if missing && config.create_namespace.unwrap_or(context.get_ref().create_namespaces) {
+                        create_namespace(context, &ns).await?;
+                        result = write_copy(sec, config, context, source_uid, source_namespace, name, &ns, &target_name, &annotations, None, CopyStep::Create, generated_names).await;
+                        missing = false;
+                    }
+                    if missing {
+                        skip_missing_namespace(context, sec, source_uid, &ns).await;
+                        return Ok(Some(CopyAction::Skipped));
+                    } else if result.as_ref().is_err_and(Error::is_namespace_terminating) {
+                        // the namespace is going away, there's nothing to sync anymore
+                        debug!(target_namespace = %ns, "Target namespace is being deleted, skipping");
+                        context.get_ref().sinks.on_skipped(sec, &ns, "namespace is being deleted").await;
+                        return Ok(Some(CopyAction::Skipped));
+                    } else {
+                        match result {
+                            Ok(action) => {
+                                match action {
+                                    CopyAction::Created | CopyAction::Updated => {
+                                        context.get_ref().recorder.normal(sec, "Synced", &format!("Synced to namespace {}", ns)).await;
+                                    }
+                                    CopyAction::Blocked => {
+                                        warn!(target_namespace = %ns, name = %target_name, "Blocked by unmanaged secret with the same name");
+                                        metrics::inc("spread_blocked_unmanaged_total", source_namespace);
+                                        context.get_ref().recorder.warn(sec, "Blocked", &format!("Blocked by unmanaged secret in {}, set {}: \"true\" to adopt it", ns, ADOPT_UNMANAGED_ANNOTATION)).await;
+                                    }
+                                    CopyAction::Unchanged | CopyAction::Skipped => {}
+                                }
+                                if quarantine.record_success(source_uid, &ns) {
+                                    info!(target_namespace = %ns, "Released namespace from quarantine");
+                                }
+                                return Ok(Some(action));
+                            }
+                            Err(e) => {
+                                let namespace_uid = targets::namespace_uid(client.clone(), &ns).await.unwrap_or(None);
+                                if !quarantine.record_failure(source_uid, &ns, namespace_uid) {
+                                    return Err(e);
+                                }
+                                warn!(target_namespace = %ns, error = %e, "Quarantining namespace after repeated failures");
+                                let message = format!("Quarantined target namespaces: {}", quarantine.quarantined(source_uid).join(", "));
+                                context.get_ref().recorder.warn(sec, "TargetQuarantined", &message).await;
+                            }
+                        }
+                    }
+                }
+                Ok(None)
+            }
+            .await;
+            (ns, target_name, result)
+        })
+        .buffer_unordered(context.get_ref().sync_concurrency)
+        .collect()
+        .await;
+    results.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let mut target_states: Vec<report::TargetState> = Vec::new();
+    for (ns, _, result) in results {
+        target_states.push(match &result {
+            Ok(Some(CopyAction::Blocked)) => report::TargetState::new(&ns, "Blocked", Some("unmanaged secret with the same name".to_string())),
+            Ok(Some(CopyAction::Skipped)) => report::TargetState::new(&ns, "Skipped", None),
+            Ok(Some(_)) => report::TargetState::new(&ns, "Synced", None),
+            Ok(None) => report::TargetState::new(&ns, "Error", Some("quarantined after repeated failures".to_string())),
+            Err(e) => report::TargetState::new(&ns, "Error", Some(e.to_string())),
+        });
+        match result {
+            Ok(Some(action)) => outcome.count(action),
+            Ok(None) => {}
+            Err(e) => {
+                warn!(target_namespace = %ns, error = %e, "Syncing to namespace failed");
+                outcome.failed += 1;
+                failures.push((ns.clone(), e));
+            }
+        }
+    }
+
+    // the stale copies were planned from the copies listed before the sync, so a namespace
+    // changing its labels during the reconcile can't get its fresh copy pruned
+    let mut stale_copies: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for (ns, copy_name) in plan.deletes {
+        stale_copies.entry(ns).or_default().push(copy_name);
+    }
+    let stale = delete_listed::<Secret>(client.clone(), stale_copies, &context.get_ref().delete_params()).await?;
+    outcome.pruned = stale.len() as u32;
+    for (ns, copy_name) in stale {
+        context.get_ref().sinks.on_deleted(sec, &ns, &copy_name).await;
+    }
+
+    let changed = outcome.pruned > 0 || outcome.created > 0 || outcome.updated > 0;
+    let source_kind = if targets::annotation(&sec.metadata, VAULT_PATH_ANNOTATION).is_some() { "ConfigMap" } else { "Secret" };
+    // the SpreadStatus shows the failed namespaces too, the annotations only successful syncs
+    if context.get_ref().status_crd {
+        let pp = context.get_ref().apply_params(true);
+        report::record(source_client.clone(), source_kind, name, source_namespace, source_uid, target_states, changed, &pp).await?;
+    }
+
+    // the other namespaces are synced regardless, the source is retried after the error interval
+    if !failures.is_empty() {
+        outcome.failure = Some(Error::from_target_failures(failures).to_string());
+    }
+
+    let target_count = outcome.created + outcome.updated + outcome.unchanged;
+    let pp = context.get_ref().patch_params();
+    if context.get_ref().status_crd {
+        // the source is left alone
+    } else if source_kind == "ConfigMap" {
+        status::record::<ConfigMap>(source_client.clone(), name, source_namespace, &sec.metadata, target_count, changed, &pp).await?;
+    } else {
+        status::record::<Secret>(source_client.clone(), name, source_namespace, &sec.metadata, target_count, changed, &pp).await?;
+    }
+
+    context.get_ref().history.record(&format!("{}/{}", source_namespace, name), history::Entry {
+        created: outcome.created,
+        updated: outcome.updated,
+        unchanged: outcome.unchanged,
+        skipped: outcome.blocked + outcome.skipped,
+        error: outcome.failure.clone(),
+        ..history::Entry::now()
+    });
+    info!(
+        created = outcome.created,
+        updated = outcome.updated,
+        unchanged = outcome.unchanged,
+        blocked = outcome.blocked,
+        skipped = outcome.skipped,
+        skipped_source = outcome.skipped_source,
+        pruned = outcome.pruned,
+        failed = outcome.failed,
+        "Spread secret"
+    );
+
+    Ok(outcome)
+}
+
+/// Skips the target namespace `ns` of the source `sec`, which doesn't exist. A listed namespace
+/// that is gone is warned about once, not on every reconcile, until it gets a copy again.
+async fn skip_missing_namespace(context: &Context<ContextData>, sec: &Secret, source_uid: &str, ns: &str) {
+    if context.get_ref().quarantine.record_missing(source_uid, ns) {
+        warn!(target_namespace = %ns, "Target namespace does not exist, skipping");
+    } else {
+        debug!(target_namespace = %ns, "Target namespace still does not exist, skipping");
+    }
+    context.get_ref().sinks.on_skipped(sec, ns, "namespace does not exist").await;
+}
+
+/// Creates the namespace `ns`. A namespace created meanwhile by somebody else is fine.
+async fn create_namespace(context: &Context<ContextData>, ns: &str) -> Result<(), Error> {
+    if context.get_ref().dry_run {
+        info!(target_namespace = %ns, "[dry-run] Would create missing target namespace");
+        return Ok(());
+    }
+    info!(target_namespace = %ns, "Creating missing target namespace");
+    let namespace_api: Api<Namespace> = Api::all(context.get_ref().target_client.clone());
+    let namespace = Namespace {
+        metadata: ObjectMeta {
+            name: Some(ns.to_string()),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    match namespace_api.create(&context.get_ref().post_params(), &namespace).await {
+        Ok(_) | Err(kube::Error::Api(kube::error::ErrorResponse { code: 409, .. })) => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// What happened to a copy during a sync.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CopyAction {
+    Created,
+    Updated,
+    Unchanged,
+    /// The copy was not written because an unmanaged secret has its name.
+    Blocked,
+    /// The namespace was left alone, e.g. because it is quarantined or doesn't exist.
+    Skipped,
+}
+
+/// Summary of a sync of a source: the number of target namespaces by what happened to their
+/// copy.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SyncOutcome {
+    pub created: u32,
+    pub updated: u32,
+    pub unchanged: u32,
+    /// Namespaces where an unmanaged secret has the name of the copy.
+    pub blocked: u32,
+    /// Namespaces left alone for other reasons, see [`CopyAction::Skipped`].
+    pub skipped: u32,
+    /// Namespaces the sync failed in.
+    pub failed: u32,
+    /// Stale copies deleted.
+    pub pruned: u32,
+    /// Whether the namespace of the source was targeted and skipped, it never gets a copy.
+    pub skipped_source: bool,
+    /// Errors of the namespaces the sync failed in, if any. The sync of the others is recorded
+    /// regardless and the source is requeued after the error interval.
+    pub failure: Option<String>,
+}
+
+impl SyncOutcome {
+    /// Counts `action` in the outcome.
+    fn count(&mut self, action: CopyAction) {
+        match action {
+            CopyAction::Created => self.created += 1,
+            CopyAction::Updated => self.updated += 1,
+            CopyAction::Unchanged => self.unchanged += 1,
+            CopyAction::Blocked => self.blocked += 1,
+            CopyAction::Skipped => self.skipped += 1,
+        }
+    }
+}
+
+/// Creates or updates the copy of the source `sec` named `target_name` in namespace `ns`: the
+/// secret under the name of the copy is read, the copy planned by [`plan::plan_copy`] and
+/// written by [`write_copy`].
+#[allow(clippy::too_many_arguments)]
+async fn sync_copy(sec: &Secret, config: &config::SpreadConfig, context: &Context<ContextData>, source_uid: &str, source_namespace: &str, name: &str, ns: &str, target_name: &str, annotations: &BTreeMap<String, String>, generated_names: &futures::lock::Mutex<BTreeMap<String, String>>) -> Result<CopyAction, Error> {
+    let copy_name = if config.use_generate_name { generated_names.lock().await.get(ns).cloned() } else { Some(target_name.to_string()) };
+    let existing = existing_copy(context, ns, copy_name.as_deref()).await?;
+    let step = plan::plan_copy(sec, existing.as_ref(), config, source_uid, &compare::with_owner_reference(annotations, sec), &context.get_ref().ignored_copy_keys);
+    write_copy(sec, config, context, source_uid, source_namespace, name, ns, target_name, annotations, existing.as_ref(), step, generated_names).await
+}
+
+/// Reads the secret named `copy_name` in `ns`, from the copy cache if it holds it. There is
+/// none without a name, i.e. before a copy of a generateName source was created in `ns`.
+async fn existing_copy(context: &Context<ContextData>, ns: &str, copy_name: Option<&str>) -> Result<Option<Secret>, Error> {
+    let copy_name = match copy_name {
+        Some(copy_name) => copy_name,
+        None => return Ok(None),
+    };
+    if let Some(cached) = context.get_ref().copy_cache.as_ref().and_then(|c| c.get(ns, copy_name)) {
+        return Ok(Some(cached));
+    }
+    let secret_api: Api<Secret> = Api::namespaced(context.get_ref().target_client.clone(), ns);
+    match secret_api.get(copy_name).await {
+        Ok(existing) => Ok(Some(existing)),
+        // the secret does not exist in the target namespace yet
+        Err(kube::Error::Api(kube::error::ErrorResponse { code: 404, .. })) => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Carries out the `step` planned for the copy of the source `sec` named `target_name` in
+/// namespace `ns`, where `existing` has the name of the copy. In generateName mode the name of a
+/// newly created copy is recorded in `generated_names`, which is locked for the whole write:
+/// the names are a single annotation of the source, so copies of a generateName source are
+/// written one at a time.
+///
+/// An immutable source is spread like any other, its immutability only forbids changing its
+/// data, which the operator never does. Its copies are immutable as well and replaced instead of
+/// patched when the source is recreated with new data.
+#[allow(clippy::too_many_arguments)]
+async fn write_copy(sec: &Secret, config: &config::SpreadConfig, context: &Context<ContextData>, source_uid: &str, source_namespace: &str, name: &str, ns: &str, target_name: &str, annotations: &BTreeMap<String, String>, existing: Option<&Secret>, step: CopyStep, generated_names: &futures::lock::Mutex<BTreeMap<String, String>>) -> Result<CopyAction, Error> {
+    let client: Client = context.get_ref().target_client.clone();
+    // the owner reference never changes for a source, so it is compared like the others
+    let annotations = &compare::with_owner_reference(annotations, sec);
+    // annotations written on the copy, the comparison only uses `annotations`
+    let written_annotations = if context.get_ref().stamp_source_version {
+        compare::with_source_version(annotations, sec)
+    } else {
+        annotations.clone()
+    };
+    let secret_api: Api<Secret> = Api::namespaced(client.clone(), ns);
+    let mut generated_names = if config.use_generate_name { Some(generated_names.lock().await) } else { None };
+
+    // A secret of the same name without owner label is only taken over with adopt-unmanaged.
+    // Adopting overwrites the secret somebody else created: its data is replaced by the data of
+    // the source, and it is deleted along with the source later on. A copy that merely lost its
+    // owner label still carries the owner reference to this source and is relabeled, without the
+    // label it would escape the cleanup and block the sync.
+    let existing_name = existing.map(|existing| existing.name()).unwrap_or_default();
+    if let Some(existing) = existing.filter(|_| step != CopyStep::Blocked) {
+        let owner = existing.metadata.labels.as_ref().and_then(|labels| labels.iter().find(|&a| a.0.eq_ignore_ascii_case(keys::owner_label())));
+        if plan::owned_unlabeled(existing, source_uid) {
+            warn!(target_namespace = ns, name = %existing_name, "Restoring the owner label of a copy");
+        } else if owner.is_none() && step == CopyStep::Replace {
+            // the type of a secret can't be patched, adopting one of another type deletes it
+            let message = format!(
+                "Recreating unmanaged secret {} in {} to adopt it, its type or immutability differs from the source",
+                existing_name, ns
+            );
+            warn!(target_namespace = ns, name = %existing_name, type_ = %compare::normalized_type(existing), "{}", message);
+            context.get_ref().recorder.warn(sec, "AdoptionRecreate", &message).await;
+        } else if owner.is_none() {
+            warn!(target_namespace = ns, name = %existing_name, "Adopting unmanaged secret");
+        }
+        // a copy of a previous source under the same name, e.g. deleted and recreated, is
+        // taken over: the patch sets the owner label to the uid of this source, so the
+        // cleanup of either source never misses or deletes the wrong copies
+        if let Some((_, owner)) = owner.filter(|(_, owner)| owner.as_str() != source_uid) {
+            info!(target_namespace = ns, name = %existing_name, previous_owner = %owner, "Taking over copy of a previous source");
+        }
+    }
+
+    if step == CopyStep::Replace {
+        if context.get_ref().dry_run {
+            info!(target_namespace = ns, name = %existing_name, "[dry-run] Would replace copy, type, immutability or immutable data changed");
+            return Ok(CopyAction::Updated);
+        }
+        info!(target_namespace = ns, name = %existing_name, type_ = %compare::normalized_type(sec), "Replacing copy, type, immutability or immutable data changed");
+        match secret_api.delete(&existing_name, &DeleteParams::default()).await {
+            Ok(_) | Err(kube::Error::Api(kube::error::ErrorResponse { code: 404, .. })) => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    // The copy as the operator wants it. It is written with server-side apply as field manager
+    // `--instance-name`: fields the operator applied before and no longer wants are removed, fields
+    // of other managers are kept. An existing copy is the operator's, it is owned or adopted, so
+    // conflicting fields are taken over; a new copy only conflicts with a secret created in the
+    // meantime, which is left to `--force-apply`.
+    let mut target_labels: BTreeMap<String, String> = compare::desired_labels(sec, source_uid);
+    target_labels.extend(compare::recommended_labels(&context.get_ref().managed_by));
+    let written_annotations = compare::with_content_hash(&written_annotations, compare::content_hash(sec, source_uid, annotations));
+    let written_annotations = compare::with_resynced(&written_annotations, sec);
+    let desired_copy = |name: Option<String>| Secret {
+        type_: Some(compare::normalized_type(sec)),
+        immutable: sec.immutable,
+        data: Some(compare::normalized_data(sec)),
+        metadata: ObjectMeta {
+            name,
+            namespace: Some(ns.to_string()),
+            labels: Some(target_labels.clone()),
+            annotations: Some(written_annotations.clone()),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+
+    let action = match step {
+        CopyStep::Create | CopyStep::Replace => {
+            if context.get_ref().dry_run {
+                info!(target_namespace = ns, name = %target_name, "[dry-run] Would create copy");
+                return Ok(CopyAction::Created);
+            }
+            let created = if config.use_generate_name {
+                // the API server only picks a name on create, apply needs one
+                let new_secret = Secret {
+                    metadata: ObjectMeta {
+                        generate_name: Some(format!("{}-", target_name)),
+                        ..desired_copy(None).metadata
+                    },
+                    ..desired_copy(None)
+                };
+                secret_api.create(&context.get_ref().post_params(), &new_secret).await?
+            } else {
+                secret_api.patch(target_name, &context.get_ref().apply_params(false), &Patch::Apply(&desired_copy(Some(target_name.to_string())))).await?
+            };
+            context.get_ref().sinks.on_created(sec, ns, &created.name()).await;
+            if let Some(names) = generated_names.as_mut() {
+                // record right away, a later failing namespace must not lose the name
+                names.insert(ns.to_string(), created.name());
+                generated::record_names(context.get_ref().client.clone(), name, source_namespace, names, &context.get_ref().patch_params()).await?;
+            }
+            CopyAction::Created
+        }
+        CopyStep::Update => {
+            let diff = existing
+                .map(|existing| compare::diff_data(&compare::managed_data(sec, existing, &context.get_ref().ignored_copy_keys), &compare::normalized_data(sec)))
+                .unwrap_or_default();
+            if context.get_ref().dry_run {
+                info!(target_namespace = ns, name = %existing_name, added = ?diff.added, removed = ?diff.removed, changed = ?diff.changed, "[dry-run] Would update copy");
+                return Ok(CopyAction::Updated);
+            }
+            debug!(target_namespace = ns, name = %existing_name, added = ?diff.added, removed = ?diff.removed, changed = ?diff.changed, "Updating copy");
+            let copy = desired_copy(Some(existing_name.clone()));
+            secret_api.patch(&existing_name, &context.get_ref().apply_params(true), &Patch::Apply(&copy)).await?;
+            context.get_ref().sinks.on_updated(sec, ns, &existing_name).await;
+            CopyAction::Updated
+        }
+        CopyStep::Unchanged => CopyAction::Unchanged,
+        CopyStep::Blocked => {
+            context.get_ref().sinks.on_skipped(sec, ns, "there is an unmanaged secret with the same name").await;
+            CopyAction::Blocked
+        }
+    };
+
+    Ok(action)
+}
+
+pub async fn secret_cleanup(sec: Secret, context: Context<ContextData>, source_namespace: String, name: String, source_uid: String) -> Result<ReconcilerAction, Error> {
+    // the copies are deleted in the target cluster, the finalizer is removed in the source cluster
+    let client: Client = context.get_ref().target_client.clone();
+
+    // the copies are looked up in the cache if it is warm, instead of listing all copies of the
+    // cluster by owner label
+    let by_namespace = match context.get_ref().copy_cache.as_ref().and_then(|c| c.copies_of(&source_uid)) {
+        Some(by_namespace) => by_namespace,
+        None => list_copies::<Secret>(client.clone(), &source_uid).await?,
+    };
+    // an invalid policy on a deleted source falls back to the default
+    if config::orphan_on_delete(&sec.metadata).unwrap_or(false) {
+        let released = release_listed(client.clone(), by_namespace, &context.get_ref().patch_params()).await?;
+        context.get_ref().recorder.normal(&sec, "CleanedUp", &format!("Released {} copies, delete-policy is orphan", released.len())).await;
+    } else {
+        let dp = context.get_ref().delete_params();
+        let mut deleted = delete_listed::<Secret>(client.clone(), by_namespace, &dp).await?;
+        deleted.extend(generated::delete_recorded(client.clone(), &generated::recorded_names(&sec.metadata)?, &dp).await?);
+        let cleaned_up = deleted.len();
+        for (ns, copy_name) in deleted {
+            context.get_ref().sinks.on_deleted(&sec, &ns, &copy_name).await;
+        }
+        context.get_ref().recorder.normal(&sec, "CleanedUp", &format!("Cleaned up {} copies", cleaned_up)).await;
+    }
+
+    // somebody else may have removed the finalizer meanwhile and the source is gone already,
+    // which rm tolerates; copies missed by then are left to the orphan scan
+    finalizer::rm(context.get_ref().client.clone(), &name, &source_namespace, &sec, &context.get_ref().patch_params()).await?;
+    context.get_ref().quarantine.forget(&source_uid);
+    context.get_ref().configmap_index.update((source_namespace.clone(), name.clone()), None);
+    context.get_ref().namespace_index.update((source_namespace.clone(), name.clone()), false, Vec::new());
+    context.get_ref().history.forget(&format!("{}/{}", source_namespace, name));
+
+    Ok(ReconcilerAction {
+        // Finalizer is added, copies are synced, re-check later.
+        requeue_after: None,
+    })
+}
+
+/// Deletes copies of kind `K` of the source with uid `source_uid` in the target namespaces of
+/// `desired_names` that are not named as desired, e.g. after the target name was changed.
+/// Copies in namespaces that aren't targeted are only deleted if `prune_untargeted` is set. Only
+/// copies owned by `source_uid` are considered, copies of other sources are never touched.
+/// Returns the namespaces and names of the deleted copies, none with a dry run `dp`.
+async fn delete_stale_copies<K>(client: Client, source_uid: &str, desired_names: &BTreeMap<String, String>, prune_untargeted: bool, dp: &DeleteParams) -> Result<Vec<(String, String)>, Error>
+where
+    K: Resource<DynamicType = ()> + Clone + DeserializeOwned + Debug,
+{
+    let api: Api<K> = Api::all(client.clone());
+    let mut deleted = Vec::new();
+
+    let lp = ListParams::default().labels(format!("{}={}", keys::owner_label(), source_uid).as_str());
+
+    for copy in api.list(&lp).await? {
+        let ns = match copy.namespace() {
+            Some(ns) => ns,
+            None => continue,
+        };
+        if !plan::is_stale(&ns, &copy.name(), desired_names, prune_untargeted) {
+            continue;
+        }
+        if dp.dry_run {
+            info!(target_namespace = %ns, name = %copy.name(), "[dry-run] Would delete stale copy");
+            continue;
+        }
+        let ns_api: Api<K> = Api::namespaced(client.clone(), &ns);
+        match ns_api.delete(copy.name().as_str(), dp).await {
+            Ok(_) => deleted.push((ns, copy.name())),
+            // gone meanwhile, e.g. together with its namespace
+            Err(kube::Error::Api(kube::error::ErrorResponse { code: 404, .. })) => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    Ok(deleted)
+}
+
+/// Deletes all copies of kind `K` carrying the owner label of the source with uid `source_uid`.
+/// Returns the namespaces and names of the deleted copies. Copies gone meanwhile count as
+/// deleted, a namespace failing doesn't stop the deletion in the others.
+///
+/// The copies are listed anew on every call, so a cleanup interrupted half way, e.g. by a
+/// restart of the operator, picks up the remaining copies on the next reconcile. The finalizer
+/// keeps the source around until then. With a dry run `dp` nothing is deleted.
+async fn delete_copies<K>(client: Client, source_uid: &str, dp: &DeleteParams) -> Result<Vec<(String, String)>, Error>
+where
+    K: Resource<DynamicType = ()> + Clone + DeserializeOwned + Debug,
+{
+    let by_namespace = list_copies::<K>(client.clone(), source_uid).await?;
+    delete_listed::<K>(client, by_namespace, dp).await
+}
+
+/// Lists the names of the copies of kind `K` of the source with uid `source_uid` by namespace.
+async fn list_copies<K>(client: Client, source_uid: &str) -> Result<BTreeMap<String, Vec<String>>, Error>
+where
+    K: Resource<DynamicType = ()> + Clone + DeserializeOwned + Debug,
+{
+    let api: Api<K> = Api::all(client);
+
+    let lp = ListParams::default().labels(format!("{}={}", keys::owner_label(), source_uid).as_str());
+
+    // copy names by namespace, so each namespace is addressed by one Api
+    let mut by_namespace: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for copy in api.list(&lp).await? {
+        match copy.namespace() {
+            Some(ns) => by_namespace.entry(ns).or_default().push(copy.name()),
+            None => warn!(name = %copy.name(), "Ignoring copy without namespace"),
+        }
+    }
+    Ok(by_namespace)
+}
+
+/// Turns the copies named in `by_namespace` into unmanaged secrets, for the `orphan` delete
+/// policy: the labels and annotations linking them to their source are removed, so neither the
+/// cleanup nor the orphan scan finds them anymore. Returns the namespaces and names of the
+/// released copies.
+async fn release_listed(client: Client, by_namespace: BTreeMap<String, Vec<String>>, pp: &PatchParams) -> Result<Vec<(String, String)>, Error> {
+    let patch = serde_json::json!({
+        "metadata": {
+            "labels": {
+                keys::owner_label(): null,
+                keys::key(compare::COPY_LABEL): null,
+                keys::key(compare::SOURCE_LABEL): null,
+                compare::MANAGED_BY_LABEL: null
+            },
+            "annotations": {
+                keys::key(compare::OWNER_REFERENCE_ANNOTATION): null,
+                keys::key(compare::SOURCE_RESOURCE_VERSION_ANNOTATION): null,
+                keys::key(compare::CONTENT_HASH_ANNOTATION): null,
+                keys::key(compare::RESYNCED_ANNOTATION): null
+            }
+        }
+    });
+    let mut released = Vec::new();
+    let mut failures: Vec<(String, Error)> = Vec::new();
+    for (ns, names) in by_namespace {
+        let ns_api: Api<Secret> = Api::namespaced(client.clone(), &ns);
+        for name in names {
+            if pp.dry_run {
+                info!(target_namespace = %ns, name = %name, "[dry-run] Would release copy");
+                continue;
+            }
+            match ns_api.patch(&name, pp, &Patch::Merge(&patch)).await {
+                Ok(_) => {
+                    info!(target_namespace = %ns, name = %name, "Released copy");
+                    released.push((ns.clone(), name));
+                }
+                Err(kube::Error::Api(kube::error::ErrorResponse { code: 404, .. })) => {}
+                Err(e) => failures.push((ns.clone(), e.into())),
+            }
+        }
+    }
+    if !failures.is_empty() {
+        return Err(Error::from_target_failures(failures));
+    }
+    Ok(released)
+}
+
+/// Deletes the copies of kind `K` named in `by_namespace`, see [`delete_copies`].
+async fn delete_listed<K>(client: Client, by_namespace: BTreeMap<String, Vec<String>>, dp: &DeleteParams) -> Result<Vec<(String, String)>, Error>
+where
+    K: Resource<DynamicType = ()> + Clone + DeserializeOwned + Debug,
+{
+    let mut deleted = Vec::new();
+    let mut failures: Vec<(String, Error)> = Vec::new();
+    for (ns, names) in by_namespace {
+        let ns_api: Api<K> = Api::namespaced(client.clone(), &ns);
+        for name in names {
+            if dp.dry_run {
+                info!(target_namespace = %ns, name = %name, "[dry-run] Would delete copy");
+                continue;
+            }
+            match ns_api.delete(&name, dp).await {
+                Ok(_) => deleted.push((ns.clone(), name)),
+                // gone meanwhile, e.g. together with its namespace
+                Err(kube::Error::Api(kube::error::ErrorResponse { code: 404, .. })) => {}
+                Err(e) => {
+                    failures.push((ns.clone(), e.into()));
+                    break;
+                }
+            }
+        }
+    }
+
+    if !failures.is_empty() {
+        return Err(Error::from_target_failures(failures));
+    }
+    Ok(deleted)
+}
+
+/// Actions to be taken when a reconciliation fails - for whatever reason.
+/// Prints out the error to `stderr` and requeues the resource for another reconciliation after
+/// the backoff of its source (see [`requeue::Backoff`]), or after the configured error interval
+/// (default five seconds) if the controller doesn't track one.
+///
+/// # Arguments
+/// - `error`: A reference to the `kube::Error` that occurred during reconciliation.
+/// - `context`: Context Data "injected" automatically by kube-rs.
+fn on_error(error: &Error, context: Context<ContextData>) -> ReconcilerAction {
+    error!(error = ?error, "Reconciliation error");
+    let requeue_after = match error {
+        Error::Retry { requeue_after, .. } => *requeue_after,
+        _ => context.get_ref().requeue().error,
+    };
+    ReconcilerAction {
+        requeue_after: Some(requeue_after),
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+    use k8s_openapi::ByteString;
+    use hyper::Method;
+
+    use crate::fake_api::FakeApi;
+
+    /// Context of the reconciles with the default options, talking to `client`.
+    fn context(client: Client) -> Context<ContextData> {
+        Context::new(ContextData::new(client, &Opts::parse_from(["spreading-operator"])))
+    }
+
+    fn namespace(name: &str) -> Namespace {
+        Namespace {
+            metadata: ObjectMeta {
+                name: Some(name.to_string()),
+                ..ObjectMeta::default()
+            },
+            ..Namespace::default()
+        }
+    }
+
+    fn secret(namespace: &str, name: &str, value: &str) -> Secret {
+        let mut data = BTreeMap::new();
+        data.insert("password".to_string(), ByteString(value.as_bytes().to_vec()));
+        Secret {
+            metadata: ObjectMeta {
+                name: Some(name.to_string()),
+                namespace: Some(namespace.to_string()),
+                ..ObjectMeta::default()
+            },
+            data: Some(data),
+            ..Secret::default()
+        }
+    }
+
+    /// Stores the source `source/<name>` spread to `targets` with the finalizer, returns it as
+    /// stored.
+    fn insert_source(fake: &FakeApi, name: &str, targets: &str) -> Secret {
+        insert_annotated(fake, secret("source", name, "secret"), &[(targets::TARGET_NAMESPACE_ANNOTATION, targets)])
+    }
+
+    /// Stores the source `sec` with the spread `annotations`, given without prefix, and the
+    /// finalizer, returns it as stored.
+    fn insert_annotated(fake: &FakeApi, mut sec: Secret, annotations: &[(&str, &str)]) -> Secret {
+        sec.metadata.annotations.get_or_insert_with(BTreeMap::new).extend(annotations.iter().map(|(key, value)| (keys::key(key), value.to_string())));
+        sec.metadata.finalizers = Some(vec![keys::finalizer().to_string()]);
+        serde_json::from_value(fake.insert(&sec)).unwrap()
+    }
+
+    /// Syncs the source `sec` as stored in `fake` right now.
+    async fn sync(fake: &FakeApi, context: &Context<ContextData>, sec: &Secret) -> SyncOutcome {
+        let sec: Secret = fake.get("source", &sec.name()).unwrap();
+        let config = config::SpreadConfig::from_secret(&sec).unwrap().unwrap();
+        let uid = sec.metadata.uid.clone().unwrap();
+        sync_secret(sec.clone(), &config, context.clone(), uid, "source".to_string(), sec.name()).await.unwrap()
+    }
+
+    /// Namespaces and names of the copies of the source with `uid`.
+    fn copies_of(fake: &FakeApi, uid: &str) -> Vec<(String, String)> {
+        fake.list::<Secret>()
+            .into_iter()
+            .filter(|s| s.metadata.labels.as_ref().and_then(|l| l.get(keys::owner_label())).map(String::as_str) == Some(uid))
+            .map(|s| (s.namespace().unwrap(), s.name()))
+            .collect()
+    }
+
+    /// Change of the source in a step of a [`scenario`].
+    enum Change {
+        /// Sets the `target-namespace` annotation.
+        Target(&'static str),
+        /// Removes the `target-namespace` annotation.
+        Untarget,
+        /// Sets the value of the data key `password`.
+        Data(&'static str),
+        /// Deletes the source.
+        Delete,
+    }
+
+    /// Creates the source `source/db` with the data `password: secret` and no annotations, then
+    /// applies each change of `steps` and reconciles the source once. After each step the source
+    /// has to have a copy in exactly the listed namespaces, carrying the data of the source.
+    async fn scenario(steps: &[(Change, &[&str])]) {
+        let (client, fake) = FakeApi::start();
+        for ns in &["source", "a", "b", "c"] {
+            fake.insert(&namespace(ns));
+        }
+        let sec: Secret = serde_json::from_value(fake.insert(&secret("source", "db", "secret"))).unwrap();
+        let uid = sec.metadata.uid.clone().unwrap();
+        let api: Api<Secret> = Api::namespaced(client.clone(), "source");
+        let context = context(client);
+        let mut value = "secret";
+
+        for (step, (change, expected)) in steps.iter().enumerate() {
+            let target = keys::key(targets::TARGET_NAMESPACE_ANNOTATION);
+            let patch = match change {
+                Change::Target(targets) => Some(serde_json::json!({ "metadata": { "annotations": { target: targets } } })),
+                Change::Untarget => Some(serde_json::json!({ "metadata": { "annotations": { target: null } } })),
+                Change::Data(data) => {
+                    value = data;
+                    Some(serde_json::json!({ "data": { "password": ByteString(data.as_bytes().to_vec()) } }))
+                }
+                Change::Delete => None,
+            };
+            match patch {
+                Some(patch) => {
+                    api.patch("db", &PatchParams::default(), &Patch::Merge(&patch)).await.unwrap();
+                }
+                None => {
+                    api.delete("db", &DeleteParams::default()).await.unwrap();
+                }
+            }
+            if let Some(sec) = fake.get::<Secret>("source", "db") {
+                reconcile(sec, context.clone()).await.unwrap();
+            }
+            // the cleanup removed the finalizer, the deletion is complete
+            if let Change::Delete = change {
+                assert!(fake.get::<Secret>("source", "db").is_none());
+            }
+
+            let mut copies: Vec<String> = copies_of(&fake, &uid).into_iter().map(|(ns, _)| ns).collect();
+            copies.sort();
+            assert_eq!(copies, *expected, "copies after step {}", step);
+            for ns in copies {
+                let copy: Secret = fake.get(&ns, "db").unwrap();
+                assert_eq!(copy.data.unwrap()["password"], ByteString(value.as_bytes().to_vec()), "data of the copy in {} after step {}", ns, step);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn scenario_expand_targets() {
+        scenario(&[
+            (Change::Target("a"), &["a"]),
+            (Change::Target("a,b"), &["a", "b"]),
+            (Change::Data("rotated"), &["a", "b"]),
+            (Change::Target("*"), &["a", "b", "c"]),
+        ])
+        .await;
+    }
+
+    #[tokio::test]
+    async fn scenario_shrink_targets() {
+        scenario(&[
+            (Change::Target("a,b,c"), &["a", "b", "c"]),
+            (Change::Target("a,c"), &["a", "c"]),
+            (Change::Data("rotated"), &["a", "c"]),
+            (Change::Target("c"), &["c"]),
+            (Change::Untarget, &[]),
+        ])
+        .await;
+    }
+
+    #[tokio::test]
+    async fn scenario_delete_source() {
+        scenario(&[
+            (Change::Target("a,b"), &["a", "b"]),
+            (Change::Data("rotated"), &["a", "b"]),
+            (Change::Delete, &[]),
+        ])
+        .await;
+    }
+
+    #[tokio::test]
+    async fn sync_secret_creates_copies() {
+        let (client, fake) = FakeApi::start();
+        for ns in &["source", "a", "b"] {
+            fake.insert(&namespace(ns));
+        }
+        let sec = insert_source(&fake, "db", "a,b");
+        let uid = sec.metadata.uid.clone().unwrap();
+        let context = context(client);
+
+        let outcome = sync(&fake, &context, &sec).await;
+        assert_eq!((outcome.created, outcome.updated, outcome.unchanged), (2, 0, 0));
+        assert_eq!(copies_of(&fake, &uid), vec![("a".to_string(), "db".to_string()), ("b".to_string(), "db".to_string())]);
+        let copy: Secret = fake.get("a", "db").unwrap();
+        assert_eq!(copy.data, sec.data);
+        assert!(compare::references_source(&copy, &uid));
+        let labels = copy.metadata.labels.unwrap();
+        assert_eq!(labels[keys::owner_label()], uid);
+        assert_eq!(labels[&keys::key(compare::SOURCE_LABEL)], "source.db");
+
+        let outcome = sync(&fake, &context, &sec).await;
+        assert_eq!((outcome.created, outcome.updated, outcome.unchanged), (0, 0, 2));
+    }
+
+    #[tokio::test]
+    async fn copy_of_untyped_source_is_opaque_and_not_patched_again() {
+        let (client, fake) = FakeApi::start();
+        for ns in &["source", "a"] {
+            fake.insert(&namespace(ns));
+        }
+        let sec = insert_source(&fake, "db", "a");
+        assert_eq!(sec.type_, None);
+        let context = context(client);
+
+        sync(&fake, &context, &sec).await;
+        assert_eq!(fake.get::<Secret>("a", "db").unwrap().type_.as_deref(), Some("Opaque"));
+        fake.take_writes();
+        let outcome = sync(&fake, &context, &sec).await;
+        assert_eq!((outcome.created, outcome.updated, outcome.unchanged), (0, 0, 1));
+        assert!(fake.take_writes().is_empty());
+    }
+
+    /// Sets the annotation `key`, without prefix, of the source `source/<name>` to `value`, or
+    /// removes it.
+    async fn annotate(client: &Client, name: &str, key: &str, value: Option<&str>) {
+        let patch = serde_json::json!({ "metadata": { "annotations": { keys::key(key): value } } });
+        Api::<Secret>::namespaced(client.clone(), "source").patch(name, &PatchParams::default(), &Patch::Merge(&patch)).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn bumped_resync_annotation_rewrites_copies_once() {
+        let (client, fake) = FakeApi::start();
+        for ns in &["source", "a", "b"] {
+            fake.insert(&namespace(ns));
+        }
+        let sec = insert_source(&fake, "db", "a,b");
+        let context = context(client.clone());
+        sync(&fake, &context, &sec).await;
+
+        annotate(&client, "db", compare::RESYNC_ANNOTATION, Some("1")).await;
+        let outcome = sync(&fake, &context, &sec).await;
+        assert_eq!((outcome.updated, outcome.unchanged), (2, 0));
+        let copy: Secret = fake.get("a", "db").unwrap();
+        assert_eq!(targets::annotation(&copy.metadata, compare::RESYNCED_ANNOTATION).as_deref(), Some("1"));
+        // the resync annotation itself is not copied
+        assert_eq!(targets::annotation(&copy.metadata, compare::RESYNC_ANNOTATION), None);
+        assert_eq!(copy.data, sec.data);
+
+        let outcome = sync(&fake, &context, &sec).await;
+        assert_eq!((outcome.updated, outcome.unchanged), (0, 2));
+    }
+
+    #[tokio::test]
+    async fn additions_of_a_webhook_to_copies_are_not_patched() {
+        let (client, fake) = FakeApi::start();
+        for ns in &["source", "a"] {
+            fake.insert(&namespace(ns));
+        }
+        let sec = insert_source(&fake, "db", "a");
+        let opts = Opts::parse_from(["spreading-operator", "--ignored-copy-keys", "ca.crt"]);
+        let context = Context::new(ContextData::new(client.clone(), &opts));
+        sync(&fake, &context, &sec).await;
+
+        // what a mutating webhook injects, the content hash included in case it is dropped
+        let patch = serde_json::json!({ "metadata": {
+            "labels": { "injected": "true" },
+            "annotations": { "injected-by": "webhook", keys::key(compare::CONTENT_HASH_ANNOTATION): null },
+        }, "data": { "ca.crt": ByteString(b"injected".to_vec()) } });
+        Api::<Secret>::namespaced(client, "a").patch("db", &PatchParams::default(), &Patch::Merge(&patch)).await.unwrap();
+        fake.take_writes();
+        let outcome = sync(&fake, &context, &sec).await;
+        assert_eq!((outcome.updated, outcome.unchanged), (0, 1));
+        assert!(fake.take_writes().is_empty());
+        assert_eq!(fake.get::<Secret>("a", "db").unwrap().metadata.labels.unwrap()["injected"], "true");
+    }
+
+    #[tokio::test]
+    async fn key_map_renames_keys_of_the_copies() {
+        let (client, fake) = FakeApi::start();
+        for ns in &["source", "a"] {
+            fake.insert(&namespace(ns));
+        }
+        let mut sec = secret("source", "db", "secret");
+        sec.data.as_mut().unwrap().insert("tls.crt".to_string(), ByteString(b"cert".to_vec()));
+        let sec = insert_annotated(&fake, sec, &[(targets::TARGET_NAMESPACE_ANNOTATION, "a"), (compare::KEY_MAP_ANNOTATION, "tls.crt=ca.crt")]);
+        let context = context(client);
+
+        sync(&fake, &context, &sec).await;
+        let data = fake.get::<Secret>("a", "db").unwrap().data.unwrap();
+        assert_eq!(data.keys().collect::<Vec<_>>(), vec!["ca.crt", "password"]);
+        assert_eq!(data["ca.crt"], ByteString(b"cert".to_vec()));
+        let outcome = sync(&fake, &context, &sec).await;
+        assert_eq!((outcome.updated, outcome.unchanged), (0, 1));
+    }
+
+    #[tokio::test]
+    async fn copies_carry_the_recommended_labels() {
+        let (client, fake) = FakeApi::start();
+        for ns in &["source", "a"] {
+            fake.insert(&namespace(ns));
+        }
+        let sec = insert_source(&fake, "db", "a");
+        let opts = Opts::parse_from(["spreading-operator", "--managed-by", "platform-operator"]);
+        sync(&fake, &Context::new(ContextData::new(client, &opts)), &sec).await;
+        let labels = fake.get::<Secret>("a", "db").unwrap().metadata.labels.unwrap();
+        assert_eq!(labels[compare::MANAGED_BY_LABEL], "platform-operator");
+        assert_eq!(labels[&keys::key(compare::COPY_LABEL)], "true");
+        assert_eq!(labels[keys::owner_label()], sec.metadata.uid.unwrap());
+    }
+
+    #[tokio::test]
+    async fn copies_are_stamped_with_the_source_version() {
+        let (client, fake) = FakeApi::start();
+        for ns in &["source", "a"] {
+            fake.insert(&namespace(ns));
+        }
+        let sec = insert_source(&fake, "db", "a");
+        let opts = Opts::parse_from(["spreading-operator", "--stamp-source-version"]);
+        let context = Context::new(ContextData::new(client.clone(), &opts));
+        let stamp = || targets::annotation(&fake.get::<Secret>("a", "db").unwrap().metadata, compare::SOURCE_RESOURCE_VERSION_ANNOTATION);
+
+        // the version the copy was written from, the sync patches the status of the source after
+        sync(&fake, &context, &sec).await;
+        let version = sec.metadata.resource_version.clone();
+        assert_eq!(stamp(), version);
+        let outcome = sync(&fake, &context, &sec).await;
+        assert_ne!(fake.get::<Secret>("source", "db").unwrap().metadata.resource_version, version);
+        assert_eq!(outcome.unchanged, 1);
+        assert_eq!(stamp(), version);
+
+        let patch = serde_json::json!({ "data": { "password": ByteString(b"rotated".to_vec()) } });
+        let rotated = Api::<Secret>::namespaced(client, "source").patch("db", &PatchParams::default(), &Patch::Merge(&patch)).await.unwrap();
+        sync(&fake, &context, &sec).await;
+        assert_ne!(rotated.metadata.resource_version, version);
+        assert_eq!(stamp(), rotated.metadata.resource_version);
+    }
+
+    #[tokio::test]
+    async fn target_annotations_are_set_per_namespace() {
+        let (client, fake) = FakeApi::start();
+        for ns in &["source", "a", "b"] {
+            fake.insert(&namespace(ns));
+        }
+        let mapping = r#"{"a": {"cost-center": "1234"}, "*": {"cost-center": "shared"}}"#;
+        let sec = insert_annotated(&fake, secret("source", "db", "secret"), &[(targets::TARGET_NAMESPACE_ANNOTATION, "a,b"), (compare::TARGET_ANNOTATIONS_ANNOTATION, mapping)]);
+        sync(&fake, &context(client), &sec).await;
+        for (ns, cost_center) in &[("a", "1234"), ("b", "shared")] {
+            let copy: Secret = fake.get(ns, "db").unwrap();
+            let annotations = copy.metadata.annotations.unwrap();
+            assert_eq!(annotations["cost-center"], *cost_center);
+            // next to the provenance annotations
+            assert!(annotations.contains_key(&keys::key(compare::OWNER_REFERENCE_ANNOTATION)));
+        }
+    }
+
+    #[tokio::test]
+    async fn copy_that_lost_its_owner_label_is_relabeled() {
+        let (client, fake) = FakeApi::start();
+        for ns in &["source", "a"] {
+            fake.insert(&namespace(ns));
+        }
+        let sec = insert_source(&fake, "db", "a");
+        let uid = sec.metadata.uid.clone().unwrap();
+        let context = context(client.clone());
+        sync(&fake, &context, &sec).await;
+
+        let patch = serde_json::json!({ "metadata": { "labels": { keys::owner_label(): null } } });
+        Api::<Secret>::namespaced(client, "a").patch("db", &PatchParams::default(), &Patch::Merge(&patch)).await.unwrap();
+        assert!(copies_of(&fake, &uid).is_empty());
+        let outcome = sync(&fake, &context, &sec).await;
+        assert_eq!((outcome.updated, outcome.blocked), (1, 0));
+        assert_eq!(copies_of(&fake, &uid), vec![("a".to_string(), "db".to_string())]);
+    }
+
+    #[tokio::test]
+    async fn key_filter_limits_and_removes_copied_keys() {
+        let (client, fake) = FakeApi::start();
+        for ns in &["source", "a"] {
+            fake.insert(&namespace(ns));
+        }
+        let mut sec = secret("source", "db", "secret");
+        sec.data.as_mut().unwrap().insert("ca.crt".to_string(), ByteString(b"cert".to_vec()));
+        let sec = insert_annotated(&fake, sec, &[(targets::TARGET_NAMESPACE_ANNOTATION, "a")]);
+        let context = context(client.clone());
+        let copied_keys = || fake.get::<Secret>("a", "db").unwrap().data.unwrap().into_keys().collect::<Vec<_>>();
+        sync(&fake, &context, &sec).await;
+        assert_eq!(copied_keys(), vec!["ca.crt", "password"]);
+
+        // the left out key is removed from the copy, which converges
+        annotate(&client, "db", compare::INCLUDE_KEYS_ANNOTATION, Some("ca.crt")).await;
+        assert_eq!(sync(&fake, &context, &sec).await.updated, 1);
+        assert_eq!(copied_keys(), vec!["ca.crt"]);
+        assert_eq!(sync(&fake, &context, &sec).await.unchanged, 1);
+
+        annotate(&client, "db", compare::INCLUDE_KEYS_ANNOTATION, None).await;
+        annotate(&client, "db", compare::EXCLUDE_KEYS_ANNOTATION, Some("ca.crt")).await;
+        sync(&fake, &context, &sec).await;
+        assert_eq!(copied_keys(), vec!["password"]);
+    }
+
+    #[tokio::test]
+    async fn allow_listed_annotations_are_copied() {
+        let (client, fake) = FakeApi::start();
+        for ns in &["source", "a"] {
+            fake.insert(&namespace(ns));
+        }
+        let mut sec = secret("source", "db", "secret");
+        sec.metadata.annotations = Some(vec![("team".to_string(), "platform".to_string()), ("owner".to_string(), "alice".to_string())].into_iter().collect());
+        let sec = insert_annotated(&fake, sec, &[(targets::TARGET_NAMESPACE_ANNOTATION, "a"), (compare::COPY_ANNOTATIONS_ANNOTATION, "team")]);
+        sync(&fake, &context(client), &sec).await;
+
+        let annotations = fake.get::<Secret>("a", "db").unwrap().metadata.annotations.unwrap();
+        assert_eq!(annotations["team"], "platform");
+        assert!(!annotations.contains_key("owner"));
+        assert!(!annotations.contains_key(&keys::key(targets::TARGET_NAMESPACE_ANNOTATION)));
+        assert!(!annotations.contains_key(&keys::key(compare::COPY_ANNOTATIONS_ANNOTATION)));
+    }
+
+    #[tokio::test]
+    async fn string_data_source_converges() {
+        let (client, fake) = FakeApi::start();
+        for ns in &["source", "a"] {
+            fake.insert(&namespace(ns));
+        }
+        let mut sec = secret("source", "db", "secret");
+        sec.data = None;
+        sec.string_data = Some(vec![("username".to_string(), "admin".to_string())].into_iter().collect());
+        sec.type_ = Some("kubernetes.io/basic-auth".to_string());
+        let sec = insert_annotated(&fake, sec, &[(targets::TARGET_NAMESPACE_ANNOTATION, "a")]);
+        let context = context(client.clone());
+        sync(&fake, &context, &sec).await;
+        let copy: Secret = fake.get("a", "db").unwrap();
+        assert_eq!(copy.data.unwrap()["username"], ByteString(b"admin".to_vec()));
+        assert_eq!(copy.string_data, None);
+        assert_eq!(copy.type_.as_deref(), Some("kubernetes.io/basic-auth"));
+
+        // compared field by field without the content hash, the copy is up to date as well
+        let patch = serde_json::json!({ "metadata": { "annotations": { keys::key(compare::CONTENT_HASH_ANNOTATION): null } } });
+        Api::<Secret>::namespaced(client, "a").patch("db", &PatchParams::default(), &Patch::Merge(&patch)).await.unwrap();
+        fake.take_writes();
+        assert_eq!(sync(&fake, &context, &sec).await.unchanged, 1);
+        assert!(fake.take_writes().is_empty());
+    }
+
+    #[tokio::test]
+    async fn immutable_source_is_spread_and_never_patched() {
+        let (client, fake) = FakeApi::start();
+        for ns in &["source", "a"] {
+            fake.insert(&namespace(ns));
+        }
+        let mut sec = secret("source", "db", "secret");
+        sec.immutable = Some(true);
+        let sec = insert_annotated(&fake, sec, &[(targets::TARGET_NAMESPACE_ANNOTATION, "a")]);
+        let context = context(client);
+
+        let outcome = sync(&fake, &context, &sec).await;
+        assert_eq!(outcome.created, 1);
+        let copy: Secret = fake.get("a", "db").unwrap();
+        assert_eq!((copy.immutable, copy.data), (Some(true), sec.data.clone()));
+        // only the status annotations of the source are written, never its data
+        let source: Secret = fake.get("source", "db").unwrap();
+        assert_eq!((source.immutable, source.data), (Some(true), sec.data.clone()));
+        let source_writes: Vec<_> = fake.take_writes().into_iter().filter(|(_, path)| path.contains("/namespaces/source/secrets/")).collect();
+        assert_eq!(source_writes, vec![(Method::PATCH, "/api/v1/namespaces/source/secrets/db".to_string())]);
+        assert_eq!(sync(&fake, &context, &sec).await.unchanged, 1);
+    }
+
+    #[tokio::test]
+    async fn immutable_copy_of_changed_source_is_replaced() {
+        let (client, fake) = FakeApi::start();
+        for ns in &["source", "a"] {
+            fake.insert(&namespace(ns));
+        }
+        let sec = insert_source(&fake, "db", "a");
+        let context = context(client.clone());
+        sync(&fake, &context, &sec).await;
+
+        // a mutable copy can't be made immutable in place
+        let patch = serde_json::json!({ "immutable": true });
+        Api::<Secret>::namespaced(client, "source").patch("db", &PatchParams::default(), &Patch::Merge(&patch)).await.unwrap();
+        fake.take_writes();
+        // deleted and created anew
+        assert_eq!(sync(&fake, &context, &sec).await.created, 1);
+        let copy_writes: Vec<_> = fake.take_writes().into_iter().filter(|(_, path)| path.contains("/namespaces/a/")).map(|(method, _)| method).collect();
+        assert_eq!(copy_writes, vec![Method::DELETE, Method::PATCH]);
+        assert_eq!(fake.get::<Secret>("a", "db").unwrap().immutable, Some(true));
+
+        // an immutable source only changes by being recreated, its immutable copy is recreated too
+        let mut recreated = secret("source", "db", "rotated");
+        recreated.immutable = Some(true);
+        let recreated = insert_annotated(&fake, recreated, &[(targets::TARGET_NAMESPACE_ANNOTATION, "a")]);
+        assert_eq!(sync(&fake, &context, &recreated).await.created, 1);
+        let copy_writes: Vec<_> = fake.take_writes().into_iter().filter(|(_, path)| path.contains("/namespaces/a/")).map(|(method, _)| method).collect();
+        assert_eq!(copy_writes, vec![Method::DELETE, Method::PATCH]);
+        let copy: Secret = fake.get("a", "db").unwrap();
+        assert_eq!((copy.immutable, copy.data), (Some(true), recreated.data));
+        assert_eq!(copies_of(&fake, recreated.metadata.uid.as_deref().unwrap()).len(), 1);
+    }
+
+    #[tokio::test]
+    async fn key_removed_from_source_is_removed_from_copies() {
+        let (client, fake) = FakeApi::start();
+        for ns in &["source", "a"] {
+            fake.insert(&namespace(ns));
+        }
+        let mut sec = secret("source", "db", "secret");
+        sec.data.as_mut().unwrap().insert("stale".to_string(), ByteString(b"old".to_vec()));
+        let sec = insert_annotated(&fake, sec, &[(targets::TARGET_NAMESPACE_ANNOTATION, "a")]);
+        let uid = sec.metadata.uid.clone().unwrap();
+        let context = context(client.clone());
+        sync(&fake, &context, &sec).await;
+        let patch = serde_json::json!({ "metadata": { "annotations": { "injected-by": "webhook" } } });
+        Api::<Secret>::namespaced(client.clone(), "a").patch("db", &PatchParams::default(), &Patch::Merge(&patch)).await.unwrap();
+
+        let patch = serde_json::json!({ "data": { "stale": null } });
+        Api::<Secret>::namespaced(client, "source").patch("db", &PatchParams::default(), &Patch::Merge(&patch)).await.unwrap();
+        assert_eq!(sync(&fake, &context, &sec).await.updated, 1);
+        let copy: Secret = fake.get("a", "db").unwrap();
+        assert_eq!(copy.data.unwrap().into_keys().collect::<Vec<_>>(), vec!["password"]);
+        assert_eq!(copy.metadata.labels.unwrap()[keys::owner_label()], uid);
+        assert_eq!(copy.metadata.annotations.unwrap()["injected-by"], "webhook");
+    }
+
+    #[tokio::test]
+    async fn label_added_to_source_reaches_existing_copies() {
+        let (client, fake) = FakeApi::start();
+        for ns in &["source", "a"] {
+            fake.insert(&namespace(ns));
+        }
+        let sec = insert_source(&fake, "db", "a");
+        let context = context(client.clone());
+        sync(&fake, &context, &sec).await;
+
+        let patch = serde_json::json!({ "metadata": { "labels": { "team": "platform" } } });
+        Api::<Secret>::namespaced(client, "source").patch("db", &PatchParams::default(), &Patch::Merge(&patch)).await.unwrap();
+        assert_eq!(sync(&fake, &context, &sec).await.updated, 1);
+        assert_eq!(fake.get::<Secret>("a", "db").unwrap().metadata.labels.unwrap()["team"], "platform");
+    }
+
+    #[tokio::test]
+    async fn labels_of_others_survive_updates() {
+        let (client, fake) = FakeApi::start();
+        for ns in &["source", "a"] {
+            fake.insert(&namespace(ns));
+        }
+        let sec = insert_source(&fake, "db", "a");
+        let uid = sec.metadata.uid.clone().unwrap();
+        let context = context(client.clone());
+        sync(&fake, &context, &sec).await;
+        let patch = serde_json::json!({ "metadata": { "labels": { "injected": "true" } } });
+        Api::<Secret>::namespaced(client.clone(), "a").patch("db", &PatchParams::default(), &Patch::Merge(&patch)).await.unwrap();
+
+        let patch = serde_json::json!({ "metadata": { "labels": { "team": "platform" } } });
+        Api::<Secret>::namespaced(client, "source").patch("db", &PatchParams::default(), &Patch::Merge(&patch)).await.unwrap();
+        assert_eq!(sync(&fake, &context, &sec).await.updated, 1);
+        let labels = fake.get::<Secret>("a", "db").unwrap().metadata.labels.unwrap();
+        assert_eq!(labels["injected"], "true");
+        assert_eq!(labels["team"], "platform");
+        assert_eq!(labels[keys::owner_label()], uid);
+    }
+
+    #[tokio::test]
+    async fn type_change_recreates_copies() {
+        let (client, fake) = FakeApi::start();
+        for ns in &["source", "a"] {
+            fake.insert(&namespace(ns));
+        }
+        let sec = insert_source(&fake, "db", "a");
+        let context = context(client);
+        sync(&fake, &context, &sec).await;
+
+        // the type of a secret can't be changed, the source is recreated with the new one
+        let mut retyped = secret("source", "db", "secret");
+        retyped.type_ = Some("kubernetes.io/basic-auth".to_string());
+        let retyped = insert_annotated(&fake, retyped, &[(targets::TARGET_NAMESPACE_ANNOTATION, "a")]);
+        fake.take_writes();
+        assert_eq!(sync(&fake, &context, &retyped).await.created, 1);
+        let copy_writes: Vec<_> = fake.take_writes().into_iter().filter(|(_, path)| path.contains("/namespaces/a/secrets/")).map(|(method, _)| method).collect();
+        assert_eq!(copy_writes, vec![Method::DELETE, Method::PATCH]);
+        let copy: Secret = fake.get("a", "db").unwrap();
+        assert_eq!(copy.type_.as_deref(), Some("kubernetes.io/basic-auth"));
+        assert_eq!(copy.metadata.labels.unwrap()[keys::owner_label()], retyped.metadata.uid.unwrap());
+    }
+
+    #[tokio::test]
+    async fn dockerconfigjson_is_replaced_as_a_whole() {
+        let (client, fake) = FakeApi::start();
+        for ns in &["source", "a"] {
+            fake.insert(&namespace(ns));
+        }
+        let config = |auths: serde_json::Value| ByteString(serde_json::json!({ "auths": auths }).to_string().into_bytes());
+        let mut sec = secret("source", "registry", "");
+        sec.type_ = Some("kubernetes.io/dockerconfigjson".to_string());
+        sec.data = Some(vec![(".dockerconfigjson".to_string(), config(serde_json::json!({ "old.example.com": { "auth": "b2xk" } })))].into_iter().collect());
+        let sec = insert_annotated(&fake, sec, &[(targets::TARGET_NAMESPACE_ANNOTATION, "a")]);
+        let context = context(client.clone());
+        sync(&fake, &context, &sec).await;
+
+        let rotated = config(serde_json::json!({ "new.example.com": { "auth": "bmV3" } }));
+        let patch = serde_json::json!({ "data": { ".dockerconfigjson": rotated } });
+        Api::<Secret>::namespaced(client, "source").patch("registry", &PatchParams::default(), &Patch::Merge(&patch)).await.unwrap();
+        assert_eq!(sync(&fake, &context, &sec).await.updated, 1);
+        let copy: Secret = fake.get("a", "registry").unwrap();
+        assert_eq!(copy.type_.as_deref(), Some("kubernetes.io/dockerconfigjson"));
+        assert_eq!(copy.data.unwrap().into_iter().collect::<Vec<_>>(), vec![(".dockerconfigjson".to_string(), rotated)]);
+    }
+
+    #[tokio::test]
+    async fn string_data_is_written_as_data() {
+        let (client, fake) = FakeApi::start();
+        for ns in &["source", "a"] {
+            fake.insert(&namespace(ns));
+        }
+        let mut sec = secret("source", "db", "secret");
+        sec.string_data = Some(vec![("password".to_string(), "plain".to_string()), ("user".to_string(), "admin".to_string())].into_iter().collect());
+        let sec = insert_annotated(&fake, sec, &[(targets::TARGET_NAMESPACE_ANNOTATION, "a")]);
+        sync(&fake, &context(client), &sec).await;
+        let copy: Secret = fake.get("a", "db").unwrap();
+        // no key in both data and stringData, which the API server would reject
+        assert_eq!(copy.string_data, None);
+        let data = copy.data.unwrap();
+        assert_eq!((&data["password"], &data["user"]), (&ByteString(b"plain".to_vec()), &ByteString(b"admin".to_vec())));
+    }
+
+    #[tokio::test]
+    async fn added_labels_are_set_on_copies_only() {
+        let (client, fake) = FakeApi::start();
+        for ns in &["source", "a"] {
+            fake.insert(&namespace(ns));
+        }
+        let sec = insert_annotated(&fake, secret("source", "db", "secret"), &[(targets::TARGET_NAMESPACE_ANNOTATION, "a"), (compare::ADD_LABELS_ANNOTATION, "managed-by=platform")]);
+        let context = context(client.clone());
+        sync(&fake, &context, &sec).await;
+        assert_eq!(fake.get::<Secret>("a", "db").unwrap().metadata.labels.unwrap()["managed-by"], "platform");
+        assert!(fake.get::<Secret>("source", "db").unwrap().metadata.labels.is_none());
+
+        // a changed label reaches the existing copy
+        annotate(&client, "db", compare::ADD_LABELS_ANNOTATION, Some("managed-by=team")).await;
+        let outcome = sync(&fake, &context, &sec).await;
+        assert_eq!(outcome.updated, 1);
+        assert_eq!(fake.get::<Secret>("a", "db").unwrap().metadata.labels.unwrap()["managed-by"], "team");
+    }
+
+    #[tokio::test]
+    async fn unknown_annotations_are_reported_with_an_event() {
+        let (client, fake) = FakeApi::start();
+        for ns in &["source", "a"] {
+            fake.insert(&namespace(ns));
+        }
+        let sec = insert_annotated(&fake, secret("source", "db", "secret"), &[(targets::TARGET_NAMESPACE_ANNOTATION, "a"), ("eu.fitzek.spread.target-namespaces", "b")]);
+        let uid = sec.metadata.uid.clone().unwrap();
+        reconcile(sec, context(client)).await.unwrap();
+
+        // reported, not rejected
+        assert_eq!(copies_of(&fake, &uid), vec![("a".to_string(), "db".to_string())]);
+        let events: Vec<k8s_openapi::api::core::v1::Event> = fake.list();
+        let event = events.iter().find(|e| e.reason.as_deref() == Some("UnknownAnnotation")).unwrap();
+        assert_eq!(event.type_.as_deref(), Some("Warning"));
+        assert_eq!(
+            event.message.as_deref(),
+            Some("Unknown annotations: eu.fitzek.spread.target-namespaces (did you mean eu.fitzek.spread.target-namespace?)")
+        );
+    }
+
+    #[tokio::test]
+    async fn sync_secret_skips_unmanaged_secret() {
+        let (client, fake) = FakeApi::start();
+        for ns in &["source", "a", "b"] {
+            fake.insert(&namespace(ns));
+        }
+        let unmanaged: Secret = serde_json::from_value(fake.insert(&secret("b", "db", "theirs"))).unwrap();
+        let sec = insert_source(&fake, "db", "a,b");
+        let uid = sec.metadata.uid.clone().unwrap();
+
+        let outcome = sync(&fake, &context(client), &sec).await;
+        assert_eq!((outcome.created, outcome.blocked), (1, 1));
+        assert_eq!(copies_of(&fake, &uid), vec![("a".to_string(), "db".to_string())]);
+        assert_eq!(fake.get::<Secret>("b", "db").unwrap(), unmanaged);
+        assert!(!fake.take_writes().iter().any(|(_, path)| path.contains("/namespaces/b/")));
+    }
+
+    #[tokio::test]
+    async fn secret_cleanup_deletes_copies() {
+        let (client, fake) = FakeApi::start();
+        for ns in &["source", "a", "b"] {
+            fake.insert(&namespace(ns));
+        }
+        let other = insert_source(&fake, "other", "a");
+        let sec = insert_source(&fake, "db", "a,b");
+        let uid = sec.metadata.uid.clone().unwrap();
+        let context = context(client.clone());
+        sync(&fake, &context, &other).await;
+        sync(&fake, &context, &sec).await;
+
+        // deleting the source only marks it deleted, the finalizer is still on it
+        Api::<Secret>::namespaced(client, "source").delete("db", &DeleteParams::default()).await.unwrap();
+        let deleted: Secret = fake.get("source", "db").unwrap();
+        assert!(deleted.metadata.deletion_timestamp.is_some());
+
+        secret_cleanup(deleted, context, "source".to_string(), "db".to_string(), uid.clone()).await.unwrap();
+        assert!(copies_of(&fake, &uid).is_empty());
+        assert!(fake.get::<Secret>("source", "db").is_none());
+        // the copies of another source are left alone
+        assert_eq!(copies_of(&fake, other.metadata.uid.as_deref().unwrap()), vec![("a".to_string(), "other".to_string())]);
+    }
+
+    #[tokio::test]
+    async fn settings_configmap_applies_to_next_reconcile() {
+        let (client, fake) = FakeApi::start();
+        for ns in &["source", "a", "b"] {
+            fake.insert(&namespace(ns));
+        }
+        let sec = insert_source(&fake, "db", "a,b");
+        let uid = sec.metadata.uid.clone().unwrap();
+        let opts = Opts::parse_from(["spreading-operator", "--requeue-jitter", "0"]);
+        let context = Context::new(ContextData::new(client.clone(), &opts));
+        let reconcile_source = || async { reconcile(fake.get("source", "db").unwrap(), context.clone()).await.unwrap() };
+
+        let action = reconcile_source().await;
+        assert_eq!(action.requeue_after, Some(Duration::from_secs(60)));
+        assert_eq!(copies_of(&fake, &uid).len(), 2);
+
+        let mut data = BTreeMap::new();
+        data.insert("allowed-target-namespaces".to_string(), "a".to_string());
+        data.insert("requeue-synced".to_string(), "600".to_string());
+        let mut cm = ConfigMap {
+            data: Some(data),
+            ..ConfigMap::default()
+        };
+        let shared = context.get_ref().settings();
+        settings::apply(&shared, &settings::Settings::from_opts(&opts), Some(&cm));
+        let action = reconcile_source().await;
+        assert_eq!(action.requeue_after, Some(Duration::from_secs(600)));
+        assert_eq!(copies_of(&fake, &uid), vec![("a".to_string(), "db".to_string())]);
+
+        // while paused, a change of the source isn't spread
+        cm.data.as_mut().unwrap().insert("paused".to_string(), "true".to_string());
+        settings::apply(&shared, &settings::Settings::from_opts(&opts), Some(&cm));
+        let patch = serde_json::json!({ "data": { "password": ByteString(b"rotated".to_vec()) } });
+        Api::<Secret>::namespaced(client, "source").patch("db", &PatchParams::default(), &Patch::Merge(&patch)).await.unwrap();
+        let action = reconcile_source().await;
+        assert_eq!(action.requeue_after, Some(Duration::from_secs(300)));
+        assert_eq!(fake.get::<Secret>("a", "db").unwrap().data, sec.data);
+
+        // the ConfigMap gone, the options apply again
+        settings::apply(&shared, &settings::Settings::from_opts(&opts), None);
+        reconcile_source().await;
+        assert_eq!(copies_of(&fake, &uid).len(), 2);
+        assert_eq!(fake.get::<Secret>("a", "db").unwrap().data.unwrap()["password"], ByteString(b"rotated".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn config_change_enqueues_spread_sources() {
+        let mut spread = secret("source", "spread", "secret");
+        let mut annotations = BTreeMap::new();
+        annotations.insert(keys::key(targets::TARGET_NAMESPACE_ANNOTATION), "a".to_string());
+        spread.metadata.annotations = Some(annotations);
+        let mut unspread = secret("source", "unspread", "secret");
+        unspread.metadata.finalizers = Some(vec![keys::finalizer().to_string()]);
+        let plain = secret("source", "plain", "secret");
+        let mut writer: reflector::store::Writer<Secret> = Default::default();
+        writer.apply_watcher_event(&watcher::Event::Restarted(vec![spread.clone(), unspread.clone(), plain]));
+
+        let triggered: Vec<_> = config_triggers(futures::stream::iter(vec![(), ()]).boxed(), writer.as_reader())
+            .map(Result::unwrap)
+            .collect()
+            .await;
+        let mut expected = vec![ObjectRef::from_obj(&spread), ObjectRef::from_obj(&unspread)];
+        expected.extend(expected.clone());
+        assert_eq!(triggered.len(), expected.len());
+        for object in expected {
+            assert!(triggered.contains(&object));
+        }
+    }
+
+    fn certificate(contents: &[u8]) -> Vec<u8> {
+        pem::encode(&pem::Pem {
+            tag: "CERTIFICATE".to_string(),
+            contents: contents.to_vec(),
+        })
+        .into_bytes()
+    }
+
+    #[test]
+    fn client_config_keeps_config_without_overrides() {
+        let config = kube::Config::new("https://cluster.example:6443".parse().unwrap());
+        let config = client_config(config, None, None).unwrap();
+        assert_eq!(config.cluster_url.as_str(), "https://cluster.example:6443/");
+        assert!(config.root_cert.is_none());
+    }
+
+    #[test]
+    fn client_config_overrides_url_and_adds_ca() {
+        let mut config = kube::Config::new("https://cluster.example:6443".parse().unwrap());
+        config.root_cert = Some(vec![vec![1]]);
+        let mut ca_pem = certificate(&[2]);
+        ca_pem.extend(pem::encode(&pem::Pem { tag: "PRIVATE KEY".to_string(), contents: vec![3] }).into_bytes());
+        ca_pem.extend(certificate(&[4]));
+
+        let config = client_config(config, Some("https://10.0.0.1:443"), Some(&ca_pem)).unwrap();
+        assert_eq!(config.cluster_url.as_str(), "https://10.0.0.1/");
+        assert_eq!(config.root_cert, Some(vec![vec![1], vec![2], vec![4]]));
+    }
+
+    #[test]
+    fn client_config_rejects_invalid_overrides() {
+        let config = kube::Config::new("https://cluster.example:6443".parse().unwrap());
+        assert!(client_config(config.clone(), Some("not a url"), None).is_err());
+        assert!(client_config(config, None, Some(b"no certificate")).is_err());
+    }
+
+    #[tokio::test]
+    async fn service_account_tokens_are_not_spread_by_default() {
+        let (client, fake) = FakeApi::start();
+        for ns in &["source", "a"] {
+            fake.insert(&namespace(ns));
+        }
+        let mut token = secret("source", "builder-token", "token");
+        token.type_ = Some(SA_TOKEN_TYPE.to_string());
+        let sec = insert_annotated(&fake, token, &[(targets::TARGET_NAMESPACE_ANNOTATION, "a")]);
+        let uid = sec.metadata.uid.clone().unwrap();
+        let context = context(client.clone());
+        reconcile(sec, context.clone()).await.unwrap();
+        assert!(copies_of(&fake, &uid).is_empty());
+        assert!(fake.take_writes().iter().all(|(_, path)| !path.contains("/namespaces/a/")));
+
+        annotate(&client, "builder-token", ALLOW_SA_TOKEN_ANNOTATION, Some("true")).await;
+        reconcile(fake.get("source", "builder-token").unwrap(), context).await.unwrap();
+        assert_eq!(copies_of(&fake, &uid), vec![("a".to_string(), "builder-token".to_string())]);
+    }
+
+    #[tokio::test]
+    async fn generate_name_copies_are_recorded_and_cleaned_up() {
+        let (client, fake) = FakeApi::start();
+        for ns in &["source", "a", "b"] {
+            fake.insert(&namespace(ns));
+        }
+        // a secret of somebody else with the name of the source is left alone
+        fake.insert(&secret("a", "db", "theirs"));
+        let sec = insert_annotated(&fake, secret("source", "db", "secret"), &[(targets::TARGET_NAMESPACE_ANNOTATION, "a,b"), (generated::USE_GENERATE_NAME_ANNOTATION, "true")]);
+        let uid = sec.metadata.uid.clone().unwrap();
+        let context = context(client.clone());
+        reconcile(sec, context.clone()).await.unwrap();
+
+        let sec: Secret = fake.get("source", "db").unwrap();
+        let recorded = generated::recorded_names(&sec.metadata).unwrap();
+        assert_eq!(recorded.keys().collect::<Vec<_>>(), vec!["a", "b"]);
+        let mut copies = copies_of(&fake, &uid);
+        copies.sort();
+        assert_eq!(copies, recorded.iter().map(|(ns, name)| (ns.clone(), name.clone())).collect::<Vec<_>>());
+        assert!(recorded.values().all(|name| name.starts_with("db-")));
+        assert_eq!(fake.get::<Secret>("a", "db").unwrap().data.unwrap()["password"], ByteString(b"theirs".to_vec()));
+
+        // synced again, the recorded copies are updated, not created once more
+        reconcile(sec, context.clone()).await.unwrap();
+        assert_eq!(copies_of(&fake, &uid).len(), 2);
+
+        Api::<Secret>::namespaced(client, "source").delete("db", &DeleteParams::default()).await.unwrap();
+        reconcile(fake.get("source", "db").unwrap(), context).await.unwrap();
+        assert!(fake.get::<Secret>("source", "db").is_none());
+        assert!(copies_of(&fake, &uid).is_empty());
+        assert!(fake.get::<Secret>("a", "db").is_some());
+    }
+
+    #[tokio::test]
+    async fn copies_follow_the_condition() {
+        for cleanup in &[false, true] {
+            let (client, fake) = FakeApi::start();
+            for ns in &["source", "a"] {
+                fake.insert(&namespace(ns));
+            }
+            let mut annotations = vec![(targets::TARGET_NAMESPACE_ANNOTATION, "a"), (CONDITION_ANNOTATION, "myapp.io/ready=true")];
+            if *cleanup {
+                annotations.push((CONDITION_CLEANUP_ANNOTATION, "true"));
+            }
+            let sec = insert_annotated(&fake, secret("source", "db", "secret"), &annotations);
+            let uid = sec.metadata.uid.clone().unwrap();
+            let context = context(client.clone());
+            let reconcile_source = || {
+                let sec: Secret = fake.get("source", "db").unwrap();
+                let context = context.clone();
+                async move { reconcile(sec, context).await.unwrap() }
+            };
+            let copy = vec![("a".to_string(), "db".to_string())];
+
+            // not met yet, requeued without spreading
+            let action = reconcile_source().await;
+            assert!(action.requeue_after.is_some());
+            assert!(copies_of(&fake, &uid).is_empty());
+
+            annotate(&client, "db", "myapp.io/ready", Some("false")).await;
+            reconcile_source().await;
+            assert!(copies_of(&fake, &uid).is_empty());
+
+            annotate(&client, "db", "myapp.io/ready", Some("true")).await;
+            reconcile_source().await;
+            assert_eq!(copies_of(&fake, &uid), copy);
+
+            // false again, the copies are only deleted with condition-cleanup
+            annotate(&client, "db", "myapp.io/ready", Some("false")).await;
+            reconcile_source().await;
+            assert_eq!(copies_of(&fake, &uid).is_empty(), *cleanup);
+
+            annotate(&client, "db", "myapp.io/ready", Some("true")).await;
+            reconcile_source().await;
+            assert_eq!(copies_of(&fake, &uid), copy);
+        }
+    }
+
+    #[tokio::test]
+    async fn changed_target_name_replaces_the_old_copies() {
+        let (client, fake) = FakeApi::start();
+        for ns in &["source", "a", "b"] {
+            fake.insert(&namespace(ns));
+        }
+        let sec = insert_annotated(&fake, secret("source", "db", "secret"), &[(targets::TARGET_NAMESPACE_ANNOTATION, "a,b"), (naming::TARGET_NAME_ANNOTATION, "db-{namespace}")]);
+        let uid = sec.metadata.uid.clone().unwrap();
+        let context = context(client.clone());
+        reconcile(sec, context.clone()).await.unwrap();
+        let mut copies = copies_of(&fake, &uid);
+        copies.sort();
+        assert_eq!(copies, vec![("a".to_string(), "db-a".to_string()), ("b".to_string(), "db-b".to_string())]);
+
+        annotate(&client, "db", naming::TARGET_NAME_ANNOTATION, Some("shared")).await;
+        reconcile(fake.get("source", "db").unwrap(), context.clone()).await.unwrap();
+        let mut copies = copies_of(&fake, &uid);
+        copies.sort();
+        assert_eq!(copies, vec![("a".to_string(), "shared".to_string()), ("b".to_string(), "shared".to_string())]);
+
+        // back to the source name
+        annotate(&client, "db", naming::TARGET_NAME_ANNOTATION, None).await;
+        reconcile(fake.get("source", "db").unwrap(), context).await.unwrap();
+        let mut copies = copies_of(&fake, &uid);
+        copies.sort();
+        assert_eq!(copies, vec![("a".to_string(), "db".to_string()), ("b".to_string(), "db".to_string())]);
+    }
+
+    #[tokio::test]
+    async fn instance_name_reports_the_events_and_manages_the_fields() {
+        let (client, fake) = FakeApi::start();
+        for ns in &["source", "a"] {
+            fake.insert(&namespace(ns));
+        }
+        let sec = insert_source(&fake, "db", "a");
+        let opts = Opts::parse_from(["spreading-operator", "--instance-name", "spreading-operator-prod"]);
+        let context = Context::new(ContextData::new(client, &opts));
+        reconcile(sec, context.clone()).await.unwrap();
+
+        let events: Vec<k8s_openapi::api::core::v1::Event> = fake.list();
+        assert!(!events.is_empty());
+        for event in events {
+            assert_eq!(event.reporting_component.as_deref(), Some("spreading-operator-prod"));
+            assert_eq!(event.source.and_then(|s| s.component).as_deref(), Some("spreading-operator-prod"));
+        }
+        let data = context.get_ref();
+        assert_eq!(data.post_params().field_manager.as_deref(), Some("spreading-operator-prod"));
+        assert_eq!(data.patch_params().field_manager.as_deref(), Some("spreading-operator-prod"));
+        assert_eq!(data.apply_params(true).field_manager.as_deref(), Some("spreading-operator-prod"));
+        let default = ContextData::new(data.client.clone(), &Opts::parse_from(["spreading-operator"]));
+        assert_eq!(default.patch_params().field_manager.as_deref(), Some("spreading-operator"));
+    }
+
+    /// Sets the label `key` of the namespace `name` to `value`, or removes it.
+    async fn label_namespace(client: &Client, name: &str, key: &str, value: Option<&str>) {
+        let patch = serde_json::json!({ "metadata": { "labels": { key: value } } });
+        Api::<Namespace>::all(client.clone()).patch(name, &PatchParams::default(), &Patch::Merge(&patch)).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn namespace_relabeled_out_of_the_selector_loses_its_copy() {
+        let (client, fake) = FakeApi::start();
+        for ns in &["source", "a", "b"] {
+            fake.insert(&namespace(ns));
+        }
+        label_namespace(&client, "a", "tenant", Some("true")).await;
+        label_namespace(&client, "b", "tenant", Some("true")).await;
+        let sec = insert_annotated(&fake, secret("source", "db", "secret"), &[(targets::TARGET_NAMESPACE_SELECTOR_ANNOTATION, "tenant=true")]);
+        let uid = sec.metadata.uid.clone().unwrap();
+        let context = context(client.clone());
+        reconcile(sec, context.clone()).await.unwrap();
+        let mut copies = copies_of(&fake, &uid);
+        copies.sort();
+        assert_eq!(copies, vec![("a".to_string(), "db".to_string()), ("b".to_string(), "db".to_string())]);
+
+        label_namespace(&client, "b", "tenant", Some("false")).await;
+        reconcile(fake.get("source", "db").unwrap(), context.clone()).await.unwrap();
+        assert_eq!(copies_of(&fake, &uid), vec![("a".to_string(), "db".to_string())]);
+
+        label_namespace(&client, "a", "tenant", None).await;
+        reconcile(fake.get("source", "db").unwrap(), context).await.unwrap();
+        assert!(copies_of(&fake, &uid).is_empty());
+    }
+
+    #[tokio::test]
+    async fn failing_namespace_is_quarantined_while_the_others_are_synced() {
+        let (client, fake) = FakeApi::start();
+        for ns in &["source", "a", "b"] {
+            fake.insert(&namespace(ns));
+        }
+        fake.fail(Method::GET, "/api/v1/namespaces/b/secrets/db", Some(500));
+        let sec = insert_source(&fake, "db", "a,b");
+        let uid = sec.metadata.uid.clone().unwrap();
+        let opts = Opts::parse_from(["spreading-operator", "--quarantine-threshold", "2", "--quarantine-retry", "3600"]);
+        let context = Context::new(ContextData::new(client, &opts));
+
+        let first = sync(&fake, &context, &sec).await;
+        assert!(first.failure.is_some());
+        assert!(!context.get_ref().quarantine.is_quarantined(&uid, "b"));
+        let second = sync(&fake, &context, &sec).await;
+        assert!(second.failure.is_none());
+        assert_eq!(context.get_ref().quarantine.quarantined(&uid), vec!["b"]);
+        assert_eq!(copies_of(&fake, &uid), vec![("a".to_string(), "db".to_string())]);
+        let events: Vec<k8s_openapi::api::core::v1::Event> = fake.list();
+        assert!(events.iter().any(|e| e.reason.as_deref() == Some("TargetQuarantined") && e.message.as_deref() == Some("Quarantined target namespaces: b")));
+
+        // skipped while quarantined, the sync doesn't fail on it anymore
+        assert!(sync(&fake, &context, &sec).await.failure.is_none());
+        assert_eq!(context.get_ref().quarantine.quarantined(&uid), vec!["b"]);
+    }
+
+    #[tokio::test]
+    async fn copies_denied_by_the_policy_are_skipped_with_an_event() {
+        let (client, fake) = FakeApi::start();
+        for ns in &["tls-source", "b"] {
+            fake.insert(&namespace(ns));
+        }
+        let mut allowed = namespace("a");
+        allowed.metadata.labels = Some(std::iter::once(("tls-allowed".to_string(), "true".to_string())).collect());
+        fake.insert(&allowed);
+        fake.insert(&k8s_openapi::api::core::v1::ConfigMap {
+            metadata: ObjectMeta {
+                name: Some("policy".to_string()),
+                namespace: Some("operator".to_string()),
+                ..ObjectMeta::default()
+            },
+            data: Some(std::iter::once(("rules".to_string(), "- types: [kubernetes.io/tls]\n  requireNamespaceLabel: tls-allowed\n".to_string())).collect()),
+            ..Default::default()
+        });
+        let mut tls = secret("tls-source", "cert", "secret");
+        tls.type_ = Some("kubernetes.io/tls".to_string());
+        tls.metadata.annotations = Some(std::iter::once((keys::key(targets::TARGET_NAMESPACE_ANNOTATION), "a,b".to_string())).collect());
+        tls.metadata.finalizers = Some(vec![keys::finalizer().to_string()]);
+        let tls: Secret = serde_json::from_value(fake.insert(&tls)).unwrap();
+        let uid = tls.metadata.uid.clone().unwrap();
+        let opts = Opts::parse_from(["spreading-operator", "--spread-policy-configmap", "operator/policy"]);
+        reconcile(tls, Context::new(ContextData::new(client, &opts))).await.unwrap();
+
+        assert_eq!(copies_of(&fake, &uid), vec![("a".to_string(), "cert".to_string())]);
+        let events: Vec<k8s_openapi::api::core::v1::Event> = fake.list();
+        assert!(events.iter().any(|e| e.reason.as_deref() == Some("PolicyDenied")
+            && e.message.as_deref() == Some("Copy to b denied by policy: kubernetes.io/tls secrets require the label tls-allowed on namespace b")));
+        assert!(metrics::render().contains("spread_policy_denied_total{namespace=\"tls-source\"} 1"));
+    }
+
+    #[tokio::test]
+    async fn copy_is_recreated_in_a_recreated_namespace() {
+        let (client, fake) = FakeApi::start();
+        for ns in &["source", "a"] {
+            fake.insert(&namespace(ns));
+        }
+        let sec = insert_source(&fake, "db", "a");
+        let uid = sec.metadata.uid.clone().unwrap();
+        let opts = Opts::parse_from(["spreading-operator", "--quarantine-threshold", "1", "--quarantine-retry", "3600"]);
+        let context = Context::new(ContextData::new(client.clone(), &opts));
+        sync(&fake, &context, &sec).await;
+        assert_eq!(copies_of(&fake, &uid), vec![("a".to_string(), "db".to_string())]);
+
+        // the terminating namespace fails the sync and is quarantined
+        fake.fail(Method::GET, "/api/v1/namespaces/a/secrets/db", Some(500));
+        sync(&fake, &context, &sec).await;
+        assert!(context.get_ref().quarantine.is_quarantined(&uid, "a"));
+
+        // deleted with its copy and recreated under the same name
+        fake.fail(Method::GET, "/api/v1/namespaces/a/secrets/db", None);
+        Api::<Secret>::namespaced(client.clone(), "a").delete("db", &DeleteParams::default()).await.unwrap();
+        Api::<Namespace>::all(client).delete("a", &DeleteParams::default()).await.unwrap();
+        assert!(fake.get::<Namespace>("", "a").is_none());
+        fake.insert(&namespace("a"));
+
+        let outcome = sync(&fake, &context, &sec).await;
+        assert_eq!(outcome.created, 1);
+        assert!(!context.get_ref().quarantine.is_quarantined(&uid, "a"));
+        assert_eq!(copies_of(&fake, &uid), vec![("a".to_string(), "db".to_string())]);
+    }
+
+    #[tokio::test]
+    async fn max_namespaces_spreads_to_the_first_by_name_and_cleans_up_when_lowered() {
+        let (client, fake) = FakeApi::start();
+        for ns in &["source", "team-d", "team-b", "team-a", "team-c"] {
+            fake.insert(&namespace(ns));
+        }
+        let sec = insert_annotated(&fake, secret("source", "db", "secret"), &[(targets::TARGET_NAMESPACE_ANNOTATION, "team-*"), (targets::MAX_NAMESPACES_ANNOTATION, "3")]);
+        let uid = sec.metadata.uid.clone().unwrap();
+        let context = context(client.clone());
+        let namespaces = || {
+            let mut copies: Vec<String> = copies_of(&fake, &uid).into_iter().map(|(ns, _)| ns).collect();
+            copies.sort();
+            copies
+        };
+        reconcile(sec, context.clone()).await.unwrap();
+        assert_eq!(namespaces(), vec!["team-a", "team-b", "team-c"]);
+
+        annotate(&client, "db", targets::MAX_NAMESPACES_ANNOTATION, Some("1")).await;
+        reconcile(fake.get("source", "db").unwrap(), context.clone()).await.unwrap();
+        assert_eq!(namespaces(), vec!["team-a"]);
+
+        // the source namespace doesn't take up a slot
+        annotate(&client, "db", targets::TARGET_NAMESPACE_ANNOTATION, Some("source,team-d,team-c")).await;
+        annotate(&client, "db", targets::MAX_NAMESPACES_ANNOTATION, Some("2")).await;
+        reconcile(fake.get("source", "db").unwrap(), context.clone()).await.unwrap();
+        assert_eq!(namespaces(), vec!["team-c", "team-d"]);
+
+        annotate(&client, "db", targets::MAX_NAMESPACES_ANNOTATION, Some("0")).await;
+        reconcile(fake.get("source", "db").unwrap(), context).await.unwrap();
+        assert!(namespaces().is_empty());
+    }
+
+    #[tokio::test]
+    async fn cleanup_survives_a_concurrently_removed_finalizer() {
+        let (client, fake) = FakeApi::start();
+        for ns in &["source", "a", "b"] {
+            fake.insert(&namespace(ns));
+        }
+        let sec = insert_source(&fake, "db", "a,b");
+        let uid = sec.metadata.uid.clone().unwrap();
+        let context = context(client.clone());
+        reconcile(sec, context.clone()).await.unwrap();
+        assert_eq!(copies_of(&fake, &uid).len(), 2);
+
+        let api: Api<Secret> = Api::namespaced(client, "source");
+        api.delete("db", &DeleteParams::default()).await.unwrap();
+        let deleted: Secret = fake.get("source", "db").unwrap();
+        // another actor strips the finalizer before the cleanup ran, the source is gone
+        let patch = serde_json::json!({ "metadata": { "finalizers": null } });
+        api.patch("db", &PatchParams::default(), &Patch::Merge(&patch)).await.unwrap();
+        assert!(fake.get::<Secret>("source", "db").is_none());
+
+        // the reconcile of the deletion event still cleans up
+        reconcile(deleted, context).await.unwrap();
+        assert!(copies_of(&fake, &uid).is_empty());
+    }
+
+    #[tokio::test]
+    async fn reconciles_are_recorded_in_the_history() {
+        let (client, fake) = FakeApi::start();
+        for ns in &["source", "a", "b"] {
+            fake.insert(&namespace(ns));
+        }
+        let sec = insert_source(&fake, "db", "a,b");
+        let context = context(client);
+        reconcile(sec, context.clone()).await.unwrap();
+        reconcile(fake.get("source", "db").unwrap(), context.clone()).await.unwrap();
+
+        let recent = context.get_ref().history.recent("source/db", 10);
+        assert_eq!(recent.iter().map(|e| (e.created, e.unchanged)).collect::<Vec<_>>(), vec![(0, 2), (2, 0)]);
+        assert!(recent.iter().all(|e| e.error.is_none()));
+    }
+
+    #[tokio::test]
+    async fn copy_cache_saves_the_reads_of_the_copies() {
+        let (client, fake) = FakeApi::start();
+        for ns in &["source", "a", "b", "c"] {
+            fake.insert(&namespace(ns));
+        }
+        let sec = insert_source(&fake, "db", "a,b,c");
+        let uncached = context(client.clone());
+        sync(&fake, &uncached, &sec).await;
+        let copy_reads = |reads: Vec<String>| reads.into_iter().filter(|path| path.contains("/secrets/db")).count();
+
+        fake.take_reads();
+        assert_eq!(sync(&fake, &uncached, &sec).await.unchanged, 3);
+        assert_eq!(copy_reads(fake.take_reads()), 3);
+
+        let copies = fake.list::<Secret>().into_iter().filter(|s| s.namespace().as_deref() != Some("source")).collect();
+        let cached = Context::new(ContextData::new(client, &Opts::parse_from(["spreading-operator"])).with_copy_cache(cache::CopyCache::of(copies)));
+        fake.take_reads();
+        assert_eq!(sync(&fake, &cached, &sec).await.unchanged, 3);
+        assert_eq!(copy_reads(fake.take_reads()), 0);
+    }
+
+    #[tokio::test]
+    async fn stale_cached_copy_keeps_the_changes_of_others() {
+        let (client, fake) = FakeApi::start();
+        for ns in &["source", "a"] {
+            fake.insert(&namespace(ns));
+        }
+        let sec = insert_source(&fake, "db", "a");
+        sync(&fake, &context(client.clone()), &sec).await;
+        let cached = cache::CopyCache::of(vec![fake.get("a", "db").unwrap()]);
+        let context = Context::new(ContextData::new(client.clone(), &Opts::parse_from(["spreading-operator"])).with_copy_cache(cached));
+
+        // the copy changed since it was cached, the update applies the fields of the operator only
+        let patch = serde_json::json!({ "metadata": { "labels": { "team": "a" } } });
+        Api::<Secret>::namespaced(client.clone(), "a").patch("db", &PatchParams::default(), &Patch::Merge(&patch)).await.unwrap();
+        let patch = serde_json::json!({ "data": { "password": ByteString(b"rotated".to_vec()) } });
+        Api::<Secret>::namespaced(client, "source").patch("db", &PatchParams::default(), &Patch::Merge(&patch)).await.unwrap();
+        assert_eq!(sync(&fake, &context, &sec).await.updated, 1);
+        let copy: Secret = fake.get("a", "db").unwrap();
+        assert_eq!(copy.data.unwrap()["password"], ByteString(b"rotated".to_vec()));
+        assert_eq!(copy.metadata.labels.unwrap()["team"], "a");
+    }
+
+    #[tokio::test]
+    async fn edited_target_configmap_respreads_the_sources_reading_it() {
+        let (client, fake) = FakeApi::start();
+        for ns in &["source", "a", "b"] {
+            fake.insert(&namespace(ns));
+        }
+        let targets_configmap = |namespaces: &str| ConfigMap {
+            metadata: ObjectMeta {
+                name: Some("targets".to_string()),
+                namespace: Some("source".to_string()),
+                ..ObjectMeta::default()
+            },
+            data: Some(std::iter::once(("namespaces".to_string(), namespaces.to_string())).collect()),
+            ..ConfigMap::default()
+        };
+        fake.insert(&targets_configmap("a"));
+        let sec = insert_annotated(&fake, secret("source", "db", "secret"), &[(targets::TARGET_NAMESPACES_FROM_ANNOTATION, "targets")]);
+        let uid = sec.metadata.uid.clone().unwrap();
+        let context = context(client.clone());
+        reconcile(sec, context.clone()).await.unwrap();
+        assert_eq!(copies_of(&fake, &uid), vec![("a".to_string(), "db".to_string())]);
+
+        // the watch of the ConfigMaps maps the edit to the sources reading it
+        fake.insert(&targets_configmap("a\nb"));
+        let index = context.get_ref().configmap_index.clone();
+        let triggered = index.sources_for("source", "targets");
+        assert_eq!(triggered, vec![ObjectRef::new("db").within("source")]);
+        assert!(index.sources_for("source", "other").is_empty());
+        for source in triggered {
+            reconcile(fake.get(source.namespace.as_deref().unwrap(), &source.name).unwrap(), context.clone()).await.unwrap();
+        }
+        let mut copies = copies_of(&fake, &uid);
+        copies.sort();
+        assert_eq!(copies, vec![("a".to_string(), "db".to_string()), ("b".to_string(), "db".to_string())]);
+
+        // a source no longer reading it isn't triggered anymore
+        annotate(&client, "db", targets::TARGET_NAMESPACES_FROM_ANNOTATION, None).await;
+        annotate(&client, "db", targets::TARGET_NAMESPACE_ANNOTATION, Some("a")).await;
+        reconcile(fake.get("source", "db").unwrap(), context).await.unwrap();
+        assert!(index.sources_for("source", "targets").is_empty());
+    }
+
+    #[tokio::test]
+    async fn sinks_are_notified_of_what_happened_to_the_copies() {
+        let (client, fake) = FakeApi::start();
+        for ns in &["source", "a", "b"] {
+            fake.insert(&namespace(ns));
+        }
+        let sec = insert_source(&fake, "db", "a,b,gone");
+        fake.fail(Method::PATCH, "/api/v1/namespaces/gone/secrets/db", Some(404));
+        let recording = sinks::RecordingSink::default();
+        let mut data = ContextData::new(client.clone(), &Opts::parse_from(["spreading-operator"]));
+        data.sinks = sinks::Sinks::of(vec![Box::new(recording.clone())]);
+        let context = Context::new(data);
+
+        reconcile(sec, context.clone()).await.unwrap();
+        let mut calls = recording.take();
+        calls.sort();
+        assert_eq!(calls, vec!["created a db", "created b db", "skipped gone namespace does not exist"]);
+
+        let patch = serde_json::json!({ "data": { "password": ByteString(b"rotated".to_vec()) } });
+        Api::<Secret>::namespaced(client.clone(), "source").patch("db", &PatchParams::default(), &Patch::Merge(&patch)).await.unwrap();
+        annotate(&client, "db", targets::TARGET_NAMESPACE_ANNOTATION, Some("a")).await;
+        reconcile(fake.get("source", "db").unwrap(), context.clone()).await.unwrap();
+        let mut calls = recording.take();
+        calls.sort();
+        assert_eq!(calls, vec!["deleted b db", "updated a db"]);
+
+        Api::<Secret>::namespaced(client, "source").delete("db", &DeleteParams::default()).await.unwrap();
+        reconcile(fake.get("source", "db").unwrap(), context).await.unwrap();
+        assert_eq!(recording.take(), vec!["deleted a db"]);
+    }
+
+    #[tokio::test]
+    async fn copy_that_is_a_source_as_well_is_refused() {
+        let (client, fake) = FakeApi::start();
+        for ns in &["source", "a", "b"] {
+            fake.insert(&namespace(ns));
+        }
+        let mut copy = secret("a", "db", "secret");
+        copy.metadata.labels = Some(vec![(keys::owner_label().to_string(), "0123".to_string())].into_iter().collect());
+        copy.metadata.annotations = Some(vec![(keys::key(targets::TARGET_NAMESPACE_ANNOTATION), "b".to_string())].into_iter().collect());
+        let copy: Secret = serde_json::from_value(fake.insert(&copy)).unwrap();
+        fake.take_writes();
+
+        let action = reconcile(copy, context(client)).await.unwrap();
+        assert_eq!(action.requeue_after, None);
+        // neither spread nor given a finalizer, only reported
+        assert_eq!(fake.take_writes(), vec![(Method::POST, "/api/v1/namespaces/a/events".to_string())]);
+        assert!(fake.get::<Secret>("b", "db").is_none());
+        let events: Vec<k8s_openapi::api::core::v1::Event> = fake.list();
+        let event = events.iter().find(|e| e.reason.as_deref() == Some("CopyIsSource")).unwrap();
+        assert_eq!(event.type_.as_deref(), Some("Warning"));
+        assert_eq!(
+            event.message.as_deref(),
+            Some(format!("Secret carries the owner label {} of a copy and a target annotation, refusing to spread it", keys::owner_label()).as_str())
+        );
+    }
+
+    #[tokio::test]
+    async fn denied_namespace_fails_the_reconcile_after_the_others_got_their_copies() {
+        let (client, fake) = FakeApi::start();
+        for ns in &["source", "a", "b", "c", "d"] {
+            fake.insert(&namespace(ns));
+        }
+        // writing to b is forbidden
+        fake.fail(Method::PATCH, "/api/v1/namespaces/b/secrets/db", Some(403));
+        let sec = insert_source(&fake, "db", "a,b,c,d");
+        let uid = sec.metadata.uid.clone().unwrap();
+        let context = context(client);
+
+        let outcome = sync(&fake, &context, &sec).await;
+        assert_eq!((outcome.created, outcome.failed), (3, 1));
+        assert!(outcome.failure.unwrap().contains("b"));
+        assert_eq!(copies_of(&fake, &uid), vec![("a".to_string(), "db".to_string()), ("c".to_string(), "db".to_string()), ("d".to_string(), "db".to_string())]);
+
+        // reported as failed, so it is retried after the error interval
+        let action = reconcile(fake.get("source", "db").unwrap(), context.clone()).await.unwrap();
+        assert_eq!(action.requeue_after, Some(context.get_ref().requeue().error));
+    }
+
+    #[tokio::test]
+    async fn copies_get_the_annotations_without_the_control_ones_and_do_not_spread() {
+        let (client, fake) = FakeApi::start();
+        for ns in &["source", "a", "b"] {
+            fake.insert(&namespace(ns));
+        }
+        let copied = format!("reloader.stakater.com/match, {}, {}", keys::key(targets::TARGET_NAMESPACE_ANNOTATION), keys::key(PAUSED_ANNOTATION));
+        let sec = insert_annotated(&fake, secret("source", "db", "secret"), &[
+            (targets::TARGET_NAMESPACE_ANNOTATION, "a"),
+            (compare::COPY_ANNOTATIONS_ANNOTATION, &copied),
+            ("reloader.stakater.com/match", "true"),
+        ]);
+        let uid = sec.metadata.uid.clone().unwrap();
+        let context = context(client.clone());
+        reconcile(sec, context.clone()).await.unwrap();
+
+        let copy: Secret = fake.get("a", "db").unwrap();
+        let annotations = copy.metadata.annotations.clone().unwrap();
+        assert_eq!(annotations.get("reloader.stakater.com/match").map(String::as_str), Some("true"));
+        assert!(!annotations.contains_key(&keys::key(targets::TARGET_NAMESPACE_ANNOTATION)));
+        assert!(!annotations.contains_key(&keys::key(compare::COPY_ANNOTATIONS_ANNOTATION)));
+        assert!(!targets::has_targets(&copy.metadata));
+
+        // the copy is no source, reconciling it spreads nothing and leaves it alone
+        fake.take_writes();
+        reconcile(copy, context.clone()).await.unwrap();
+        assert_eq!(fake.take_writes(), vec![]);
+        assert!(fake.get::<Secret>("b", "db").is_none());
+        assert_eq!(copies_of(&fake, &uid), vec![("a".to_string(), "db".to_string())]);
+
+        // an update keeps them in sync and still stripped
+        annotate(&client, "db", "reloader.stakater.com/match", Some("false")).await;
+        annotate(&client, "db", PAUSED_ANNOTATION, Some("false")).await;
+        reconcile(fake.get("source", "db").unwrap(), context).await.unwrap();
+        let annotations = fake.get::<Secret>("a", "db").unwrap().metadata.annotations.unwrap();
+        assert_eq!(annotations.get("reloader.stakater.com/match").map(String::as_str), Some("false"));
+        assert!(!annotations.contains_key(&keys::key(PAUSED_ANNOTATION)));
+        assert!(!annotations.contains_key(&keys::key(targets::TARGET_NAMESPACE_ANNOTATION)));
+    }
+
+    #[tokio::test]
+    async fn no_copies_are_written_without_the_finalizer() {
+        let (client, fake) = FakeApi::start();
+        for ns in &["source", "a"] {
+            fake.insert(&namespace(ns));
+        }
+        let mut source = secret("source", "db", "secret");
+        source.metadata.annotations = Some(vec![(keys::key(targets::TARGET_NAMESPACE_ANNOTATION), "a".to_string())].into_iter().collect());
+        let sec: Secret = serde_json::from_value(fake.insert(&source)).unwrap();
+        let context = context(client.clone());
+
+        // the finalizer patch fails
+        fake.fail(Method::PATCH, "/api/v1/namespaces/source/secrets/db", Some(500));
+        assert!(reconcile(sec.clone(), context.clone()).await.is_err());
+        assert!(fake.get::<Secret>("a", "db").is_none());
+        fake.fail(Method::PATCH, "/api/v1/namespaces/source/secrets/db", None);
+
+        // the source was recreated meanwhile, the finalizer of the new one doesn't count
+        Api::<Secret>::namespaced(client, "source").delete("db", &DeleteParams::default()).await.unwrap();
+        fake.insert(&source);
+        let action = reconcile(sec, context.clone()).await.unwrap();
+        assert_eq!(action.requeue_after, Some(context.get_ref().requeue().error));
+        assert!(fake.get::<Secret>("a", "db").is_none());
+    }
+
+    #[tokio::test]
+    async fn patterns_spread_to_the_matching_namespaces() {
+        let (client, fake) = FakeApi::start();
+        for ns in &["source", "team-a", "team-b", "teams", "other"] {
+            fake.insert(&namespace(ns));
+        }
+        let context = context(client);
+        let copies = |uid: &str| copies_of(&fake, uid).into_iter().map(|(ns, _)| ns).collect::<Vec<_>>();
+
+        // a glob and a regular expression matching several namespaces, plus a listed name
+        let glob = insert_source(&fake, "glob", "team-*");
+        sync(&fake, &context, &glob).await;
+        assert_eq!(copies(glob.metadata.uid.as_ref().unwrap()), vec!["team-a", "team-b"]);
+        let regex = insert_source(&fake, "regex", "other, regex:^team-(a|b)$");
+        sync(&fake, &context, &regex).await;
+        assert_eq!(copies(regex.metadata.uid.as_ref().unwrap()), vec!["other", "team-a", "team-b"]);
+
+        // a pattern matching no namespace spreads nowhere, which is no error
+        let none = insert_source(&fake, "none", "staging-*");
+        let outcome = sync(&fake, &context, &none).await;
+        assert!(outcome.failure.is_none());
+        assert_eq!(outcome.created, 0);
+        assert!(copies(none.metadata.uid.as_ref().unwrap()).is_empty());
+    }
+
+    #[tokio::test]
+    async fn duplicated_namespace_is_written_once() {
+        let (client, fake) = FakeApi::start();
+        for ns in &["source", "a", "b"] {
+            fake.insert(&namespace(ns));
+        }
+        let sec = insert_source(&fake, "db", "b, a,a , b,a");
+        let context = context(client);
+        fake.take_writes();
+
+        let outcome = sync(&fake, &context, &sec).await;
+        assert_eq!(outcome.created, 2);
+        // the copies, not the status on the source
+        let mut writes: Vec<String> = fake.take_writes().into_iter().map(|(_, path)| path).filter(|path| path.contains("/secrets/") && !path.contains("/source/")).collect();
+        writes.sort();
+        assert_eq!(writes, vec!["/api/v1/namespaces/a/secrets/db", "/api/v1/namespaces/b/secrets/db"]);
+    }
+
+    #[tokio::test]
+    async fn recreated_source_owns_its_copies_and_not_the_former_ones() {
+        let (client, fake) = FakeApi::start();
+        for ns in &["source", "a", "b"] {
+            fake.insert(&namespace(ns));
+        }
+        let context = context(client.clone());
+        let api: Api<Secret> = Api::namespaced(client.clone(), "source");
+
+        // deleted and cleaned up, then recreated under the same name
+        let first = insert_source(&fake, "db", "a,b");
+        let first_uid = first.metadata.uid.clone().unwrap();
+        reconcile(first, context.clone()).await.unwrap();
+        api.delete("db", &DeleteParams::default()).await.unwrap();
+        reconcile(fake.get("source", "db").unwrap(), context.clone()).await.unwrap();
+        assert!(fake.get::<Secret>("source", "db").is_none());
+        assert!(copies_of(&fake, &first_uid).is_empty());
+        let second = insert_source(&fake, "db", "a,b");
+        let second_uid = second.metadata.uid.clone().unwrap();
+        assert_ne!(first_uid, second_uid);
+        reconcile(second, context.clone()).await.unwrap();
+        assert_eq!(copies_of(&fake, &second_uid), vec![("a".to_string(), "db".to_string()), ("b".to_string(), "db".to_string())]);
+
+        // deleted without cleanup, somebody removed the finalizer
+        let patch = serde_json::json!({ "metadata": { "finalizers": null } });
+        api.patch("db", &PatchParams::default(), &Patch::Merge(&patch)).await.unwrap();
+        api.delete("db", &DeleteParams::default()).await.unwrap();
+        assert!(fake.get::<Secret>("source", "db").is_none());
+        let third = insert_source(&fake, "db", "a");
+        let third_uid = third.metadata.uid.clone().unwrap();
+        reconcile(third, context.clone()).await.unwrap();
+        // the copy in a is taken over with the owner label of the new source, the one in b
+        // still belongs to the former one
+        assert_eq!(copies_of(&fake, &third_uid), vec![("a".to_string(), "db".to_string())]);
+        assert_eq!(copies_of(&fake, &second_uid), vec![("b".to_string(), "db".to_string())]);
+
+        // which is gone, so the orphan scan deletes its copy, keyed on the uid
+        orphans::scan(client, &DeleteParams::default()).await.unwrap();
+        assert!(copies_of(&fake, &second_uid).is_empty());
+        assert_eq!(copies_of(&fake, &third_uid), vec![("a".to_string(), "db".to_string())]);
+    }
+
+    #[tokio::test]
+    async fn cleanup_completes_when_the_source_is_not_found_on_removing_the_finalizer() {
+        let (client, fake) = FakeApi::start();
+        for ns in &["source", "a"] {
+            fake.insert(&namespace(ns));
+        }
+        let sec = insert_source(&fake, "db", "a");
+        let uid = sec.metadata.uid.clone().unwrap();
+        let context = context(client.clone());
+        reconcile(sec, context.clone()).await.unwrap();
+        Api::<Secret>::namespaced(client, "source").delete("db", &DeleteParams::default()).await.unwrap();
+
+        fake.fail(Method::PATCH, "/api/v1/namespaces/source/secrets/db", Some(404));
+        let action = reconcile(fake.get("source", "db").unwrap(), context).await.unwrap();
+        assert_eq!(action.requeue_after, None);
+        assert!(copies_of(&fake, &uid).is_empty());
+    }
+
+    #[tokio::test]
+    async fn delete_policy_deletes_or_orphans_the_copies() {
+        let (client, fake) = FakeApi::start();
+        for ns in &["source", "a"] {
+            fake.insert(&namespace(ns));
+        }
+        let context = context(client.clone());
+        let api: Api<Secret> = Api::namespaced(client, "source");
+        let deleted = insert_source(&fake, "deleted", "a");
+        let orphaned = insert_annotated(&fake, secret("source", "orphaned", "secret"), &[(targets::TARGET_NAMESPACE_ANNOTATION, "a"), (DELETE_POLICY_ANNOTATION, "orphan")]);
+        let orphaned_uid = orphaned.metadata.uid.clone().unwrap();
+        reconcile(deleted, context.clone()).await.unwrap();
+        reconcile(orphaned, context.clone()).await.unwrap();
+
+        for name in &["deleted", "orphaned"] {
+            api.delete(name, &DeleteParams::default()).await.unwrap();
+            reconcile(fake.get("source", name).unwrap(), context.clone()).await.unwrap();
+            // the finalizer is removed either way
+            assert!(fake.get::<Secret>("source", name).is_none());
+        }
+
+        // `delete`, the default, deletes the copy
+        assert!(fake.get::<Secret>("a", "deleted").is_none());
+        // `orphan` keeps it unmanaged, without the labels and annotations linking it to the source
+        let kept: Secret = fake.get("a", "orphaned").unwrap();
+        assert!(!is_copy(&kept));
+        assert!(copies_of(&fake, &orphaned_uid).is_empty());
+        assert!(!kept.metadata.annotations.iter().flatten().any(|(key, _)| key.starts_with(keys::prefix())));
+        assert_eq!(kept.data.unwrap().get("password"), Some(&ByteString(b"secret".to_vec())));
+    }
+
+    #[tokio::test]
+    async fn partial_failure_is_retried_after_the_error_interval_with_the_status_recorded() {
+        let (client, fake) = FakeApi::start();
+        for ns in &["source", "a", "b"] {
+            fake.insert(&namespace(ns));
+        }
+        let opts = Opts::parse_from(["spreading-operator", "--requeue-jitter", "0", "--requeue-synced", "120", "--requeue-error", "7"]);
+        let context = Context::new(ContextData::new(client, &opts));
+
+        // all namespaces synced
+        let synced = insert_source(&fake, "synced", "a,b");
+        let action = reconcile(synced, context.clone()).await.unwrap();
+        assert_eq!(action.requeue_after, Some(Duration::from_secs(120)));
+
+        // b fails, a is synced and recorded regardless
+        fake.fail(Method::PATCH, "/api/v1/namespaces/b/secrets/partial", Some(500));
+        let partial = insert_source(&fake, "partial", "a,b");
+        let action = reconcile(partial, context.clone()).await.unwrap();
+        assert_eq!(action.requeue_after, Some(Duration::from_secs(7)));
+        assert!(fake.get::<Secret>("a", "partial").is_some());
+        let source: Secret = fake.get("source", "partial").unwrap();
+        assert_eq!(targets::annotation(&source.metadata, status::TARGET_COUNT_ANNOTATION).as_deref(), Some("1"));
+        let recent = context.get_ref().history.recent("source/partial", 1);
+        assert_eq!(recent[0].created, 1);
+        assert!(recent[0].error.as_deref().unwrap().contains("b"));
+
+        // and synced once b recovered
+        fake.fail(Method::PATCH, "/api/v1/namespaces/b/secrets/partial", None);
+        let action = reconcile(fake.get("source", "partial").unwrap(), context).await.unwrap();
+        assert_eq!(action.requeue_after, Some(Duration::from_secs(120)));
+    }
+
+    #[tokio::test]
+    async fn reconciles_are_counted_by_their_trigger() {
+        let sec = secret("source", "db", "secret");
+        let requests = futures::stream::iter(vec![Ok(ObjectRef::from_obj(&sec)), Ok(ObjectRef::from_obj(&sec))]);
+        assert_eq!(counted("test-trigger", requests).count().await, 2);
+        assert!(metrics::render().contains("spread_controller_triggers_total{controller=\"secret\",trigger=\"test-trigger\"} 2\n"));
+    }
+
+#[tokio::test]
+async fn simultaneous_triggers_collapse_into_one_reconcile() {
+    let (client, fake) = FakeApi::start();
+    for ns in &["source", "a", "b"] {
+        fake.insert(&namespace(ns));
+    }
+    let sec = insert_source(&fake, "db", "a,b");
+    let context = context(client);
+    let mut writer: reflector::store::Writer<Secret> = Default::default();
+    writer.apply_watcher_event(&watcher::Event::Restarted(vec![sec.clone()]));
+
+    // the source changes, its namespace is relabeled and a ConfigMap it reads is changed at once,
+    // the namespace watch requests it once more a while after its reconcile
+    let request = move || futures::stream::once(futures::future::ready(Ok(ObjectRef::from_obj(&sec))));
+    let later = request().then(|request| async move {
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        request
+    });
+    let triggers = vec![
+        counted("secret", request().chain(futures::stream::pending())),
+        counted("configmap", request().chain(futures::stream::pending())),
+        counted("namespace", request().chain(later).chain(futures::stream::pending())),
+    ];
+    let reconciles = secret_applier(context.clone(), writer.as_reader(), triggers);
+    futures::pin_mut!(reconciles);
+
+    reconciles.next().await.unwrap().unwrap();
+    assert!(tokio::time::timeout(Duration::from_millis(100), reconciles.next()).await.is_err());
+    assert_eq!(context.get_ref().history.recent("source/db", 10).len(), 1);
+
+    // a request after the reconcile isn't lost
+    tokio::time::timeout(Duration::from_secs(2), reconciles.next()).await.unwrap().unwrap().unwrap();
+    assert_eq!(context.get_ref().history.recent("source/db", 10).len(), 2);
+}
+
+#[tokio::test]
+async fn namespace_relabeled_into_the_selector_gets_its_copy() {
+    let (client, fake) = FakeApi::start();
+    for ns in &["source", "a", "b"] {
+        fake.insert(&namespace(ns));
+    }
+    label_namespace(&client, "a", "tenant", Some("true")).await;
+    let sec = insert_annotated(&fake, secret("source", "db", "secret"), &[(targets::TARGET_NAMESPACE_SELECTOR_ANNOTATION, "tenant=true")]);
+    let uid = sec.metadata.uid.clone().unwrap();
+    let context = context(client.clone());
+    reconcile(sec, context.clone()).await.unwrap();
+    assert_eq!(copies_of(&fake, &uid), vec![("a".to_string(), "db".to_string())]);
+
+    // the namespace watch sees b, which has been there for long, then b relabeled with a label
+    // the selector doesn't read
+    let index = context.get_ref().namespace_index.clone();
+    let namespaces: Api<Namespace> = Api::all(client.clone());
+    let watched = |mut ns: Namespace| {
+        ns.metadata.creation_timestamp = None;
+        index.sources_for(&ns)
+    };
+    assert!(watched(namespaces.get("b").await.unwrap()).is_empty());
+    label_namespace(&client, "b", "team", Some("payments")).await;
+    assert!(watched(namespaces.get("b").await.unwrap()).is_empty());
+
+    // relabeling b into the selector requests the source, its reconcile spreads into b
+    label_namespace(&client, "b", "tenant", Some("true")).await;
+    let requested = watched(namespaces.get("b").await.unwrap());
+    assert_eq!(requested, vec![ObjectRef::new("db").within("source")]);
+    reconcile(fake.get("source", "db").unwrap(), context).await.unwrap();
+    let mut copies = copies_of(&fake, &uid);
+    copies.sort();
+    assert_eq!(copies, vec![("a".to_string(), "db".to_string()), ("b".to_string(), "db".to_string())]);
+}
+}
@@ -0,0 +1,196 @@
+//! Prometheus metrics of the operator, served on `/metrics` by the HTTP server.
+//!
+//! Counters are labeled by the namespace of the source. The metrics are process wide, so all
+//! controllers report into the same counters, except the `spread_controller_*` ones labeled by
+//! the controller, see [`crate::concurrency`].
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use k8s_openapi::api::core::v1::Secret;
+use kube::Resource;
+
+use crate::sinks::EventSink;
+
+/// Upper bounds in seconds of the reconcile duration histogram buckets.
+const DURATION_BUCKETS: [f64; 9] = [0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0];
+
+/// Counter values keyed by metric name and source namespace.
+static COUNTERS: Mutex<BTreeMap<(&'static str, String), u64>> = Mutex::new(BTreeMap::new());
+
+/// Values of the per controller metrics keyed by metric name, controller and trigger, the
+/// trigger is empty for the metrics without.
+static CONTROLLERS: Mutex<BTreeMap<(&'static str, &'static str, &'static str), i64>> = Mutex::new(BTreeMap::new());
+
+/// Reconcile durations: observations per bucket, plus the count and the sum of all of them.
+static DURATIONS: Mutex<Histogram> = Mutex::new(Histogram {
+    buckets: [0; DURATION_BUCKETS.len()],
+    count: 0,
+    sum: 0.0,
+});
+
+/// Reconciles per second the pacer currently permits, as the bits of an `f64`, 0 before it
+/// started.
+static RECONCILE_RATE: AtomicU64 = AtomicU64::new(0);
+
+/// Unix time in seconds of the last heartbeat of the Secret controller, 0 before the first.
+static LAST_HEARTBEAT: AtomicU64 = AtomicU64::new(0);
+
+struct Histogram {
+    buckets: [u64; DURATION_BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+/// Counter names and their help texts.
+const HELP: [(&str, &str); 8] = [
+    ("spread_reconciles_total", "Reconciles of sources."),
+    ("spread_reconciles_rate_limited_total", "Reconciles of sources deferred by the per source rate limit."),
+    ("spread_reconcile_errors_total", "Reconciles of sources that failed."),
+    ("spread_copies_created_total", "Copies created."),
+    ("spread_copies_updated_total", "Copies updated."),
+    ("spread_copies_deleted_total", "Copies deleted."),
+    ("spread_blocked_unmanaged_total", "Copies not written because of an unmanaged secret with the same name."),
+    ("spread_policy_denied_total", "Copies not written because the spread policy denied them."),
+];
+
+/// Per controller metrics, their types and help texts.
+const CONTROLLER_HELP: [(&str, &str, &str); 4] = [
+    ("spread_controller_reconciles_total", "counter", "Reconciles started per controller."),
+    ("spread_controller_reconciles_running", "gauge", "Reconciles running per controller."),
+    ("spread_controller_reconciles_waiting", "gauge", "Reconciles waiting for a free slot per controller, see --controller-concurrency."),
+    ("spread_controller_triggers_total", "counter", "Reconciles requested per controller by each of its watches and timers."),
+];
+
+/// Increments the counter `name` for a source in `namespace`.
+pub fn inc(name: &'static str, namespace: &str) {
+    *COUNTERS.lock().unwrap().entry((name, namespace.to_string())).or_insert(0) += 1;
+}
+
+/// Adds `delta` to the per controller metric `name` of `controller`.
+pub fn controller_add(name: &'static str, controller: &'static str, delta: i64) {
+    *CONTROLLERS.lock().unwrap().entry((name, controller, "")).or_insert(0) += delta;
+}
+
+/// Counts a reconcile of `controller` requested by `trigger`, e.g. the namespace watch.
+pub fn triggered(controller: &'static str, trigger: &'static str) {
+    *CONTROLLERS.lock().unwrap().entry(("spread_controller_triggers_total", controller, trigger)).or_insert(0) += 1;
+}
+
+/// Records a reconcile that took `seconds`.
+pub fn observe_reconcile_duration(seconds: f64) {
+    let mut durations = DURATIONS.lock().unwrap();
+    for (i, bound) in DURATION_BUCKETS.iter().enumerate() {
+        if seconds <= *bound {
+            durations.buckets[i] += 1;
+        }
+    }
+    durations.count += 1;
+    durations.sum += seconds;
+}
+
+/// Records the reconciles per second the pacer permits right now.
+pub fn set_reconcile_rate(rate: f64) {
+    RECONCILE_RATE.store(rate.to_bits(), Ordering::Relaxed);
+}
+
+/// Records that the Secret controller is alive: it started, completed a reconcile or its watches
+/// delivered an event.
+pub fn heartbeat() {
+    LAST_HEARTBEAT.store(unix_now(), Ordering::Relaxed);
+}
+
+/// Time since the last [`heartbeat`], `None` if there was none, e.g. on a standby.
+pub fn since_heartbeat() -> Option<Duration> {
+    match LAST_HEARTBEAT.load(Ordering::Relaxed) {
+        0 => None,
+        last => Some(Duration::from_secs(unix_now().saturating_sub(last))),
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default()
+}
+
+/// Renders all metrics in the Prometheus text format.
+pub fn render() -> String {
+    let mut out = String::new();
+    let counters = COUNTERS.lock().unwrap();
+    for (name, help) in HELP.iter() {
+        let _ = writeln!(out, "# HELP {} {}", name, help);
+        let _ = writeln!(out, "# TYPE {} counter", name);
+        for ((_, namespace), value) in counters.iter().filter(|((n, _), _)| n == name) {
+            let _ = writeln!(out, "{}{{namespace=\"{}\"}} {}", name, namespace, value);
+        }
+    }
+
+    let controllers = CONTROLLERS.lock().unwrap();
+    for (name, kind, help) in CONTROLLER_HELP.iter() {
+        let _ = writeln!(out, "# HELP {} {}", name, help);
+        let _ = writeln!(out, "# TYPE {} {}", name, kind);
+        for ((_, controller, trigger), value) in controllers.iter().filter(|((n, _, _), _)| n == name) {
+            match *trigger {
+                "" => writeln!(out, "{}{{controller=\"{}\"}} {}", name, controller, value),
+                trigger => writeln!(out, "{}{{controller=\"{}\",trigger=\"{}\"}} {}", name, controller, trigger, value),
+            }
+            .unwrap_or_default();
+        }
+    }
+
+    let durations = DURATIONS.lock().unwrap();
+    let name = "spread_reconcile_duration_seconds";
+    let _ = writeln!(out, "# HELP {} Duration of reconciles of sources.", name);
+    let _ = writeln!(out, "# TYPE {} histogram", name);
+    for (bound, value) in DURATION_BUCKETS.iter().zip(durations.buckets.iter()) {
+        let _ = writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, bound, value);
+    }
+    let _ = writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, durations.count);
+    let _ = writeln!(out, "{}_sum {}", name, durations.sum);
+    let _ = writeln!(out, "{}_count {}", name, durations.count);
+
+    let name = "spread_reconcile_rate";
+    let _ = writeln!(out, "# HELP {} Reconciles per second permitted by the adaptive pacing.", name);
+    let _ = writeln!(out, "# TYPE {} gauge", name);
+    let _ = writeln!(out, "{} {}", name, f64::from_bits(RECONCILE_RATE.load(Ordering::Relaxed)));
+
+    let name = "spread_build_info";
+    let _ = writeln!(out, "# HELP {} Build of the operator, always 1.", name);
+    let _ = writeln!(out, "# TYPE {} gauge", name);
+    let _ = writeln!(out, "{}{{version=\"{}\",commit=\"{}\"}} 1", name, crate::VERSION, crate::GIT_COMMIT);
+
+    // alert on `time() - spread_last_heartbeat_timestamp_seconds`, it is missing on standbys
+    let name = "spread_last_heartbeat_timestamp_seconds";
+    let _ = writeln!(out, "# HELP {} Unix time of the last reconcile or watch event of the Secret controller.", name);
+    let _ = writeln!(out, "# TYPE {} gauge", name);
+    match LAST_HEARTBEAT.load(Ordering::Relaxed) {
+        0 => {}
+        last => {
+            let _ = writeln!(out, "{} {}", name, last);
+        }
+    }
+    out
+}
+
+/// Counts created, updated and deleted copies. Always part of the configured sinks.
+pub struct MetricsSink;
+
+#[async_trait]
+impl EventSink for MetricsSink {
+    async fn on_created(&self, source: &Secret, _namespace: &str, _name: &str) {
+        inc("spread_copies_created_total", &source.namespace().unwrap_or_default());
+    }
+
+    async fn on_updated(&self, source: &Secret, _namespace: &str, _name: &str) {
+        inc("spread_copies_updated_total", &source.namespace().unwrap_or_default());
+    }
+
+    async fn on_deleted(&self, source: &Secret, _namespace: &str, _name: &str) {
+        inc("spread_copies_deleted_total", &source.namespace().unwrap_or_default());
+    }
+
+    async fn on_skipped(&self, _source: &Secret, _namespace: &str, _reason: &str) {}
+}
@@ -0,0 +1,151 @@
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::ObjectMeta;
+use regex::Regex;
+
+use crate::{targets, Error};
+
+/// Prefix prepended to the source name to name the copies.
+pub const TARGET_NAME_PREFIX_ANNOTATION: &str = "eu.fitzek.spread.target-name-prefix";
+/// Suffix appended to the source name to name the copies.
+pub const TARGET_NAME_SUFFIX_ANNOTATION: &str = "eu.fitzek.spread.target-name-suffix";
+/// Template for the copy names, `{name}` and `{namespace}` are replaced by the source name and
+/// the target namespace.
+pub const TARGET_NAME_ANNOTATION: &str = "eu.fitzek.spread.target-name";
+
+/// Decides how the copy of a source is named in a target namespace.
+pub trait CopyNamer: Send + Sync {
+    /// Returns the name of the copy of `source_name` in `namespace`.
+    fn name_for(&self, source_name: &str, namespace: &str) -> Result<String, Error>;
+}
+
+/// Names copies like their source.
+pub struct IdentityNamer;
+
+impl CopyNamer for IdentityNamer {
+    fn name_for(&self, source_name: &str, _namespace: &str) -> Result<String, Error> {
+        validate(source_name.to_string())
+    }
+}
+
+/// Names copies `<prefix><source name><suffix>`.
+pub struct AffixNamer {
+    prefix: String,
+    suffix: String,
+}
+
+impl CopyNamer for AffixNamer {
+    fn name_for(&self, source_name: &str, _namespace: &str) -> Result<String, Error> {
+        validate(format!("{}{}{}", self.prefix, source_name, self.suffix))
+    }
+}
+
+/// Names copies from a template with `{name}` and `{namespace}` placeholders.
+pub struct TemplateNamer {
+    template: String,
+}
+
+impl CopyNamer for TemplateNamer {
+    fn name_for(&self, source_name: &str, namespace: &str) -> Result<String, Error> {
+        validate(
+            self.template
+                .replace("{name}", source_name)
+                .replace("{namespace}", namespace),
+        )
+    }
+}
+
+/// Selects the namer configured by the annotations of the source.
+///
+/// A template can not be combined with a prefix or suffix.
+pub fn namer_for(meta: &ObjectMeta) -> Result<Box<dyn CopyNamer>, Error> {
+    let template = targets::annotation(meta, TARGET_NAME_ANNOTATION);
+    let prefix = targets::annotation(meta, TARGET_NAME_PREFIX_ANNOTATION);
+    let suffix = targets::annotation(meta, TARGET_NAME_SUFFIX_ANNOTATION);
+
+    match (template, prefix, suffix) {
+        (Some(_), Some(_), _) | (Some(_), _, Some(_)) => Err(Error::UserInputError(format!(
+            "{} can not be combined with {} or {}",
+            TARGET_NAME_ANNOTATION, TARGET_NAME_PREFIX_ANNOTATION, TARGET_NAME_SUFFIX_ANNOTATION
+        ))),
+        (template, prefix, suffix) => Ok(namer(template, prefix, suffix)),
+    }
+}
+
+/// Returns the namer for a `template`, else for a `prefix` and `suffix`, else naming copies like
+/// their source.
+pub fn namer(template: Option<String>, prefix: Option<String>, suffix: Option<String>) -> Box<dyn CopyNamer> {
+    match (template, prefix, suffix) {
+        (Some(template), _, _) => Box::new(TemplateNamer { template }),
+        (None, None, None) => Box::new(IdentityNamer),
+        (None, prefix, suffix) => Box::new(AffixNamer {
+            prefix: prefix.unwrap_or_default(),
+            suffix: suffix.unwrap_or_default(),
+        }),
+    }
+}
+
+/// Checks that `name` is a valid secret name, a DNS-1123 subdomain.
+fn validate(name: String) -> Result<String, Error> {
+    let format = Regex::new(r"^[a-z0-9]([-a-z0-9]*[a-z0-9])?(\.[a-z0-9]([-a-z0-9]*[a-z0-9])?)*$").unwrap();
+    if name.len() > 253 || !format.is_match(&name) {
+        return Err(Error::UserInputError(format!("{} is not a valid secret name", name)));
+    }
+    Ok(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keys;
+
+    fn meta(annotations: &[(&str, &str)]) -> ObjectMeta {
+        ObjectMeta {
+            annotations: Some(annotations.iter().map(|(k, v)| (keys::key(k), v.to_string())).collect()),
+            ..ObjectMeta::default()
+        }
+    }
+
+    fn name_for(annotations: &[(&str, &str)], namespace: &str) -> Result<String, Error> {
+        namer_for(&meta(annotations))?.name_for("db", namespace)
+    }
+
+    #[test]
+    fn identity_namer_keeps_the_source_name() {
+        assert_eq!(name_for(&[], "team-a").unwrap(), "db");
+        assert!(IdentityNamer.name_for("DB", "team-a").is_err());
+    }
+
+    #[test]
+    fn affix_namer_adds_prefix_and_suffix() {
+        assert_eq!(name_for(&[(TARGET_NAME_PREFIX_ANNOTATION, "shared-")], "team-a").unwrap(), "shared-db");
+        assert_eq!(name_for(&[(TARGET_NAME_SUFFIX_ANNOTATION, "-copy")], "team-a").unwrap(), "db-copy");
+        assert_eq!(
+            name_for(&[(TARGET_NAME_PREFIX_ANNOTATION, "shared-"), (TARGET_NAME_SUFFIX_ANNOTATION, ".v1")], "team-a").unwrap(),
+            "shared-db.v1"
+        );
+        assert!(name_for(&[(TARGET_NAME_SUFFIX_ANNOTATION, "-")], "team-a").is_err());
+    }
+
+    #[test]
+    fn template_namer_replaces_the_placeholders() {
+        let template = [(TARGET_NAME_ANNOTATION, "{name}-from-{namespace}")];
+        assert_eq!(name_for(&template, "team-a").unwrap(), "db-from-team-a");
+        assert_eq!(name_for(&template, "team-b").unwrap(), "db-from-team-b");
+        assert_eq!(name_for(&[(TARGET_NAME_ANNOTATION, "fixed")], "team-a").unwrap(), "fixed");
+        assert!(name_for(&[(TARGET_NAME_ANNOTATION, "{name}_{namespace}")], "team-a").is_err());
+    }
+
+    #[test]
+    fn template_can_not_be_combined_with_affixes() {
+        assert!(namer_for(&meta(&[(TARGET_NAME_ANNOTATION, "{name}"), (TARGET_NAME_PREFIX_ANNOTATION, "a-")])).is_err());
+        assert!(namer_for(&meta(&[(TARGET_NAME_ANNOTATION, "{name}"), (TARGET_NAME_SUFFIX_ANNOTATION, "-a")])).is_err());
+    }
+
+    #[test]
+    fn produced_names_are_validated() {
+        assert!(validate("a".repeat(253)).is_ok());
+        assert!(validate("a".repeat(254)).is_err());
+        for invalid in &["", "-db", "db-", "db..v1", "Db", "db/1"] {
+            assert!(validate(invalid.to_string()).is_err(), "{}", invalid);
+        }
+    }
+}
@@ -0,0 +1,42 @@
+//! Run-to-convergence mode: every source is reconciled once, then the process exits. Meant for
+//! migration jobs and CI, where no controller is to keep running.
+
+use k8s_openapi::api::core::v1::Secret;
+use kube::{Api, Client, Resource};
+use kube_runtime::controller::Context;
+use tracing::{error, info};
+
+use crate::opts::Opts;
+use crate::{reconcile, scoped_api, targets, ContextData, Error};
+
+/// Reconciles all Secret sources in the watch `scopes` one after another, with the options
+/// `opts`. Returns whether all reconciles succeeded, a failing source doesn't keep the others
+/// from being reconciled.
+pub async fn run(client: Client, scopes: &[Option<String>], opts: &Opts) -> Result<bool, Error> {
+    let context: Context<ContextData> = Context::new(ContextData::new(client.clone(), opts));
+    let mut succeeded = true;
+    for scope in scopes {
+        let secret_api: Api<Secret> = scoped_api(client.clone(), scope.as_deref());
+        let sources = secret_api
+            .list(&context.get_ref().source_list_params())
+            .await?
+            .into_iter()
+            .filter(|s| targets::has_targets(&s.metadata));
+        for sec in sources {
+            let (source_namespace, name) = (sec.namespace().unwrap_or_default(), sec.name());
+            match reconcile(sec, context.clone()).await {
+                // requeued after the error interval, e.g. failed in some target namespaces
+                Ok(action) if action.requeue_after == Some(context.get_ref().requeue().error) => {
+                    error!(source_namespace = %source_namespace, secret_name = %name, "Source did not converge");
+                    succeeded = false;
+                }
+                Ok(_) => info!(source_namespace = %source_namespace, secret_name = %name, "Reconciled source"),
+                Err(e) => {
+                    error!(source_namespace = %source_namespace, secret_name = %name, error = %e, "Reconciling source failed");
+                    succeeded = false;
+                }
+            }
+        }
+    }
+    Ok(succeeded)
+}
@@ -0,0 +1,401 @@
+//! Command line options of the operator. Every option can be given by its environment variable
+//! as well, the flag wins if both are set.
+
+use std::path::PathBuf;
+
+use clap::Parser;
+
+/// Spreads Secrets and ConfigMaps annotated with target namespaces into those namespaces.
+#[derive(Parser, Debug, Clone)]
+#[command(version)]
+pub struct Opts {
+    /// Compares the cluster against the source manifests in DIR and exits, with 0 if in sync,
+    /// 1 if copies diverge and 2 if the check itself failed.
+    #[arg(long, value_name = "DIR")]
+    pub check_against: Option<PathBuf>,
+
+    /// Checks that the service account may read, write and watch Secrets and read and watch
+    /// Namespaces in all namespaces, and that the spread annotations of the sources parse. Prints
+    /// a report and exits, with 0 if all is fine, 1 if not and 2 if the check itself failed.
+    #[arg(long)]
+    pub validate_config: bool,
+
+    /// Prints the copies of the source `<namespace>/<name>` as YAML and exits.
+    #[arg(long, value_name = "SOURCE")]
+    pub export: Option<String>,
+
+    /// Removes the labels and annotations of the operator from the exported copies.
+    #[arg(long, requires = "export")]
+    pub strip_managed: bool,
+
+    /// Prints the `SpreadStatus` and `SecretSpread` CustomResourceDefinitions as YAML and exits,
+    /// see `--status-crd` and `--secret-spread-crd`.
+    #[arg(long)]
+    pub print_crd: bool,
+
+    /// Prints the ClusterRole and bindings the operator needs with the other options as YAML,
+    /// granted to the service account `<namespace>/<name>`, and exits.
+    #[arg(long, value_name = "NAMESPACE/NAME")]
+    pub dump_rbac: Option<String>,
+
+    /// Prints the sources and their copies as graph and exits. Only `dot` is supported.
+    #[arg(long, value_name = "FORMAT", value_parser = ["dot"])]
+    pub topology: Option<String>,
+
+    /// Output of the modes above: `text` in the format of the mode, e.g. YAML, or `json`, one
+    /// object with the result or the error and the exit code. All of them exit with 0 on success,
+    /// 1 if they found something wrong, e.g. diverging copies, and 2 if they failed themselves.
+    #[arg(long, value_name = "FORMAT", default_value = "text", value_parser = ["text", "json"])]
+    pub output: String,
+
+    /// Reconciles every source once and exits, with 0 if all succeeded, 1 if any failed and 2
+    /// if the sources couldn't be listed.
+    #[arg(long, env = "RUN_ONCE")]
+    pub once: bool,
+
+    /// Format of the logs.
+    #[arg(long, env = "LOG_FORMAT", default_value = "text", value_parser = ["text", "json"])]
+    pub log_format: String,
+
+    /// Log filter, a level like `debug` or per target directives like
+    /// `spreading_operator=debug,kube=info`.
+    #[arg(long, env = "RUST_LOG", default_value = "info")]
+    pub log_level: String,
+
+    /// Address the HTTP server with the metrics, health checks and history listens on. Empty
+    /// disables the server.
+    #[arg(long, env = "HTTP_ADDR", default_value = "0.0.0.0:8080")]
+    pub http_addr: String,
+
+    /// Kubeconfig of the cluster the Secret sources are spread into, the cluster of the sources
+    /// if not set. The sources, their finalizers and status annotations stay in the cluster of
+    /// the sources; the target namespaces, the copies and their cleanup are in the target
+    /// cluster. The target identity needs to get, list and watch namespaces and to get, list,
+    /// watch, create, patch and delete secrets there, plus create namespaces with
+    /// create-namespace. ConfigMap and pull secret sources are spread within their own cluster.
+    #[arg(long, env = "TARGET_KUBECONFIG", value_name = "PATH")]
+    pub target_kubeconfig: Option<PathBuf>,
+
+    /// Address of the API server of the cluster of the sources, instead of the in-cluster or
+    /// kubeconfig one. For API servers reached through another address, e.g. a proxy.
+    #[arg(long, env = "API_SERVER_URL", value_name = "URL")]
+    pub api_server_url: Option<String>,
+
+    /// PEM file of CA certificates trusted for the API server of the cluster of the sources, in
+    /// addition to the in-cluster or kubeconfig ones. For API servers behind a proxy with its
+    /// own CA.
+    #[arg(long, env = "KUBE_CA_FILE", value_name = "PATH")]
+    pub kube_ca_file: Option<PathBuf>,
+
+    /// Seconds without heartbeat of the Secret controller after which `/readyz` answers 503, 0
+    /// disables the check. A heartbeat is a completed reconcile or an event of its watches, so the
+    /// value should exceed `REQUEUE_IDLE` on clusters with few changes. The time of the last
+    /// heartbeat is exported as metric `spread_last_heartbeat_timestamp_seconds`.
+    #[arg(long, env = "READINESS_STALENESS", default_value_t = 0)]
+    pub readiness_staleness: u64,
+
+    /// Namespaces the sources are watched in, comma separated. All namespaces if not set.
+    #[arg(long, env = "WATCH_NAMESPACES", value_delimiter = ',')]
+    pub watch_namespaces: Vec<String>,
+
+    /// Secret types never reconciled unless they carry a targeting annotation, comma separated.
+    /// Empty reconciles Secrets of all types.
+    #[arg(long, env = "EXCLUDE_TYPES", value_delimiter = ',', default_value = "kubernetes.io/service-account-token,helm.sh/release.v1")]
+    pub exclude_types: Vec<String>,
+
+    /// Label selector the watched Secret sources have to match.
+    #[arg(long, env = "SOURCE_LABEL_SELECTOR")]
+    pub source_label_selector: Option<String>,
+
+    /// Seconds after which all sources are reconciled anew, 0 disables the resync.
+    #[arg(long, env = "RESYNC_INTERVAL", default_value_t = 0)]
+    pub resync_interval: u64,
+
+    /// Namespaces `*` doesn't expand to, comma separated, for sources without
+    /// `exclude-namespaces` annotation.
+    #[arg(long, env = "EXCLUDE_NAMESPACES", value_delimiter = ',')]
+    pub exclude_namespaces: Vec<String>,
+
+    /// Namespaces copies may be written to, comma separated. Targets outside the list are
+    /// dropped whatever the annotations of the source say. Empty allows all namespaces.
+    #[arg(long, env = "ALLOWED_TARGET_NAMESPACES", value_delimiter = ',')]
+    pub allowed_target_namespaces: Vec<String>,
+
+    /// Data keys added to copies by others, e.g. mutating webhooks, comma separated. A copy
+    /// carrying such a key that the source doesn't have is still up to date, the key is left
+    /// alone instead of the copy being patched on every reconcile.
+    #[arg(long, env = "IGNORED_COPY_KEYS", value_delimiter = ',')]
+    pub ignored_copy_keys: Vec<String>,
+
+    /// Label, `key` or `key=value`, of Secrets spread to `--default-target-namespaces` without a
+    /// targeting annotation of their own. A targeting annotation replaces the default.
+    #[arg(long, env = "DEFAULT_TRIGGER_LABEL", requires = "default_target_namespaces")]
+    pub default_trigger_label: Option<String>,
+
+    /// Target namespaces of the Secrets carrying `--default-trigger-label`, comma separated, like
+    /// the `target-namespace` annotation.
+    #[arg(long, env = "DEFAULT_TARGET_NAMESPACES", value_delimiter = ',')]
+    pub default_target_namespaces: Vec<String>,
+
+    /// Most target namespaces a source may have. A source targeting more, e.g. by a `*` on a
+    /// large cluster, is not synced at all. 0 disables the limit.
+    #[arg(long, env = "MAX_TARGETS_PER_SOURCE", default_value_t = 1000)]
+    pub max_targets_per_source: usize,
+
+    /// Serves the validating admission webhook rejecting Secrets with malformed spread
+    /// annotations, see `--webhook-addr`.
+    #[arg(long, env = "WEBHOOK", requires_all = ["webhook_cert", "webhook_key"])]
+    pub webhook: bool,
+
+    /// Address the admission webhook listens on with TLS.
+    #[arg(long, env = "WEBHOOK_ADDR", default_value = "0.0.0.0:8443")]
+    pub webhook_addr: String,
+
+    /// PEM encoded certificate chain the admission webhook serves.
+    #[arg(long, env = "WEBHOOK_CERT", value_name = "PATH")]
+    pub webhook_cert: Option<PathBuf>,
+
+    /// PEM encoded PKCS #8 private key of the webhook certificate.
+    #[arg(long, env = "WEBHOOK_KEY", value_name = "PATH")]
+    pub webhook_key: Option<PathBuf>,
+
+    /// Logs every write the operator would make, prefixed with `[dry-run]`, instead of making it:
+    /// no copy, finalizer, status annotation, namespace or event is created, changed or deleted.
+    #[arg(long, env = "DRY_RUN")]
+    pub dry_run: bool,
+
+    /// Takes over the fields of a secret not owned by the operator that another field manager set
+    /// to a different value, e.g. a secret created under the name of a new copy meanwhile. Without
+    /// it the apply of such a copy fails with a conflict. Copies owned by the operator, by owner
+    /// label or adopted with `adopt-unmanaged`, are always taken over.
+    #[arg(long, env = "FORCE_APPLY")]
+    pub force_apply: bool,
+
+    /// Records the sync status of every source in a `SpreadStatus` resource of the same name next
+    /// to it instead of in annotations on the source. The CRD printed by `--print-crd` has to be
+    /// installed and the operator needs to get and patch `spreadstatuses` and
+    /// `spreadstatuses/status`.
+    #[arg(long, env = "STATUS_CRD")]
+    pub status_crd: bool,
+
+    /// Spreads the Secrets declared by `SecretSpread` resources, next to the annotated ones. The
+    /// CRD printed by `--print-crd` has to be installed.
+    #[arg(long, env = "SECRET_SPREAD_CRD")]
+    pub secret_spread_crd: bool,
+
+    /// Leaves the metadata of the sources alone, no finalizer is added. A deleted source then
+    /// doesn't clean up its copies right away, they are deleted by the orphan scan as configured by
+    /// `ORPHAN_SCAN_INTERVAL`. Copies of a source that is no longer spread are kept. The copies of
+    /// a deleted `SecretSpread` are deleted when the operator sees the deletion, they are kept if
+    /// it is deleted while the operator is not running.
+    #[arg(long, env = "DISABLE_FINALIZER")]
+    pub disable_finalizer: bool,
+
+    /// Seconds after which a secret without targeting annotation is checked again, at least 5.
+    #[arg(long, env = "REQUEUE_IDLE", default_value_t = 300)]
+    pub requeue_idle: u64,
+
+    /// Seconds after which a synced source is reconciled again, at least 5.
+    #[arg(long, env = "REQUEUE_SYNCED", default_value_t = 60)]
+    pub requeue_synced: u64,
+
+    /// Seconds after which a failed reconcile is retried, at least 1.
+    #[arg(long, env = "REQUEUE_ERROR", default_value_t = 5)]
+    pub requeue_error: u64,
+
+    /// Minimum number of seconds between two reconciles of the same source, 0 disables the
+    /// limit. A source changing more often is reconciled once the interval passed, so it can't
+    /// get the operator throttled by the API server. Deleted sources are cleaned up right away.
+    #[arg(long, env = "MIN_RECONCILE_INTERVAL", default_value_t = 0)]
+    pub min_reconcile_interval: u64,
+
+    /// Number of sources reconciled at once per controller, 0 for no limit. The controller starts
+    /// the reconcile of every source that is due right away; on large clusters, e.g. after a
+    /// restart, they all compete for the API server over one client. 4 to 16 keep the request
+    /// bursts moderate, together with `SYNC_CONCURRENCY` it bounds the requests in flight.
+    #[arg(long, env = "MAX_CONCURRENT_RECONCILES", default_value_t = 0)]
+    pub max_concurrent_reconciles: u16,
+
+    /// Number of sources reconciled at once by the listed controllers instead of
+    /// `MAX_CONCURRENT_RECONCILES`, e.g. `secret=16,configmap=4`. The controllers are `secret`,
+    /// `configmap`, `pull-secret` and `vault`. Each has slots of its own, so one flooded with
+    /// reconciles, e.g. the Secret controller when a new namespace is due for every source spread
+    /// by `*`, doesn't hold up the others.
+    #[arg(long, env = "CONTROLLER_CONCURRENCY", value_name = "CONTROLLER=N", value_delimiter = ',', value_parser = parse_controller_limit)]
+    pub controller_concurrency: Vec<(String, u16)>,
+
+    /// Number of target namespaces a source is synced to concurrently. Every sync makes a few
+    /// requests to the API server, so a higher value speeds up sources with many target
+    /// namespaces at the price of request bursts; 1 syncs one namespace after the other.
+    #[arg(long, env = "SYNC_CONCURRENCY", default_value_t = 8, value_parser = clap::value_parser!(u16).range(1..))]
+    pub sync_concurrency: u16,
+
+    /// Most reconciles started per second. The rate is halved while the API server throttles
+    /// the operator and raised again afterwards.
+    #[arg(long, env = "RECONCILE_RATE", default_value_t = 10.0, value_parser = parse_rate)]
+    pub reconcile_rate: f64,
+
+    /// Percent by which the requeue durations of synced sources vary at random, so sources
+    /// synced together don't stay in step.
+    #[arg(long, env = "REQUEUE_JITTER", default_value_t = 10, value_parser = clap::value_parser!(u32).range(0..=100))]
+    pub requeue_jitter: u32,
+
+    /// Seed of the random requeue jitter, making the sequence of durations reproducible.
+    #[arg(long, env = "REQUEUE_JITTER_SEED")]
+    pub requeue_jitter_seed: Option<u64>,
+
+    /// Failures in a row after which a target namespace of a source is skipped for
+    /// `--quarantine-retry` seconds, 0 disables the quarantine.
+    #[arg(long, env = "QUARANTINE_THRESHOLD", default_value_t = 5)]
+    pub quarantine_threshold: u32,
+
+    /// Seconds a quarantined target namespace is skipped.
+    #[arg(long, env = "QUARANTINE_RETRY", default_value_t = 600)]
+    pub quarantine_retry: u64,
+
+    /// Reconcile outcomes kept per source and served on `/history`, 0 keeps none.
+    #[arg(long, env = "HISTORY_SIZE", default_value_t = 50)]
+    pub history_size: usize,
+
+    /// Seconds between two scans for copies of deleted sources, 0 disables the scan.
+    #[arg(long, env = "ORPHAN_SCAN_INTERVAL", default_value_t = 600)]
+    pub orphan_scan_interval: u64,
+
+    /// Seconds the running reconciles are waited for when the operator is asked to stop.
+    #[arg(long, env = "SHUTDOWN_TIMEOUT", default_value_t = 25)]
+    pub shutdown_timeout: u64,
+
+    /// Creates target namespaces that don't exist for all sources, instead of skipping them.
+    /// The `create-namespace` annotation of a source overrides it.
+    #[arg(long, env = "CREATE_NAMESPACES")]
+    pub create_namespaces: bool,
+
+    /// How several targeting annotations on one source combine.
+    #[arg(long, env = "TARGETING_CONFLICT_MODE", value_enum, default_value_t = ConflictMode::Union)]
+    pub targeting_conflict_mode: ConflictMode,
+
+    /// Bearer token sent with the requests for the target namespaces of `target-url`.
+    #[arg(long, env = "TARGET_URL_TOKEN", hide_env_values = true)]
+    pub target_url_token: Option<String>,
+
+    /// ConfigMap `<namespace>/<name>` holding the rules every copy has to satisfy, under the key
+    /// `rules`. Every copy is allowed if not set.
+    #[arg(long, env = "SPREAD_POLICY_CONFIGMAP", value_name = "NAMESPACE/NAME", value_parser = parse_namespaced_name)]
+    pub spread_policy_configmap: Option<(String, String)>,
+
+    /// ConfigMap `<namespace>/<name>` overriding the requeue intervals, the excluded and allowed
+    /// namespaces and pausing all sources while the operator runs, see the `settings` module.
+    #[arg(long, env = "SETTINGS_CONFIGMAP", value_name = "NAMESPACE/NAME", value_parser = parse_namespaced_name)]
+    pub settings_configmap: Option<(String, String)>,
+
+    /// Where created, updated, deleted and skipped copies are reported, comma separated: `log`,
+    /// `event` (Kubernetes events on the source) and `webhook` (see `--event-webhook-url`). The
+    /// metrics are always kept.
+    #[arg(long, env = "EVENT_SINKS", value_delimiter = ',', default_value = "log", value_parser = ["log", "event", "webhook"])]
+    pub event_sinks: Vec<String>,
+
+    /// URL the `webhook` event sink posts its notifications to.
+    #[arg(long, env = "EVENT_WEBHOOK_URL", value_name = "URL")]
+    pub event_webhook_url: Option<String>,
+
+    /// Shared secret the notifications of the `webhook` event sink are signed with. The
+    /// HMAC-SHA256 of the JSON body is sent as `X-Spread-Signature: sha256=<hex>`. Unsigned if
+    /// not set.
+    #[arg(long, env = "EVENT_WEBHOOK_SECRET", hide_env_values = true)]
+    pub event_webhook_secret: Option<String>,
+
+    /// Namespace of the Lease of the leader election. Only one replica reconciles if set.
+    #[arg(long, env = "LEADER_ELECTION_NAMESPACE")]
+    pub leader_election_namespace: Option<String>,
+
+    /// Name of the Lease of the leader election.
+    #[arg(long, env = "LEASE_NAME", default_value = "spreading-operator")]
+    pub lease_name: String,
+
+    /// Seconds a leader holds the Lease without renewing it.
+    #[arg(long, env = "LEASE_DURATION", default_value_t = 15, value_parser = clap::value_parser!(u64).range(1..))]
+    pub lease_duration: u64,
+
+    /// Seconds between two renewals of the Lease.
+    #[arg(long, env = "LEASE_RENEW_INTERVAL", default_value_t = 5, value_parser = clap::value_parser!(u64).range(1..))]
+    pub lease_renew_interval: u64,
+
+    /// Identity of this replica in the leader election, the hostname if not set.
+    #[arg(long, env = "POD_NAME")]
+    pub pod_name: Option<String>,
+
+    /// Prefix of all annotations and labels of the operator. Two operator instances with
+    /// different prefixes leave each other's sources and copies alone.
+    #[arg(long, env = "ANNOTATION_PREFIX", default_value = "eu.fitzek.spread.")]
+    pub annotation_prefix: String,
+
+    /// Label linking a copy to its source, `<prefix>owner` if not set.
+    #[arg(long, env = "OWNER_LABEL")]
+    pub owner_label: Option<String>,
+
+    /// Finalizer guarding the cleanup of a source.
+    #[arg(long, env = "FINALIZER_NAME", default_value = "secretspreading.fitzek.eu/finalizer")]
+    pub finalizer_name: String,
+
+    /// Seconds after which the sources held in Vault are fetched again, with the `vault`
+    /// feature.
+    #[arg(long, env = "VAULT_REFRESH_INTERVAL", default_value_t = 300)]
+    pub vault_refresh_interval: u64,
+
+    /// Name identifying this operator instance, the field manager of all its writes and the
+    /// name of its RBAC objects.
+    #[arg(long, env = "INSTANCE_NAME", default_value = "spreading-operator")]
+    pub instance_name: String,
+
+    /// Value of the `app.kubernetes.io/managed-by` label set on copies.
+    #[arg(long, env = "MANAGED_BY", default_value = "spreading-operator")]
+    pub managed_by: String,
+
+    /// Stamps every copy with the resourceVersion of its source.
+    #[arg(long, env = "STAMP_SOURCE_VERSION")]
+    pub stamp_source_version: bool,
+
+    /// Namespace holding the central pull secrets, spread into the namespaces of the Deployments
+    /// referencing them. The pull secret controller only runs if set.
+    #[arg(long, env = "PULL_SECRET_SOURCE_NAMESPACE")]
+    pub pull_secret_source_namespace: Option<String>,
+}
+
+/// How several targeting annotations on one source combine, see `--targeting-conflict-mode`.
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Default)]
+pub enum ConflictMode {
+    /// All of them are honored, their namespaces are combined.
+    #[default]
+    Union,
+    /// Combining several of them is an error.
+    Strict,
+    /// Only the first one by precedence is honored, the others are ignored with a warning.
+    Lenient,
+}
+
+/// Parses a rate of reconciles per second, a positive number.
+fn parse_rate(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(rate) if rate.is_finite() && rate > 0.0 => Ok(rate),
+        _ => Err(format!("expected a positive number, got {}", value)),
+    }
+}
+
+/// Parses the concurrency limit `<controller>=<n>` of one controller.
+fn parse_controller_limit(value: &str) -> Result<(String, u16), String> {
+    let (controller, limit) = value.split_once('=').ok_or_else(|| format!("expected <controller>=<n>, got {}", value))?;
+    let controller = controller.trim();
+    if !crate::concurrency::CONTROLLERS.contains(&controller) {
+        return Err(format!("unknown controller {}, expected one of {}", controller, crate::concurrency::CONTROLLERS.join(", ")));
+    }
+    let limit = limit.trim().parse().map_err(|_| format!("expected a number of reconciles, got {}", limit))?;
+    Ok((controller.to_string(), limit))
+}
+
+/// Parses a reference `<namespace>/<name>` to a namespaced object.
+fn parse_namespaced_name(value: &str) -> Result<(String, String), String> {
+    match value.split_once('/') {
+        Some((namespace, name)) if !namespace.is_empty() && !name.is_empty() => Ok((namespace.to_string(), name.to_string())),
+        _ => Err(format!("expected <namespace>/<name>, got {}", value)),
+    }
+}
@@ -0,0 +1,239 @@
+use std::collections::HashSet;
+use std::fmt::Debug;
+
+use k8s_openapi::api::core::v1::{ConfigMap, Secret};
+use kube::api::{DeleteParams, ListParams};
+use kube::{Api, Client, Resource};
+use kube_runtime::controller::Context;
+use serde::de::DeserializeOwned;
+use tokio::time::{sleep, Duration};
+use tracing::{info, warn};
+
+use crate::opts::Opts;
+use crate::{finalizer, keys, scoped_api, secret_cleanup, ContextData, Error};
+
+/// Periodically deletes orphaned copies, see [`scan`], and cleans up sources that left the
+/// selection of the watch `scopes`, see [`release_deselected`]. The interval is configured by
+/// `--orphan-scan-interval` of `opts` in seconds, 0 disables the scan.
+pub async fn run(client: Client, scopes: &[Option<String>], opts: &Opts) {
+    let interval = opts.orphan_scan_interval;
+    if interval == 0 {
+        if opts.disable_finalizer {
+            warn!("Finalizer and orphan scan are both disabled, copies of deleted sources are never cleaned up");
+        }
+        return;
+    }
+
+    let context: Context<ContextData> = Context::new(ContextData::new(client.clone(), opts));
+    let dp = context.get_ref().delete_params();
+    loop {
+        sleep(Duration::from_secs(interval)).await;
+        if let Err(e) = scan(client.clone(), &dp).await {
+            warn!(error = ?e, "Orphan scan failed");
+        }
+        for scope in scopes {
+            if let Err(e) = release_deselected(context.clone(), scope.as_deref()).await {
+                warn!(error = ?e, "Cleanup of deselected sources failed");
+            }
+        }
+    }
+}
+
+/// Deletes copies whose source no longer exists.
+///
+/// The cleanup of a deleted source is guarded by a finalizer. If somebody else removes the
+/// finalizer while copies are still being deleted, the source is gone before the cleanup is
+/// complete and no further reconcile happens for it. The scan is the backstop for that case, and
+/// the only cleanup of deleted sources if the finalizer is disabled by `--disable-finalizer`.
+///
+/// Sources are Secrets, or ConfigMaps for ConfigMap and backend sources, so a copy is orphaned
+/// if no Secret and no ConfigMap has the uid in its owner label. With a dry run `dp` the orphans
+/// are only logged.
+pub async fn scan(client: Client, dp: &DeleteParams) -> Result<(), Error> {
+    let secret_api: Api<Secret> = Api::all(client.clone());
+    let configmap_api: Api<ConfigMap> = Api::all(client.clone());
+    let copies = secret_api.list(&ListParams::default().labels(keys::owner_label())).await?;
+    let configmap_copies = configmap_api.list(&ListParams::default().labels(keys::owner_label())).await?;
+    if copies.items.is_empty() && configmap_copies.items.is_empty() {
+        return Ok(());
+    }
+
+    let mut uids: HashSet<String> = secret_api
+        .list(&ListParams::default())
+        .await?
+        .iter()
+        .filter_map(|s| s.metadata.uid.clone())
+        .collect();
+    uids.extend(
+        configmap_api
+            .list(&ListParams::default())
+            .await?
+            .iter()
+            .filter_map(|c| c.metadata.uid.clone()),
+    );
+
+    delete_orphans(client.clone(), copies.items, &uids, dp).await?;
+    delete_orphans(client, configmap_copies.items, &uids, dp).await
+}
+
+/// Cleans up the sources in the watch scope `scope` that carry the finalizer but no longer match
+/// `SOURCE_LABEL_SELECTOR`.
+///
+/// A source losing its label drops out of the watch of the controller, which doesn't see it
+/// anymore, not even its deletion. Its finalizer would block the deletion for good, so it is
+/// treated like a deleted source: its copies are deleted and the finalizer is removed.
+pub async fn release_deselected(context: Context<ContextData>, scope: Option<&str>) -> Result<(), Error> {
+    let selected_params = context.get_ref().source_list_params();
+    if selected_params.label_selector.is_none() {
+        return Ok(());
+    }
+
+    let secret_api: Api<Secret> = scoped_api(context.get_ref().client.clone(), scope);
+    // listed before the selected ones, a source labeled in between counts as selected
+    let finalized: Vec<Secret> = secret_api
+        .list(&ListParams::default())
+        .await?
+        .into_iter()
+        .filter(finalizer::has_finalizer)
+        .collect();
+    if finalized.is_empty() {
+        return Ok(());
+    }
+    let selected: HashSet<String> = secret_api
+        .list(&selected_params)
+        .await?
+        .iter()
+        .filter_map(|s| s.metadata.uid.clone())
+        .collect();
+
+    for sec in finalized {
+        let (source_namespace, uid) = match (sec.namespace(), sec.metadata.uid.clone()) {
+            (Some(ns), Some(uid)) if !selected.contains(&uid) => (ns, uid),
+            _ => continue,
+        };
+        let name = sec.name();
+        info!(source_namespace = %source_namespace, secret_name = %name, "Source no longer matches SOURCE_LABEL_SELECTOR, cleaning up");
+        secret_cleanup(sec, context.clone(), source_namespace, name, uid).await?;
+    }
+
+    Ok(())
+}
+
+/// Deletes the `copies` whose owner label holds none of the `uids`.
+async fn delete_orphans<K>(client: Client, copies: Vec<K>, uids: &HashSet<String>, dp: &DeleteParams) -> Result<(), Error>
+where
+    K: Resource<DynamicType = ()> + Clone + DeserializeOwned + Debug,
+{
+    for copy in copies {
+        let owner = copy.meta().labels.as_ref().and_then(|l| l.get(keys::owner_label()));
+        let ns = match copy.namespace() {
+            Some(ns) => ns,
+            None => continue,
+        };
+        match owner {
+            Some(owner) if !uids.contains(owner) => {
+                if dp.dry_run {
+                    info!(target_namespace = %ns, name = %copy.name(), "[dry-run] Would clean up orphaned copy");
+                    continue;
+                }
+                info!(target_namespace = %ns, name = %copy.name(), "Cleaning up orphaned copy");
+                let ns_api: Api<K> = Api::namespaced(client.clone(), &ns);
+                match ns_api.delete(&copy.name(), dp).await {
+                    Ok(_) | Err(kube::Error::Api(kube::error::ErrorResponse { code: 404, .. })) => {}
+                    Err(e) => return Err(e.into()),
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    use clap::Parser;
+    use k8s_openapi::api::core::v1::Namespace;
+    use k8s_openapi::ByteString;
+    use kube::api::ObjectMeta;
+
+    use crate::fake_api::FakeApi;
+    use crate::{reconcile, targets};
+
+    fn insert_source(fake: &FakeApi, name: &str) -> Secret {
+        let mut annotations = BTreeMap::new();
+        annotations.insert(keys::key(targets::TARGET_NAMESPACE_ANNOTATION), "a,b".to_string());
+        let mut data = BTreeMap::new();
+        data.insert("password".to_string(), ByteString(b"secret".to_vec()));
+        let sec = Secret {
+            metadata: ObjectMeta {
+                name: Some(name.to_string()),
+                namespace: Some("source".to_string()),
+                annotations: Some(annotations),
+                ..ObjectMeta::default()
+            },
+            data: Some(data),
+            ..Secret::default()
+        };
+        serde_json::from_value(fake.insert(&sec)).unwrap()
+    }
+
+    /// Namespaces and names of all copies, with the uid of their source.
+    fn copies(fake: &FakeApi) -> Vec<(String, String, String)> {
+        fake.list::<Secret>()
+            .into_iter()
+            .filter_map(|s| {
+                let owner = s.metadata.labels.as_ref()?.get(keys::owner_label())?.clone();
+                Some((s.namespace().unwrap(), s.name(), owner))
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn scan_removes_copies_of_a_source_recreated_under_another_name() {
+        let (client, fake) = FakeApi::start();
+        for ns in &["source", "a", "b"] {
+            fake.insert(&Namespace {
+                metadata: ObjectMeta {
+                    name: Some(ns.to_string()),
+                    ..ObjectMeta::default()
+                },
+                ..Namespace::default()
+            });
+        }
+        // without the finalizer the orphan scan is the only cleanup of a deleted source
+        let opts = Opts::parse_from(["spreading-operator", "--disable-finalizer"]);
+        let context: Context<ContextData> = Context::new(ContextData::new(client.clone(), &opts));
+        let dp = DeleteParams::default();
+
+        let old = insert_source(&fake, "db");
+        reconcile(old.clone(), context.clone()).await.unwrap();
+        let api: Api<Secret> = Api::namespaced(client.clone(), "source");
+        api.delete("db", &dp).await.unwrap();
+        let new = insert_source(&fake, "db-v2");
+        reconcile(new.clone(), context.clone()).await.unwrap();
+        let old_uid = old.metadata.uid.clone().unwrap();
+        let new_uid = new.metadata.uid.clone().unwrap();
+        assert_eq!(copies(&fake).len(), 4);
+
+        scan(client.clone(), &dp).await.unwrap();
+        assert_eq!(
+            copies(&fake),
+            vec![
+                ("a".to_string(), "db-v2".to_string(), new_uid.clone()),
+                ("b".to_string(), "db-v2".to_string(), new_uid),
+            ]
+        );
+        assert!(copies(&fake).iter().all(|(_, _, owner)| *owner != old_uid));
+
+        // nothing is left to clean up, a resync of the new source keeps its copies
+        fake.take_writes();
+        scan(client, &dp).await.unwrap();
+        assert!(fake.take_writes().is_empty());
+        reconcile(new, context).await.unwrap();
+        assert_eq!(copies(&fake).len(), 2);
+    }
+}
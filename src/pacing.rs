@@ -0,0 +1,166 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use tokio::time::{sleep_until, Duration, Instant};
+use tracing::warn;
+
+use crate::metrics;
+
+/// Lowest rate the pacer backs off to.
+const MIN_RATE: f64 = 0.5;
+
+struct State {
+    /// Reconciles permitted per second.
+    rate: f64,
+    /// Earliest time the next reconcile may start.
+    next: Instant,
+}
+
+/// Paces reconciles adaptively: the rate is halved whenever the API server answers with 429
+/// Too Many Requests and grows back by one reconcile per second with every reconcile that
+/// wasn't throttled, up to the configured maximum. The current rate is exported as the
+/// `spread_reconcile_rate` metric.
+pub struct Pacer {
+    max_rate: f64,
+    state: Mutex<State>,
+}
+
+impl Pacer {
+    /// Constructs a new Pacer permitting at most `max_rate` reconciles per second.
+    pub fn new(max_rate: f64) -> Self {
+        let max_rate = max_rate.max(MIN_RATE);
+        metrics::set_reconcile_rate(max_rate);
+        Pacer {
+            max_rate,
+            state: Mutex::new(State {
+                rate: max_rate,
+                next: Instant::now(),
+            }),
+        }
+    }
+
+    /// Waits until the next reconcile may start.
+    pub async fn acquire(&self) {
+        let slot = {
+            let mut state = self.state.lock().unwrap();
+            let now = Instant::now();
+            let slot = if state.next > now { state.next } else { now };
+            state.next = slot + Duration::from_secs_f64(1.0 / state.rate);
+            slot
+        };
+        sleep_until(slot).await;
+    }
+
+    /// Records a reconcile that was throttled by the API server, halving the rate.
+    pub fn throttled(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.rate = (state.rate / 2.0).max(MIN_RATE);
+        metrics::set_reconcile_rate(state.rate);
+        warn!(rate = state.rate, "API server is throttling, pacing reconciles");
+    }
+
+    /// Records a reconcile that wasn't throttled, raising the rate again.
+    pub fn succeeded(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.rate = (state.rate + 1.0).min(self.max_rate);
+        metrics::set_reconcile_rate(state.rate);
+    }
+}
+
+/// Limits how often a single source is reconciled: a reconcile of a source starts at least
+/// `interval` after the previous one, the excess is deferred. Unlike the [`Pacer`], which
+/// spreads all reconciles over time, this keeps one frequently updated source from taking up the
+/// rate of the others.
+pub struct SourceLimiter {
+    interval: Duration,
+    /// Time the last reconcile of each source started, keyed by source uid.
+    started: Mutex<HashMap<String, Instant>>,
+}
+
+impl SourceLimiter {
+    /// Constructs a new SourceLimiter with at least `interval` between the reconciles of a
+    /// source, zero disables the limit.
+    pub fn new(interval: Duration) -> Self {
+        SourceLimiter {
+            interval,
+            started: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Registers a reconcile of the source `uid` about to start. Returns the time to defer it
+    /// by if the previous reconcile of the source started less than the interval ago.
+    pub fn defer(&self, uid: &str) -> Option<Duration> {
+        if self.interval.is_zero() {
+            return None;
+        }
+        let mut started = self.started.lock().unwrap();
+        let now = Instant::now();
+        started.retain(|_, at| now.duration_since(*at) < self.interval);
+        match started.get(uid) {
+            Some(at) => Some(self.interval - now.duration_since(*at)),
+            None => {
+                started.insert(uid.to_string(), now);
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rate(pacer: &Pacer) -> f64 {
+        pacer.state.lock().unwrap().rate
+    }
+
+    #[test]
+    fn rate_backs_off_on_throttling_and_recovers() {
+        let pacer = Pacer::new(8.0);
+        assert_eq!(rate(&pacer), 8.0);
+        for expected in &[4.0, 2.0, 1.0, 0.5, 0.5] {
+            pacer.throttled();
+            assert_eq!(rate(&pacer), *expected);
+        }
+        for expected in &[1.5, 2.5, 3.5] {
+            pacer.succeeded();
+            assert_eq!(rate(&pacer), *expected);
+        }
+        // throttled again in between
+        pacer.throttled();
+        assert_eq!(rate(&pacer), 1.75);
+        for _ in 0..10 {
+            pacer.succeeded();
+        }
+        assert_eq!(rate(&pacer), 8.0);
+    }
+
+    #[tokio::test]
+    async fn reconciles_are_spaced_by_the_rate() {
+        let pacer = Pacer::new(40.0);
+        let started = Instant::now();
+        for _ in 0..5 {
+            pacer.acquire().await;
+        }
+        // the first starts right away, the others 25ms apart
+        assert!(started.elapsed() >= Duration::from_millis(100));
+
+        for _ in 0..3 {
+            pacer.throttled();
+        }
+        let started = Instant::now();
+        for _ in 0..2 {
+            pacer.acquire().await;
+        }
+        assert!(started.elapsed() >= Duration::from_millis(150));
+    }
+
+    #[test]
+    fn source_limiter_defers_frequent_reconciles_of_a_source() {
+        let limiter = SourceLimiter::new(Duration::from_secs(60));
+        assert_eq!(limiter.defer("uid"), None);
+        assert!(limiter.defer("uid").is_some_and(|d| d > Duration::from_secs(59)));
+        assert_eq!(limiter.defer("other"), None);
+        assert_eq!(SourceLimiter::new(Duration::from_secs(0)).defer("uid"), None);
+    }
+}
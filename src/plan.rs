@@ -0,0 +1,304 @@
+//! Decisions of a sync, separated from the requests carrying them out: given the source and the
+//! secrets found under the copy names, what is to be done in each target namespace and which
+//! copies are stale. Nothing here talks to the API server.
+
+use std::collections::{BTreeMap, HashMap};
+
+use k8s_openapi::api::core::v1::Secret;
+
+use crate::config::SpreadConfig;
+use crate::{compare, is_copy, keys};
+
+/// What is to be done with the copy in one target namespace.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CopyStep {
+    /// There is no copy yet.
+    Create,
+    /// The copy is outdated and patched.
+    Update,
+    /// The copy can't be patched to match, e.g. its type changed, it is deleted and created anew.
+    Replace,
+    Unchanged,
+    /// An unmanaged secret has the name of the copy and is left alone.
+    Blocked,
+}
+
+/// The steps of a sync of a source, by target namespace, and the stale copies to delete.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SyncPlan {
+    pub steps: BTreeMap<String, CopyStep>,
+    /// Namespaces and names of the copies of the source that are deleted after the sync.
+    pub deletes: Vec<(String, String)>,
+    /// Whether the namespace of the source was targeted, it never gets a copy.
+    pub skipped_source: bool,
+}
+
+/// Plans the sync of `source` into the namespaces of `existing_targets`, each with the secret
+/// found under the name of the copy there, if any. Data keys of `ignored_keys` added to the
+/// copies by others are ignored.
+///
+/// `copies` are the copies of the source found by its owner label. The ones not named as in
+/// `desired_names`, by target namespace, are stale, see [`is_stale`], and planned for deletion.
+/// A copy of a generateName source only has a desired name once it was created and recorded.
+pub fn plan_sync(source: &Secret, existing_targets: &HashMap<String, Option<Secret>>, copies: &[Secret], desired_names: &BTreeMap<String, String>, config: &SpreadConfig, ignored_keys: &[String]) -> SyncPlan {
+    let source_uid = source.metadata.uid.clone().unwrap_or_default();
+    let mut plan = SyncPlan::default();
+    for (ns, existing) in existing_targets {
+        if Some(ns) == source.metadata.namespace.as_ref() {
+            plan.skipped_source = true;
+            continue;
+        }
+        let annotations = compare::with_owner_reference(&compare::desired_annotations(source, &config.target_annotations, ns), source);
+        let step = plan_copy(source, existing.as_ref(), config, &source_uid, &annotations, ignored_keys);
+        plan.steps.insert(ns.clone(), step);
+    }
+    // only copies owned by the source are considered, copies of other sources are never touched
+    let owned = |copy: &&Secret| copy.metadata.labels.as_ref().and_then(|labels| labels.get(keys::owner_label())) == Some(&source_uid);
+    for copy in copies.iter().filter(owned) {
+        let (ns, name) = match (&copy.metadata.namespace, &copy.metadata.name) {
+            (Some(ns), Some(name)) => (ns, name),
+            _ => continue,
+        };
+        if is_stale(ns, name, desired_names, config.prune_untargeted) {
+            plan.deletes.push((ns.clone(), name.clone()));
+        }
+    }
+    plan.deletes.sort();
+    plan
+}
+
+/// Returns whether the copy `name` in namespace `ns` is stale, given the `desired_names` of the
+/// copies by target namespace: it is named differently than desired, e.g. after the target name
+/// was changed, or its namespace is no longer targeted and `prune_untargeted` is set.
+pub fn is_stale(ns: &str, name: &str, desired_names: &BTreeMap<String, String>, prune_untargeted: bool) -> bool {
+    match desired_names.get(ns) {
+        Some(desired_name) => desired_name != name,
+        None => prune_untargeted,
+    }
+}
+
+/// Plans the copy of `source` where `existing` has the name of the copy. `annotations` are the
+/// annotations the copy is compared by and `ignored_keys` the data keys added by others, see
+/// [`compare::secrets_equivalent`].
+///
+/// An existing secret is only touched if it is a copy, a copy that lost its owner label but
+/// still references this source (see [`owned_unlabeled`]), or if `adopt-unmanaged` is set.
+///
+/// A copy carrying the [`compare::content_hash`] of the desired content is unchanged without
+/// comparing the content itself, so an edit of a copy that keeps the hash annotation is only
+/// undone by the next change of the source or its [`compare::RESYNC_ANNOTATION`]. Copies without
+/// the hash are compared. A copy not yet written for the current resync value is updated
+/// whatever its content.
+pub fn plan_copy(source: &Secret, existing: Option<&Secret>, config: &SpreadConfig, source_uid: &str, annotations: &BTreeMap<String, String>, ignored_keys: &[String]) -> CopyStep {
+    let existing = match existing {
+        None => return CopyStep::Create,
+        Some(existing) => existing,
+    };
+    if !is_copy(existing) && !owned_unlabeled(existing, source_uid) && !config.adopt_unmanaged {
+        CopyStep::Blocked
+    } else if needs_replacement(source, existing, ignored_keys) {
+        CopyStep::Replace
+    } else if compare::resync_pending(source, existing) {
+        CopyStep::Update
+    } else if is_copy(existing) && compare::content_hash_matches(existing, &compare::content_hash(source, source_uid, annotations)) {
+        CopyStep::Unchanged
+    } else if !compare::secrets_equivalent(source, existing, source_uid, annotations, ignored_keys) {
+        CopyStep::Update
+    } else {
+        CopyStep::Unchanged
+    }
+}
+
+/// Returns whether `existing` is a copy of the source with `source_uid` that lost its owner
+/// label: it still carries the owner reference to the source.
+pub fn owned_unlabeled(existing: &Secret, source_uid: &str) -> bool {
+    !is_copy(existing) && compare::references_source(existing, source_uid)
+}
+
+/// Returns whether the copy `existing` can't be patched to match `sec` and is to be recreated.
+///
+/// The type of a secret is immutable, a copy of the wrong type is replaced. So is an immutable
+/// copy whose data is outdated, and a copy whose immutability differs from the source, as an
+/// immutable secret can't be made mutable again.
+fn needs_replacement(sec: &Secret, existing: &Secret, ignored_keys: &[String]) -> bool {
+    let immutable = |s: &Secret| s.immutable == Some(true);
+    compare::normalized_type(existing) != compare::normalized_type(sec)
+        || immutable(existing) != immutable(sec)
+        || (immutable(existing) && compare::managed_data(sec, existing, ignored_keys) != compare::normalized_data(sec))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use k8s_openapi::ByteString;
+    use kube::api::ObjectMeta;
+
+    use crate::targets;
+
+    const UID: &str = "source-uid";
+
+    fn source(annotations: &[(&str, &str)]) -> Secret {
+        let mut data = BTreeMap::new();
+        data.insert("password".to_string(), ByteString(b"secret".to_vec()));
+        Secret {
+            metadata: ObjectMeta {
+                name: Some("db".to_string()),
+                namespace: Some("source".to_string()),
+                uid: Some(UID.to_string()),
+                annotations: Some(annotations.iter().map(|(k, v)| (keys::key(k), v.to_string())).collect()),
+                ..ObjectMeta::default()
+            },
+            data: Some(data),
+            ..Secret::default()
+        }
+    }
+
+    /// Returns an up to date copy of `source` named `name` in `namespace`.
+    fn copy(source: &Secret, namespace: &str, name: &str) -> Secret {
+        let annotations = compare::desired_annotations(source, &Default::default(), namespace);
+        Secret {
+            metadata: ObjectMeta {
+                name: Some(name.to_string()),
+                namespace: Some(namespace.to_string()),
+                labels: Some(compare::desired_labels(source, UID)),
+                annotations: Some(compare::with_owner_reference(&annotations, source)),
+                ..ObjectMeta::default()
+            },
+            data: source.data.clone(),
+            type_: Some("Opaque".to_string()),
+            ..Secret::default()
+        }
+    }
+
+    /// Returns `copy` carrying the content hash of `source` it was written with.
+    fn hashed(source: &Secret, mut copy: Secret) -> Secret {
+        let annotations = copy.metadata.annotations.clone().unwrap_or_default();
+        copy.metadata.annotations = Some(compare::with_content_hash(&annotations, compare::content_hash(source, UID, &annotations)));
+        copy
+    }
+
+    fn plan_of(source: &Secret, existing: Vec<(&str, Option<Secret>)>, copies: &[Secret]) -> SyncPlan {
+        let config = SpreadConfig::from_secret(source).unwrap().unwrap();
+        let existing_targets: HashMap<String, Option<Secret>> = existing.into_iter().map(|(ns, s)| (ns.to_string(), s)).collect();
+        let desired_names: BTreeMap<String, String> = existing_targets.keys().filter(|ns| *ns != "source").map(|ns| (ns.clone(), "db".to_string())).collect();
+        plan_sync(source, &existing_targets, copies, &desired_names, &config, &[])
+    }
+
+    #[test]
+    fn creates_missing_copies() {
+        let source = source(&[(targets::TARGET_NAMESPACE_ANNOTATION, "a,b")]);
+        let plan = plan_of(&source, vec![("a", None), ("b", None)], &[]);
+        assert_eq!(plan.steps.into_iter().collect::<Vec<_>>(), vec![("a".to_string(), CopyStep::Create), ("b".to_string(), CopyStep::Create)]);
+        assert!(plan.deletes.is_empty());
+    }
+
+    #[test]
+    fn updates_outdated_copies() {
+        let source = source(&[(targets::TARGET_NAMESPACE_ANNOTATION, "a")]);
+        let mut outdated = copy(&source, "a", "db");
+        outdated.data = Some(BTreeMap::new());
+        let plan = plan_of(&source, vec![("a", Some(outdated.clone()))], &[outdated]);
+        assert_eq!(plan.steps["a"], CopyStep::Update);
+        assert!(plan.deletes.is_empty());
+
+        let mut retyped = copy(&source, "a", "db");
+        retyped.type_ = Some("kubernetes.io/tls".to_string());
+        assert_eq!(plan_of(&source, vec![("a", Some(retyped.clone()))], &[]).steps["a"], CopyStep::Replace);
+        // even if it carries the content hash of the source
+        let hashed_retyped = hashed(&source, retyped);
+        assert_eq!(plan_of(&source, vec![("a", Some(hashed_retyped))], &[]).steps["a"], CopyStep::Replace);
+    }
+
+    #[test]
+    fn skips_up_to_date_copies_unmanaged_secrets_and_the_source_namespace() {
+        let source = source(&[(targets::TARGET_NAMESPACE_ANNOTATION, "a,b,source")]);
+        let up_to_date = copy(&source, "a", "db");
+        let mut unmanaged = copy(&source, "b", "db");
+        unmanaged.metadata.labels = None;
+        unmanaged.metadata.annotations = None;
+        let plan = plan_of(&source, vec![("a", Some(up_to_date.clone())), ("b", Some(unmanaged)), ("source", None)], &[up_to_date]);
+        assert_eq!(plan.steps["a"], CopyStep::Unchanged);
+        assert_eq!(plan.steps["b"], CopyStep::Blocked);
+        assert!(!plan.steps.contains_key("source"));
+        assert!(plan.skipped_source);
+        assert!(plan.deletes.is_empty());
+    }
+
+    #[test]
+    fn copies_that_lost_their_owner_label_are_not_blocked() {
+        let source = source(&[(targets::TARGET_NAMESPACE_ANNOTATION, "a")]);
+        let mut unlabeled = copy(&source, "a", "db");
+        unlabeled.metadata.labels = None;
+        assert!(owned_unlabeled(&unlabeled, UID));
+        assert!(!owned_unlabeled(&unlabeled, "other-uid"));
+        assert!(!owned_unlabeled(&copy(&source, "a", "db"), UID));
+        assert_eq!(plan_of(&source, vec![("a", Some(unlabeled))], &[]).steps["a"], CopyStep::Update);
+    }
+
+    #[test]
+    fn replaces_copies_that_cant_be_patched_to_immutable() {
+        let mut source = source(&[(targets::TARGET_NAMESPACE_ANNOTATION, "a")]);
+        source.immutable = Some(true);
+        let up_to_date = copy(&source, "a", "db");
+        let mut mutable = up_to_date.clone();
+        mutable.immutable = None;
+        assert_eq!(plan_of(&source, vec![("a", Some(mutable))], &[]).steps["a"], CopyStep::Replace);
+
+        let mut immutable = up_to_date.clone();
+        immutable.immutable = Some(true);
+        assert_eq!(plan_of(&source, vec![("a", Some(immutable.clone()))], &[]).steps["a"], CopyStep::Unchanged);
+        immutable.data = Some(BTreeMap::new());
+        assert_eq!(plan_of(&source, vec![("a", Some(immutable))], &[]).steps["a"], CopyStep::Replace);
+    }
+
+    #[test]
+    fn deletes_stale_copies() {
+        let source = source(&[(targets::TARGET_NAMESPACE_ANNOTATION, "a")]);
+        let mut other_source = copy(&source, "c", "other");
+        other_source.metadata.labels.get_or_insert_with(BTreeMap::new).insert(keys::owner_label().to_string(), "other-uid".to_string());
+        let copies = [copy(&source, "a", "db"), copy(&source, "a", "db-renamed"), copy(&source, "b", "db"), other_source];
+        let plan = plan_of(&source, vec![("a", Some(copies[0].clone()))], &copies);
+        assert_eq!(plan.steps["a"], CopyStep::Unchanged);
+        assert_eq!(plan.deletes, vec![("a".to_string(), "db-renamed".to_string()), ("b".to_string(), "db".to_string())]);
+    }
+
+    #[test]
+    fn matching_content_hash_skips_the_comparison() {
+        let source = source(&[(targets::TARGET_NAMESPACE_ANNOTATION, "a")]);
+        // edited by hand, but still carrying the hash of the current content
+        let mut edited = hashed(&source, copy(&source, "a", "db"));
+        edited.data = Some(BTreeMap::new());
+        assert_eq!(plan_of(&source, vec![("a", Some(edited.clone()))], &[]).steps["a"], CopyStep::Unchanged);
+
+        // without the hash, or with the hash of an older content, the copy is compared
+        let mut unhashed = edited.clone();
+        unhashed.metadata.annotations.as_mut().unwrap().remove(&keys::key(compare::CONTENT_HASH_ANNOTATION));
+        assert_eq!(plan_of(&source, vec![("a", Some(unhashed))], &[]).steps["a"], CopyStep::Update);
+        let mut rotated = source.clone();
+        rotated.data.as_mut().unwrap().insert("password".to_string(), ByteString(b"rotated".to_vec()));
+        assert_eq!(plan_of(&rotated, vec![("a", Some(edited))], &[]).steps["a"], CopyStep::Update);
+    }
+
+    #[test]
+    fn resync_bypasses_a_matching_content_hash_once() {
+        let source = source(&[(targets::TARGET_NAMESPACE_ANNOTATION, "a"), (compare::RESYNC_ANNOTATION, "2021-06-01T00:00:00Z")]);
+        let hashed_copy = hashed(&source, copy(&source, "a", "db"));
+        assert_eq!(plan_of(&source, vec![("a", Some(hashed_copy.clone()))], &[]).steps["a"], CopyStep::Update);
+
+        // written for the resync value, the copy is unchanged until the value changes again
+        let mut resynced = hashed_copy;
+        resynced.metadata.annotations = Some(compare::with_resynced(resynced.metadata.annotations.as_ref().unwrap(), &source));
+        assert_eq!(plan_of(&source, vec![("a", Some(resynced.clone()))], &[]).steps["a"], CopyStep::Unchanged);
+        let bumped = self::source(&[(targets::TARGET_NAMESPACE_ANNOTATION, "a"), (compare::RESYNC_ANNOTATION, "2021-06-02T00:00:00Z")]);
+        assert_eq!(plan_of(&bumped, vec![("a", Some(resynced))], &[]).steps["a"], CopyStep::Update);
+    }
+
+    #[test]
+    fn keeps_untargeted_copies_without_pruning() {
+        let source = source(&[(targets::TARGET_NAMESPACE_ANNOTATION, "a"), (targets::PRUNE_UNTARGETED_ANNOTATION, "false")]);
+        let copies = [copy(&source, "a", "db-renamed"), copy(&source, "b", "db")];
+        let plan = plan_of(&source, vec![("a", None)], &copies);
+        assert_eq!(plan.steps["a"], CopyStep::Create);
+        assert_eq!(plan.deletes, vec![("a".to_string(), "db-renamed".to_string())]);
+    }
+}
@@ -0,0 +1,178 @@
+//! Governance rules checked before a copy is written.
+//!
+//! The rules are read from the ConfigMap named by `--spread-policy-configmap` as
+//! `<namespace>/<name>`, key `rules`, a YAML list:
+//!
+//! ```yaml
+//! - types: ["kubernetes.io/tls"]
+//!   requireNamespaceLabel: tls-allowed
+//! - types: ["Opaque"]
+//!   allowedKeys: ["username", "password"]
+//! ```
+//!
+//! A rule applies to secrets of one of its `types`, or to all secrets if `types` is empty. A copy
+//! is denied if the target namespace lacks the `requireNamespaceLabel` (`key` or `key=value`) of
+//! an applying rule, or if the secret holds a key not in its `allowedKeys`.
+
+use std::collections::BTreeMap;
+
+use k8s_openapi::api::core::v1::{ConfigMap, Namespace, Secret};
+use kube::{Api, Client};
+use serde::Deserialize;
+
+use crate::{compare, Error};
+
+/// One rule of the policy.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct Rule {
+    /// Secret types the rule applies to, all types if empty.
+    #[serde(default)]
+    pub types: Vec<String>,
+    /// Label, `key` or `key=value`, the target namespace has to carry.
+    pub require_namespace_label: Option<String>,
+    /// Keys the secret may hold, any key if not set.
+    pub allowed_keys: Option<Vec<String>>,
+}
+
+/// Rules every copy has to satisfy.
+#[derive(Debug, Default)]
+pub struct Policy {
+    rules: Vec<Rule>,
+}
+
+impl Policy {
+    /// Loads the policy from the ConfigMap `reference`, `<namespace>/<name>`, see
+    /// `--spread-policy-configmap`. Without a reference every copy is allowed.
+    pub async fn load(client: Client, reference: Option<&(String, String)>) -> Result<Self, Error> {
+        let (namespace, name) = match reference {
+            Some((namespace, name)) => (namespace.as_str(), name.as_str()),
+            None => return Ok(Policy::default()),
+        };
+        let reference = format!("{}/{}", namespace, name);
+        let api: Api<ConfigMap> = Api::namespaced(client, namespace);
+        let cm = api.get(name).await?;
+        let rules = match cm.data.as_ref().and_then(|d| d.get("rules")) {
+            Some(r) => serde_yaml::from_str(r)
+                .map_err(|e| Error::UserInputError(format!("Invalid policy in {}: {}", reference, e)))?,
+            None => Vec::new(),
+        };
+        Ok(Policy { rules })
+    }
+
+    /// Returns true if the policy has no rules.
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Checks whether a copy of `sec` may be written to `namespace`. Returns the reason if not.
+    pub fn check(&self, sec: &Secret, namespace: &Namespace) -> Option<String> {
+        let type_ = compare::normalized_type(sec);
+        let labels: BTreeMap<String, String> = namespace.metadata.labels.clone().unwrap_or_default();
+        let ns_name = namespace.metadata.name.clone().unwrap_or_default();
+
+        for rule in self.rules.iter().filter(|r| r.types.is_empty() || r.types.contains(&type_)) {
+            if let Some(required) = &rule.require_namespace_label {
+                let present = match required.split_once('=') {
+                    Some((key, value)) => labels.get(key).map(String::as_str) == Some(value),
+                    None => labels.contains_key(required),
+                };
+                if !present {
+                    return Some(format!("{} secrets require the label {} on namespace {}", type_, required, ns_name));
+                }
+            }
+            if let Some(allowed) = &rule.allowed_keys {
+                if let Some(key) = compare::normalized_data(sec).keys().find(|k| !allowed.contains(k)) {
+                    return Some(format!("key {} is not allowed in {} secrets", key, type_));
+                }
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use k8s_openapi::apimachinery::pkg::apis::meta::v1::ObjectMeta;
+    use k8s_openapi::ByteString;
+
+    use crate::fake_api::FakeApi;
+
+    const RULES: &str = r#"
+- types: ["kubernetes.io/tls"]
+  requireNamespaceLabel: tls-allowed
+- types: ["Opaque"]
+  allowedKeys: ["username", "password"]
+- requireNamespaceLabel: env=prod
+"#;
+
+    fn secret(type_: Option<&str>, keys: &[&str]) -> Secret {
+        Secret {
+            type_: type_.map(str::to_string),
+            data: Some(keys.iter().map(|k| (k.to_string(), ByteString(b"x".to_vec()))).collect()),
+            ..Secret::default()
+        }
+    }
+
+    fn namespace(labels: &[(&str, &str)]) -> Namespace {
+        Namespace {
+            metadata: ObjectMeta {
+                name: Some("team-a".to_string()),
+                labels: Some(labels.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()),
+                ..ObjectMeta::default()
+            },
+            ..Namespace::default()
+        }
+    }
+
+    async fn load(rules: Option<&str>) -> Result<Policy, Error> {
+        let (client, fake) = FakeApi::start();
+        fake.insert(&ConfigMap {
+            metadata: ObjectMeta {
+                name: Some("policy".to_string()),
+                namespace: Some("operator".to_string()),
+                ..ObjectMeta::default()
+            },
+            data: rules.map(|r| std::iter::once(("rules".to_string(), r.to_string())).collect()),
+            ..ConfigMap::default()
+        });
+        Policy::load(client, Some(&("operator".to_string(), "policy".to_string()))).await
+    }
+
+    #[tokio::test]
+    async fn allowed_copies_pass() {
+        let policy = load(Some(RULES)).await.unwrap();
+        let prod = namespace(&[("env", "prod"), ("tls-allowed", "")]);
+        assert_eq!(policy.check(&secret(Some("kubernetes.io/tls"), &["tls.crt", "tls.key"]), &prod), None);
+        assert_eq!(policy.check(&secret(None, &["username", "password"]), &prod), None);
+        assert_eq!(policy.check(&secret(Some("example.com/other"), &["token"]), &prod), None);
+    }
+
+    #[tokio::test]
+    async fn violating_copies_are_denied_with_the_reason() {
+        let policy = load(Some(RULES)).await.unwrap();
+        let tls = secret(Some("kubernetes.io/tls"), &["tls.crt", "tls.key"]);
+        assert_eq!(
+            policy.check(&tls, &namespace(&[("env", "prod")])),
+            Some("kubernetes.io/tls secrets require the label tls-allowed on namespace team-a".to_string())
+        );
+        assert_eq!(
+            policy.check(&secret(Some("Opaque"), &["username", "token"]), &namespace(&[("env", "prod")])),
+            Some("key token is not allowed in Opaque secrets".to_string())
+        );
+        // the rule without types applies to every secret
+        assert_eq!(
+            policy.check(&secret(Some("example.com/other"), &["token"]), &namespace(&[("env", "dev")])),
+            Some("example.com/other secrets require the label env=prod on namespace team-a".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn missing_policy_allows_everything() {
+        let (client, _fake) = FakeApi::start();
+        assert!(Policy::load(client, None).await.unwrap().is_empty());
+        assert!(load(None).await.unwrap().is_empty());
+        assert!(matches!(load(Some("- typo: [Opaque]")).await, Err(Error::UserInputError(_))));
+    }
+}
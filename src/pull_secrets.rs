@@ -0,0 +1,204 @@
+
+use futures::stream::StreamExt;
+use k8s_openapi::api::apps::v1::Deployment;
+use k8s_openapi::api::core::v1::Secret;
+use kube::api::ListParams;
+use kube::{Api, Client, Resource};
+use kube_runtime::controller::{Context, ReconcilerAction};
+use kube_runtime::Controller;
+use tokio::time::Duration;
+use tracing::{error, info};
+
+use crate::config::SpreadConfig;
+use crate::opts::Opts;
+use crate::{compare, concurrency, on_error, shutdown, sync_copy, ContextData, Error};
+
+/// Runs the controller spreading the pull secrets referenced by Deployments, if
+/// `--pull-secret-source-namespace` names the namespace holding the central pull secrets.
+///
+/// Every secret listed in the `imagePullSecrets` of a Deployment is copied from that namespace
+/// into the namespace of the Deployment, named like the central secret. Copies are kept up to
+/// date like any other copy, but stay in place when the Deployment goes away, other workloads
+/// of the namespace may still pull with them.
+pub async fn run(client: Client, opts: &Opts) {
+    let context_data = ContextData::new(client.clone(), opts).with_slots(concurrency::Slots::for_controller(concurrency::PULL_SECRET, opts));
+    if context_data.pull_secret_namespace.is_none() {
+        return;
+    }
+
+    let deployment_api: Api<Deployment> = Api::all(client);
+    let context: Context<ContextData> = Context::new(context_data);
+
+    Controller::new(deployment_api, ListParams::default())
+        .run(reconcile, on_error, context)
+        .for_each(|reconciliation_result| async move {
+            if let Err(reconciliation_err) = reconciliation_result {
+                error!(error = ?reconciliation_err, "Reconciliation error")
+            }
+        })
+        .await;
+}
+
+/// Reconciles a Deployment by making sure each of its pull secrets exists in its namespace.
+async fn reconcile(deployment: Deployment, context: Context<ContextData>) -> Result<ReconcilerAction, Error> {
+    // no reconcile starts once the operator shuts down, see shutdown::drain
+    let _running = match shutdown::begin() {
+        Some(guard) => guard,
+        None => return Ok(ReconcilerAction { requeue_after: None }),
+    };
+    let source_namespace = match &context.get_ref().pull_secret_namespace {
+        Some(ns) => ns.clone(),
+        None => return Ok(ReconcilerAction { requeue_after: None }),
+    };
+
+    let namespace: String = match deployment.namespace() {
+        None => {
+            return Err(Error::UserInputError(
+                "Expected Deployment resource to be namespaced.".to_owned(),
+            ));
+        }
+        Some(namespace) => namespace,
+    };
+
+    let pull_secrets: Vec<String> = deployment
+        .spec
+        .as_ref()
+        .and_then(|s| s.template.spec.as_ref())
+        .and_then(|s| s.image_pull_secrets.as_ref())
+        .map(|refs| refs.iter().filter_map(|r| r.name.clone()).collect())
+        .unwrap_or_default();
+
+    let client: Client = context.get_ref().client.clone();
+    let source_api: Api<Secret> = Api::namespaced(client, &source_namespace);
+
+    if namespace == source_namespace {
+        return Ok(ReconcilerAction { requeue_after: None });
+    }
+    // the pull secret controller has reconcile slots of its own, see concurrency
+    let _slot = context.get_ref().reconcile_slots.acquire().await;
+
+    for name in pull_secrets {
+        let sec = match source_api.get(&name).await {
+            Ok(s) => s,
+            Err(kube::Error::Api(kube::error::ErrorResponse { code: 404, .. })) => {
+                info!(source_namespace = %source_namespace, secret_name = %name, deployment = %deployment.name(), target_namespace = %namespace, "No central pull secret");
+                continue;
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let config = SpreadConfig::for_copies(&sec)?;
+        if config.use_generate_name {
+            return Err(Error::UserInputError(format!(
+                "Pull secret {}.{} can't use generateName, Deployments reference it by name",
+                source_namespace, name
+            )));
+        }
+        let sec = config.copied(sec);
+        let source_uid = sec.metadata.uid.clone().unwrap_or_default();
+        let annotations = compare::desired_annotations(&sec, &config.target_annotations, &namespace);
+
+        info!(source_namespace = %source_namespace, secret_name = %name, deployment = %deployment.name(), target_namespace = %namespace, "Spreading pull secret");
+        sync_copy(&sec, &config, &context, &source_uid, &source_namespace, &name, &namespace, &name, &annotations, &Default::default()).await?;
+    }
+
+    Ok(ReconcilerAction {
+        // pick up changes of the central secrets
+        requeue_after: Some(context.get_ref().jitter.apply(Duration::from_secs(300))),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+    use k8s_openapi::api::apps::v1::DeploymentSpec;
+    use k8s_openapi::api::core::v1::{LocalObjectReference, Namespace, PodSpec, PodTemplateSpec};
+    use k8s_openapi::apimachinery::pkg::apis::meta::v1::ObjectMeta;
+    use k8s_openapi::ByteString;
+    use kube::api::{Patch, PatchParams};
+
+    use crate::fake_api::FakeApi;
+    use crate::keys;
+
+    fn deployment(namespace: &str, pull_secrets: &[&str]) -> Deployment {
+        Deployment {
+            metadata: ObjectMeta {
+                name: Some("app".to_string()),
+                namespace: Some(namespace.to_string()),
+                ..ObjectMeta::default()
+            },
+            spec: Some(DeploymentSpec {
+                template: PodTemplateSpec {
+                    spec: Some(PodSpec {
+                        image_pull_secrets: Some(pull_secrets.iter().map(|name| LocalObjectReference { name: Some(name.to_string()) }).collect()),
+                        ..PodSpec::default()
+                    }),
+                    ..PodTemplateSpec::default()
+                },
+                ..DeploymentSpec::default()
+            }),
+            ..Deployment::default()
+        }
+    }
+
+    fn password(sec: &Secret) -> Vec<u8> {
+        sec.data.as_ref().unwrap()["password"].0.clone()
+    }
+
+    #[tokio::test]
+    async fn pull_secrets_of_deployments_are_spread_into_their_namespace() {
+        let (client, fake) = FakeApi::start();
+        for ns in &["registry", "team-a", "team-b"] {
+            fake.insert(&Namespace {
+                metadata: ObjectMeta {
+                    name: Some(ns.to_string()),
+                    ..ObjectMeta::default()
+                },
+                ..Namespace::default()
+            });
+        }
+        let central: Secret = serde_json::from_value(fake.insert(&Secret {
+            metadata: ObjectMeta {
+                name: Some("regcred".to_string()),
+                namespace: Some("registry".to_string()),
+                ..ObjectMeta::default()
+            },
+            data: Some(std::iter::once(("password".to_string(), ByteString(b"secret".to_vec()))).collect()),
+            ..Secret::default()
+        }))
+        .unwrap();
+        let uid = central.metadata.uid.clone().unwrap();
+        let opts = Opts::parse_from(["spreading-operator", "--pull-secret-source-namespace", "registry"]);
+        let context = Context::new(ContextData::new(client.clone(), &opts));
+
+        // a pull secret missing in the central namespace is skipped
+        let action = reconcile(deployment("team-a", &["regcred", "missing"]), context.clone()).await.unwrap();
+        assert!(action.requeue_after.is_some());
+        let copy: Secret = fake.get("team-a", "regcred").unwrap();
+        assert_eq!(password(&copy), b"secret");
+        assert_eq!(copy.metadata.labels.unwrap()[keys::owner_label()], uid);
+        assert!(fake.get::<Secret>("team-a", "missing").is_none());
+        assert!(fake.get::<Secret>("team-b", "regcred").is_none());
+
+        // changes of the central secret reach the copies
+        let patch = serde_json::json!({ "data": { "password": ByteString(b"rotated".to_vec()) } });
+        Api::<Secret>::namespaced(client, "registry").patch("regcred", &PatchParams::default(), &Patch::Merge(&patch)).await.unwrap();
+        reconcile(deployment("team-a", &["regcred"]), context.clone()).await.unwrap();
+        assert_eq!(password(&fake.get("team-a", "regcred").unwrap()), b"rotated");
+
+        // nothing is copied for Deployments of the central namespace or without pull secrets
+        fake.take_writes();
+        reconcile(deployment("registry", &["regcred"]), context.clone()).await.unwrap();
+        reconcile(deployment("team-b", &[]), context).await.unwrap();
+        assert!(fake.take_writes().is_empty());
+    }
+
+    #[tokio::test]
+    async fn pull_secrets_are_not_spread_without_source_namespace() {
+        let (client, fake) = FakeApi::start();
+        let context = Context::new(ContextData::new(client, &Opts::parse_from(["spreading-operator"])));
+        let action = reconcile(deployment("team-a", &["regcred"]), context).await.unwrap();
+        assert!(action.requeue_after.is_none());
+        assert!(fake.take_reads().is_empty());
+    }
+}
@@ -0,0 +1,219 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+
+use tokio::time::{Duration, Instant};
+
+/// Failure state of the copy of one source in one target namespace.
+#[derive(Default)]
+struct Entry {
+    /// Consecutive failed syncs.
+    failures: u32,
+    /// Until when the namespace is skipped, if it is quarantined.
+    until: Option<Instant>,
+    /// Uid of the namespace instance that failed.
+    namespace_uid: Option<String>,
+}
+
+/// Keeps track of target namespaces that fail repeatedly, e.g. because of a broken mutating
+/// webhook, so they don't slow down or block the sync of the healthy namespaces.
+///
+/// After `threshold` consecutive failures the namespace is skipped for `retry_after`. The next
+/// attempt after that either releases it on success or quarantines it again on failure.
+pub struct Quarantine {
+    threshold: u32,
+    retry_after: Duration,
+    entries: Mutex<HashMap<(String, String), Entry>>,
+    /// Target namespaces found missing, reported once until they are synced again.
+    missing: Mutex<HashSet<(String, String)>>,
+}
+
+impl Quarantine {
+    /// Constructs a new Quarantine. A `threshold` of 0 disables it.
+    pub fn new(threshold: u32, retry_after: Duration) -> Self {
+        Quarantine {
+            threshold,
+            retry_after,
+            entries: Mutex::new(HashMap::new()),
+            missing: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// Returns true if `namespace` is quarantined for the source with uid `source_uid` and has
+    /// to be skipped for now.
+    pub fn is_quarantined(&self, source_uid: &str, namespace: &str) -> bool {
+        let entries = self.entries.lock().unwrap();
+        match entries.get(&(source_uid.to_string(), namespace.to_string())) {
+            Some(Entry { until: Some(until), .. }) => Instant::now() < *until,
+            _ => false,
+        }
+    }
+
+    /// Records a failed sync to `namespace`, currently the namespace instance with uid
+    /// `namespace_uid`. Returns true if the namespace has been put into quarantine by this
+    /// failure.
+    pub fn record_failure(&self, source_uid: &str, namespace: &str, namespace_uid: Option<String>) -> bool {
+        if self.threshold == 0 {
+            return false;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        let entry = entries
+            .entry((source_uid.to_string(), namespace.to_string()))
+            .or_default();
+        if namespace_uid.is_some() && entry.namespace_uid.is_some() && entry.namespace_uid != namespace_uid {
+            // failures of a former namespace with the same name don't count
+            entry.failures = 0;
+        }
+        if namespace_uid.is_some() {
+            entry.namespace_uid = namespace_uid;
+        }
+        entry.failures += 1;
+        if entry.failures >= self.threshold {
+            entry.until = Some(Instant::now() + self.retry_after);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Releases `namespace` from quarantine if it is no longer the namespace instance that
+    /// failed, i.e. it was deleted and recreated under the same name since. Returns true if it
+    /// was released.
+    pub fn release_if_recreated(&self, source_uid: &str, namespace: &str, namespace_uid: Option<&str>) -> bool {
+        let mut entries = self.entries.lock().unwrap();
+        let key = (source_uid.to_string(), namespace.to_string());
+        let recreated = match (entries.get(&key).and_then(|e| e.namespace_uid.as_deref()), namespace_uid) {
+            (Some(failed), Some(current)) => failed != current,
+            _ => false,
+        };
+        if recreated {
+            entries.remove(&key);
+        }
+        recreated
+    }
+
+    /// Records that `namespace` doesn't exist. Returns true if it wasn't known to be missing, so
+    /// a namespace listed but gone is reported once rather than on every reconcile.
+    pub fn record_missing(&self, source_uid: &str, namespace: &str) -> bool {
+        self.missing.lock().unwrap().insert((source_uid.to_string(), namespace.to_string()))
+    }
+
+    /// Records a successful sync to `namespace`, releasing it from quarantine. Returns true if
+    /// it was quarantined.
+    pub fn record_success(&self, source_uid: &str, namespace: &str) -> bool {
+        self.missing.lock().unwrap().remove(&(source_uid.to_string(), namespace.to_string()));
+        let mut entries = self.entries.lock().unwrap();
+        match entries.remove(&(source_uid.to_string(), namespace.to_string())) {
+            Some(entry) => entry.until.is_some(),
+            None => false,
+        }
+    }
+
+    /// Returns the namespaces currently quarantined for the source with uid `source_uid`.
+    pub fn quarantined(&self, source_uid: &str) -> Vec<String> {
+        let now = Instant::now();
+        let entries = self.entries.lock().unwrap();
+        let mut namespaces: Vec<String> = entries
+            .iter()
+            .filter(|((uid, _), entry)| uid == source_uid && entry.until.is_some_and(|u| now < u))
+            .map(|((_, ns), _)| ns.clone())
+            .collect();
+        namespaces.sort();
+        namespaces
+    }
+
+    /// Forgets everything recorded for the source with uid `source_uid`.
+    pub fn forget(&self, source_uid: &str) {
+        self.entries.lock().unwrap().retain(|(uid, _), _| uid != source_uid);
+        self.missing.lock().unwrap().retain(|(uid, _)| uid != source_uid);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn namespace_enters_quarantine_at_the_threshold() {
+        let quarantine = Quarantine::new(3, Duration::from_secs(60));
+        assert!(!quarantine.record_failure("uid", "a", None));
+        assert!(!quarantine.record_failure("uid", "a", None));
+        assert!(!quarantine.is_quarantined("uid", "a"));
+        assert!(quarantine.record_failure("uid", "a", None));
+        assert!(quarantine.is_quarantined("uid", "a"));
+        assert_eq!(quarantine.quarantined("uid"), vec!["a"]);
+        // other sources and namespaces are served normally
+        assert!(!quarantine.is_quarantined("uid", "b"));
+        assert!(!quarantine.is_quarantined("other", "a"));
+        assert!(quarantine.quarantined("other").is_empty());
+    }
+
+    #[test]
+    fn success_in_between_starts_counting_over() {
+        let quarantine = Quarantine::new(2, Duration::from_secs(60));
+        quarantine.record_failure("uid", "a", None);
+        assert!(!quarantine.record_success("uid", "a"));
+        assert!(!quarantine.record_failure("uid", "a", None));
+        assert!(!quarantine.is_quarantined("uid", "a"));
+    }
+
+    #[tokio::test]
+    async fn namespace_is_retried_and_leaves_quarantine_on_success() {
+        let quarantine = Quarantine::new(1, Duration::from_millis(50));
+        assert!(quarantine.record_failure("uid", "a", None));
+        assert!(quarantine.is_quarantined("uid", "a"));
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        // retried after the period, failing again quarantines it again
+        assert!(!quarantine.is_quarantined("uid", "a"));
+        assert!(quarantine.quarantined("uid").is_empty());
+        assert!(quarantine.record_failure("uid", "a", None));
+        assert!(quarantine.is_quarantined("uid", "a"));
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        assert!(quarantine.record_success("uid", "a"));
+        assert!(!quarantine.is_quarantined("uid", "a"));
+    }
+
+    #[test]
+    fn recreated_namespace_is_released() {
+        let quarantine = Quarantine::new(2, Duration::from_secs(60));
+        quarantine.record_failure("uid", "a", Some("ns-1".to_string()));
+        quarantine.record_failure("uid", "a", Some("ns-1".to_string()));
+        assert!(!quarantine.release_if_recreated("uid", "a", Some("ns-1")));
+        assert!(!quarantine.release_if_recreated("uid", "a", None));
+        assert!(quarantine.is_quarantined("uid", "a"));
+        assert!(quarantine.release_if_recreated("uid", "a", Some("ns-2")));
+        assert!(!quarantine.is_quarantined("uid", "a"));
+
+        // failures of the former namespace don't count against the new one
+        quarantine.record_failure("uid", "b", Some("ns-1".to_string()));
+        assert!(!quarantine.record_failure("uid", "b", Some("ns-2".to_string())));
+        assert!(quarantine.record_failure("uid", "b", Some("ns-2".to_string())));
+    }
+
+    #[test]
+    fn threshold_zero_disables_the_quarantine() {
+        let quarantine = Quarantine::new(0, Duration::from_secs(60));
+        for _ in 0..10 {
+            assert!(!quarantine.record_failure("uid", "a", None));
+        }
+        assert!(!quarantine.is_quarantined("uid", "a"));
+    }
+
+    #[test]
+    fn forgotten_sources_are_released() {
+        let quarantine = Quarantine::new(1, Duration::from_secs(60));
+        quarantine.record_failure("uid", "a", None);
+        quarantine.record_failure("other", "a", None);
+        quarantine.forget("uid");
+        assert!(!quarantine.is_quarantined("uid", "a"));
+        assert!(quarantine.is_quarantined("other", "a"));
+    }
+
+    #[test]
+    fn missing_namespaces_are_reported_once() {
+        let quarantine = Quarantine::new(1, Duration::from_secs(60));
+        assert!(quarantine.record_missing("uid", "a"));
+        assert!(!quarantine.record_missing("uid", "a"));
+        quarantine.record_success("uid", "a");
+        assert!(quarantine.record_missing("uid", "a"));
+    }
+}
@@ -0,0 +1,132 @@
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+use hyper::body::to_bytes;
+use hyper::{Body, Client, Request};
+use hyper_tls::HttpsConnector;
+use tokio::time::{timeout, Duration};
+use tracing::warn;
+
+use crate::Error;
+
+/// Time allowed for fetching a namespace list.
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Last namespace list fetched successfully from each URL, used while the URL fails.
+static LAST_GOOD: Mutex<BTreeMap<String, Vec<String>>> = Mutex::new(BTreeMap::new());
+
+/// Fetches a JSON array of namespace names from `url`.
+///
+/// The request carries `Authorization: Bearer <token>` if `token` is given. If the
+/// fetch fails the last list fetched successfully from the same URL is returned with a warning,
+/// an error is only returned if there is none.
+pub async fn fetch_namespaces(url: &str, token: Option<&str>) -> Result<Vec<String>, Error> {
+    match timeout(FETCH_TIMEOUT, fetch(url, token)).await.unwrap_or_else(|_| Err(format!("timed out after {:?}", FETCH_TIMEOUT))) {
+        Ok(namespaces) => {
+            LAST_GOOD.lock().unwrap().insert(url.to_string(), namespaces.clone());
+            Ok(namespaces)
+        }
+        Err(e) => match LAST_GOOD.lock().unwrap().get(url) {
+            Some(namespaces) => {
+                warn!(url, error = %e, "Fetching target namespaces failed, keeping the last list");
+                Ok(namespaces.clone())
+            }
+            None => Err(Error::UserInputError(format!("Fetching target namespaces from {} failed: {}", url, e))),
+        },
+    }
+}
+
+async fn fetch(url: &str, token: Option<&str>) -> Result<Vec<String>, String> {
+    let mut request = Request::get(url);
+    if let Some(token) = token {
+        request = request.header("Authorization", format!("Bearer {}", token));
+    }
+    let request = request.body(Body::empty()).map_err(|e| e.to_string())?;
+
+    let client = Client::builder().build::<_, Body>(HttpsConnector::new());
+    let response = client.request(request).await.map_err(|e| e.to_string())?;
+    if !response.status().is_success() {
+        return Err(format!("status {}", response.status()));
+    }
+    let body = to_bytes(response.into_body()).await.map_err(|e| e.to_string())?;
+    serde_json::from_slice(&body).map_err(|e| format!("expected a JSON array of namespace names: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+    use std::sync::Arc;
+
+    use hyper::service::{make_service_fn, service_fn};
+    use hyper::{Response, Server, StatusCode};
+
+    use super::*;
+
+    /// Mock of the namespace service answering with the status and body in `answer`, recording
+    /// the Authorization header of each request in `seen`.
+    struct Mock {
+        url: String,
+        answer: Arc<Mutex<(u16, &'static str)>>,
+        seen: Arc<Mutex<Vec<Option<String>>>>,
+    }
+
+    impl Mock {
+        fn start(status: u16, body: &'static str) -> Self {
+            let answer = Arc::new(Mutex::new((status, body)));
+            let seen = Arc::new(Mutex::new(Vec::new()));
+            let (a, s) = (answer.clone(), seen.clone());
+            let make_service = make_service_fn(move |_| {
+                let (answer, seen) = (a.clone(), s.clone());
+                async move {
+                    Ok::<_, Infallible>(service_fn(move |req: Request<Body>| {
+                        let authorization = req.headers().get("Authorization").map(|v| v.to_str().unwrap().to_string());
+                        seen.lock().unwrap().push(authorization);
+                        let (status, body) = *answer.lock().unwrap();
+                        let mut response = Response::new(Body::from(body));
+                        *response.status_mut() = StatusCode::from_u16(status).unwrap();
+                        async move { Ok::<_, Infallible>(response) }
+                    }))
+                }
+            });
+            let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_service);
+            let url = format!("http://{}/namespaces", server.local_addr());
+            tokio::spawn(server);
+            Mock { url, answer, seen }
+        }
+
+        fn answer(&self, status: u16, body: &'static str) {
+            *self.answer.lock().unwrap() = (status, body);
+        }
+    }
+
+    #[tokio::test]
+    async fn namespaces_are_fetched_with_the_token() {
+        let mock = Mock::start(200, r#"["team-a", "team-b"]"#);
+        assert_eq!(fetch_namespaces(&mock.url, Some("s3cr3t")).await.unwrap(), vec!["team-a", "team-b"]);
+        assert_eq!(fetch_namespaces(&mock.url, None).await.unwrap(), vec!["team-a", "team-b"]);
+        assert_eq!(*mock.seen.lock().unwrap(), vec![Some("Bearer s3cr3t".to_string()), None]);
+    }
+
+    #[tokio::test]
+    async fn failing_fetch_keeps_the_last_good_list() {
+        let mock = Mock::start(200, r#"["team-a"]"#);
+        assert_eq!(fetch_namespaces(&mock.url, None).await.unwrap(), vec!["team-a"]);
+        mock.answer(503, "unavailable");
+        assert_eq!(fetch_namespaces(&mock.url, None).await.unwrap(), vec!["team-a"]);
+        mock.answer(200, r#"{"namespaces": ["team-b"]}"#);
+        assert_eq!(fetch_namespaces(&mock.url, None).await.unwrap(), vec!["team-a"]);
+        mock.answer(200, r#"["team-b"]"#);
+        assert_eq!(fetch_namespaces(&mock.url, None).await.unwrap(), vec!["team-b"]);
+    }
+
+    #[tokio::test]
+    async fn failing_fetch_without_last_good_list_is_an_error() {
+        let mock = Mock::start(500, "broken");
+        match fetch_namespaces(&mock.url, None).await {
+            Err(Error::UserInputError(message)) => assert_eq!(message, format!("Fetching target namespaces from {} failed: status 500 Internal Server Error", mock.url)),
+            other => panic!("expected a user error, got {:?}", other),
+        }
+        let mock = Mock::start(200, "team-a,team-b");
+        assert!(fetch_namespaces(&mock.url, None).await.is_err());
+    }
+}
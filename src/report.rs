@@ -0,0 +1,107 @@
+//! Sync status of the sources as `SpreadStatus` custom resources, see `--status-crd`.
+//!
+//! Every source gets a `SpreadStatus` of the same name in its namespace, owned by the source so
+//! it is deleted along with it. Its status subresource lists the target namespaces and the state
+//! of the copy in each, so `kubectl get spreadstatus` shows the sync state without the status
+//! annotations of [`crate::status`] on the source. `--print-crd` prints the definition to install.
+
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::OwnerReference;
+use kube::api::{Patch, PatchParams};
+use kube::{Api, Client, CustomResource, Resource};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tracing::info;
+
+use crate::Error;
+
+/// Points a `SpreadStatus` at its source.
+#[derive(CustomResource, Deserialize, Serialize, Clone, Debug, JsonSchema)]
+#[kube(group = "secretspreading.fitzek.eu", version = "v1alpha1", kind = "SpreadStatus", namespaced, status = "SpreadReport")]
+#[serde(rename_all = "camelCase")]
+pub struct SpreadStatusSpec {
+    /// Kind of the source, `Secret` or, for Vault backed sources, `ConfigMap`.
+    pub source_kind: String,
+    /// Name of the source, in the namespace of the `SpreadStatus`.
+    pub source_name: String,
+}
+
+/// Status of a `SpreadStatus`: the outcome of the last sync of its source.
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct SpreadReport {
+    /// RFC 3339 time of the last sync that changed a copy or the state of a target namespace.
+    pub last_synced: Option<String>,
+    /// State of the copy in every target namespace, sorted by namespace.
+    #[serde(default)]
+    pub targets: Vec<TargetState>,
+}
+
+/// State of the copy in one target namespace.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct TargetState {
+    pub namespace: String,
+    /// `Synced`, `Blocked` by an unmanaged secret, `Skipped` or `Error`.
+    pub state: String,
+    /// Why the copy is not synced.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+impl TargetState {
+    pub fn new(namespace: &str, state: &str, message: Option<String>) -> Self {
+        TargetState {
+            namespace: namespace.to_string(),
+            state: state.to_string(),
+            message,
+        }
+    }
+}
+
+/// Upserts the `SpreadStatus` of the source `name` of `kind` in `namespace` with `targets`.
+///
+/// Like [`crate::status::record`] the status is only written if `changed`, i.e. copies were
+/// written or deleted, or if the state of a target namespace differs from the recorded one.
+#[allow(clippy::too_many_arguments)]
+pub async fn record(client: Client, kind: &str, name: &str, namespace: &str, source_uid: &str, targets: Vec<TargetState>, changed: bool, pp: &PatchParams) -> Result<(), Error> {
+    let api: Api<SpreadStatus> = Api::namespaced(client, namespace);
+    let recorded = match api.get(name).await {
+        Ok(current) => current.status,
+        Err(kube::Error::Api(kube::error::ErrorResponse { code: 404, .. })) => None,
+        Err(e) => return Err(e.into()),
+    };
+    if !changed && recorded.is_some_and(|r| r.targets == targets) {
+        return Ok(());
+    }
+    if pp.dry_run {
+        info!(source_namespace = %namespace, name = %name, "[dry-run] Would record SpreadStatus");
+        return Ok(());
+    }
+
+    let mut spread_status = SpreadStatus::new(name, SpreadStatusSpec {
+        source_kind: kind.to_string(),
+        source_name: name.to_string(),
+    });
+    spread_status.metadata.namespace = Some(namespace.to_string());
+    spread_status.metadata.owner_references = Some(vec![OwnerReference {
+        api_version: "v1".to_string(),
+        kind: kind.to_string(),
+        name: name.to_string(),
+        uid: source_uid.to_string(),
+        ..Default::default()
+    }]);
+    api.patch(name, pp, &Patch::Apply(&spread_status)).await?;
+
+    // the status subresource ignores everything but the status, and the main resource the status
+    let report = json!({
+        "apiVersion": SpreadStatus::api_version(&()),
+        "kind": SpreadStatus::kind(&()),
+        "status": SpreadReport {
+            last_synced: Some(chrono::Utc::now().to_rfc3339()),
+            targets,
+        }
+    });
+    api.patch_status(name, pp, &Patch::Apply(&report)).await?;
+    Ok(())
+}
@@ -0,0 +1,136 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use tokio::time::Duration;
+
+/// Spreads requeue durations randomly around their base value, so sources synced at the same
+/// time, e.g. after a restart, don't all reconcile again at the same moment.
+pub struct Jitter {
+    percent: u32,
+    rng: Mutex<StdRng>,
+}
+
+impl Jitter {
+    /// Constructs a new Jitter applying up to ±`percent` percent. The RNG is seeded with `seed`
+    /// if given, which makes the sequence of durations reproducible.
+    pub fn new(percent: u32, seed: Option<u64>) -> Self {
+        let rng = match seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        Jitter {
+            percent: percent.min(100),
+            rng: Mutex::new(rng),
+        }
+    }
+
+    /// Returns `base` changed by a random amount of at most ±`percent` percent.
+    pub fn apply(&self, base: Duration) -> Duration {
+        if self.percent == 0 {
+            return base;
+        }
+        let spread = base.as_secs_f64() * f64::from(self.percent) / 100.0;
+        let offset = self.rng.lock().unwrap().gen_range(-spread..=spread);
+        Duration::from_secs_f64(base.as_secs_f64() + offset)
+    }
+}
+
+/// Shortest requeue duration of a source, synced or not, so sources don't hot-loop.
+const MIN_REQUEUE: Duration = Duration::from_secs(5);
+/// Shortest requeue duration after a failed reconcile.
+const MIN_ERROR_REQUEUE: Duration = Duration::from_secs(1);
+
+/// Durations after which a source is reconciled again.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Intervals {
+    /// After a reconcile of a secret without targeting annotation.
+    pub idle: Duration,
+    /// After a successful sync.
+    pub synced: Duration,
+    /// After a failed reconcile.
+    pub error: Duration,
+}
+
+impl Intervals {
+    /// Constructs new Intervals from seconds, raising each to its minimum.
+    pub fn new(idle: u64, synced: u64, error: u64) -> Self {
+        Intervals {
+            idle: Duration::from_secs(idle).max(MIN_REQUEUE),
+            synced: Duration::from_secs(synced).max(MIN_REQUEUE),
+            error: Duration::from_secs(error).max(MIN_ERROR_REQUEUE),
+        }
+    }
+}
+
+/// Longest requeue duration after failed reconciles in a row.
+const MAX_ERROR_BACKOFF: Duration = Duration::from_secs(300);
+
+/// Backs off the retries of a source failing again and again: the requeue duration after a
+/// failed reconcile starts at `base` and doubles with every further failure in a row, up to five
+/// minutes. It starts over once the source reconciled successfully.
+pub struct Backoff {
+    base: Duration,
+    /// Failed reconciles in a row, keyed by source uid.
+    failures: Mutex<HashMap<String, u32>>,
+}
+
+impl Backoff {
+    /// Constructs a new Backoff retrying after `base` first.
+    pub fn new(base: Duration) -> Self {
+        Backoff {
+            base,
+            failures: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Records a failed reconcile of the source `uid`. Returns the duration to retry it after.
+    pub fn failed(&self, uid: &str) -> Duration {
+        let mut failures = self.failures.lock().unwrap();
+        let count = failures.entry(uid.to_string()).or_insert(0);
+        *count = count.saturating_add(1);
+        let factor = 1u32 << (*count - 1).min(16);
+        self.base.saturating_mul(factor).min(MAX_ERROR_BACKOFF.max(self.base))
+    }
+
+    /// Records a successful reconcile of the source `uid`, the next failure is retried after
+    /// `base` again.
+    pub fn succeeded(&self, uid: &str) {
+        self.failures.lock().unwrap().remove(uid);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn jittered_durations_stay_in_range() {
+        let jitter = Jitter::new(10, Some(42));
+        let base = Duration::from_secs(60);
+        let durations: Vec<Duration> = (0..1000).map(|_| jitter.apply(base)).collect();
+        assert!(durations.iter().all(|d| *d >= Duration::from_secs(54) && *d <= Duration::from_secs(66)));
+        // spread out, not all the same
+        assert!(durations.iter().any(|d| *d < Duration::from_secs(57)));
+        assert!(durations.iter().any(|d| *d > Duration::from_secs(63)));
+    }
+
+    #[test]
+    fn seeded_jitter_is_reproducible() {
+        let base = Duration::from_secs(60);
+        let first = Jitter::new(10, Some(7));
+        let second = Jitter::new(10, Some(7));
+        for _ in 0..100 {
+            assert_eq!(first.apply(base), second.apply(base));
+        }
+    }
+
+    #[test]
+    fn jitter_is_capped_and_can_be_disabled() {
+        let base = Duration::from_secs(60);
+        assert_eq!(Jitter::new(0, None).apply(base), base);
+        let capped = Jitter::new(500, Some(1));
+        assert!((0..100).map(|_| capped.apply(base)).all(|d| d <= Duration::from_secs(120)));
+    }
+}
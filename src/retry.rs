@@ -0,0 +1,32 @@
+use std::future::Future;
+
+use tokio::time::{sleep, Duration};
+use tracing::debug;
+
+/// Number of attempts made before a conflict is given up on.
+const ATTEMPTS: u32 = 4;
+/// Backoff before the second attempt, doubled for every further one.
+const INITIAL_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Runs `attempt` until it doesn't fail with 409 Conflict, at most four times with backoff in
+/// between. `attempt` is passed the number of the attempt starting at 0 and is expected to read
+/// the object anew from the second attempt on, as the conflict says its resourceVersion moved.
+pub async fn on_conflict<T, F, Fut>(mut attempt: F) -> Result<T, kube::Error>
+where
+    F: FnMut(u32) -> Fut,
+    Fut: Future<Output = Result<T, kube::Error>>,
+{
+    let mut backoff = INITIAL_BACKOFF;
+    let mut n = 0;
+    loop {
+        match attempt(n).await {
+            Err(kube::Error::Api(kube::error::ErrorResponse { code: 409, .. })) if n + 1 < ATTEMPTS => {
+                debug!(attempt = n, "Conflict, retrying");
+                sleep(backoff).await;
+                backoff *= 2;
+                n += 1;
+            }
+            result => return result,
+        }
+    }
+}
@@ -0,0 +1,239 @@
+//! Settings that can be changed while the operator runs.
+//!
+//! Options are read once at startup. The following ones can be overridden in the ConfigMap named
+//! by `--settings-configmap` as `<namespace>/<name>`, a change applies from the next reconcile
+//! of each source on:
+//!
+//! - `requeue-idle`, `requeue-synced`, `requeue-error`: seconds, as `--requeue-idle`,
+//!   `--requeue-synced` and `--requeue-error`.
+//! - `exclude-namespaces`: comma separated, as `--exclude-namespaces`.
+//! - `allowed-target-namespaces`: comma separated, as `--allowed-target-namespaces`.
+//! - `paused`: `true` pauses all sources like their `paused` annotation, copies are neither
+//!   written nor deleted until it is removed.
+//!
+//! A change of the settings ConfigMap or the `--spread-policy-configmap` reconciles every source
+//! spread, see [`changes`], once the edits have settled for [`DEBOUNCE`].
+//!
+//! A key missing in the ConfigMap, or the ConfigMap missing altogether, falls back to the
+//! option. A ConfigMap with an invalid value is ignored as a whole, the settings in place are
+//! kept. All other options, e.g. the annotation prefix, the owner label or the finalizer name,
+//! need a restart: changing them while copies exist would lose track of the copies.
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, RwLock};
+
+use futures::stream::{BoxStream, Stream, StreamExt};
+use k8s_openapi::api::core::v1::ConfigMap;
+use kube::api::ListParams;
+use kube::{Api, Client};
+use kube_runtime::watcher::{self, watcher};
+use tokio::time::Duration;
+use tracing::{info, warn};
+
+use crate::opts::Opts;
+use crate::{requeue, Error};
+
+/// Time without further change after which a change of a configuration ConfigMap is acted on,
+/// so a series of edits reconciles the sources once.
+pub const DEBOUNCE: Duration = Duration::from_secs(5);
+
+/// The settings that can be changed while the operator runs.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Settings {
+    /// Durations after which sources are reconciled again.
+    pub requeue: requeue::Intervals,
+    /// Namespaces `*` doesn't expand to, unless the source lists its own.
+    pub exclude_namespaces: Vec<String>,
+    /// Namespaces copies may be written to, all if empty.
+    pub allowed_target_namespaces: Vec<String>,
+    /// Whether the spreading of all sources is paused.
+    pub paused: bool,
+}
+
+/// Settings shared by the reconciles, replaced as a whole when the ConfigMap changes.
+pub type Shared = Arc<RwLock<Settings>>;
+
+impl Settings {
+    /// The settings configured by `opts`.
+    pub fn from_opts(opts: &Opts) -> Self {
+        Settings {
+            requeue: requeue::Intervals::new(opts.requeue_idle, opts.requeue_synced, opts.requeue_error),
+            exclude_namespaces: opts.exclude_namespaces.clone(),
+            allowed_target_namespaces: opts.allowed_target_namespaces.iter().map(|ns| ns.trim()).filter(|ns| !ns.is_empty()).map(str::to_string).collect(),
+            paused: false,
+        }
+    }
+
+    /// Returns the settings overridden by the `data` of the settings ConfigMap.
+    pub fn with_overrides(&self, data: &BTreeMap<String, String>) -> Result<Self, Error> {
+        let seconds = |key: &str, default: std::time::Duration| -> Result<u64, Error> {
+            match data.get(key) {
+                Some(value) => value
+                    .trim()
+                    .parse()
+                    .map_err(|_| Error::UserInputError(format!("Invalid setting {}: {} is not a number of seconds", key, value))),
+                None => Ok(default.as_secs()),
+            }
+        };
+        let paused = match data.get("paused").map(|value| value.trim()) {
+            None | Some("false") => false,
+            Some("true") => true,
+            Some(value) => return Err(Error::UserInputError(format!("Invalid setting paused: {}, expected true or false", value))),
+        };
+        Ok(Settings {
+            requeue: requeue::Intervals::new(
+                seconds("requeue-idle", self.requeue.idle)?,
+                seconds("requeue-synced", self.requeue.synced)?,
+                seconds("requeue-error", self.requeue.error)?,
+            ),
+            exclude_namespaces: data.get("exclude-namespaces").map(|value| list(value)).unwrap_or_else(|| self.exclude_namespaces.clone()),
+            allowed_target_namespaces: data
+                .get("allowed-target-namespaces")
+                .map(|value| list(value))
+                .unwrap_or_else(|| self.allowed_target_namespaces.clone()),
+            paused,
+        })
+    }
+}
+
+/// Splits the comma separated `value`, leaving out empty entries.
+fn list(value: &str) -> Vec<String> {
+    value.split(',').map(str::trim).filter(|entry| !entry.is_empty()).map(str::to_string).collect()
+}
+
+/// Replaces `settings` by the `defaults` of the options overridden by the ConfigMap `cm`, or by
+/// the `defaults` alone if the ConfigMap is gone. Invalid settings are logged and not applied.
+pub fn apply(settings: &RwLock<Settings>, defaults: &Settings, cm: Option<&ConfigMap>) {
+    let data = cm.and_then(|cm| cm.data.clone()).unwrap_or_default();
+    let updated = match defaults.with_overrides(&data) {
+        Ok(updated) => updated,
+        Err(e) => {
+            warn!(error = %e, "Ignoring the settings ConfigMap");
+            return;
+        }
+    };
+    let mut current = settings.write().unwrap();
+    if *current != updated {
+        info!(settings = ?updated, "Settings changed");
+        *current = updated;
+    }
+}
+
+/// Watches the ConfigMap `reference`, `<namespace>/<name>`, and applies it to `settings` on
+/// every change, see [`apply`]. `defaults` are the settings of the options.
+pub async fn watch(client: Client, reference: &(String, String), settings: Shared, defaults: Settings) {
+    let (namespace, name) = reference;
+    let api: Api<ConfigMap> = Api::namespaced(client, namespace);
+    let lp = ListParams::default().fields(&format!("metadata.name={}", name));
+    let mut events = watcher(api, lp).boxed();
+    while let Some(event) = events.next().await {
+        match event {
+            Ok(watcher::Event::Applied(cm)) => apply(&settings, &defaults, Some(&cm)),
+            Ok(watcher::Event::Deleted(_)) => apply(&settings, &defaults, None),
+            Ok(watcher::Event::Restarted(cms)) => apply(&settings, &defaults, cms.first()),
+            Err(e) => warn!(error = ?e, "Settings ConfigMap watch error"),
+        }
+    }
+}
+
+/// Yields once after each change of the ConfigMaps `references`, `<namespace>/<name>` each, that
+/// isn't followed by another change within `window`. Created, changed and deleted ConfigMaps
+/// count as a change, as do restarts of the watches, which may have missed one; the initial
+/// list at the start doesn't.
+pub fn changes(client: Client, references: &[(String, String)], window: Duration) -> BoxStream<'static, ()> {
+    let watches = references.iter().map(|(namespace, name)| {
+        let api: Api<ConfigMap> = Api::namespaced(client.clone(), namespace);
+        let lp = ListParams::default().fields(&format!("metadata.name={}", name));
+        let reference = format!("{}/{}", namespace, name);
+        watcher(api, lp)
+            .filter_map(move |event| {
+                let changed = match event {
+                    Ok(_) => true,
+                    Err(e) => {
+                        warn!(configmap = %reference, error = ?e, "Configuration ConfigMap watch error");
+                        false
+                    }
+                };
+                futures::future::ready(Some(()).filter(|_| changed))
+            })
+            .skip(1)
+            .boxed()
+    });
+    debounce(futures::stream::select_all(watches), window)
+}
+
+/// Collapses the items of `events` following each other within `window` into one, yielded once
+/// `window` passed without another item.
+fn debounce<S>(events: S, window: Duration) -> BoxStream<'static, ()>
+where
+    S: Stream + Send + 'static,
+{
+    futures::stream::unfold(events.boxed(), move |mut events| async move {
+        events.next().await?;
+        while let Ok(Some(_)) = tokio::time::timeout(window, events.next()).await {}
+        Some(((), events))
+    })
+    .boxed()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    fn data(entries: &[(&str, &str)]) -> BTreeMap<String, String> {
+        entries.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect()
+    }
+
+    #[test]
+    fn overrides_replace_the_options() {
+        let defaults = Settings::from_opts(&Opts::parse_from(["spreading-operator", "--exclude-namespaces", "kube-system"]));
+        let settings = defaults
+            .with_overrides(&data(&[("requeue-error", "30"), ("exclude-namespaces", " kube-public, ,default"), ("paused", "true")]))
+            .unwrap();
+        assert_eq!(settings.requeue, requeue::Intervals::new(300, 60, 30));
+        assert_eq!(settings.exclude_namespaces, vec!["kube-public", "default"]);
+        assert!(settings.allowed_target_namespaces.is_empty());
+        assert!(settings.paused);
+        assert_eq!(defaults.with_overrides(&BTreeMap::new()).unwrap(), defaults);
+    }
+
+    #[test]
+    fn invalid_settings_are_not_applied() {
+        let defaults = Settings::from_opts(&Opts::parse_from(["spreading-operator"]));
+        assert!(defaults.with_overrides(&data(&[("requeue-idle", "5m")])).is_err());
+        assert!(defaults.with_overrides(&data(&[("paused", "yes")])).is_err());
+
+        let settings = RwLock::new(defaults.with_overrides(&data(&[("paused", "true")])).unwrap());
+        let cm = ConfigMap {
+            data: Some(data(&[("paused", "false"), ("requeue-synced", "-1")])),
+            ..ConfigMap::default()
+        };
+        apply(&settings, &defaults, Some(&cm));
+        assert!(settings.read().unwrap().paused);
+    }
+
+    #[tokio::test]
+    async fn debounce_collapses_rapid_changes() {
+        let window = Duration::from_millis(200);
+        let (sender, receiver) = futures::channel::mpsc::unbounded();
+        let mut changes = debounce(receiver, window);
+        let start = tokio::time::Instant::now();
+        tokio::spawn(async move {
+            // three edits within the window, then another one after it
+            for wait in &[0, 20, 20, 600] {
+                tokio::time::sleep(Duration::from_millis(*wait)).await;
+                sender.unbounded_send(()).unwrap();
+            }
+            // the watches ending would end the window early
+            tokio::time::sleep(Duration::from_secs(1)).await;
+        });
+
+        // the window restarts with every edit, the last edit is a change of its own
+        assert_eq!(changes.next().await, Some(()));
+        assert!(start.elapsed() >= Duration::from_millis(240) && start.elapsed() < Duration::from_millis(640), "{:?}", start.elapsed());
+        assert_eq!(changes.next().await, Some(()));
+        assert!(start.elapsed() >= Duration::from_millis(840), "{:?}", start.elapsed());
+        assert_eq!(changes.next().await, None);
+    }
+}
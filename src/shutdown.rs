@@ -0,0 +1,46 @@
+//! Graceful shutdown: once the operator is asked to stop no further reconcile starts, the running
+//! ones are waited for, so no patch of a copy or of a finalizer is cut off half way.
+
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use tokio::time::{sleep, Duration, Instant};
+use tracing::warn;
+
+static SHUTTING_DOWN: AtomicBool = AtomicBool::new(false);
+static IN_FLIGHT: AtomicUsize = AtomicUsize::new(0);
+
+/// A running reconcile, counted until dropped.
+pub struct Guard;
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        IN_FLIGHT.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Registers a reconcile about to start. Returns `None` once the shutdown began, the reconcile
+/// is to be skipped then.
+pub fn begin() -> Option<Guard> {
+    IN_FLIGHT.fetch_add(1, Ordering::SeqCst);
+    if SHUTTING_DOWN.load(Ordering::SeqCst) {
+        IN_FLIGHT.fetch_sub(1, Ordering::SeqCst);
+        return None;
+    }
+    Some(Guard)
+}
+
+/// Stops new reconciles from starting and waits for the running ones to finish, at most
+/// `timeout`, see `--shutdown-timeout`. The default of 25 seconds stays below the default
+/// termination grace period of 30 seconds of a pod. The controllers have to be polled
+/// meanwhile, or the running reconciles can't make progress.
+pub async fn drain(timeout: Duration) {
+    SHUTTING_DOWN.store(true, Ordering::SeqCst);
+    let deadline = Instant::now() + timeout;
+    while IN_FLIGHT.load(Ordering::SeqCst) > 0 {
+        if Instant::now() >= deadline {
+            warn!(running = IN_FLIGHT.load(Ordering::SeqCst), "Reconciles still running at shutdown");
+            return;
+        }
+        sleep(Duration::from_millis(100)).await;
+    }
+}
@@ -0,0 +1,361 @@
+//! Notifications about what happened to the copies of a source.
+//!
+//! The sinks notifications are sent to are configured by `--event-sinks`, a comma separated list
+//! of `log` (the default), `event` and `webhook`:
+//!
+//! - `log` prints a line per notification.
+//! - `event` records a Kubernetes event on the source.
+//! - `webhook` POSTs a JSON document to `--event-webhook-url`. With `--event-webhook-secret` the
+//!   document is signed, see [`signature`].
+//!
+//! Copies are always counted in the metrics, see [`crate::metrics::MetricsSink`].
+
+use async_trait::async_trait;
+use hmac::{Hmac, Mac};
+use hyper::{Body, Request};
+use hyper_tls::HttpsConnector;
+use k8s_openapi::api::core::v1::Secret;
+use kube::Resource;
+use serde_json::json;
+use sha2::Sha256;
+use tracing::{info, warn};
+
+use crate::{events, metrics};
+
+/// Receives notifications about the copies of a source. Sinks report their own failures, a
+/// failing sink never fails a reconcile.
+#[async_trait]
+pub trait EventSink: Send + Sync {
+    /// The copy `name` of `source` was created in `namespace`.
+    async fn on_created(&self, source: &Secret, namespace: &str, name: &str);
+    /// The copy `name` of `source` in `namespace` was updated.
+    async fn on_updated(&self, source: &Secret, namespace: &str, name: &str);
+    /// The copy `name` of `source` in `namespace` was deleted.
+    async fn on_deleted(&self, source: &Secret, namespace: &str, name: &str);
+    /// No copy of `source` was written to `namespace` for `reason`.
+    async fn on_skipped(&self, source: &Secret, namespace: &str, reason: &str);
+}
+
+/// Prints notifications to stdout.
+pub struct LogSink;
+
+#[async_trait]
+impl EventSink for LogSink {
+    async fn on_created(&self, _source: &Secret, namespace: &str, name: &str) {
+        info!(target_namespace = namespace, secret_name = name, "Created copy");
+    }
+
+    async fn on_updated(&self, _source: &Secret, namespace: &str, name: &str) {
+        info!(target_namespace = namespace, secret_name = name, "Updated copy");
+    }
+
+    async fn on_deleted(&self, _source: &Secret, namespace: &str, name: &str) {
+        info!(target_namespace = namespace, secret_name = name, "Deleted copy");
+    }
+
+    async fn on_skipped(&self, _source: &Secret, namespace: &str, reason: &str) {
+        info!(target_namespace = namespace, reason, "Skipped");
+    }
+}
+
+/// Records notifications as Kubernetes events on the source.
+pub struct KubeEventSink {
+    recorder: events::Recorder,
+}
+
+#[async_trait]
+impl EventSink for KubeEventSink {
+    async fn on_created(&self, source: &Secret, namespace: &str, name: &str) {
+        self.recorder.normal(source, "CopyCreated", &format!("Created copy {} in {}", name, namespace)).await;
+    }
+
+    async fn on_updated(&self, source: &Secret, namespace: &str, name: &str) {
+        self.recorder.normal(source, "CopyUpdated", &format!("Updated copy {} in {}", name, namespace)).await;
+    }
+
+    async fn on_deleted(&self, source: &Secret, namespace: &str, name: &str) {
+        self.recorder.normal(source, "CopyDeleted", &format!("Deleted copy {} in {}", name, namespace)).await;
+    }
+
+    async fn on_skipped(&self, source: &Secret, namespace: &str, reason: &str) {
+        self.recorder.warn(source, "CopySkipped", &format!("Skipped {}: {}", namespace, reason)).await;
+    }
+}
+
+/// Header carrying the signature of a webhook notification.
+const SIGNATURE_HEADER: &str = "X-Spread-Signature";
+
+/// POSTs notifications as JSON to a URL, signed if a secret is set.
+pub struct WebhookSink {
+    url: String,
+    secret: Option<String>,
+}
+
+/// Signature of the webhook notification `body` with the shared `secret`: `sha256=` followed
+/// by the hex encoded HMAC-SHA256 of the body, as sent in the `X-Spread-Signature` header.
+pub fn signature(secret: &[u8], body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC takes keys of any length");
+    mac.update(body);
+    let hex: String = mac.finalize().into_bytes().iter().map(|b| format!("{:02x}", b)).collect();
+    format!("sha256={}", hex)
+}
+
+impl WebhookSink {
+    async fn post(&self, source: &Secret, event: &str, namespace: &str, detail: (&str, &str)) {
+        let mut body = json!({
+            "event": event,
+            "source": {
+                "namespace": source.namespace(),
+                "name": source.name(),
+            },
+            "namespace": namespace,
+        });
+        body[detail.0] = json!(detail.1);
+        let body = body.to_string();
+        let mut request = Request::post(&self.url).header("Content-Type", "application/json");
+        if let Some(secret) = &self.secret {
+            request = request.header(SIGNATURE_HEADER, signature(secret.as_bytes(), body.as_bytes()));
+        }
+        let request = match request.body(Body::from(body)) {
+            Ok(r) => r,
+            Err(e) => {
+                warn!(url = %self.url, error = %e, "Invalid event webhook url");
+                return;
+            }
+        };
+        let client = hyper::Client::builder().build::<_, Body>(HttpsConnector::new());
+        match client.request(request).await {
+            Ok(response) if response.status().is_success() => {}
+            Ok(response) => warn!(status = %response.status(), "Event webhook answered with an error"),
+            Err(e) => warn!(error = %e, "Event webhook failed"),
+        }
+    }
+}
+
+#[async_trait]
+impl EventSink for WebhookSink {
+    async fn on_created(&self, source: &Secret, namespace: &str, name: &str) {
+        self.post(source, "created", namespace, ("name", name)).await;
+    }
+
+    async fn on_updated(&self, source: &Secret, namespace: &str, name: &str) {
+        self.post(source, "updated", namespace, ("name", name)).await;
+    }
+
+    async fn on_deleted(&self, source: &Secret, namespace: &str, name: &str) {
+        self.post(source, "deleted", namespace, ("name", name)).await;
+    }
+
+    async fn on_skipped(&self, source: &Secret, namespace: &str, reason: &str) {
+        self.post(source, "skipped", namespace, ("reason", reason)).await;
+    }
+}
+
+/// Sends every notification to all configured sinks.
+#[derive(Default)]
+pub struct Sinks {
+    sinks: Vec<Box<dyn EventSink>>,
+}
+
+impl Sinks {
+    /// Assembles the `configured` sinks, see `--event-sinks`, plus the metrics sink. The
+    /// `webhook` sink posts to `webhook_url`, signed with `webhook_secret` if given. Unknown sinks
+    /// are reported and ignored.
+    pub fn new(recorder: events::Recorder, configured: &[String], webhook_url: Option<&str>, webhook_secret: Option<&str>) -> Self {
+        let mut sinks: Vec<Box<dyn EventSink>> = vec![Box::new(metrics::MetricsSink)];
+        for sink in configured.iter().map(|s| s.trim()).filter(|s| !s.is_empty()) {
+            match sink {
+                "log" => sinks.push(Box::new(LogSink)),
+                "event" => sinks.push(Box::new(KubeEventSink {
+                    recorder: recorder.clone(),
+                })),
+                "webhook" => match webhook_url {
+                    Some(url) => sinks.push(Box::new(WebhookSink {
+                        url: url.to_string(),
+                        secret: webhook_secret.map(str::to_string),
+                    })),
+                    None => warn!("Event sink webhook disabled, --event-webhook-url is not set"),
+                },
+                other => warn!(sink = other, "Unknown event sink"),
+            }
+        }
+        Sinks { sinks }
+    }
+}
+
+#[async_trait]
+impl EventSink for Sinks {
+    async fn on_created(&self, source: &Secret, namespace: &str, name: &str) {
+        for sink in &self.sinks {
+            sink.on_created(source, namespace, name).await;
+        }
+    }
+
+    async fn on_updated(&self, source: &Secret, namespace: &str, name: &str) {
+        for sink in &self.sinks {
+            sink.on_updated(source, namespace, name).await;
+        }
+    }
+
+    async fn on_deleted(&self, source: &Secret, namespace: &str, name: &str) {
+        for sink in &self.sinks {
+            sink.on_deleted(source, namespace, name).await;
+        }
+    }
+
+    async fn on_skipped(&self, source: &Secret, namespace: &str, reason: &str) {
+        for sink in &self.sinks {
+            sink.on_skipped(source, namespace, reason).await;
+        }
+    }
+}
+
+#[cfg(test)]
+impl Sinks {
+    /// Constructs Sinks sending to `sinks` only.
+    pub fn of(sinks: Vec<Box<dyn EventSink>>) -> Self {
+        Sinks { sinks }
+    }
+}
+
+/// Sink recording the notifications it receives as `<kind> <namespace> <name or reason>`.
+#[cfg(test)]
+#[derive(Clone, Default)]
+pub struct RecordingSink {
+    pub calls: std::sync::Arc<std::sync::Mutex<Vec<String>>>,
+}
+
+#[cfg(test)]
+impl RecordingSink {
+    /// Returns the recorded notifications and forgets them.
+    pub fn take(&self) -> Vec<String> {
+        std::mem::take(&mut *self.calls.lock().unwrap())
+    }
+}
+
+#[cfg(test)]
+#[async_trait]
+impl EventSink for RecordingSink {
+    async fn on_created(&self, _source: &Secret, namespace: &str, name: &str) {
+        self.calls.lock().unwrap().push(format!("created {} {}", namespace, name));
+    }
+
+    async fn on_updated(&self, _source: &Secret, namespace: &str, name: &str) {
+        self.calls.lock().unwrap().push(format!("updated {} {}", namespace, name));
+    }
+
+    async fn on_deleted(&self, _source: &Secret, namespace: &str, name: &str) {
+        self.calls.lock().unwrap().push(format!("deleted {} {}", namespace, name));
+    }
+
+    async fn on_skipped(&self, _source: &Secret, namespace: &str, reason: &str) {
+        self.calls.lock().unwrap().push(format!("skipped {} {}", namespace, reason));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+    use std::sync::{Arc, Mutex};
+
+    use hyper::service::{make_service_fn, service_fn};
+    use hyper::{Response, Server};
+    use k8s_openapi::apimachinery::pkg::apis::meta::v1::ObjectMeta;
+
+    use super::*;
+    use crate::fake_api::FakeApi;
+
+    fn source() -> Secret {
+        Secret {
+            metadata: ObjectMeta {
+                name: Some("db".to_string()),
+                namespace: Some("source".to_string()),
+                ..ObjectMeta::default()
+            },
+            ..Secret::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn notifications_reach_every_sink() {
+        let (first, second) = (RecordingSink::default(), RecordingSink::default());
+        let sinks = Sinks::of(vec![Box::new(first.clone()), Box::new(second.clone())]);
+        sinks.on_created(&source(), "a", "db").await;
+        sinks.on_updated(&source(), "b", "db").await;
+        sinks.on_deleted(&source(), "c", "db").await;
+        sinks.on_skipped(&source(), "d", "namespace is quarantined").await;
+        let expected = vec!["created a db", "updated b db", "deleted c db", "skipped d namespace is quarantined"];
+        assert_eq!(first.take(), expected);
+        assert_eq!(second.take(), expected);
+    }
+
+    #[tokio::test]
+    async fn configured_sinks_are_assembled_with_the_metrics_sink() {
+        let (client, _fake) = FakeApi::start();
+        let recorder = events::Recorder::new(client, "spreading-operator", false);
+        let configured = |sinks: &[&str], url: Option<&str>| {
+            let sinks: Vec<String> = sinks.iter().map(|s| s.to_string()).collect();
+            Sinks::new(recorder.clone(), &sinks, url, None).sinks.len()
+        };
+        assert_eq!(configured(&[], None), 1);
+        assert_eq!(configured(&["log", " event ", ""], None), 3);
+        // the webhook needs its url, unknown sinks are ignored
+        assert_eq!(configured(&["webhook", "pager"], None), 1);
+        assert_eq!(configured(&["webhook"], Some("https://hooks.example.com/spread")), 2);
+    }
+
+    #[tokio::test]
+    async fn webhook_posts_signed_notifications() {
+        // signature header and body of each request
+        type Received = Vec<(Option<String>, String)>;
+        let received: Arc<Mutex<Received>> = Arc::default();
+        let seen = received.clone();
+        let make_service = make_service_fn(move |_| {
+            let seen = seen.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req: Request<Body>| {
+                    let seen = seen.clone();
+                    async move {
+                        let signature = req.headers().get(SIGNATURE_HEADER).map(|v| v.to_str().unwrap().to_string());
+                        let body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+                        seen.lock().unwrap().push((signature, String::from_utf8(body.to_vec()).unwrap()));
+                        Ok::<_, Infallible>(Response::new(Body::empty()))
+                    }
+                }))
+            }
+        });
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_service);
+        let url = format!("http://{}/spread", server.local_addr());
+        tokio::spawn(server);
+
+        let sink = WebhookSink {
+            url,
+            secret: Some("shared".to_string()),
+        };
+        sink.on_created(&source(), "a", "db").await;
+        sink.on_skipped(&source(), "b", "namespace does not exist").await;
+
+        let received = received.lock().unwrap();
+        let bodies: Vec<serde_json::Value> = received.iter().map(|(_, body)| serde_json::from_str(body).unwrap()).collect();
+        assert_eq!(bodies, vec![
+            json!({"event": "created", "source": {"namespace": "source", "name": "db"}, "namespace": "a", "name": "db"}),
+            json!({"event": "skipped", "source": {"namespace": "source", "name": "db"}, "namespace": "b", "reason": "namespace does not exist"}),
+        ]);
+        for (signature, body) in received.iter() {
+            assert_eq!(signature.as_deref(), Some(signature_of(body).as_str()));
+        }
+    }
+
+    fn signature_of(body: &str) -> String {
+        signature(b"shared", body.as_bytes())
+    }
+
+    #[test]
+    fn signature_is_hmac_sha256() {
+        // test case 2 of RFC 4231
+        assert_eq!(
+            signature(b"Jefe", b"what do ya want for nothing?"),
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+}
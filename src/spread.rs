@@ -0,0 +1,256 @@
+//! Spreading of Secrets declared by `SecretSpread` custom resources, see `--secret-spread-crd`.
+//!
+//! A `SecretSpread` names a Secret in its own namespace and the target namespaces of it, instead
+//! of annotations on the Secret. The spec is turned into the targeting annotations and the Secret
+//! is synced by [`crate::sync_secret`] as if it carried them, so copies, pruning and status
+//! behave the same. The Secret itself is not changed, only the status annotations are written to
+//! it as for annotated sources. The `SecretSpread` carries the finalizer, deleting it deletes the
+//! copies. With `--disable-finalizer` the copies are deleted once the deletion is watched, see
+//! [`release_deleted`]. `--print-crd` prints the definition to install.
+
+use std::collections::BTreeMap;
+
+use futures::stream::StreamExt;
+use k8s_openapi::api::core::v1::Secret;
+use kube::api::{ListParams, Patch};
+use kube::{Api, Client, CustomResource, Resource};
+use kube_runtime::controller::{Context, ReconcilerAction};
+use kube_runtime::watcher::{self, watcher};
+use kube_runtime::Controller;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tracing::{error, info, warn};
+
+use crate::config::SpreadConfig;
+use crate::{delete_copies, finalizer, index, keys, naming, on_error, shutdown, sync_secret, targets, ContextData, Error, SyncOutcome};
+
+/// Declares a Secret in the namespace of the `SecretSpread` and where it is spread to.
+#[derive(CustomResource, Deserialize, Serialize, Clone, Debug, JsonSchema)]
+#[kube(group = "secretspreading.fitzek.eu", version = "v1alpha1", kind = "SecretSpread", namespaced, status = "SecretSpreadStatus")]
+#[serde(rename_all = "camelCase")]
+pub struct SecretSpreadSpec {
+    /// Name of the Secret spread, in the namespace of the `SecretSpread`.
+    pub secret_name: String,
+    /// Target namespaces like the `target-namespace` annotation: names, globs, `regex:`
+    /// expressions or `*`.
+    #[serde(default)]
+    pub target_namespaces: Vec<String>,
+    /// Kubernetes label selector of target namespaces like `target-namespace-selector`.
+    pub namespace_selector: Option<String>,
+    /// Namespaces `*` doesn't expand to like `exclude-namespaces`.
+    #[serde(default)]
+    pub exclude_namespaces: Vec<String>,
+    /// Template of the copy names like `target-name`.
+    pub target_name: Option<String>,
+}
+
+/// Status of a `SecretSpread`: the outcome of the last sync.
+#[derive(Deserialize, Serialize, Clone, Debug, Default, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct SecretSpreadStatus {
+    /// Uid of the Secret the copies were written from. Its copies are deleted once `secretName`
+    /// names another Secret.
+    pub source_uid: Option<String>,
+    /// RFC 3339 time of the last successful sync.
+    pub last_synced: Option<String>,
+    /// Number of target namespaces with an up to date copy.
+    #[serde(default)]
+    pub copies: u32,
+    /// Why the last sync failed.
+    pub error: Option<String>,
+}
+
+impl SecretSpreadSpec {
+    /// Returns `sec` carrying the targeting annotations of the spec, the way it is synced.
+    fn annotate(&self, sec: &Secret) -> Secret {
+        let mut annotations: BTreeMap<String, String> = sec.metadata.annotations.clone().unwrap_or_default();
+        let mut set = |key: &str, value: String| {
+            annotations.insert(keys::key(key), value);
+        };
+        if !self.target_namespaces.is_empty() {
+            set(targets::TARGET_NAMESPACE_ANNOTATION, self.target_namespaces.join(","));
+        }
+        if let Some(selector) = &self.namespace_selector {
+            set(targets::TARGET_NAMESPACE_SELECTOR_ANNOTATION, selector.clone());
+        }
+        if !self.exclude_namespaces.is_empty() {
+            set(targets::EXCLUDE_NAMESPACES_ANNOTATION, self.exclude_namespaces.join(","));
+        }
+        if let Some(target_name) = &self.target_name {
+            set(naming::TARGET_NAME_ANNOTATION, target_name.clone());
+        }
+        let mut annotated = sec.clone();
+        annotated.metadata.annotations = Some(annotations);
+        annotated
+    }
+}
+
+/// Runs the controller of the `SecretSpread`s in all namespaces, with `context` of the Secret
+/// controller. A change of a Secret reconciles the `SecretSpread`s naming it.
+pub async fn run(client: Client, context: Context<ContextData>) {
+    let sources = std::sync::Arc::new(index::SpreadIndex::default());
+    let spread_api: Api<SecretSpread> = Api::all(client.clone());
+    let secret_api: Api<Secret> = Api::all(client);
+    let mapped = sources.clone();
+    if !context.get_ref().use_finalizer {
+        tokio::spawn(release_deleted(spread_api.clone(), context.clone()));
+    }
+    Controller::new(spread_api, ListParams::default())
+        .watches(secret_api, ListParams::default(), move |sec| mapped.spreads_for(&sec.namespace().unwrap_or_default(), &sec.name()))
+        .run(
+            move |spread: SecretSpread, context| {
+                sources.update((spread.namespace().unwrap_or_default(), spread.name()), Some(spread.spec.secret_name.clone()).filter(|_| spread.metadata.deletion_timestamp.is_none()));
+                reconcile(spread, context)
+            },
+            on_error,
+            context,
+        )
+        .for_each(|reconciliation_result| async move {
+            if let Err(reconciliation_err) = reconciliation_result {
+                error!(error = ?reconciliation_err, "Reconciliation error")
+            }
+        })
+        .await;
+}
+
+/// Reconciles a `SecretSpread` by spreading its Secret, or deleting the copies once it is
+/// deleted. The outcome is recorded in its status.
+async fn reconcile(spread: SecretSpread, context: Context<ContextData>) -> Result<ReconcilerAction, Error> {
+    // no reconcile starts once the operator shuts down, see shutdown::drain
+    let _running = match shutdown::begin() {
+        Some(guard) => guard,
+        None => return Ok(ReconcilerAction { requeue_after: None }),
+    };
+    // paused in the settings ConfigMap, see the Secret reconcile
+    if context.get_ref().paused() {
+        return Ok(ReconcilerAction {
+            requeue_after: Some(context.get_ref().jitter.apply(context.get_ref().requeue().idle)),
+        });
+    }
+    let namespace = spread
+        .namespace()
+        .ok_or_else(|| Error::UserInputError("Expected SecretSpread resource to be namespaced.".to_owned()))?;
+    let name = spread.name();
+    let client = context.get_ref().client.clone();
+    let recorded_uid = spread.status.as_ref().and_then(|status| status.source_uid.clone());
+
+    if spread.metadata.deletion_timestamp.is_some() {
+        if let Some(source_uid) = &recorded_uid {
+            for (ns, copy_name) in delete_copies::<Secret>(context.get_ref().target_client.clone(), source_uid, &context.get_ref().delete_params()).await? {
+                info!(target_namespace = %ns, name = %copy_name, "Deleted copy");
+            }
+        }
+        finalizer::rm(client, &name, &namespace, &spread, &context.get_ref().patch_params()).await?;
+        return Ok(ReconcilerAction { requeue_after: None });
+    }
+
+    let result = spread_secret(&spread, &namespace, recorded_uid.as_deref(), context.clone()).await;
+    let status = match &result {
+        Ok((source_uid, outcome)) => SecretSpreadStatus {
+            source_uid: Some(source_uid.clone()),
+            last_synced: Some(chrono::Utc::now().to_rfc3339()),
+            copies: outcome.created + outcome.updated + outcome.unchanged,
+            error: outcome.failure.clone(),
+        },
+        Err(e) => SecretSpreadStatus {
+            error: Some(e.to_string()),
+            ..spread.status.clone().unwrap_or_default()
+        },
+    };
+    if !context.get_ref().dry_run {
+        let api: Api<SecretSpread> = Api::namespaced(client, &namespace);
+        let patch = json!({ "status": status });
+        if let Err(e) = api.patch_status(&name, &context.get_ref().patch_params(), &Patch::Merge(&patch)).await {
+            warn!(namespace = %namespace, name = %name, error = %e, "Can't record the status of the SecretSpread");
+        }
+    }
+
+    result.map(|(_, outcome)| ReconcilerAction {
+        requeue_after: Some(match outcome.failure {
+            Some(_) => context.get_ref().requeue().error,
+            None => context.get_ref().jitter.apply(context.get_ref().requeue().synced),
+        }),
+    })
+}
+
+/// Deletes the copies of the `SecretSpread`s deleted while they are watched, for
+/// `--disable-finalizer`. Without the finalizer the controller never sees a deletion, and the
+/// orphan scan keeps the copies, because the Secret they were written from still exists.
+/// `SecretSpread`s deleted while the operator is not running keep their copies.
+async fn release_deleted(spread_api: Api<SecretSpread>, context: Context<ContextData>) {
+    let mut events = watcher(spread_api, ListParams::default()).boxed();
+    while let Some(event) = events.next().await {
+        let spread = match event {
+            Ok(watcher::Event::Deleted(spread)) => spread,
+            Ok(_) => continue,
+            Err(e) => {
+                warn!(error = ?e, "SecretSpread watch error");
+                continue;
+            }
+        };
+        let source_uid = match spread.status.as_ref().and_then(|status| status.source_uid.as_deref()) {
+            Some(source_uid) => source_uid,
+            None => continue,
+        };
+        match delete_copies::<Secret>(context.get_ref().target_client.clone(), source_uid, &context.get_ref().delete_params()).await {
+            Ok(deleted) => {
+                for (ns, copy_name) in deleted {
+                    info!(target_namespace = %ns, name = %copy_name, spread = %spread.name(), "Deleted copy of the deleted SecretSpread");
+                }
+            }
+            Err(e) => warn!(spread = %spread.name(), error = ?e, "Can't delete the copies of the deleted SecretSpread"),
+        }
+    }
+}
+
+/// Spreads the Secret of `spread` in `namespace`. Returns the uid of the Secret and the outcome
+/// of the sync. The copies of the Secret with `recorded_uid`, which the copies were written from
+/// before, are deleted if it is another one.
+async fn spread_secret(spread: &SecretSpread, namespace: &str, recorded_uid: Option<&str>, context: Context<ContextData>) -> Result<(String, SyncOutcome), Error> {
+    let client = context.get_ref().client.clone();
+    let secret_name = &spread.spec.secret_name;
+    let secret_api: Api<Secret> = Api::namespaced(client.clone(), namespace);
+    let sec = match secret_api.get(secret_name).await {
+        Ok(sec) => sec,
+        Err(kube::Error::Api(kube::error::ErrorResponse { code: 404, .. })) => {
+            return Err(Error::UserInputError(format!("Secret {} does not exist", secret_name)));
+        }
+        Err(e) => return Err(e.into()),
+    };
+    // both would prune the copies of the other
+    if targets::has_targets(&sec.metadata) {
+        return Err(Error::UserInputError(format!(
+            "Secret {} is spread by its annotations already, remove them or the SecretSpread",
+            secret_name
+        )));
+    }
+    let spread_api: Api<SecretSpread> = Api::namespaced(client.clone(), namespace);
+    let first = spread_api
+        .list(&ListParams::default())
+        .await?
+        .into_iter()
+        .filter(|other| other.spec.secret_name == *secret_name && other.metadata.deletion_timestamp.is_none())
+        .min_by_key(|other| (other.metadata.creation_timestamp.clone().map(|t| t.0), other.name()));
+    if let Some(first) = first.filter(|first| first.metadata.uid != spread.metadata.uid) {
+        return Err(Error::UserInputError(format!("Secret {} is spread by SecretSpread {} already", secret_name, first.name())));
+    }
+
+    let name = spread.name();
+    if context.get_ref().use_finalizer && !finalizer::add(client.clone(), &name, namespace, spread, &context.get_ref().patch_params()).await? {
+        return Err(Error::UserInputError(format!("Finalizer not confirmed on SecretSpread {}, not spreading yet", name)));
+    }
+
+    let source_uid = sec.metadata.uid.clone().unwrap_or_default();
+    if let Some(previous) = recorded_uid.filter(|previous| *previous != source_uid) {
+        for (ns, copy_name) in delete_copies::<Secret>(context.get_ref().target_client.clone(), previous, &context.get_ref().delete_params()).await? {
+            info!(target_namespace = %ns, name = %copy_name, "Deleted copy of the previous Secret");
+        }
+    }
+
+    let sec = spread.spec.annotate(&sec);
+    let config = SpreadConfig::from_secret(&sec)?
+        .ok_or_else(|| Error::UserInputError("The SecretSpread needs targetNamespaces or a namespaceSelector".to_string()))?;
+    let outcome = sync_secret(sec, &config, context, source_uid.clone(), namespace.to_string(), secret_name.clone()).await?;
+    Ok((source_uid, outcome))
+}
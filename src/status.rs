@@ -0,0 +1,52 @@
+//! Sync status recorded on the source secret, so it can be inspected with `kubectl` directly.
+
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::ObjectMeta;
+use kube::api::{Patch, PatchParams};
+use kube::{Api, Client, Resource};
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+use tracing::info;
+
+use crate::{keys, targets, Error};
+
+/// RFC 3339 time of the last sync that changed a copy or the set of target namespaces.
+/// Maintained by the operator on the source.
+pub const LAST_SYNCED_ANNOTATION: &str = "eu.fitzek.spread.last-synced";
+/// Number of namespaces holding an up to date copy after the last sync. Maintained by the
+/// operator on the source.
+pub const TARGET_COUNT_ANNOTATION: &str = "eu.fitzek.spread.target-count";
+
+/// Records a successful sync to `target_count` namespaces on the source `name` in `namespace`,
+/// a Secret or, for Vault backed sources, a ConfigMap.
+///
+/// The patch changes the source and so triggers another reconcile. To not flap the timestamp on
+/// every reconcile, the status is only written if `changed`, i.e. copies were written or
+/// deleted, or if the recorded target count differs.
+pub async fn record<K>(client: Client, name: &str, namespace: &str, meta: &ObjectMeta, target_count: u32, changed: bool, pp: &PatchParams) -> Result<(), Error>
+where
+    K: Resource<DynamicType = ()> + Clone + DeserializeOwned + std::fmt::Debug,
+{
+    let recorded = targets::annotation(meta, TARGET_COUNT_ANNOTATION);
+    let synced_before = targets::annotation(meta, LAST_SYNCED_ANNOTATION).is_some();
+    if !changed && synced_before && recorded.as_deref() == Some(target_count.to_string().as_str()) {
+        return Ok(());
+    }
+    if pp.dry_run {
+        info!(source_namespace = %namespace, name = %name, target_count, "[dry-run] Would record sync status");
+        return Ok(());
+    }
+    let api: Api<K> = Api::namespaced(client, namespace);
+    let patch: Value = json!({
+        "metadata": {
+            "annotations": {
+                keys::key(LAST_SYNCED_ANNOTATION): chrono::Utc::now().to_rfc3339(),
+                keys::key(TARGET_COUNT_ANNOTATION): target_count.to_string()
+            }
+        }
+    });
+    match api.patch(name, pp, &Patch::Merge(&patch)).await {
+        // the source was deleted meanwhile, its cleanup follows
+        Ok(_) | Err(kube::Error::Api(kube::error::ErrorResponse { code: 404, .. })) => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
@@ -0,0 +1,1210 @@
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+
+use k8s_openapi::api::core::v1::{ConfigMap, Namespace};
+use k8s_openapi::api::rbac::v1::RoleBinding;
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::ObjectMeta;
+use kube::api::{DynamicObject, GroupVersionKind, ListParams};
+use kube::{Api, Client, Resource};
+use regex::Regex;
+use serde::Deserialize;
+use tracing::{info, warn};
+
+use crate::opts::{ConflictMode, Opts};
+use crate::{keys, remote, Error};
+
+/// Comma separated list of target namespaces, or `*` for all namespaces. Entries may be globs
+/// like `team-*` or regular expressions like `regex:^team-[0-9]+$`.
+pub const TARGET_NAMESPACE_ANNOTATION: &str = "eu.fitzek.spread.target-namespace";
+/// Comma separated list of namespaces left out when `target-namespace` is `*`. Replaces the
+/// default list configured by `--exclude-namespaces`.
+pub const EXCLUDE_NAMESPACES_ANNOTATION: &str = "eu.fitzek.spread.exclude-namespaces";
+/// Label of a namespace, with the value `true` the namespace is left out when `target-namespace`
+/// is `*`, in addition to the excluded namespaces.
+pub const OPT_OUT_LABEL: &str = "eu.fitzek.spread.opt-out";
+/// With `future-only`, `*` and the patterns of `target-namespace` only expand to namespaces
+/// created after the source, existing namespaces are never filled.
+pub const MODE_ANNOTATION: &str = "eu.fitzek.spread.mode";
+/// Kubernetes label selector, e.g. `tenant=true`; every namespace with matching labels is a
+/// target. A selector matching no namespace spreads nowhere, which is not an error.
+pub const TARGET_NAMESPACE_SELECTOR_ANNOTATION: &str = "eu.fitzek.spread.target-namespace-selector";
+/// Name of a group; every namespace with a RoleBinding granting a role to this group is a target.
+pub const TARGET_FOR_GROUP_ANNOTATION: &str = "eu.fitzek.spread.target-for-group";
+/// Name of a namespace managed by the Hierarchical Namespace Controller; all of its descendants
+/// are targets.
+pub const TARGET_SUBTREE_ANNOTATION: &str = "eu.fitzek.spread.target-subtree";
+/// Maximum number of namespaces to spread to. The resolved namespaces, without the source
+/// namespace, are sorted by name and the first ones are kept.
+pub const MAX_NAMESPACES_ANNOTATION: &str = "eu.fitzek.spread.max-namespaces";
+/// ConfigMap holding the target namespaces, `<name>` in the namespace of the source or
+/// `<namespace>/<name>`. Its `namespaces` key lists them separated by commas or newlines.
+pub const TARGET_NAMESPACES_FROM_ANNOTATION: &str = "eu.fitzek.spread.target-namespaces-from";
+/// URL answering GET with a JSON array of target namespace names, fetched on every reconcile.
+pub const TARGET_URL_ANNOTATION: &str = "eu.fitzek.spread.target-url";
+/// JSON encoded [`TargetPolicy`] combining several namespace criteria.
+pub const TARGET_POLICY_ANNOTATION: &str = "eu.fitzek.spread.target";
+/// JSON list of [`TargetRule`]s, each selecting namespaces and naming the copies there on its
+/// own, e.g. `[{"namespaces": ["team-a", "team-b"]}, {"namespaces": ["shared"], "namePrefix":
+/// "mirror-"}]`. Combines with the other targeting annotations.
+pub const TARGETS_ANNOTATION: &str = "eu.fitzek.spread.targets";
+/// Copies in namespaces that are no longer targeted, e.g. after a namespace was removed from the
+/// `target-namespace` list or relabeled out of the policy selector, are deleted. Set to `false`
+/// to keep them.
+pub const PRUNE_UNTARGETED_ANNOTATION: &str = "eu.fitzek.spread.prune-untargeted";
+
+/// Criteria a namespace has to fulfill to be a target. All criteria that are set have to match.
+///
+/// Example: `{"selector": "env=prod", "regex": "team-.*", "exclude": ["team-legacy"],
+/// "phase": "Active", "hasResource": "apps/v1/Deployment"}`
+///
+/// To spread only to namespaces in the same zone as the source, annotate the source with
+/// `topology.kubernetes.io/zone-preference: eu-west-1a` and use
+/// `{"matchSourceAnnotations": ["topology.kubernetes.io/zone-preference"]}`. Namespaces without
+/// the annotation are skipped, a source without it is rejected.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct TargetPolicy {
+    /// Kubernetes label selector the namespace labels have to match.
+    pub selector: Option<String>,
+    /// Regular expression the whole namespace name has to match.
+    pub regex: Option<String>,
+    /// Namespace names that are never targeted.
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    /// Phase the namespace has to be in, `Active` or `Terminating`.
+    pub phase: Option<String>,
+    /// Resource the namespace has to contain at least one object of, as
+    /// `<group>/<version>/<Kind>`, or `<version>/<Kind>` for the core group.
+    pub has_resource: Option<String>,
+    /// Annotations the namespace has to carry with exactly these values.
+    #[serde(default)]
+    pub annotations: BTreeMap<String, String>,
+    /// Annotation keys the namespace has to carry with the same value as the source.
+    #[serde(default)]
+    pub match_source_annotations: Vec<String>,
+}
+
+impl TargetPolicy {
+    /// Parses and validates the JSON policy from an annotation value.
+    pub fn parse(value: &str) -> Result<Self, Error> {
+        let policy: TargetPolicy = serde_json::from_str(value).map_err(|e| {
+            Error::UserInputError(format!("Invalid {} annotation: {}", TARGET_POLICY_ANNOTATION, e))
+        })?;
+        policy.name_regex()?;
+        policy.resource_kind()?;
+        if let Some(phase) = &policy.phase {
+            if phase != "Active" && phase != "Terminating" {
+                return Err(Error::UserInputError(format!(
+                    "Invalid {} annotation: phase must be Active or Terminating, got {}",
+                    TARGET_POLICY_ANNOTATION, phase
+                )));
+            }
+        }
+        Ok(policy)
+    }
+
+    fn name_regex(&self) -> Result<Option<Regex>, Error> {
+        match &self.regex {
+            Some(r) => Regex::new(&format!("^(?:{})$", r)).map(Some).map_err(|e| {
+                Error::UserInputError(format!("Invalid {} annotation: {}", TARGET_POLICY_ANNOTATION, e))
+            }),
+            None => Ok(None),
+        }
+    }
+
+    fn resource_kind(&self) -> Result<Option<GroupVersionKind>, Error> {
+        let resource = match &self.has_resource {
+            Some(r) => r,
+            None => return Ok(None),
+        };
+        let parts: Vec<&str> = resource.split('/').collect();
+        let gvk = match parts.as_slice() {
+            [version, kind] => GroupVersionKind::gvk("", version, kind),
+            [group, version, kind] => GroupVersionKind::gvk(group, version, kind),
+            _ => {
+                return Err(Error::UserInputError(format!(
+                    "Invalid {} annotation: hasResource must be <group>/<version>/<Kind>, got {}",
+                    TARGET_POLICY_ANNOTATION, resource
+                )))
+            }
+        };
+        gvk.map(Some).map_err(|e| {
+            Error::UserInputError(format!("Invalid {} annotation: {}", TARGET_POLICY_ANNOTATION, e))
+        })
+    }
+
+    /// Annotations the namespaces have to carry: the fixed `annotations` plus the
+    /// `matchSourceAnnotations` with the values taken from the source.
+    fn required_annotations(&self, source: &ObjectMeta) -> Result<BTreeMap<String, String>, Error> {
+        let mut required = self.annotations.clone();
+        for key in &self.match_source_annotations {
+            let value = annotation(source, key).ok_or_else(|| {
+                Error::UserInputError(format!(
+                    "Invalid {} annotation: matchSourceAnnotations requires the source to carry {}",
+                    TARGET_POLICY_ANNOTATION, key
+                ))
+            })?;
+            required.insert(key.clone(), value);
+        }
+        Ok(required)
+    }
+
+    /// Lists the namespaces of the source `source` matching all criteria of the policy.
+    ///
+    /// Namespaces are listed once, filtered by the label selector on the server side. The
+    /// `hasResource` criterion costs one additional cluster wide list of that resource.
+    pub async fn resolve(&self, client: Client, source: &ObjectMeta) -> Result<Vec<String>, Error> {
+        let required_annotations = self.required_annotations(source)?;
+        let namespace_api: Api<Namespace> = Api::all(client.clone());
+        let mut lp = ListParams::default();
+        if let Some(selector) = &self.selector {
+            lp = lp.labels(selector);
+        }
+        let namespaces = namespace_api.list(&lp).await.map_err(|e| list_forbidden(e, "Policy"))?;
+
+        let name_regex = self.name_regex()?;
+        let with_resource: Option<HashSet<String>> = match self.resource_kind()? {
+            Some(gvk) => {
+                let api: Api<DynamicObject> = Api::all_with(client, &gvk);
+                let objects = api.list(&ListParams::default()).await?;
+                Some(objects.iter().filter_map(|o| o.namespace()).collect())
+            }
+            None => None,
+        };
+
+        Ok(namespaces
+            .iter()
+            .filter(|ns| match &name_regex {
+                Some(r) => r.is_match(&ns.name()),
+                None => true,
+            })
+            .filter(|ns| !self.exclude.contains(&ns.name()))
+            .filter(|ns| match &self.phase {
+                Some(phase) => ns.status.as_ref().and_then(|s| s.phase.as_ref()) == Some(phase),
+                None => true,
+            })
+            .filter(|ns| match &with_resource {
+                Some(set) => set.contains(&ns.name()),
+                None => true,
+            })
+            .filter(|ns| {
+                required_annotations
+                    .iter()
+                    .all(|(k, v)| annotation(&ns.metadata, k).as_ref() == Some(v))
+            })
+            .map(|ns| ns.name())
+            .collect())
+    }
+}
+
+/// One rule of the `targets` annotation: the namespaces it selects, by the entries of
+/// `namespaces` and by `selector` combined, and how the copies there are named. The entries are
+/// names, globs, `regex:` expressions or `*` like in `target-namespace`, the name transform is
+/// `name` or `namePrefix` and `nameSuffix` like the `target-name*` annotations; without one the
+/// copies are named like the source.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct TargetRule {
+    #[serde(default)]
+    pub namespaces: Vec<String>,
+    /// Kubernetes label selector of the namespaces.
+    pub selector: Option<String>,
+    /// Template of the copy names, see `target-name`.
+    pub name: Option<String>,
+    pub name_prefix: Option<String>,
+    pub name_suffix: Option<String>,
+}
+
+impl TargetRule {
+    /// Returns the `namespaces` entries as `target-namespace` would list them.
+    fn listed(&self) -> String {
+        self.namespaces.join(",")
+    }
+
+    /// Returns true if the rule selects among the namespaces of the cluster, see [`expands`].
+    fn expands(&self) -> bool {
+        self.selector.is_some() || lists_patterns(TARGETS_ANNOTATION, &self.listed())
+    }
+}
+
+/// Parses and validates the rules of the `targets` annotation of the object, none without it.
+/// A rule has to select namespaces by `namespaces` or `selector`.
+pub fn target_rules(meta: &ObjectMeta) -> Result<Vec<TargetRule>, Error> {
+    let value = match annotation(meta, TARGETS_ANNOTATION) {
+        Some(value) => value,
+        None => return Ok(Vec::new()),
+    };
+    let rules: Vec<TargetRule> = serde_json::from_str(&value)
+        .map_err(|e| Error::UserInputError(format!("Invalid {} annotation: {}", TARGETS_ANNOTATION, e)))?;
+    for rule in &rules {
+        if rule.namespaces.is_empty() && rule.selector.is_none() {
+            return Err(Error::UserInputError(format!("Invalid {} annotation: a rule needs namespaces or a selector", TARGETS_ANNOTATION)));
+        }
+        let listed = rule.listed();
+        if listed != "*" {
+            listed_namespaces(TARGETS_ANNOTATION, &listed)?;
+        }
+    }
+    Ok(rules)
+}
+
+/// Computes the namespaces `rule` of the object selects, looked up with `client`. `*` and the
+/// patterns expand as for `target-namespace`, see [`resolve_target_namespaces`].
+pub async fn resolve_rule(client: Client, meta: &ObjectMeta, rule: &TargetRule, targeting: &Targeting) -> Result<BTreeSet<String>, Error> {
+    let mut namespaces: BTreeSet<String> = BTreeSet::new();
+    if !rule.namespaces.is_empty() {
+        namespaces.extend(listed_targets(client.clone(), meta, TARGETS_ANNOTATION, &rule.listed(), &targeting.exclude_namespaces).await?);
+    }
+    if let Some(selector) = &rule.selector {
+        namespaces.extend(selected_namespaces(client, TARGETS_ANNOTATION, selector).await?);
+    }
+    Ok(namespaces)
+}
+
+/// Looks up the annotation `key` on `meta`, ignoring the case of the key. Keys of the operator
+/// are translated to the configured prefix, see [`keys::key`].
+pub fn annotation(meta: &ObjectMeta, key: &str) -> Option<String> {
+    let key = keys::key(key);
+    match &meta.annotations {
+        Some(a) => a
+            .iter()
+            .find(|x| x.0.eq_ignore_ascii_case(&key))
+            .map(|x| x.1.clone()),
+        None => None,
+    }
+}
+
+/// Returns true if the object carries any annotation selecting target namespaces.
+pub fn has_targets(meta: &ObjectMeta) -> bool {
+    annotation(meta, TARGET_NAMESPACE_ANNOTATION).is_some()
+        || annotation(meta, TARGET_NAMESPACE_SELECTOR_ANNOTATION).is_some()
+        || annotation(meta, TARGET_FOR_GROUP_ANNOTATION).is_some()
+        || annotation(meta, TARGET_SUBTREE_ANNOTATION).is_some()
+        || annotation(meta, TARGET_POLICY_ANNOTATION).is_some()
+        || annotation(meta, TARGET_URL_ANNOTATION).is_some()
+        || annotation(meta, TARGET_NAMESPACES_FROM_ANNOTATION).is_some()
+        || annotation(meta, TARGETS_ANNOTATION).is_some()
+}
+
+/// Returns true if the target namespaces of the object are selected among the namespaces of the
+/// cluster, by `*`, a pattern, a label selector, a group, a subtree, a policy or a rule doing so,
+/// so a namespace created later may become a target.
+pub fn expands(meta: &ObjectMeta) -> bool {
+    let patterns = annotation(meta, TARGET_NAMESPACE_ANNOTATION).is_some_and(|value| lists_patterns(TARGET_NAMESPACE_ANNOTATION, &value));
+    let rules = target_rules(meta).is_ok_and(|rules| rules.iter().any(TargetRule::expands));
+    patterns
+        || rules
+        || annotation(meta, TARGET_NAMESPACE_SELECTOR_ANNOTATION).is_some()
+        || annotation(meta, TARGET_FOR_GROUP_ANNOTATION).is_some()
+        || annotation(meta, TARGET_SUBTREE_ANNOTATION).is_some()
+        || annotation(meta, TARGET_POLICY_ANNOTATION).is_some()
+}
+
+/// Returns true if the namespace list `value` of the annotation `key` is `*` or has patterns.
+fn lists_patterns(key: &str, value: &str) -> bool {
+    value == "*" || listed_namespaces(key, value).is_ok_and(|(_, patterns)| !patterns.is_empty())
+}
+
+/// Label selectors of the namespaces the object targets: the `target-namespace-selector`, the
+/// selectors of the policy and of the rules, the HNC depth label of `target-subtree`, and the
+/// opt-out label if `*` or patterns are listed. A namespace whose labels change from matching
+/// one of them to not matching or back may gain or lose its copy.
+pub fn namespace_selectors(meta: &ObjectMeta) -> Vec<String> {
+    let mut selectors: Vec<String> = Vec::new();
+    selectors.extend(annotation(meta, TARGET_NAMESPACE_SELECTOR_ANNOTATION));
+    if let Some(policy) = annotation(meta, TARGET_POLICY_ANNOTATION).and_then(|value| TargetPolicy::parse(&value).ok()) {
+        selectors.extend(policy.selector);
+    }
+    let rules = target_rules(meta).unwrap_or_default();
+    selectors.extend(rules.iter().filter_map(|rule| rule.selector.clone()));
+    if let Some(parent) = annotation(meta, TARGET_SUBTREE_ANNOTATION) {
+        selectors.push(format!("{}.tree.hnc.x-k8s.io/depth", parent));
+    }
+    let patterns = annotation(meta, TARGET_NAMESPACE_ANNOTATION).is_some_and(|value| lists_patterns(TARGET_NAMESPACE_ANNOTATION, &value))
+        || rules.iter().any(|rule| lists_patterns(TARGETS_ANNOTATION, &rule.listed()));
+    if patterns {
+        selectors.push(format!("{}=true", keys::key(OPT_OUT_LABEL)));
+    }
+    selectors
+}
+
+/// Size in bytes of the `target-namespace` list from which on the source is warned to use a
+/// ConfigMap instead. All annotations of an object together are limited to 256KiB.
+pub const LONG_TARGET_LIST_BYTES: usize = 64 * 1024;
+
+/// Returns true if the `target-namespace` list of the object comes close to the size limit of
+/// annotations.
+pub fn target_list_too_long(meta: &ObjectMeta) -> bool {
+    annotation(meta, TARGET_NAMESPACE_ANNOTATION).map_or(0, |v| v.len()) > LONG_TARGET_LIST_BYTES
+}
+
+/// Returns the namespace and name of the ConfigMap the target namespaces of the object are
+/// read from, if any.
+pub fn namespaces_from_reference(meta: &ObjectMeta) -> Option<(String, String)> {
+    let reference = annotation(meta, TARGET_NAMESPACES_FROM_ANNOTATION)?;
+    match reference.split_once('/') {
+        Some((namespace, name)) => Some((namespace.to_string(), name.to_string())),
+        None => Some((meta.namespace.clone().unwrap_or_default(), reference)),
+    }
+}
+
+/// Returns true if copies in namespaces that are no longer targeted are deleted, unless
+/// `prune-untargeted` is `false`. With a namespace limit this is always the case, lowering the
+/// limit removes the surplus copies.
+pub fn prune_untargeted(meta: &ObjectMeta) -> bool {
+    annotation(meta, PRUNE_UNTARGETED_ANNOTATION).as_deref() != Some("false")
+        || annotation(meta, MAX_NAMESPACES_ANNOTATION).is_some()
+}
+
+/// Validates the targeting annotations of the object that don't need the API server.
+pub fn validate(meta: &ObjectMeta) -> Result<(), Error> {
+    max_namespaces(meta)?;
+    if let Some(value) = annotation(meta, TARGET_NAMESPACE_ANNOTATION).filter(|v| v != "*") {
+        listed_namespaces(TARGET_NAMESPACE_ANNOTATION, &value)?;
+    }
+    if let Some(value) = annotation(meta, TARGET_NAMESPACE_SELECTOR_ANNOTATION) {
+        label_selector(TARGET_NAMESPACE_SELECTOR_ANNOTATION, &value)?;
+    }
+    target_rules(meta)?;
+    if let Some(value) = annotation(meta, TARGET_POLICY_ANNOTATION) {
+        TargetPolicy::parse(&value)?;
+    }
+    if let Some(mode) = annotation(meta, MODE_ANNOTATION).filter(|m| m != "future-only") {
+        return Err(Error::UserInputError(format!(
+            "Invalid {} annotation: expected future-only, got {}",
+            MODE_ANNOTATION, mode
+        )));
+    }
+    Ok(())
+}
+
+/// Parses the comma separated namespace list of `target-namespace`, or of another annotation
+/// `key` with the same syntax, into the listed names and
+/// the patterns. An entry with `*` or `?` is a glob, e.g. `team-*`, an entry starting with
+/// `regex:` a regular expression, e.g. `regex:^team-(a|b)$`, which can't contain commas. Both
+/// have to match the whole namespace name. Whitespace around the entries and empty entries, e.g.
+/// from a trailing comma, are dropped. A name that is no valid namespace name, a DNS-1123 label,
+/// or an invalid regular expression is an error.
+fn listed_namespaces(key: &str, value: &str) -> Result<(Vec<String>, Vec<Regex>), Error> {
+    let format = Regex::new(r"^[a-z0-9]([-a-z0-9]*[a-z0-9])?$").unwrap();
+    let invalid = |entry: &str, reason: String| {
+        Error::UserInputError(format!("Invalid {} annotation: {} {}", key, entry, reason))
+    };
+    let mut names = Vec::new();
+    let mut patterns = Vec::new();
+    for entry in value.split(',').map(str::trim).filter(|ns| !ns.is_empty()) {
+        if let Some(expression) = entry.strip_prefix("regex:") {
+            let pattern = Regex::new(&format!("^(?:{})$", expression)).map_err(|e| invalid(entry, format!("is not a valid regular expression: {}", e)))?;
+            patterns.push(pattern);
+        } else if entry.contains(['*', '?']) {
+            let glob = regex::escape(entry).replace(r"\*", ".*").replace(r"\?", ".");
+            patterns.push(Regex::new(&format!("^{}$", glob)).map_err(|e| invalid(entry, format!("is not a valid pattern: {}", e)))?);
+        } else if entry.len() > 63 || !format.is_match(entry) {
+            return Err(invalid(entry, "is not a valid namespace name".to_string()));
+        } else {
+            names.push(entry.to_string());
+        }
+    }
+    Ok((names, patterns))
+}
+
+/// Checks the syntax of the label selector of the annotation `key`, comma separated requirements
+/// of the forms `key`, `!key`, `key=value`, `key==value`, `key!=value`, `key in (a,b)` and
+/// `key notin (a,b)`. An empty selector selects every namespace and is accepted.
+fn label_selector(key: &str, value: &str) -> Result<(), Error> {
+    if value.trim().is_empty() {
+        return Ok(());
+    }
+    let label_key = r"([a-z0-9]([-a-z0-9.]*[a-z0-9])?/)?[A-Za-z0-9]([-A-Za-z0-9_.]*[A-Za-z0-9])?";
+    let label_value = r"[A-Za-z0-9]([-A-Za-z0-9_.]*[A-Za-z0-9])?";
+    let requirement = Regex::new(&format!(
+        r"^(!?\s*{k}|{k}\s*(=|==|!=)\s*({v})?|{k}\s+(in|notin)\s*\(\s*{v}(\s*,\s*{v})*\s*\))$",
+        k = label_key,
+        v = label_value
+    ))
+    .unwrap();
+    // commas separate requirements outside of parentheses and values inside
+    let mut requirements = Vec::new();
+    let (mut depth, mut start) = (0, 0);
+    for (i, c) in value.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                requirements.push(&value[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    requirements.push(&value[start..]);
+    match requirements.into_iter().map(str::trim).find(|r| !requirement.is_match(r)) {
+        Some(invalid) => Err(Error::UserInputError(format!("Invalid {} annotation: {:?} is no valid label selector requirement", key, invalid))),
+        None => Ok(()),
+    }
+}
+
+/// Reads the namespace limit of the source, if any.
+fn max_namespaces(meta: &ObjectMeta) -> Result<Option<usize>, Error> {
+    match annotation(meta, MAX_NAMESPACES_ANNOTATION) {
+        Some(v) => v.parse::<usize>().map(Some).map_err(|_| {
+            Error::UserInputError(format!(
+                "Invalid {} annotation: expected a number, got {}",
+                MAX_NAMESPACES_ANNOTATION, v
+            ))
+        }),
+        None => Ok(None),
+    }
+}
+
+/// Targeting annotations ordered by precedence, the first one present wins if
+/// `TARGETING_CONFLICT_MODE` is `lenient`.
+const TARGETING_PRECEDENCE: [&str; 8] = [
+    TARGET_POLICY_ANNOTATION,
+    TARGET_NAMESPACE_SELECTOR_ANNOTATION,
+    TARGET_NAMESPACE_ANNOTATION,
+    TARGET_NAMESPACES_FROM_ANNOTATION,
+    TARGET_FOR_GROUP_ANNOTATION,
+    TARGET_SUBTREE_ANNOTATION,
+    TARGET_URL_ANNOTATION,
+    TARGETS_ANNOTATION,
+];
+
+/// Settings of the operator applying to the targeting of every source, see [`Opts`].
+#[derive(Debug, Clone, Default)]
+pub struct Targeting {
+    /// Namespaces `*` doesn't expand to, unless the source lists its own.
+    pub exclude_namespaces: Vec<String>,
+    /// How several targeting annotations on one source combine.
+    pub conflict_mode: ConflictMode,
+    /// Bearer token sent to the URL of `target-url`.
+    pub url_token: Option<String>,
+    /// Label, `key` or `key=value`, of sources spread to `default_target_namespaces` without a
+    /// targeting annotation of their own.
+    pub default_trigger_label: Option<String>,
+    /// Target namespaces of the sources carrying `default_trigger_label`.
+    pub default_target_namespaces: Vec<String>,
+}
+
+impl Targeting {
+    /// The targeting configured by `opts`.
+    pub fn from_opts(opts: &Opts) -> Self {
+        Targeting {
+            exclude_namespaces: opts.exclude_namespaces.clone(),
+            conflict_mode: opts.targeting_conflict_mode,
+            url_token: opts.target_url_token.clone().filter(|t| !t.is_empty()),
+            default_trigger_label: opts.default_trigger_label.clone(),
+            default_target_namespaces: opts.default_target_namespaces.clone(),
+        }
+    }
+}
+
+/// Decides which of the targeting annotations on `meta` are honored, depending on `mode`:
+///
+/// - `union` (default): all of them, their namespaces are combined.
+/// - `strict`: combining several of them is an error.
+/// - `lenient`: only the first one in the order `target`, `target-namespace-selector`,
+///   `target-namespace`, `target-namespaces-from`, `target-for-group`, `target-subtree`,
+///   `target-url`, `targets` is used, the others are ignored with a warning.
+///
+/// The rules of `targets` are resolved on their own, see [`rules_honored`].
+fn honored_targeting(meta: &ObjectMeta, mode: ConflictMode) -> Result<Vec<&'static str>, Error> {
+    let present: Vec<&'static str> = TARGETING_PRECEDENCE
+        .iter()
+        .copied()
+        .filter(|key| annotation(meta, key).is_some())
+        .collect();
+    if present.len() < 2 {
+        return Ok(present);
+    }
+
+    match mode {
+        ConflictMode::Strict => Err(Error::UserInputError(format!(
+            "Conflicting targeting annotations {}, only one is allowed",
+            present.join(", ")
+        ))),
+        ConflictMode::Lenient => {
+            warn!(using = present[0], ignoring = %present[1..].join(", "), "Conflicting targeting annotations");
+            Ok(vec![present[0]])
+        }
+        ConflictMode::Union => Ok(present),
+    }
+}
+
+/// Whether the rules of the `targets` annotation on `meta` are resolved under `mode`, see
+/// [`honored_targeting`]. Only `lenient` drops them, if another targeting annotation takes
+/// precedence; `strict` rejects the combination when resolving the other annotations.
+pub fn rules_honored(meta: &ObjectMeta, mode: ConflictMode) -> bool {
+    mode != ConflictMode::Lenient || TARGETING_PRECEDENCE.iter().find(|key| annotation(meta, key).is_some()) == Some(&TARGETS_ANNOTATION)
+}
+
+/// Computes the namespaces a source should be spread to from its annotations.
+///
+/// The namespaces selected by the different annotations are combined, each namespace is only
+/// returned once and in order of the names, so the copies are synced in a stable order. The
+/// simple annotations `target-namespace` and `target-for-group` act as shortcuts next to the
+/// `target` policy. Whether annotations may be combined is decided by the conflict mode of
+/// `targeting`, see [`honored_targeting`]. With `max-namespaces` only the first namespaces by
+/// name are returned, so the selection is stable across reconciles.
+///
+/// The namespaces are looked up with `client`, in the cluster the copies are written to. The
+/// ConfigMap of `target-namespaces-from` is read with `source_client`, next to the source.
+///
+/// The excluded namespaces of `targeting` are the ones `*` doesn't expand to, unless the source
+/// lists its own. Namespaces labeled with [`OPT_OUT_LABEL`] are never part of `*`. Glob and regex
+/// patterns in `target-namespace` match among the same namespaces, names listed literally are
+/// targeted even if excluded or opted out.
+pub async fn resolve_target_namespaces(client: Client, source_client: Client, meta: &ObjectMeta, targeting: &Targeting) -> Result<BTreeSet<String>, Error> {
+    let mut namespaces: Vec<String> = Vec::new();
+    let honored = honored_targeting(meta, targeting.conflict_mode)?;
+    let default_excluded = &targeting.exclude_namespaces;
+    let honored_annotation = |key: &str| if honored.contains(&key) { annotation(meta, key) } else { None };
+
+    if let Some(target_namespace_name) = honored_annotation(TARGET_NAMESPACE_ANNOTATION) {
+        namespaces.extend(listed_targets(client.clone(), meta, TARGET_NAMESPACE_ANNOTATION, &target_namespace_name, default_excluded).await?);
+    }
+
+    if let Some(selector) = honored_annotation(TARGET_NAMESPACE_SELECTOR_ANNOTATION) {
+        namespaces.extend(selected_namespaces(client.clone(), TARGET_NAMESPACE_SELECTOR_ANNOTATION, &selector).await?);
+    }
+
+    if honored_annotation(TARGET_NAMESPACES_FROM_ANNOTATION).is_some() {
+        if let Some((namespace, name)) = namespaces_from_reference(meta) {
+            namespaces.extend(namespaces_from_configmap(source_client, &namespace, &name).await?);
+        }
+    }
+
+    if let Some(group) = honored_annotation(TARGET_FOR_GROUP_ANNOTATION) {
+        namespaces.extend(namespaces_for_group(client.clone(), &group).await?);
+    }
+
+    if let Some(parent) = honored_annotation(TARGET_SUBTREE_ANNOTATION) {
+        namespaces.extend(namespaces_in_subtree(client.clone(), &parent).await?);
+    }
+
+    if let Some(policy) = honored_annotation(TARGET_POLICY_ANNOTATION) {
+        namespaces.extend(TargetPolicy::parse(&policy)?.resolve(client.clone(), meta).await?);
+    }
+
+    if let Some(url) = honored_annotation(TARGET_URL_ANNOTATION) {
+        namespaces.extend(remote::fetch_namespaces(&url, targeting.url_token.as_deref()).await?);
+    }
+
+    let mut namespaces: BTreeSet<String> = namespaces.into_iter().collect();
+    if let Some(max) = max_namespaces(meta)? {
+        // the source namespace never gets a copy, it must not take up a slot
+        namespaces.retain(|ns| Some(ns) != meta.namespace.as_ref());
+        namespaces = namespaces.into_iter().take(max).collect();
+    }
+    Ok(namespaces)
+}
+
+/// Resolves the namespace list `value` of the annotation `key`: `*` or the listed names plus the
+/// expandable namespaces matching its patterns.
+async fn listed_targets(client: Client, meta: &ObjectMeta, key: &str, value: &str, default_excluded: &[String]) -> Result<Vec<String>, Error> {
+    if value == "*" {
+        return expandable_namespaces(client, meta, default_excluded).await;
+    }
+    let (mut names, patterns) = listed_namespaces(key, value)?;
+    if !patterns.is_empty() {
+        let matched: Vec<String> = expandable_namespaces(client, meta, default_excluded)
+            .await?
+            .into_iter()
+            .filter(|ns| patterns.iter().any(|p| p.is_match(ns)))
+            .collect();
+        if matched.is_empty() {
+            info!(target_namespace = %value, "No namespace matches the patterns");
+        }
+        names.extend(matched);
+    }
+    Ok(names)
+}
+
+/// Lists the namespaces matching the label selector of the annotation `key`.
+async fn selected_namespaces(client: Client, key: &str, selector: &str) -> Result<Vec<String>, Error> {
+    let namespace_api: Api<Namespace> = Api::all(client);
+    let selected: Vec<String> = namespace_api
+        .list(&ListParams::default().labels(selector))
+        .await
+        .map_err(|e| match e {
+            kube::Error::Api(kube::error::ErrorResponse { code: 400, message, .. }) => {
+                Error::UserInputError(format!("Invalid {} annotation: {}", key, message))
+            }
+            e => list_forbidden(e, "Label selector"),
+        })?
+        .iter()
+        .map(|ns| ns.name())
+        .collect();
+    if selected.is_empty() {
+        info!(selector = %selector, "No namespace matches the selector");
+    }
+    Ok(selected)
+}
+
+/// Lists the namespaces `*` expands to: all namespaces but the excluded ones and the ones
+/// carrying the opt-out label, in `future-only` mode only the ones created after the source.
+/// Patterns in `target-namespace` select from these as well.
+///
+/// The namespaces are listed in pages of [`NAMESPACE_PAGE_SIZE`], only their names are kept, so
+/// a large cluster doesn't need all namespace objects in memory at once.
+async fn expandable_namespaces(client: Client, meta: &ObjectMeta, default_excluded: &[String]) -> Result<Vec<String>, Error> {
+    let namespace_api: Api<Namespace> = Api::all(client);
+    let excluded = excluded_namespaces(meta, default_excluded);
+    let created_after = match annotation(meta, MODE_ANNOTATION).as_deref() {
+        Some("future-only") => meta.creation_timestamp.clone(),
+        _ => None,
+    };
+    let mut names = Vec::new();
+    let mut lp = ListParams::default().limit(NAMESPACE_PAGE_SIZE);
+    loop {
+        let page = namespace_api.list(&lp).await.map_err(|e| list_forbidden(e, "'*' or pattern"))?;
+        names.extend(
+            page.iter()
+                .filter(|ns| !opted_out(ns))
+                .filter(|ns| match &created_after {
+                    Some(source_created) => ns.metadata.creation_timestamp.as_ref().is_some_and(|created| created.0 > source_created.0),
+                    None => true,
+                })
+                .map(|ns| ns.name())
+                .filter(|ns| !excluded.contains(ns)),
+        );
+        match page.metadata.continue_.filter(|token| !token.is_empty()) {
+            Some(token) => lp = lp.continue_token(&token),
+            None => return Ok(names),
+        }
+    }
+}
+
+/// Returns whether the namespace `labels` match the Kubernetes label selector `selector`, `None`
+/// if the selector can't be parsed. The API server evaluates the selectors when the namespaces
+/// are listed, this only tells whether a label change may change the outcome.
+pub fn selector_matches(selector: &str, labels: &BTreeMap<String, String>) -> Option<bool> {
+    // requirements are separated by commas, except within the value set of `in` and `notin`
+    let mut requirements: Vec<&str> = Vec::new();
+    let (mut depth, mut start) = (0, 0);
+    for (i, c) in selector.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                requirements.push(&selector[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    requirements.push(&selector[start..]);
+    requirements
+        .into_iter()
+        .map(str::trim)
+        .filter(|requirement| !requirement.is_empty())
+        .try_fold(true, |all, requirement| Some(requirement_matches(requirement, labels)? && all))
+}
+
+/// Returns whether the `labels` fulfill one requirement of a label selector: `key`, `!key`,
+/// `key=value`, `key==value`, `key!=value`, `key in (a,b)` or `key notin (a,b)`.
+fn requirement_matches(requirement: &str, labels: &BTreeMap<String, String>) -> Option<bool> {
+    let value_of = |key: &str| labels.get(key.trim()).map(String::as_str);
+    if let Some(open) = requirement.find('(') {
+        let values: Vec<&str> = requirement[open + 1..].strip_suffix(')')?.split(',').map(str::trim).collect();
+        let mut words = requirement[..open].split_whitespace();
+        let (key, operator) = (words.next()?, words.next()?);
+        let contained = value_of(key).is_some_and(|value| values.contains(&value));
+        return match (operator, words.next()) {
+            ("in", None) => Some(contained),
+            ("notin", None) => Some(!contained),
+            _ => None,
+        };
+    }
+    if let Some(key) = requirement.strip_prefix('!') {
+        return Some(value_of(key).is_none());
+    }
+    if let Some((key, value)) = requirement.split_once("!=") {
+        return Some(value_of(key) != Some(value.trim()));
+    }
+    if let Some((key, value)) = requirement.split_once('=') {
+        let value = value.strip_prefix('=').unwrap_or(value);
+        return Some(value_of(key) == Some(value.trim()));
+    }
+    if requirement.contains(char::is_whitespace) {
+        return None;
+    }
+    Some(value_of(requirement).is_some())
+}
+
+/// Turns a namespace list refused with 403 into a user error naming the `targeting` that needs
+/// it. The operator may be allowed to read Secrets only, explicit target lists work without
+/// listing namespaces, so the error points at the annotation instead of the API server.
+fn list_forbidden(e: kube::Error, targeting: &str) -> Error {
+    match e {
+        kube::Error::Api(kube::error::ErrorResponse { code: 403, .. }) => {
+            Error::UserInputError(format!("{} targeting requires namespace list permission", targeting))
+        }
+        e => e.into(),
+    }
+}
+
+/// Number of namespaces listed per request when expanding `*`.
+const NAMESPACE_PAGE_SIZE: u32 = 500;
+
+/// Returns whether the namespace `ns` carries the opt-out label and is left out of `*`.
+fn opted_out(ns: &Namespace) -> bool {
+    let label = keys::key(OPT_OUT_LABEL);
+    ns.metadata.labels.as_ref().and_then(|labels| labels.get(&label)).map(String::as_str) == Some("true")
+}
+
+/// Returns the namespaces `*` doesn't expand to: the `exclude-namespaces` annotation of the
+/// source if set, otherwise `default_excluded`.
+fn excluded_namespaces(meta: &ObjectMeta, default_excluded: &[String]) -> HashSet<String> {
+    let listed: Vec<String> = match annotation(meta, EXCLUDE_NAMESPACES_ANNOTATION) {
+        Some(list) => list.split(',').map(str::to_string).collect(),
+        None => default_excluded.to_vec(),
+    };
+    listed
+        .iter()
+        .map(|ns| ns.trim())
+        .filter(|ns| !ns.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Reads the target namespaces listed in the ConfigMap `namespace`/`name`.
+async fn namespaces_from_configmap(client: Client, namespace: &str, name: &str) -> Result<Vec<String>, Error> {
+    let configmap_api: Api<ConfigMap> = Api::namespaced(client, namespace);
+    let cm = match configmap_api.get(name).await {
+        Ok(cm) => cm,
+        Err(kube::Error::Api(kube::error::ErrorResponse { code: 404, .. })) => {
+            return Err(Error::UserInputError(format!(
+                "Invalid {} annotation: ConfigMap {}/{} does not exist",
+                TARGET_NAMESPACES_FROM_ANNOTATION, namespace, name
+            )))
+        }
+        Err(e) => return Err(e.into()),
+    };
+    Ok(cm
+        .data
+        .as_ref()
+        .and_then(|d| d.get("namespaces"))
+        .map(|list| {
+            list.split([',', '\n'])
+                .map(str::trim)
+                .filter(|ns| !ns.is_empty())
+                .map(str::to_string)
+                .collect()
+        })
+        .unwrap_or_default())
+}
+
+/// Returns the uid of the namespace `name`, telling apart namespaces recreated under the same
+/// name. Returns `None` if the namespace doesn't exist.
+pub async fn namespace_uid(client: Client, name: &str) -> Result<Option<String>, Error> {
+    let namespace_api: Api<Namespace> = Api::all(client);
+    match namespace_api.get(name).await {
+        Ok(ns) => Ok(ns.metadata.uid),
+        Err(kube::Error::Api(kube::error::ErrorResponse { code: 404, .. })) => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Finds the namespaces in which a RoleBinding grants any role to the group `group`.
+///
+/// This lists the RoleBindings of all namespaces, which is expensive on large clusters. The list
+/// is fetched once per call, so a reconcile only pays for it once regardless of how many
+/// bindings reference the group. ClusterRoleBindings are ignored as they are not scoped to a
+/// namespace.
+async fn namespaces_for_group(client: Client, group: &str) -> Result<Vec<String>, Error> {
+    let rolebinding_api: Api<RoleBinding> = Api::all(client);
+    let rolebindings = rolebinding_api.list(&ListParams::default()).await?;
+
+    let mut namespaces: Vec<String> = rolebindings
+        .iter()
+        .filter(|rb| {
+            rb.subjects
+                .iter()
+                .flatten()
+                .any(|s| s.kind == "Group" && s.name == group)
+        })
+        .filter_map(|rb| rb.namespace())
+        .collect();
+    namespaces.sort();
+    namespaces.dedup();
+    Ok(namespaces)
+}
+
+/// Finds all descendants of the namespace `parent` in the Hierarchical Namespace Controller tree.
+///
+/// HNC labels every namespace with `<ancestor>.tree.hnc.x-k8s.io/depth` for itself (depth 0) and
+/// each of its ancestors, so the descendants are the namespaces carrying the label of `parent`
+/// with a depth greater than zero.
+async fn namespaces_in_subtree(client: Client, parent: &str) -> Result<Vec<String>, Error> {
+    let depth_label = format!("{}.tree.hnc.x-k8s.io/depth", parent);
+    let namespace_api: Api<Namespace> = Api::all(client);
+    let namespaces = namespace_api.list(&ListParams::default().labels(&depth_label)).await.map_err(|e| list_forbidden(e, "Subtree"))?;
+
+    if namespaces.items.is_empty() {
+        return Err(Error::UserInputError(format!(
+            "Namespace {} carries no {} label, is it managed by the Hierarchical Namespace Controller?",
+            parent, depth_label
+        )));
+    }
+
+    Ok(namespaces
+        .iter()
+        .filter(|ns| {
+            ns.metadata
+                .labels
+                .as_ref()
+                .and_then(|l| l.get(&depth_label))
+                .is_some_and(|depth| depth != "0")
+        })
+        .map(|ns| ns.name())
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use k8s_openapi::api::apps::v1::Deployment;
+    use k8s_openapi::api::core::v1::NamespaceStatus;
+    use k8s_openapi::api::rbac::v1::{RoleRef, Subject};
+
+    use crate::fake_api::FakeApi;
+
+    fn namespace(name: &str, labels: &[(&str, &str)]) -> Namespace {
+        Namespace {
+            metadata: ObjectMeta {
+                name: Some(name.to_string()),
+                labels: Some(labels.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect()),
+                ..ObjectMeta::default()
+            },
+            ..Namespace::default()
+        }
+    }
+
+    /// Metadata of a source in the namespace `source` with the `annotations`.
+    fn meta(annotations: &[(&str, &str)]) -> ObjectMeta {
+        ObjectMeta {
+            name: Some("db".to_string()),
+            namespace: Some("source".to_string()),
+            annotations: Some(annotations.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect()),
+            ..ObjectMeta::default()
+        }
+    }
+
+    #[test]
+    fn strict_mode_rejects_combined_annotations() {
+        let single = meta(&[(TARGET_NAMESPACE_ANNOTATION, "a,b"), (MAX_NAMESPACES_ANNOTATION, "1")]);
+        assert_eq!(honored_targeting(&single, ConflictMode::Strict).unwrap(), vec![TARGET_NAMESPACE_ANNOTATION]);
+
+        let combined = meta(&[(TARGET_NAMESPACE_ANNOTATION, "a"), (TARGET_FOR_GROUP_ANNOTATION, "devs")]);
+        match honored_targeting(&combined, ConflictMode::Strict) {
+            Err(Error::UserInputError(message)) => {
+                assert!(message.contains(TARGET_NAMESPACE_ANNOTATION) && message.contains(TARGET_FOR_GROUP_ANNOTATION), "{}", message)
+            }
+            other => panic!("expected a conflict, got {:?}", other),
+        }
+        assert!(honored_targeting(&meta(&[]), ConflictMode::Strict).unwrap().is_empty());
+    }
+
+    #[test]
+    fn lenient_mode_honors_the_first_annotation_by_precedence() {
+        let combined = meta(&[
+            (TARGET_URL_ANNOTATION, "https://example.com/namespaces"),
+            (TARGET_NAMESPACE_ANNOTATION, "a"),
+            (TARGET_NAMESPACE_SELECTOR_ANNOTATION, "tenant=true"),
+        ]);
+        assert_eq!(honored_targeting(&combined, ConflictMode::Lenient).unwrap(), vec![TARGET_NAMESPACE_SELECTOR_ANNOTATION]);
+        assert_eq!(honored_targeting(&combined, ConflictMode::Union).unwrap().len(), 3);
+
+        // the rules alone are honored, they come last only when combined
+        let rules = meta(&[(TARGETS_ANNOTATION, r#"[{"namespaces": ["b"]}]"#)]);
+        assert_eq!(honored_targeting(&rules, ConflictMode::Lenient).unwrap(), vec![TARGETS_ANNOTATION]);
+        assert!(rules_honored(&rules, ConflictMode::Lenient));
+        assert!(rules_honored(&rules, ConflictMode::Strict));
+    }
+
+    #[test]
+    fn targets_rules_conflict_with_other_annotations() {
+        let meta = meta(&[(TARGET_NAMESPACE_ANNOTATION, "a"), (TARGETS_ANNOTATION, r#"[{"namespaces": ["b"]}]"#)]);
+
+        assert!(honored_targeting(&meta, ConflictMode::Strict).is_err());
+
+        assert_eq!(honored_targeting(&meta, ConflictMode::Lenient).unwrap(), vec![TARGET_NAMESPACE_ANNOTATION]);
+        assert!(!rules_honored(&meta, ConflictMode::Lenient));
+
+        assert_eq!(honored_targeting(&meta, ConflictMode::Union).unwrap(), vec![TARGET_NAMESPACE_ANNOTATION, TARGETS_ANNOTATION]);
+        assert!(rules_honored(&meta, ConflictMode::Union));
+    }
+
+    #[tokio::test]
+    async fn ten_thousand_namespaces_are_listed_in_pages() {
+        let (client, fake) = FakeApi::start();
+        for i in 0..10_000 {
+            fake.insert(&namespace(&format!("ns-{:05}", i), &[]));
+        }
+        fake.insert(&namespace("kube-system", &[]));
+        fake.insert(&namespace("opted-out", &[(OPT_OUT_LABEL, "true")]));
+        let targeting = Targeting {
+            exclude_namespaces: vec!["kube-system".to_string()],
+            ..Targeting::default()
+        };
+
+        let start = std::time::Instant::now();
+        let all = resolve_target_namespaces(client.clone(), client.clone(), &meta(&[(TARGET_NAMESPACE_ANNOTATION, "*")]), &targeting).await.unwrap();
+        assert!(start.elapsed() < std::time::Duration::from_secs(10), "{:?}", start.elapsed());
+        assert_eq!(all.len(), 10_000);
+        assert!(!all.contains("kube-system") && !all.contains("opted-out"));
+        let reads = fake.take_reads();
+        assert_eq!(reads.len(), 21);
+        assert!(reads.iter().all(|read| read.starts_with("/api/v1/namespaces?") && read.contains("limit=500")), "{:?}", reads.first());
+
+        // a namespace listed by name and matched by a pattern is targeted once
+        let listed = meta(&[(TARGET_NAMESPACE_ANNOTATION, "ns-00001, ns-0000*, regex:ns-0000[0-2]")]);
+        let namespaces = resolve_target_namespaces(client.clone(), client.clone(), &listed, &targeting).await.unwrap();
+        assert_eq!(namespaces.len(), 10);
+        assert_eq!(namespaces.iter().next().map(String::as_str), Some("ns-00000"));
+
+        // a list of 10k names, each listed twice, needs no lookup and is deduplicated
+        let names: Vec<String> = (0..10_000).map(|i| format!("ns-{:05}", i)).collect();
+        let value = format!("{},{}", names.join(","), names.join(", "));
+        let long = meta(&[(TARGET_NAMESPACE_ANNOTATION, &value)]);
+        assert!(target_list_too_long(&long));
+        fake.take_reads();
+        let start = std::time::Instant::now();
+        assert_eq!(listed_namespaces(TARGET_NAMESPACE_ANNOTATION, &value).unwrap().0.len(), 20_000);
+        let namespaces = resolve_target_namespaces(client.clone(), client, &long, &targeting).await.unwrap();
+        assert!(start.elapsed() < std::time::Duration::from_secs(10), "{:?}", start.elapsed());
+        assert_eq!(namespaces.into_iter().collect::<Vec<_>>(), names);
+        assert!(fake.take_reads().is_empty());
+    }
+
+    fn role_binding(namespace: &str, name: &str, subjects: &[(&str, &str)]) -> RoleBinding {
+        RoleBinding {
+            metadata: ObjectMeta {
+                name: Some(name.to_string()),
+                namespace: Some(namespace.to_string()),
+                ..ObjectMeta::default()
+            },
+            role_ref: RoleRef {
+                api_group: "rbac.authorization.k8s.io".to_string(),
+                kind: "ClusterRole".to_string(),
+                name: "edit".to_string(),
+            },
+            subjects: Some(
+                subjects
+                    .iter()
+                    .map(|(kind, name)| Subject {
+                        kind: kind.to_string(),
+                        name: name.to_string(),
+                        ..Subject::default()
+                    })
+                    .collect(),
+            ),
+        }
+    }
+
+    #[tokio::test]
+    async fn group_targets_the_namespaces_binding_it() {
+        let (client, fake) = FakeApi::start();
+        fake.insert(&role_binding("team-a", "devs-edit", &[("Group", "devs"), ("User", "alice")]));
+        fake.insert(&role_binding("team-a", "devs-view", &[("Group", "devs")]));
+        fake.insert(&role_binding("team-b", "devs", &[("Group", "ops"), ("Group", "devs")]));
+        fake.insert(&role_binding("team-c", "ops", &[("Group", "ops")]));
+        // a user or service account named like the group is no member of it
+        fake.insert(&role_binding("team-d", "user", &[("User", "devs"), ("ServiceAccount", "devs")]));
+
+        assert_eq!(namespaces_for_group(client.clone(), "devs").await.unwrap(), vec!["team-a", "team-b"]);
+        assert_eq!(namespaces_for_group(client.clone(), "ops").await.unwrap(), vec!["team-b", "team-c"]);
+        assert!(namespaces_for_group(client.clone(), "nobody").await.unwrap().is_empty());
+        let meta = meta(&[(TARGET_FOR_GROUP_ANNOTATION, "devs")]);
+        let targeted = resolve_target_namespaces(client.clone(), client, &meta, &Targeting::default()).await.unwrap();
+        assert_eq!(targeted.into_iter().collect::<Vec<_>>(), vec!["team-a", "team-b"]);
+    }
+
+    #[tokio::test]
+    async fn subtree_targets_the_descendants() {
+        let (client, fake) = FakeApi::start();
+        // org > team-a > team-a-dev, and an unrelated root
+        fake.insert(&namespace("org", &[("org.tree.hnc.x-k8s.io/depth", "0")]));
+        fake.insert(&namespace("team-a", &[("org.tree.hnc.x-k8s.io/depth", "1"), ("team-a.tree.hnc.x-k8s.io/depth", "0")]));
+        fake.insert(&namespace(
+            "team-a-dev",
+            &[("org.tree.hnc.x-k8s.io/depth", "2"), ("team-a.tree.hnc.x-k8s.io/depth", "1"), ("team-a-dev.tree.hnc.x-k8s.io/depth", "0")],
+        ));
+        fake.insert(&namespace("other", &[("other.tree.hnc.x-k8s.io/depth", "0")]));
+
+        assert_eq!(namespaces_in_subtree(client.clone(), "org").await.unwrap(), vec!["team-a", "team-a-dev"]);
+        assert_eq!(namespaces_in_subtree(client.clone(), "team-a").await.unwrap(), vec!["team-a-dev"]);
+        // a leaf has no descendants, a namespace outside HNC is an error
+        assert!(namespaces_in_subtree(client.clone(), "team-a-dev").await.unwrap().is_empty());
+        assert!(matches!(namespaces_in_subtree(client, "unmanaged").await, Err(Error::UserInputError(_))));
+    }
+
+    #[test]
+    fn target_policy_is_validated() {
+        let policy = TargetPolicy::parse(r#"{"selector": "env=prod", "regex": "team-.*", "exclude": ["team-legacy"], "phase": "Active", "hasResource": "apps/v1/Deployment"}"#).unwrap();
+        assert_eq!(policy.selector.as_deref(), Some("env=prod"));
+        assert_eq!(policy.exclude, vec!["team-legacy"]);
+        assert!(policy.name_regex().unwrap().unwrap().is_match("team-a"));
+        assert!(!policy.name_regex().unwrap().unwrap().is_match("my-team-a"));
+        assert!(TargetPolicy::parse(r#"{"hasResource": "v1/ConfigMap"}"#).unwrap().resource_kind().unwrap().is_some());
+        assert!(TargetPolicy::parse("{}").unwrap().resource_kind().unwrap().is_none());
+
+        for invalid in &[
+            "team-*",
+            r#"{"selektor": "env=prod"}"#,
+            r#"{"regex": "team-("}"#,
+            r#"{"phase": "Pending"}"#,
+            r#"{"hasResource": "Deployment"}"#,
+            r#"{"hasResource": "a/b/c/d"}"#,
+        ] {
+            assert!(matches!(TargetPolicy::parse(invalid), Err(Error::UserInputError(_))), "{}", invalid);
+        }
+    }
+
+    #[tokio::test]
+    async fn target_policy_combines_its_criteria() {
+        let (client, fake) = FakeApi::start();
+        let zone = "topology.kubernetes.io/zone-preference";
+        for (name, env, phase, zone_value) in &[
+            ("team-a", "prod", "Active", "eu-west-1a"),
+            ("team-b", "prod", "Active", "eu-west-1b"),
+            ("team-c", "prod", "Terminating", "eu-west-1a"),
+            ("team-legacy", "prod", "Active", "eu-west-1a"),
+            ("team-d", "dev", "Active", "eu-west-1a"),
+            ("other", "prod", "Active", "eu-west-1a"),
+        ] {
+            let mut ns = namespace(name, &[("env", env)]);
+            ns.metadata.annotations = Some(vec![(zone.to_string(), zone_value.to_string())].into_iter().collect());
+            ns.status = Some(NamespaceStatus {
+                phase: Some(phase.to_string()),
+                ..NamespaceStatus::default()
+            });
+            fake.insert(&ns);
+        }
+        for ns in &["team-a", "team-b", "team-c", "team-legacy", "other"] {
+            fake.insert(&Deployment {
+                metadata: ObjectMeta {
+                    name: Some("app".to_string()),
+                    namespace: Some(ns.to_string()),
+                    ..ObjectMeta::default()
+                },
+                ..Deployment::default()
+            });
+        }
+        let mut source = meta(&[]);
+        source.annotations.get_or_insert_with(BTreeMap::new).insert(zone.to_string(), "eu-west-1a".to_string());
+        let resolve = |policy: &str| {
+            let policy = TargetPolicy::parse(policy).unwrap();
+            let (client, source) = (client.clone(), source.clone());
+            async move { policy.resolve(client, &source).await }
+        };
+
+        let all = r#"{"selector": "env=prod", "regex": "team-.*", "exclude": ["team-legacy"], "phase": "Active", "hasResource": "apps/v1/Deployment"}"#;
+        assert_eq!(resolve(all).await.unwrap(), vec!["team-a", "team-b"]);
+        assert_eq!(resolve(r#"{"selector": "env=prod", "phase": "Terminating"}"#).await.unwrap(), vec!["team-c"]);
+        assert_eq!(resolve(r#"{"regex": "team-.*", "matchSourceAnnotations": ["topology.kubernetes.io/zone-preference"], "phase": "Active"}"#).await.unwrap(), vec!["team-a", "team-d", "team-legacy"]);
+        assert_eq!(resolve(r#"{"annotations": {"topology.kubernetes.io/zone-preference": "eu-west-1b"}}"#).await.unwrap(), vec!["team-b"]);
+        // a source without the annotation to match is rejected
+        let unzoned = meta(&[]);
+        let policy = TargetPolicy::parse(r#"{"matchSourceAnnotations": ["topology.kubernetes.io/zone-preference"]}"#).unwrap();
+        assert!(matches!(policy.resolve(client.clone(), &unzoned).await, Err(Error::UserInputError(_))));
+    }
+
+    #[tokio::test]
+    async fn zone_preference_of_the_source_selects_the_namespaces_of_its_zone() {
+        let (client, fake) = FakeApi::start();
+        let zone = "topology.kubernetes.io/zone-preference";
+        for (name, zone_value) in &[("a", Some("eu-west-1a")), ("b", Some("eu-west-1b")), ("c", Some("eu-west-1a")), ("d", None), ("e", Some(""))] {
+            let mut ns = namespace(name, &[]);
+            ns.metadata.annotations = zone_value.map(|z| vec![(zone.to_string(), z.to_string())].into_iter().collect());
+            fake.insert(&ns);
+        }
+        let policy = r#"{"matchSourceAnnotations": ["topology.kubernetes.io/zone-preference"]}"#;
+        let resolve = |source_zone: Option<&str>| {
+            let mut meta = meta(&[(TARGET_POLICY_ANNOTATION, policy)]);
+            if let Some(source_zone) = source_zone {
+                meta.annotations.as_mut().unwrap().insert(zone.to_string(), source_zone.to_string());
+            }
+            let client = client.clone();
+            async move { resolve_target_namespaces(client.clone(), client, &meta, &Targeting::default()).await }
+        };
+
+        // namespaces without a zone are skipped
+        assert_eq!(resolve(Some("eu-west-1a")).await.unwrap().into_iter().collect::<Vec<_>>(), vec!["a", "c"]);
+        assert_eq!(resolve(Some("eu-west-1b")).await.unwrap().into_iter().collect::<Vec<_>>(), vec!["b"]);
+        assert!(resolve(Some("us-east-1a")).await.unwrap().is_empty());
+        match resolve(None).await {
+            Err(Error::UserInputError(message)) => assert_eq!(
+                message,
+                format!("Invalid {} annotation: matchSourceAnnotations requires the source to carry {}", TARGET_POLICY_ANNOTATION, zone)
+            ),
+            other => panic!("expected a user error, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[tokio::test]
+    async fn forbidden_namespace_list_is_a_user_error() {
+        let (client, fake) = FakeApi::start();
+        fake.fail(hyper::Method::GET, "/api/v1/namespaces", Some(403));
+        let targeting = Targeting::default();
+        let resolve = |annotations: &[(&str, &str)]| {
+            let meta = meta(annotations);
+            let (client, targeting) = (client.clone(), targeting.clone());
+            async move { resolve_target_namespaces(client.clone(), client, &meta, &targeting).await }
+        };
+
+        for (key, value, needed_by) in &[
+            (TARGET_NAMESPACE_ANNOTATION, "*", "'*' or pattern"),
+            (TARGET_NAMESPACE_ANNOTATION, "a,team-*", "'*' or pattern"),
+            (TARGET_NAMESPACE_SELECTOR_ANNOTATION, "tenant=true", "Label selector"),
+            (TARGET_POLICY_ANNOTATION, r#"{"selector": "tenant=true"}"#, "Policy"),
+        ] {
+            match resolve(&[(key, value)]).await {
+                Err(Error::UserInputError(message)) => assert_eq!(message, format!("{} targeting requires namespace list permission", needed_by)),
+                other => panic!("expected a user error for {}, got {:?}", value, other),
+            }
+        }
+        // names listed literally need no list permission
+        assert_eq!(resolve(&[(TARGET_NAMESPACE_ANNOTATION, "a,b")]).await.unwrap().len(), 2);
+
+        // other failures stay API errors
+        fake.fail(hyper::Method::GET, "/api/v1/namespaces", Some(500));
+        assert!(matches!(resolve(&[(TARGET_NAMESPACE_ANNOTATION, "*")]).await, Err(Error::KubeError { .. })));
+    }
+
+#[tokio::test]
+async fn namespace_lists_are_trimmed_and_checked() {
+    let (client, fake) = FakeApi::start();
+    for ns in &["source", "a", "b", "c"] {
+        fake.insert(&namespace(ns, &[]));
+    }
+    let resolve = |value: &str| {
+        let meta = meta(&[(TARGET_NAMESPACE_ANNOTATION, value)]);
+        let client = client.clone();
+        async move { resolve_target_namespaces(client.clone(), client, &meta, &Targeting::default()).await }
+    };
+    let names = |namespaces: BTreeSet<String>| namespaces.into_iter().collect::<Vec<_>>();
+
+    // whitespace and empty entries are dropped
+    let (listed, patterns) = listed_namespaces(TARGET_NAMESPACE_ANNOTATION, "a, b ,").unwrap();
+    assert_eq!(listed, vec!["a", "b"]);
+    assert!(patterns.is_empty());
+    assert!(validate(&meta(&[(TARGET_NAMESPACE_ANNOTATION, "a, b ,")])).is_ok());
+    assert_eq!(names(resolve("a, b ,").await.unwrap()), vec!["a", "b"]);
+
+    // every namespace, the sync skips the one of the source
+    assert!(validate(&meta(&[(TARGET_NAMESPACE_ANNOTATION, "*")])).is_ok());
+    assert_eq!(names(resolve("*").await.unwrap()), vec!["a", "b", "c", "source"]);
+
+    // namespace names are lowercase DNS-1123 labels
+    let invalid = format!("Invalid {} annotation: Team-A is not a valid namespace name", TARGET_NAMESPACE_ANNOTATION);
+    match validate(&meta(&[(TARGET_NAMESPACE_ANNOTATION, "a, Team-A")])) {
+        Err(Error::UserInputError(message)) => assert_eq!(message, invalid),
+        other => panic!("expected a user error, got {:?}", other),
+    }
+    match resolve("a, Team-A").await {
+        Err(Error::UserInputError(message)) => assert_eq!(message, invalid),
+        other => panic!("expected a user error, got {:?}", other),
+    }
+}
+}
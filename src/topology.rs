@@ -0,0 +1,207 @@
+//! Renders the spread layout of the cluster as a Graphviz DOT graph.
+//!
+//! Every source is a box node, every namespace holding or expecting a copy an ellipse node. An
+//! edge leads from a source to each of those namespaces and is labeled with the copy name and
+//! its status: `in sync`, `outdated`, `missing` or `stale` for copies in namespaces that are no
+//! longer targeted. Pipe the output to `dot -Tpng` to get an image. With `--output json` the
+//! same sources, namespaces and edges are listed as JSON.
+//!
+//! Nothing in the cluster is modified.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+use k8s_openapi::api::core::v1::Secret;
+use kube::api::ListParams;
+use kube::{Api, Client, Resource};
+use serde_json::json;
+
+use crate::cli::Outcome;
+use crate::config::SpreadConfig;
+use crate::opts::Opts;
+use crate::{compare, generated, keys, targets, Error};
+
+/// Quotes `s` as a DOT identifier.
+fn quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Builds the DOT graph of all sources in the cluster and their copies, targeted with the options
+/// `opts`.
+pub async fn render(client: Client, opts: &Opts) -> Result<Outcome, Error> {
+    let targeting = targets::Targeting::from_opts(opts);
+    let secret_api: Api<Secret> = Api::all(client.clone());
+    let sources: Vec<Secret> = secret_api
+        .list(&ListParams::default())
+        .await?
+        .into_iter()
+        .filter(|s| targets::has_targets(&s.metadata))
+        .collect();
+
+    let mut namespaces: BTreeSet<String> = BTreeSet::new();
+    // source, namespace, copy name and status
+    let mut edges: Vec<(String, String, String, &str)> = Vec::new();
+
+    for source in &sources {
+        let name = source.name();
+        let source_namespace = source.namespace().unwrap_or_default();
+        let source_uid = source.metadata.uid.clone().unwrap_or_default();
+        let source_node = format!("{}/{}", source_namespace, name);
+
+        let config = match SpreadConfig::from_secret(source)? {
+            Some(config) => config,
+            None => continue,
+        };
+        let generated_names = generated::recorded_names(&source.metadata)?;
+        let spread = config.copied(source.clone());
+
+        let lp = ListParams::default().labels(format!("{}={}", keys::owner_label(), source_uid).as_str());
+        let mut copies: BTreeMap<String, Secret> = secret_api
+            .list(&lp)
+            .await?
+            .into_iter()
+            .map(|s| (s.namespace().unwrap_or_default(), s))
+            .collect();
+
+        for (ns, target_name) in config.resolve_copies(client.clone(), client.clone(), &source.metadata, &name, &targeting).await? {
+            if ns == source_namespace {
+                continue;
+            }
+            let copy_name = if config.use_generate_name {
+                generated_names.get(&ns).cloned().unwrap_or_else(|| format!("{}-", target_name))
+            } else {
+                target_name
+            };
+            let annotations = compare::desired_annotations(source, &config.target_annotations, &ns);
+            let status = match copies.remove(&ns) {
+                Some(copy) if copy.name() == copy_name => {
+                    if compare::secrets_equivalent(&spread, &copy, &source_uid, &annotations, &opts.ignored_copy_keys) {
+                        "in sync"
+                    } else {
+                        "outdated"
+                    }
+                }
+                Some(copy) => {
+                    edges.push((source_node.clone(), ns.clone(), copy.name(), "stale"));
+                    "missing"
+                }
+                None => "missing",
+            };
+            edges.push((source_node.clone(), ns.clone(), copy_name, status));
+            namespaces.insert(ns);
+        }
+
+        for (ns, copy) in copies {
+            edges.push((source_node.clone(), ns.clone(), copy.name(), "stale"));
+            namespaces.insert(ns);
+        }
+    }
+    let source_nodes: Vec<String> = sources.iter().map(|s| format!("{}/{}", s.namespace().unwrap_or_default(), s.name())).collect();
+
+    let mut dot = String::from("digraph spread {\n    rankdir=LR;\n");
+    for node in &source_nodes {
+        writeln!(dot, "    {} [shape=box];", quote(node)).unwrap();
+    }
+    for ns in &namespaces {
+        writeln!(dot, "    {} [shape=ellipse];", quote(ns)).unwrap();
+    }
+    for (from, to, copy_name, status) in &edges {
+        writeln!(dot, "    {} -> {} [label={}];", quote(from), quote(to), quote(&format!("{} ({})", copy_name, status))).unwrap();
+    }
+    dot.push_str("}\n");
+    let edges: Vec<_> = edges.iter().map(|(from, to, copy_name, status)| json!({ "source": from, "namespace": to, "name": copy_name, "status": status })).collect();
+    Ok(Outcome::passed(dot, json!({ "sources": source_nodes, "namespaces": namespaces, "edges": edges })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+    use k8s_openapi::api::core::v1::Namespace;
+    use k8s_openapi::apimachinery::pkg::apis::meta::v1::ObjectMeta;
+    use k8s_openapi::ByteString;
+    use kube_runtime::controller::Context;
+    use regex::Regex;
+
+    use crate::fake_api::FakeApi;
+    use crate::ContextData;
+
+    fn secret(namespace: &str, name: &str, annotations: &[(&str, &str)]) -> Secret {
+        Secret {
+            metadata: ObjectMeta {
+                name: Some(name.to_string()),
+                namespace: Some(namespace.to_string()),
+                annotations: Some(annotations.iter().map(|(k, v)| (keys::key(k), v.to_string())).collect()),
+                finalizers: Some(vec![keys::finalizer().to_string()]),
+                ..ObjectMeta::default()
+            },
+            data: Some(std::iter::once(("password".to_string(), ByteString(b"secret".to_vec()))).collect()),
+            ..Secret::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn renders_sources_namespaces_and_copies() {
+        let (client, fake) = FakeApi::start();
+        for ns in &["source", "a", "b", "c", "e"] {
+            fake.insert(&Namespace {
+                metadata: ObjectMeta {
+                    name: Some(ns.to_string()),
+                    ..ObjectMeta::default()
+                },
+                ..Namespace::default()
+            });
+        }
+        fake.insert(&secret("source", "db", &[(targets::TARGET_NAMESPACE_ANNOTATION, "a,b,c,e")]));
+        fake.insert(&secret("source", "unrelated", &[]));
+        let opts = Opts::parse_from(["spreading-operator"]);
+        let context = Context::new(ContextData::new(client.clone(), &opts));
+        crate::reconcile(fake.get("source", "db").unwrap(), context).await.unwrap();
+
+        // a in sync, b outdated, c missing, and a copy in e no longer targeted
+        let mut outdated: Secret = fake.get("b", "db").unwrap();
+        outdated.data = Some(std::iter::once(("password".to_string(), ByteString(b"old".to_vec()))).collect());
+        outdated.metadata.annotations.get_or_insert_with(Default::default).remove(&keys::key(compare::CONTENT_HASH_ANNOTATION));
+        fake.insert(&outdated);
+        let api: Api<Secret> = Api::namespaced(client.clone(), "c");
+        api.delete("db", &Default::default()).await.unwrap();
+        let patch = serde_json::json!({ "metadata": { "annotations": { keys::key(targets::TARGET_NAMESPACE_ANNOTATION): "a,b,c,d" } } });
+        let source: Api<Secret> = Api::namespaced(client.clone(), "source");
+        source.patch("db", &Default::default(), &kube::api::Patch::Merge(&patch)).await.unwrap();
+
+        let outcome = render(client, &opts).await.unwrap();
+        let dot = outcome.text;
+        let node = Regex::new(r#"^    "[^"]+" \[shape=(box|ellipse)\];$"#).unwrap();
+        let edge = Regex::new(r#"^    "[^"]+" -> "[^"]+" \[label="[^"]+"\];$"#).unwrap();
+        let lines: Vec<&str> = dot.lines().collect();
+        assert_eq!(lines.first(), Some(&"digraph spread {"));
+        assert_eq!(lines.last(), Some(&"}"));
+        assert!(lines[1..lines.len() - 1].iter().all(|l| *l == "    rankdir=LR;" || node.is_match(l) || edge.is_match(l)), "{}", dot);
+
+        for expected in &[
+            r#""source/db" [shape=box];"#,
+            r#""a" [shape=ellipse];"#,
+            r#""source/db" -> "a" [label="db (in sync)"];"#,
+            r#""source/db" -> "b" [label="db (outdated)"];"#,
+            r#""source/db" -> "c" [label="db (missing)"];"#,
+            r#""source/db" -> "d" [label="db (missing)"];"#,
+            r#""source/db" -> "e" [label="db (stale)"];"#,
+        ] {
+            assert!(dot.contains(expected), "{} missing in\n{}", expected, dot);
+        }
+        assert!(!dot.contains("unrelated"));
+
+        // the same graph with `--output json`
+        assert_eq!(outcome.json["sources"], serde_json::json!(["source/db"]));
+        assert_eq!(outcome.json["namespaces"], serde_json::json!(["a", "b", "c", "d", "e"]));
+        let edges = outcome.json["edges"].as_array().unwrap();
+        assert_eq!(edges.len(), 5);
+        assert!(edges.contains(&serde_json::json!({ "source": "source/db", "namespace": "b", "name": "db", "status": "outdated" })));
+        assert!(edges.contains(&serde_json::json!({ "source": "source/db", "namespace": "e", "name": "db", "status": "stale" })));
+    }
+
+    #[test]
+    fn identifiers_are_quoted() {
+        assert_eq!(quote(r#"a"b\c"#), r#""a\"b\\c""#);
+    }
+}
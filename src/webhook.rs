@@ -0,0 +1,325 @@
+//! Validating admission webhook rejecting Secrets with malformed spread annotations, so a typo
+//! surfaces on `kubectl apply` instead of as a failing reconcile.
+//!
+//! `POST` of an `admission.k8s.io/v1` `AdmissionReview` to any path is answered with the review
+//! carrying the response. A Secret is rejected if its annotations don't parse the way a reconcile
+//! parses them. An annotation with the prefix of the operator that the operator doesn't know is
+//! returned as warning only, it may belong to a newer version of the operator.
+//!
+//! Secrets without spread annotations are always allowed, and so are Secrets being deleted and
+//! updates leaving the spread annotations alone, so a malformed annotation never blocks removing
+//! a finalizer or editing the data.
+
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::path::Path;
+
+use hyper::server::conn::Http;
+use hyper::service::service_fn;
+use hyper::{Body, Method, Request, Response, StatusCode};
+use k8s_openapi::api::core::v1::Secret;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::net::TcpListener;
+use tokio_native_tls::TlsAcceptor;
+use tracing::{debug, info, warn};
+
+use crate::config::{self, SpreadConfig};
+use crate::{keys, targets, Error};
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct AdmissionReview {
+    api_version: String,
+    kind: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    request: Option<AdmissionRequest>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    response: Option<AdmissionResponse>,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct AdmissionRequest {
+    uid: String,
+    /// The object as created or updated, missing on deletion.
+    #[serde(default)]
+    object: Option<Value>,
+    /// The object before an update, missing on creation.
+    #[serde(default)]
+    old_object: Option<Value>,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct AdmissionResponse {
+    uid: String,
+    allowed: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    status: Option<AdmissionStatus>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    warnings: Vec<String>,
+}
+
+#[derive(Deserialize, Serialize)]
+struct AdmissionStatus {
+    code: u16,
+    message: String,
+}
+
+/// Runs the webhook on `addr`, serving TLS with the PEM encoded certificate chain at `cert` and
+/// the PKCS #8 key at `key`. Fails if the address or the certificate can't be used.
+pub async fn run(addr: &str, cert: &Path, key: &Path) -> Result<(), Error> {
+    let addr: SocketAddr = addr
+        .parse()
+        .map_err(|e| Error::UserInputError(format!("Invalid webhook address {}: {}", addr, e)))?;
+    let read = |path: &Path| {
+        std::fs::read(path).map_err(|e| Error::UserInputError(format!("Can't read {}: {}", path.display(), e)))
+    };
+    let identity = native_tls::Identity::from_pkcs8(&read(cert)?, &read(key)?)
+        .map_err(|e| Error::UserInputError(format!("Invalid webhook certificate or key: {}", e)))?;
+    let acceptor = native_tls::TlsAcceptor::new(identity)
+        .map_err(|e| Error::UserInputError(format!("Can't set up TLS for the webhook: {}", e)))?;
+    let acceptor = TlsAcceptor::from(acceptor);
+    let listener = TcpListener::bind(addr)
+        .await
+        .map_err(|e| Error::UserInputError(format!("Can't listen on {}: {}", addr, e)))?;
+    info!(addr = %addr, "Admission webhook listening");
+
+    loop {
+        let (tcp, peer) = match listener.accept().await {
+            Ok(conn) => conn,
+            Err(e) => {
+                warn!(error = %e, "Accepting webhook connection failed");
+                continue;
+            }
+        };
+        let acceptor = acceptor.clone();
+        tokio::spawn(async move {
+            let tls = match acceptor.accept(tcp).await {
+                Ok(tls) => tls,
+                Err(e) => {
+                    debug!(peer = %peer, error = %e, "TLS handshake failed");
+                    return;
+                }
+            };
+            if let Err(e) = Http::new().serve_connection(tls, service_fn(handle)).await {
+                debug!(peer = %peer, error = %e, "Webhook connection failed");
+            }
+        });
+    }
+}
+
+async fn handle(req: Request<Body>) -> Result<Response<Body>, Infallible> {
+    if req.method() != Method::POST {
+        return Ok(respond(StatusCode::METHOD_NOT_ALLOWED, "expected POST of an AdmissionReview\n".to_string()));
+    }
+    let body = match hyper::body::to_bytes(req.into_body()).await {
+        Ok(body) => body,
+        Err(e) => return Ok(respond(StatusCode::BAD_REQUEST, format!("can't read body: {}\n", e))),
+    };
+    let review: AdmissionReview = match serde_json::from_slice(&body) {
+        Ok(review) => review,
+        Err(e) => return Ok(respond(StatusCode::BAD_REQUEST, format!("invalid AdmissionReview: {}\n", e))),
+    };
+    let request = match review.request {
+        Some(request) => request,
+        None => return Ok(respond(StatusCode::BAD_REQUEST, "AdmissionReview without request\n".to_string())),
+    };
+
+    // a previous version that doesn't parse is treated like none, every annotation is checked
+    let old = request.old_object.and_then(|old| serde_json::from_value::<Secret>(old).ok());
+    let (rejection, warnings) = match request.object.map(serde_json::from_value::<Secret>) {
+        None => (None, Vec::new()),
+        Some(Ok(sec)) => match admit(&sec, old.as_ref()) {
+            Ok(warnings) => (None, warnings),
+            Err(message) => (Some(message), Vec::new()),
+        },
+        Some(Err(e)) => (Some(format!("not a Secret: {}", e)), Vec::new()),
+    };
+    if let Some(message) = &rejection {
+        info!(uid = %request.uid, message = %message, "Rejected Secret");
+    }
+    let response = AdmissionReview {
+        api_version: review.api_version,
+        kind: review.kind,
+        request: None,
+        response: Some(AdmissionResponse {
+            uid: request.uid,
+            allowed: rejection.is_none(),
+            status: rejection.map(|message| AdmissionStatus { code: 400, message }),
+            warnings,
+        }),
+    };
+    let body = serde_json::to_string(&response).expect("an AdmissionReview is always serializable");
+    Ok(respond(StatusCode::OK, body))
+}
+
+/// Decides on the admission of `sec`, updating `old` if given. Returns the warnings about unknown
+/// annotations of an admitted Secret, or the reason to reject it.
+///
+/// A Secret being deleted is admitted. On update only a change of the spread annotations is
+/// validated, and a malformed annotation the previous version carried already doesn't reject it.
+fn admit(sec: &Secret, old: Option<&Secret>) -> Result<Vec<String>, String> {
+    if sec.metadata.deletion_timestamp.is_some() {
+        return Ok(Vec::new());
+    }
+    if let Some(old) = old {
+        if spread_annotations(sec) == spread_annotations(old) {
+            return Ok(Vec::new());
+        }
+    }
+    if let Err(message) = validate(sec) {
+        if old.is_none_or(|old| validate(old).err().as_ref() != Some(&message)) {
+            return Err(message);
+        }
+    }
+    Ok(config::unknown_annotations(&sec.metadata)
+        .into_iter()
+        .map(|(key, closest)| format!("unknown annotation {}, did you mean {}?", key, closest))
+        .collect())
+}
+
+/// Returns the annotations of `sec` with the prefix of the operator, by lowercase key.
+fn spread_annotations(sec: &Secret) -> BTreeMap<String, &str> {
+    let prefix = keys::prefix().to_ascii_lowercase();
+    sec.metadata
+        .annotations
+        .iter()
+        .flatten()
+        .map(|(key, value)| (key.to_ascii_lowercase(), value.as_str()))
+        .filter(|(key, _)| key.starts_with(&prefix))
+        .collect()
+}
+
+/// Checks the spread annotations of `sec`. Returns the reason to reject it, if any.
+fn validate(sec: &Secret) -> Result<(), String> {
+    if let Some(value) = targets::annotation(&sec.metadata, targets::TARGET_NAMESPACE_ANNOTATION) {
+        if value.split(',').all(|ns| ns.trim().is_empty()) {
+            return Err(format!("the {} annotation lists no namespace", targets::TARGET_NAMESPACE_ANNOTATION));
+        }
+    }
+    if let Some(selector) = targets::annotation(&sec.metadata, targets::TARGET_NAMESPACE_SELECTOR_ANNOTATION) {
+        if selector.trim().is_empty() {
+            return Err(format!("the {} annotation is empty", targets::TARGET_NAMESPACE_SELECTOR_ANNOTATION));
+        }
+    }
+    match SpreadConfig::from_secret(sec) {
+        Ok(_) => Ok(()),
+        Err(Error::UserInputError(message)) => Err(message),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+fn respond(status: StatusCode, body: String) -> Response<Body> {
+    let mut response = Response::new(Body::from(body));
+    *response.status_mut() = status;
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::naming;
+    use k8s_openapi::apimachinery::pkg::apis::meta::v1::Time;
+
+    fn secret(annotations: &[(&str, &str)]) -> Secret {
+        let mut sec = Secret::default();
+        sec.metadata.name = Some("db".to_string());
+        sec.metadata.annotations = Some(annotations.iter().map(|(k, v)| (keys::key(k), v.to_string())).collect());
+        sec
+    }
+
+    async fn review(object: &Secret) -> Value {
+        let review = serde_json::json!({
+            "apiVersion": "admission.k8s.io/v1",
+            "kind": "AdmissionReview",
+            "request": {"uid": "705ab4f5", "object": object},
+        });
+        let req = Request::post("/validate").body(Body::from(review.to_string())).unwrap();
+        let response = handle(req).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        serde_json::from_slice(&hyper::body::to_bytes(response.into_body()).await.unwrap()).unwrap()
+    }
+
+    #[test]
+    fn secrets_without_spread_annotations_are_allowed() {
+        assert_eq!(admit(&secret(&[]), None), Ok(Vec::new()));
+        assert_eq!(admit(&secret(&[("example.com/target-namespace", "")]), None), Ok(Vec::new()));
+        assert_eq!(admit(&secret(&[(targets::TARGET_NAMESPACE_ANNOTATION, "a,b")]), None), Ok(Vec::new()));
+    }
+
+    #[test]
+    fn malformed_annotations_are_rejected() {
+        for (key, value) in &[
+            (targets::TARGET_NAMESPACE_SELECTOR_ANNOTATION, "env in (prod"),
+            (targets::TARGET_NAMESPACE_SELECTOR_ANNOTATION, "env=prod,=x"),
+            (targets::TARGET_NAMESPACE_SELECTOR_ANNOTATION, " "),
+            (targets::TARGET_NAMESPACE_ANNOTATION, " , "),
+            (targets::TARGET_NAMESPACE_ANNOTATION, "Team-A"),
+        ] {
+            let message = admit(&secret(&[(key, value)]), None).unwrap_err();
+            assert!(message.contains(key), "{}: {}", value, message);
+        }
+        let sec = secret(&[(targets::TARGET_NAMESPACE_ANNOTATION, "a"), (targets::MAX_NAMESPACES_ANNOTATION, "many")]);
+        assert!(admit(&sec, None).unwrap_err().contains(targets::MAX_NAMESPACES_ANNOTATION));
+        for selector in &["env=prod", "env==prod,tier!=db", "!legacy", "team in (a, b),env notin (dev)", "example.com/tenant"] {
+            assert_eq!(admit(&secret(&[(targets::TARGET_NAMESPACE_SELECTOR_ANNOTATION, selector)]), None), Ok(Vec::new()), "{}", selector);
+        }
+    }
+
+    #[test]
+    fn deletion_is_allowed() {
+        let mut sec = secret(&[(targets::TARGET_NAMESPACE_SELECTOR_ANNOTATION, "env in (prod")]);
+        sec.metadata.deletion_timestamp = Some(Time(chrono::Utc::now()));
+        assert_eq!(admit(&sec, Some(&sec.clone())), Ok(Vec::new()));
+    }
+
+    #[test]
+    fn updates_are_validated_by_the_change_of_the_spread_annotations() {
+        let malformed = secret(&[(targets::TARGET_NAMESPACE_ANNOTATION, "a"), (targets::MAX_NAMESPACES_ANNOTATION, "many")]);
+        // unchanged spread annotations, e.g. an update of the data or removing a finalizer
+        let mut edited = malformed.clone();
+        edited.metadata.finalizers = Some(vec!["example.com/cleanup".to_string()]);
+        edited.metadata.annotations.as_mut().unwrap().insert("example.com/owner".to_string(), "team-a".to_string());
+        assert_eq!(admit(&edited, Some(&malformed)), Ok(Vec::new()));
+
+        // changed, but failing the same way as before
+        let mut added = malformed.clone();
+        added.metadata.annotations.as_mut().unwrap().insert(keys::key(naming::TARGET_NAME_PREFIX_ANNOTATION), "shared-".to_string());
+        assert_eq!(admit(&added, Some(&malformed)), Ok(Vec::new()));
+
+        // changed and broken by the update
+        let valid = secret(&[(targets::TARGET_NAMESPACE_ANNOTATION, "a")]);
+        assert!(admit(&malformed, Some(&valid)).is_err());
+        let fixed = secret(&[(targets::TARGET_NAMESPACE_ANNOTATION, "a"), (targets::MAX_NAMESPACES_ANNOTATION, "2")]);
+        assert_eq!(admit(&fixed, Some(&malformed)), Ok(Vec::new()));
+    }
+
+    #[test]
+    fn unknown_annotations_are_warnings() {
+        let sec = secret(&[(targets::TARGET_NAMESPACE_ANNOTATION, "a"), ("eu.fitzek.spread.target-namespaces", "b")]);
+        assert_eq!(
+            admit(&sec, None),
+            Ok(vec!["unknown annotation eu.fitzek.spread.target-namespaces, did you mean eu.fitzek.spread.target-namespace?".to_string()])
+        );
+    }
+
+    #[tokio::test]
+    async fn reviews_are_answered_with_the_decision() {
+        let allowed = review(&secret(&[(targets::TARGET_NAMESPACE_ANNOTATION, "a")])).await;
+        assert_eq!(allowed["kind"], "AdmissionReview");
+        assert_eq!(allowed["response"]["uid"], "705ab4f5");
+        assert_eq!(allowed["response"]["allowed"], true);
+        assert!(allowed["response"].get("status").is_none());
+
+        let rejected = review(&secret(&[(targets::TARGET_NAMESPACE_SELECTOR_ANNOTATION, "env in (prod")])).await;
+        assert_eq!(rejected["response"]["allowed"], false);
+        assert_eq!(rejected["response"]["status"]["code"], 400);
+        assert!(rejected["response"]["status"]["message"].as_str().unwrap().contains("env in (prod"));
+
+        let req = Request::get("/validate").body(Body::empty()).unwrap();
+        assert_eq!(handle(req).await.unwrap().status(), StatusCode::METHOD_NOT_ALLOWED);
+    }
+}